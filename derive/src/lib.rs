@@ -0,0 +1,467 @@
+//! etfpack-derive - derive macros for the IntoTerm and FromTerm traits.
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse_macro_input, parse_quote, Attribute, Data, DeriveInput, Fields, Generics, LitStr, Result,
+    Type, WherePredicate,
+};
+
+/// How an enum variant is written on the wire.
+#[derive(Clone, Copy, PartialEq)]
+enum Tag {
+    /// As an atom (the default).
+    Atom,
+    /// As a string (STRING_EXT).
+    String,
+    /// As a UTF-8 binary.
+    Binary,
+}
+
+/// A unit enum variant and the name it is written as.
+struct Variant {
+    ident: syn::Ident,
+    name: String,
+}
+
+/// How a String field is written.
+#[derive(Clone, Copy, PartialEq)]
+enum StringKind {
+    /// Like any other field, as a charlist (the default).
+    Charlist,
+    /// As a UTF-8 binary.
+    Binary,
+}
+
+/// A named struct field and the key it is written with.
+struct Field {
+    ident: syn::Ident,
+    name: String,
+    ty: Type,
+    string: StringKind,
+}
+
+/// The shapes that can be derived.
+enum Shape {
+    /// A unit-only enum, written as a tag.
+    Enum(Tag, Vec<Variant>),
+    /// A struct with exactly one unnamed field, written as that field.
+    Newtype(Type),
+    /// A struct with named fields, written as a map with atom keys.
+    Map(Vec<Field>),
+    /// A struct with named fields, written as `{record_name, f1, f2}`.
+    Record(String, Vec<Field>),
+}
+
+/// The `#[etf(...)]` attributes of the type itself.
+#[derive(Default)]
+struct ContainerAttrs {
+    tag: Option<Tag>,
+    record: Option<Option<String>>,
+}
+
+#[proc_macro_derive(IntoTerm, attributes(etf))]
+pub fn derive_into_term(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_into_term(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[proc_macro_derive(FromTerm, attributes(etf))]
+pub fn derive_from_term(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_from_term(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand_into_term(input: &DeriveInput) -> Result<TokenStream2> {
+    let ident = &input.ident;
+    let mut generics = input.generics.clone();
+
+    let body = match shape(input)? {
+        Shape::Enum(tag, variants) => {
+            let arms = variants.iter().map(|variant| {
+                let (variant, name) = (&variant.ident, &variant.name);
+                quote! { #ident::#variant => #name }
+            });
+            let write = match tag {
+                Tag::Atom => quote! { ::etfpack::__private::write_atom(buf, name) },
+                Tag::String => quote! { name.write_term(buf) },
+                Tag::Binary => quote! { ::etfpack::__private::write_binary(buf, name) },
+            };
+            quote! {
+                let name: &str = match self { #(#arms,)* };
+                #write
+            }
+        }
+        Shape::Newtype(ty) => {
+            let bound: WherePredicate = parse_quote! { #ty: ::etfpack::IntoTerm };
+            generics.make_where_clause().predicates.push(bound);
+            quote! { self.0.write_term(buf) }
+        }
+        Shape::Map(fields) => {
+            let count = fields.len();
+            let writes = fields.iter().map(|field| {
+                let name = &field.name;
+                let write = write_field(field, &mut generics);
+                quote! {
+                    ::etfpack::__private::write_atom(buf, #name)?;
+                    #write
+                }
+            });
+            let idents = fields.iter().map(|field| &field.ident);
+            quote! {
+                let Self { #(#idents),* } = self;
+                ::etfpack::__private::write_map_header(buf, #count)?;
+                #(#writes)*
+                ::core::result::Result::Ok(())
+            }
+        }
+        Shape::Record(record, fields) => {
+            let arity = fields.len() + 1;
+            let writes: Vec<_> = fields
+                .iter()
+                .map(|field| write_field(field, &mut generics))
+                .collect();
+            let idents = fields.iter().map(|field| &field.ident);
+            quote! {
+                let Self { #(#idents),* } = self;
+                ::etfpack::__private::write_tuple_header(buf, #arity)?;
+                ::etfpack::__private::write_atom(buf, #record)?;
+                #(#writes)*
+                ::core::result::Result::Ok(())
+            }
+        }
+    };
+
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::etfpack::IntoTerm for #ident #ty_generics #where_clause {
+            fn write_term(
+                self,
+                buf: &mut ::etfpack::__private::Vec<u8>,
+            ) -> ::etfpack::__private::anyhow::Result<()> {
+                use ::etfpack::IntoTerm;
+                #body
+            }
+        }
+    })
+}
+
+/// Writes the field bound to its own name, and adds the bound it needs.
+fn write_field(field: &Field, generics: &mut Generics) -> TokenStream2 {
+    let (ident, ty) = (&field.ident, &field.ty);
+    let (bound, write): (WherePredicate, _) = match field.string {
+        StringKind::Charlist => (
+            parse_quote! { #ty: ::etfpack::IntoTerm },
+            quote! { #ident.write_term(buf)?; },
+        ),
+        StringKind::Binary => (
+            parse_quote! { #ty: ::core::convert::AsRef<str> },
+            quote! {
+                ::etfpack::__private::write_binary(buf, ::core::convert::AsRef::<str>::as_ref(&#ident))?;
+            },
+        ),
+    };
+    generics.make_where_clause().predicates.push(bound);
+    write
+}
+
+fn expand_from_term(input: &DeriveInput) -> Result<TokenStream2> {
+    let ident = &input.ident;
+    let mut generics = input.generics.clone();
+    let type_name = ident.to_string();
+
+    let body = match shape(input)? {
+        Shape::Enum(tag, variants) => {
+            let arms = variants.iter().map(|variant| {
+                let (variant, name) = (&variant.ident, &variant.name);
+                quote! { #name => ::core::result::Result::Ok(#ident::#variant) }
+            });
+            let read = match tag {
+                Tag::Atom => {
+                    quote! { <::etfpack::Atom as ::etfpack::FromTerm>::from_term(term)?.value }
+                }
+                Tag::String => {
+                    quote! { <::etfpack::__private::String as ::etfpack::FromTerm>::from_term(term)? }
+                }
+                Tag::Binary => quote! { ::etfpack::__private::read_binary(term)? },
+            };
+            quote! {
+                let name = #read;
+                match name.as_str() {
+                    #(#arms,)*
+                    other => ::core::result::Result::Err(::etfpack::__private::anyhow::anyhow!(
+                        "Unknown variant {} of {}", other, #type_name
+                    )),
+                }
+            }
+        }
+        Shape::Newtype(ty) => {
+            let bound: WherePredicate = parse_quote! { #ty: ::etfpack::FromTerm };
+            generics.make_where_clause().predicates.push(bound);
+            quote! {
+                ::core::result::Result::Ok(Self(<#ty as ::etfpack::FromTerm>::from_term(term)?))
+            }
+        }
+        Shape::Map(fields) => {
+            let names = fields.iter().map(|field| &field.name);
+            let reads = read_fields(&fields, &type_name, &mut generics);
+            quote! {
+                let mut values = ::etfpack::__private::map_fields(
+                    term,
+                    &[#(#names),*],
+                    #type_name,
+                )?.into_iter();
+                ::core::result::Result::Ok(Self { #(#reads),* })
+            }
+        }
+        Shape::Record(record, fields) => {
+            let count = fields.len();
+            let reads = read_fields(&fields, &type_name, &mut generics);
+            quote! {
+                let mut values = ::etfpack::__private::record_fields(
+                    term,
+                    #record,
+                    #count,
+                    #type_name,
+                )?.into_iter();
+                ::core::result::Result::Ok(Self { #(#reads),* })
+            }
+        }
+    };
+
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::etfpack::FromTerm for #ident #ty_generics #where_clause {
+            fn from_term(
+                term: ::etfpack::AnyTerm,
+            ) -> ::etfpack::__private::anyhow::Result<Self> {
+                #body
+            }
+        }
+    })
+}
+
+/// Reads each field from `values`, in order, and adds the bounds they need.
+fn read_fields(fields: &[Field], type_name: &str, generics: &mut Generics) -> Vec<TokenStream2> {
+    fields
+        .iter()
+        .map(|field| {
+            let (ident, ty, name) = (&field.ident, &field.ty, &field.name);
+            let (bound, read): (WherePredicate, _) = match field.string {
+                StringKind::Charlist => (
+                    parse_quote! { #ty: ::etfpack::FromTerm },
+                    quote! { <#ty as ::etfpack::FromTerm>::from_term(value) },
+                ),
+                StringKind::Binary => (
+                    parse_quote! { #ty: ::core::convert::From<::etfpack::__private::String> },
+                    quote! { ::etfpack::__private::read_binary(value).map(::core::convert::From::from) },
+                ),
+            };
+            generics.make_where_clause().predicates.push(bound);
+            quote! {
+                #ident: {
+                    let value = values.next().unwrap();
+                    ::etfpack::__private::field(#read, #name, #type_name)?
+                }
+            }
+        })
+        .collect()
+}
+
+/// Works out how the type will be written.
+fn shape(input: &DeriveInput) -> Result<Shape> {
+    let attrs = container_attrs(&input.attrs)?;
+
+    match &input.data {
+        Data::Enum(data) => {
+            if attrs.record.is_some() {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "only structs can be records",
+                ));
+            }
+
+            let mut variants = Vec::new();
+            for variant in &data.variants {
+                if !matches!(variant.fields, Fields::Unit) {
+                    return Err(syn::Error::new_spanned(
+                        variant,
+                        "only unit variants are supported",
+                    ));
+                }
+
+                let name = match rename(&variant.attrs)? {
+                    Some(name) => name,
+                    None => snake_case(&variant.ident.to_string()),
+                };
+
+                variants.push(Variant {
+                    ident: variant.ident.clone(),
+                    name,
+                });
+            }
+
+            Ok(Shape::Enum(attrs.tag.unwrap_or(Tag::Atom), variants))
+        }
+        Data::Struct(data) => {
+            if attrs.tag.is_some() {
+                return Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "tag only applies to enums",
+                ));
+            }
+
+            match &data.fields {
+                Fields::Unnamed(fields) if fields.unnamed.len() == 1 && attrs.record.is_none() => {
+                    Ok(Shape::Newtype(fields.unnamed[0].ty.clone()))
+                }
+                Fields::Named(named) => {
+                    let fields = named.named.iter().map(field).collect::<Result<Vec<_>>>()?;
+                    Ok(match attrs.record {
+                        Some(record) => Shape::Record(
+                            record.unwrap_or_else(|| snake_case(&input.ident.to_string())),
+                            fields,
+                        ),
+                        None => Shape::Map(fields),
+                    })
+                }
+                _ => Err(syn::Error::new_spanned(
+                    &input.ident,
+                    "only newtype structs and structs with named fields are supported",
+                )),
+            }
+        }
+        Data::Union(_) => Err(syn::Error::new_spanned(
+            &input.ident,
+            "unions are not supported",
+        )),
+    }
+}
+
+/// Reads `#[etf(tag = "atom" | "string" | "binary")]` from an enum, or
+/// `#[etf(record)]` or `#[etf(record = "...")]` from a struct.
+fn container_attrs(attrs: &[Attribute]) -> Result<ContainerAttrs> {
+    let mut container = ContainerAttrs::default();
+
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("etf")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("tag") {
+                let value: LitStr = meta.value()?.parse()?;
+                container.tag = Some(match value.value().as_str() {
+                    "atom" => Tag::Atom,
+                    "string" => Tag::String,
+                    "binary" => Tag::Binary,
+                    _ => return Err(meta.error("expected \"atom\", \"string\" or \"binary\"")),
+                });
+                Ok(())
+            } else if meta.path.is_ident("record") {
+                let name = match meta.value() {
+                    std::result::Result::Ok(value) => Some(value.parse::<LitStr>()?.value()),
+                    Err(_) => None,
+                };
+                container.record = Some(name);
+                Ok(())
+            } else {
+                Err(meta.error("unknown etf attribute"))
+            }
+        })?;
+    }
+
+    Ok(container)
+}
+
+/// Reads a named field with its `#[etf(rename = "...")]` and
+/// `#[etf(string = "binary" | "charlist")]` attributes.
+fn field(field: &syn::Field) -> Result<Field> {
+    let ident = field.ident.clone().unwrap();
+    let mut name = ident.to_string();
+    let mut string = StringKind::Charlist;
+
+    for attr in field
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("etf"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                let value: LitStr = meta.value()?.parse()?;
+                name = value.value();
+                Ok(())
+            } else if meta.path.is_ident("string") {
+                let value: LitStr = meta.value()?.parse()?;
+                string = match value.value().as_str() {
+                    "charlist" => StringKind::Charlist,
+                    "binary" => StringKind::Binary,
+                    _ => return Err(meta.error("expected \"charlist\" or \"binary\"")),
+                };
+                Ok(())
+            } else {
+                Err(meta.error("unknown etf attribute"))
+            }
+        })?;
+    }
+
+    Ok(Field {
+        ident,
+        name,
+        ty: field.ty.clone(),
+        string,
+    })
+}
+
+/// Reads `#[etf(rename = "...")]` from a variant.
+fn rename(attrs: &[Attribute]) -> Result<Option<String>> {
+    let mut name = None;
+
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("etf")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                let value: LitStr = meta.value()?.parse()?;
+                name = Some(value.value());
+                Ok(())
+            } else {
+                Err(meta.error("unknown etf attribute"))
+            }
+        })?;
+    }
+
+    Ok(name)
+}
+
+/// Erlang atoms are conventionally snake_case, so `NotFound` becomes
+/// `not_found`. Runs of capitals stay together, so `HTTPError` becomes
+/// `http_error`.
+fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut result = String::new();
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() && i != 0 {
+            let after_lower = !chars[i - 1].is_uppercase() && chars[i - 1] != '_';
+            let ends_run =
+                chars[i - 1].is_uppercase() && chars.get(i + 1).is_some_and(|c| c.is_lowercase());
+            if after_lower || ends_run {
+                result.push('_');
+            }
+        }
+        result.extend(c.to_lowercase());
+    }
+    result
+}
@@ -0,0 +1,201 @@
+use etfpack::{pack_value, unpack_value, AnyTerm, Atom, FromTerm, IntoTerm};
+
+#[derive(IntoTerm, FromTerm, Debug, PartialEq)]
+enum Status {
+    Ok,
+    NotFound,
+    #[etf(rename = "EXIT")]
+    Exit,
+    HTTPError,
+    GetHTTP,
+    Http2Error,
+}
+
+#[derive(IntoTerm, FromTerm, Debug, PartialEq)]
+#[etf(tag = "binary")]
+enum Level {
+    Warning,
+}
+
+#[derive(IntoTerm, FromTerm, Debug, PartialEq)]
+struct User {
+    id: u32,
+    #[etf(string = "binary")]
+    name: String,
+    #[etf(rename = "roles")]
+    tags: Vec<Status>,
+    nickname: Option<String>,
+}
+
+#[derive(IntoTerm, FromTerm, Debug, PartialEq)]
+#[etf(record)]
+struct ChatMessage {
+    from: UserId,
+    #[etf(string = "charlist")]
+    text: String,
+}
+
+#[derive(IntoTerm, FromTerm, Debug, PartialEq)]
+#[etf(record = "point")]
+struct Point<T> {
+    x: T,
+    y: T,
+}
+
+#[derive(IntoTerm, FromTerm, Debug, PartialEq)]
+#[etf(tag = "string")]
+enum Color {
+    Red,
+}
+
+#[derive(IntoTerm, FromTerm, Debug, PartialEq)]
+struct UserId(i32);
+
+#[derive(IntoTerm, FromTerm, Debug, PartialEq)]
+struct Wrapper<T>(Option<T>);
+
+#[test]
+fn atom_tags() {
+    assert_eq!(
+        pack_value(Status::NotFound).unwrap(),
+        [131, 119, 9, 110, 111, 116, 95, 102, 111, 117, 110, 100]
+    );
+    assert_eq!(
+        pack_value(Status::Exit).unwrap(),
+        [131, 119, 4, 69, 88, 73, 84]
+    );
+
+    let packed = pack_value(Status::Ok).unwrap();
+    assert_eq!(unpack_value::<Status>(packed).unwrap(), Status::Ok);
+
+    // Runs of capitals are one word.
+    for (status, name) in [
+        (Status::HTTPError, "http_error"),
+        (Status::GetHTTP, "get_http"),
+        (Status::Http2Error, "http2_error"),
+    ] {
+        let packed = pack_value(status).unwrap();
+        assert_eq!(packed, pack_value(Atom::from(name)).unwrap());
+    }
+}
+
+#[test]
+fn binary_tags() {
+    let packed = pack_value(Level::Warning).unwrap();
+    assert_eq!(
+        packed,
+        [131, 109, 0, 0, 0, 7, 119, 97, 114, 110, 105, 110, 103]
+    );
+    assert_eq!(unpack_value::<Level>(packed).unwrap(), Level::Warning);
+}
+
+#[test]
+fn unknown_variant() {
+    let packed = pack_value(etfpack::Atom::from("maybe")).unwrap();
+    let error = unpack_value::<Status>(packed).unwrap_err();
+    assert_eq!(error.to_string(), "Unknown variant maybe of Status");
+}
+
+#[test]
+fn string_tags() {
+    let packed = pack_value(Color::Red).unwrap();
+    assert_eq!(packed, [131, 107, 0, 3, 114, 101, 100]);
+    assert_eq!(unpack_value::<Color>(packed).unwrap(), Color::Red);
+}
+
+#[test]
+fn newtypes() {
+    let packed = pack_value(UserId(1000)).unwrap();
+    assert_eq!(packed, [131, 98, 0, 0, 3, 232]);
+    assert_eq!(unpack_value::<UserId>(packed).unwrap(), UserId(1000));
+
+    let packed = pack_value(Wrapper::<u8>(None)).unwrap();
+    assert_eq!(unpack_value::<Wrapper<u8>>(packed).unwrap(), Wrapper(None));
+}
+
+fn atom(name: &str) -> AnyTerm {
+    Atom::from(name).into()
+}
+
+#[test]
+fn maps() {
+    let user = User {
+        id: 7,
+        name: "Zoë".to_string(),
+        tags: vec![Status::Ok, Status::NotFound],
+        nickname: None,
+    };
+
+    let packed = pack_value(user).unwrap();
+    let term = etfpack::unpack(packed.clone()).unwrap();
+    assert_eq!(
+        term,
+        AnyTerm::Map(vec![
+            (atom("id"), AnyTerm::SmallInt(7)),
+            (atom("name"), AnyTerm::Binary("Zoë".as_bytes().to_vec())),
+            (atom("roles"), vec![atom("ok"), atom("not_found")].into()),
+            (atom("nickname"), atom("undefined")),
+        ])
+    );
+
+    let user = unpack_value::<User>(packed).unwrap();
+    assert_eq!(user.name, "Zoë");
+    assert_eq!(user.tags, [Status::Ok, Status::NotFound]);
+}
+
+#[test]
+fn map_errors() {
+    let term = AnyTerm::Map(vec![(atom("id"), AnyTerm::SmallInt(7))]);
+    let error = User::from_term(term).unwrap_err();
+    assert_eq!(error.to_string(), "Missing field name of User");
+
+    let term = AnyTerm::Map(vec![
+        (atom("id"), atom("seven")),
+        (atom("name"), AnyTerm::Binary(vec![])),
+        (atom("roles"), AnyTerm::Nil),
+        (atom("nickname"), atom("undefined")),
+    ]);
+    let error = User::from_term(term).unwrap_err();
+    assert_eq!(
+        format!("{:#}", error),
+        "In field id of User: Expected integer, found atom"
+    );
+}
+
+#[test]
+fn records() {
+    let message = ChatMessage {
+        from: UserId(1),
+        text: "hi".to_string(),
+    };
+    let packed = pack_value(message).unwrap();
+    assert_eq!(
+        etfpack::unpack(packed.clone()).unwrap(),
+        AnyTerm::Tuple(vec![
+            atom("chat_message"),
+            AnyTerm::SmallInt(1),
+            AnyTerm::String("hi".to_string()),
+        ])
+    );
+    assert_eq!(unpack_value::<ChatMessage>(packed).unwrap().from, UserId(1));
+
+    let packed = pack_value(Point { x: 1u8, y: 2u8 }).unwrap();
+    assert_eq!(
+        packed,
+        [131, 104, 3, 119, 5, 112, 111, 105, 110, 116, 97, 1, 97, 2]
+    );
+    assert_eq!(
+        unpack_value::<Point<u8>>(packed).unwrap(),
+        Point { x: 1, y: 2 }
+    );
+
+    let wrong = AnyTerm::Tuple(vec![
+        atom("line"),
+        AnyTerm::SmallInt(1),
+        AnyTerm::SmallInt(2),
+    ]);
+    assert_eq!(
+        Point::<u8>::from_term(wrong).unwrap_err().to_string(),
+        "Expected a point record with 2 fields for Point, found {line,1,2}"
+    );
+}
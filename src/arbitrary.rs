@@ -0,0 +1,345 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::structs::*;
+use crate::terms::AnyTerm;
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+/// Limits for generated terms, used with `any_with`. Lengths are counted in
+/// characters; atoms of the small kinds are also cut to 255 bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TermConfig {
+    /// The longest atom to generate. Erlang allows 255 characters.
+    pub max_atom_len: usize,
+    /// The longest string to generate. STRING_EXT allows 65535 bytes.
+    pub max_string_len: usize,
+    /// The longest binary or bitstring to generate, in bytes.
+    pub max_binary_len: usize,
+    /// How deeply tuples, lists and maps can be nested.
+    pub max_depth: u32,
+    /// The most elements in one tuple, list or map.
+    pub max_breadth: usize,
+    /// Roughly how many terms one generated term should hold in all.
+    pub max_size: u32,
+}
+
+impl Default for TermConfig {
+    fn default() -> Self {
+        TermConfig {
+            max_atom_len: 255,
+            max_string_len: 64,
+            max_binary_len: 64,
+            max_depth: 3,
+            max_breadth: 8,
+            max_size: 64,
+        }
+    }
+}
+
+/// Legacy atoms and STRING_EXT are Latin-1 on the wire, with one byte per
+/// char.
+fn latin1(max_len: usize) -> BoxedStrategy<String> {
+    vec(any::<u8>(), 0..=max_len)
+        .prop_map(|bytes| bytes.into_iter().map(char::from).collect())
+        .boxed()
+}
+
+/// Erlang requires finite floats.
+fn finite() -> BoxedStrategy<f64> {
+    use proptest::num::f64::{NEGATIVE, NORMAL, POSITIVE, SUBNORMAL, ZERO};
+
+    (POSITIVE | NEGATIVE | NORMAL | SUBNORMAL | ZERO).boxed()
+}
+
+impl Arbitrary for AtomKind {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<AtomKind>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        prop_oneof![
+            Just(AtomKind::UTF8),
+            Just(AtomKind::SmallUTF8),
+            Just(AtomKind::Legacy),
+            Just(AtomKind::SmallLegacy),
+        ]
+        .boxed()
+    }
+}
+
+impl Arbitrary for Atom {
+    type Parameters = TermConfig;
+    type Strategy = BoxedStrategy<Atom>;
+
+    fn arbitrary_with(config: TermConfig) -> Self::Strategy {
+        let max_len = config.max_atom_len;
+        any::<AtomKind>()
+            .prop_flat_map(move |kind| {
+                let value = match kind {
+                    AtomKind::UTF8 | AtomKind::SmallUTF8 => vec(any::<char>(), 0..=max_len)
+                        .prop_map(|chars| chars.into_iter().collect())
+                        .boxed(),
+                    AtomKind::Legacy | AtomKind::SmallLegacy => latin1(max_len),
+                };
+                (Just(kind), value)
+            })
+            .prop_map(|(kind, mut value)| {
+                let len = |value: &String| match kind {
+                    AtomKind::SmallUTF8 => value.len(),
+                    _ => value.chars().count(),
+                };
+                if let AtomKind::SmallUTF8 | AtomKind::SmallLegacy = kind {
+                    while len(&value) > u8::MAX.into() {
+                        value.pop();
+                    }
+                }
+                Atom::new(kind, value)
+            })
+            .boxed()
+    }
+}
+
+impl Arbitrary for LegacyFloat {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<LegacyFloat>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        finite().prop_map(LegacyFloat::from).boxed()
+    }
+}
+
+/// Bignums have at least one digit, and no zeros at the top.
+impl Arbitrary for BigInt {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<BigInt>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        (any::<bool>(), vec(any::<u8>(), 0..16), 1..=u8::MAX)
+            .prop_map(|(negative, mut digits, top)| {
+                digits.push(top);
+                BigInt { negative, digits }
+            })
+            .boxed()
+    }
+}
+
+/// The unused bits of the last byte are zero.
+impl Arbitrary for BitBinary {
+    type Parameters = TermConfig;
+    type Strategy = BoxedStrategy<BitBinary>;
+
+    fn arbitrary_with(config: TermConfig) -> Self::Strategy {
+        (vec(any::<u8>(), 1..=config.max_binary_len.max(1)), 1..=8u8)
+            .prop_map(|(mut bytes, bits)| {
+                if let Some(last) = bytes.last_mut() {
+                    *last &= u8::MAX << (8 - bits);
+                }
+                BitBinary { bytes, bits }
+            })
+            .boxed()
+    }
+}
+
+/// The old encodings only keep a few bits of each field, so only those are
+/// set.
+impl Arbitrary for Pid {
+    type Parameters = TermConfig;
+    type Strategy = BoxedStrategy<Pid>;
+
+    fn arbitrary_with(config: TermConfig) -> Self::Strategy {
+        let old = (0..1u32 << 15, 0..1u32 << 13, 0..4u32).prop_map(|fields| (PidKind::Pid, fields));
+        let new = any::<(u32, u32, u32)>().prop_map(|fields| (PidKind::NewPid, fields));
+        (any_with::<Atom>(config), prop_oneof![old, new])
+            .prop_map(|(node, (kind, (id, serial, creation)))| Pid {
+                kind,
+                node,
+                id,
+                serial,
+                creation,
+            })
+            .boxed()
+    }
+}
+
+impl Arbitrary for Port {
+    type Parameters = TermConfig;
+    type Strategy = BoxedStrategy<Port>;
+
+    fn arbitrary_with(config: TermConfig) -> Self::Strategy {
+        let old = (0..1u64 << 28, 0..4u32).prop_map(|fields| (PortKind::Port, fields));
+        let new = (any::<u32>(), any::<u32>())
+            .prop_map(|(id, creation)| (PortKind::NewPort, (id.into(), creation)));
+        let v4 = any::<(u64, u32)>().prop_map(|fields| (PortKind::V4, fields));
+        (any_with::<Atom>(config), prop_oneof![old, new, v4])
+            .prop_map(|(node, (kind, (id, creation)))| Port {
+                kind,
+                node,
+                id,
+                creation,
+            })
+            .boxed()
+    }
+}
+
+impl Arbitrary for Reference {
+    type Parameters = TermConfig;
+    type Strategy = BoxedStrategy<Reference>;
+
+    fn arbitrary_with(config: TermConfig) -> Self::Strategy {
+        // The first word of the old encodings keeps 18 bits.
+        let old_id = |len| {
+            (0..1u32 << 18, vec(any::<u32>(), len)).prop_map(|(first, mut rest)| {
+                rest.insert(0, first);
+                rest
+            })
+        };
+        let old = (old_id(0..=0), 0..4u32)
+            .prop_map(|(id, creation)| (ReferenceKind::Reference, id, creation));
+        let new = (old_id(0..=2), 0..4u32)
+            .prop_map(|(id, creation)| (ReferenceKind::NewReference, id, creation));
+        let newer = (vec(any::<u32>(), 1..=5), any::<u32>())
+            .prop_map(|(id, creation)| (ReferenceKind::NewerReference, id, creation));
+        (any_with::<Atom>(config), prop_oneof![old, new, newer])
+            .prop_map(|(node, (kind, id, creation))| Reference {
+                kind,
+                node,
+                creation,
+                id,
+            })
+            .boxed()
+    }
+}
+
+/// Generates terms that can be packed, as Erlang would accept them: floats
+/// are finite, improper lists have at least one element, and map keys are
+/// all different.
+impl Arbitrary for AnyTerm {
+    type Parameters = TermConfig;
+    type Strategy = BoxedStrategy<AnyTerm>;
+
+    fn arbitrary_with(config: TermConfig) -> Self::Strategy {
+        let leaf = prop_oneof![
+            any::<u8>().prop_map(AnyTerm::SmallInt),
+            any::<i32>().prop_map(AnyTerm::Integer),
+            any::<BigInt>().prop_map(AnyTerm::BigInt),
+            finite().prop_map(AnyTerm::Float),
+            any::<LegacyFloat>().prop_map(AnyTerm::LegacyFloat),
+            any_with::<Atom>(config).prop_map(AnyTerm::Atom),
+            any_with::<Port>(config).prop_map(AnyTerm::Port),
+            any_with::<Pid>(config).prop_map(AnyTerm::Pid),
+            any_with::<Reference>(config).prop_map(AnyTerm::Reference),
+            latin1(config.max_string_len.min(u16::MAX.into())).prop_map(AnyTerm::String),
+            Just(AnyTerm::Nil),
+            vec(any::<u8>(), 0..=config.max_binary_len).prop_map(AnyTerm::Binary),
+            any_with::<BitBinary>(config).prop_map(AnyTerm::BitBinary),
+        ];
+
+        let breadth = config.max_breadth;
+        leaf.prop_recursive(
+            config.max_depth,
+            config.max_size,
+            breadth as u32,
+            move |inner| {
+                let proper = vec(inner.clone(), 1..=breadth.max(1)).prop_map(List::new);
+                let improper = (vec(inner.clone(), 1..=breadth.max(1)), inner.clone()).prop_map(
+                    |(elements, tail)| List {
+                        elements,
+                        tail: Box::new(tail),
+                    },
+                );
+                prop_oneof![
+                    2 => vec(inner.clone(), 0..=breadth).prop_map(AnyTerm::Tuple),
+                    2 => proper.prop_map(AnyTerm::List),
+                    1 => improper.prop_map(AnyTerm::List),
+                    2 => vec((inner.clone(), inner), 0..=breadth).prop_map(|pairs| {
+                        // Keys that only differ in how they are packed, like
+                        // 1 as SMALL_INTEGER_EXT and INTEGER_EXT, are the same key.
+                        let mut unique: Vec<(AnyTerm, AnyTerm)> = Vec::new();
+                        for (key, value) in pairs {
+                            if unique.iter().all(|(other, _)| !other.same_value(&key)) {
+                                unique.push((key, value));
+                            }
+                        }
+                        AnyTerm::Map(unique)
+                    }),
+                ]
+            },
+        )
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn children(term: &AnyTerm) -> Vec<&AnyTerm> {
+        match term {
+            AnyTerm::Tuple(elements) => elements.iter().collect(),
+            AnyTerm::List(list) => list.elements.iter().chain([&*list.tail]).collect(),
+            AnyTerm::Map(pairs) => pairs.iter().flat_map(|(key, value)| [key, value]).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn depth(term: &AnyTerm) -> u32 {
+        match term {
+            AnyTerm::Tuple(_) | AnyTerm::List(_) | AnyTerm::Map(_) => {
+                1 + children(term).into_iter().map(depth).max().unwrap_or(0)
+            }
+            _ => 0,
+        }
+    }
+
+    /// Returns true if no map in the term has two keys with the same value.
+    fn unique_keys(term: &AnyTerm) -> bool {
+        let unique = match term {
+            AnyTerm::Map(pairs) => pairs.iter().enumerate().all(|(i, (key, _))| {
+                pairs[i + 1..]
+                    .iter()
+                    .all(|(other, _)| !key.same_value(other))
+            }),
+            _ => true,
+        };
+        unique && children(term).into_iter().all(unique_keys)
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip(term in any::<AnyTerm>()) {
+            let data = crate::pack(term.clone()).unwrap();
+            prop_assert_eq!(crate::encoded_size(&term).unwrap(), data.len());
+            // Legacy floats only keep their encoding when unpacked losslessly.
+            let unpacked = crate::UnpackOptions::new().lossless(true).unpack(data.clone()).unwrap();
+            prop_assert_eq!(&unpacked, &term);
+            prop_assert_eq!(crate::pack(unpacked).unwrap(), data);
+        }
+
+        #[test]
+        fn map_keys(term in any_with::<AnyTerm>(TermConfig { max_depth: 1, max_breadth: 32, ..TermConfig::default() })) {
+            prop_assert!(unique_keys(&term));
+        }
+
+        #[test]
+        fn shallow(term in any_with::<AnyTerm>(TermConfig { max_depth: 1, max_breadth: 2, ..TermConfig::default() })) {
+            prop_assert!(depth(&term) <= 1);
+        }
+
+        #[test]
+        fn config(atom in any_with::<Atom>(TermConfig { max_atom_len: 4, ..TermConfig::default() })) {
+            prop_assert!(atom.value.chars().count() <= 4);
+        }
+    }
+}
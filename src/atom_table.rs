@@ -0,0 +1,387 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::structs::*;
+
+use anyhow::*;
+use once_cell::sync::Lazy;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, RwLock};
+
+/// The default maximum number of atoms, the same as the Erlang VM's.
+pub const DEFAULT_ATOM_LIMIT: usize = 1_048_576;
+
+static GLOBAL: Lazy<AtomTable> = Lazy::new(AtomTable::new);
+
+/// A handle to an atom in an atom table. Comparing and hashing handles is as
+/// cheap as comparing integers. Handles keep their table alive, and handles
+/// from different tables are never equal.
+#[derive(Clone)]
+pub struct InternedAtom {
+    index: u32,
+    table: Arc<Shared>,
+}
+
+impl InternedAtom {
+    /// Interns an atom in the global table.
+    pub fn new(name: &str) -> Result<Self> {
+        AtomTable::global().intern(name)
+    }
+
+    /// Returns the name of the atom.
+    pub fn name(&self) -> Arc<str> {
+        // Handles are only made by their table, which never removes atoms.
+        self.table.inner.read().unwrap().names[self.index as usize].clone()
+    }
+
+    /// Returns the position of the atom in its table.
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+}
+
+impl PartialEq for InternedAtom {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && Arc::ptr_eq(&self.table, &other.table)
+    }
+}
+
+impl Eq for InternedAtom {}
+
+impl Hash for InternedAtom {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+    }
+}
+
+/// Orders atoms by when they were interned, not by name.
+impl PartialOrd for InternedAtom {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for InternedAtom {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let table = Arc::as_ptr(&self.table).cmp(&Arc::as_ptr(&other.table));
+        self.index.cmp(&other.index).then(table)
+    }
+}
+
+impl fmt::Debug for InternedAtom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("InternedAtom")
+            .field(&self.index)
+            .field(&self.name())
+            .finish()
+    }
+}
+
+impl fmt::Display for InternedAtom {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name())
+    }
+}
+
+impl TryFrom<&Atom> for InternedAtom {
+    type Error = Error;
+
+    fn try_from(value: &Atom) -> Result<Self> {
+        InternedAtom::new(&value.value)
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    names: Vec<Arc<str>>,
+    indices: HashMap<Arc<str>, u32>,
+}
+
+struct Shared {
+    inner: RwLock<Inner>,
+    limit: RwLock<usize>,
+}
+
+/// A table of atoms. There is a global table used by InternedAtom::new and
+/// the atoms! macro, but decoders can also have their own; see
+/// UnpackOptions::intern_atoms. Clones of a table share its atoms.
+#[derive(Clone)]
+pub struct AtomTable {
+    shared: Arc<Shared>,
+}
+
+impl AtomTable {
+    /// Creates an empty table with the default limit.
+    pub fn new() -> Self {
+        AtomTable::with_limit(DEFAULT_ATOM_LIMIT)
+    }
+
+    /// Creates an empty table that holds at most `limit` atoms.
+    pub fn with_limit(limit: usize) -> Self {
+        AtomTable {
+            shared: Arc::new(Shared {
+                inner: RwLock::new(Inner::default()),
+                limit: RwLock::new(limit),
+            }),
+        }
+    }
+
+    /// Returns the global table.
+    pub fn global() -> &'static AtomTable {
+        &GLOBAL
+    }
+
+    /// Changes the maximum number of atoms. Atoms that are already in the
+    /// table stay there even if the new limit is lower.
+    pub fn set_limit(&self, limit: usize) {
+        *self.shared.limit.write().unwrap() = limit;
+    }
+
+    /// Returns the maximum number of atoms.
+    pub fn limit(&self) -> usize {
+        *self.shared.limit.read().unwrap()
+    }
+
+    /// Returns the number of atoms in the table.
+    pub fn len(&self) -> usize {
+        self.shared.inner.read().unwrap().names.len()
+    }
+
+    /// Returns true if there are no atoms in the table.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the handle of an atom, adding it to the table if it isn't
+    /// there yet. Fails if the table is full.
+    pub fn intern(&self, name: &str) -> Result<InternedAtom> {
+        if let Some(atom) = self.lookup(name) {
+            return Ok(atom);
+        }
+
+        let mut inner = self.shared.inner.write().unwrap();
+
+        // Someone else might have added it between the two locks.
+        if let Some(&index) = inner.indices.get(name) {
+            return Ok(self.handle(index));
+        }
+
+        if inner.names.len() >= self.limit() {
+            return Err(anyhow!("Atom table is full ({} atoms)", self.limit()));
+        }
+
+        let index = u32::try_from(inner.names.len())?;
+        let name: Arc<str> = Arc::from(name);
+        inner.names.push(name.clone());
+        inner.indices.insert(name, index);
+
+        Ok(self.handle(index))
+    }
+
+    /// Returns the handle of an atom only if it is already in the table.
+    pub fn lookup(&self, name: &str) -> Option<InternedAtom> {
+        let inner = self.shared.inner.read().unwrap();
+        inner.indices.get(name).map(|&index| self.handle(index))
+    }
+
+    /// Returns the name of an atom, if it is from this table.
+    pub fn resolve(&self, atom: &InternedAtom) -> Option<Arc<str>> {
+        match Arc::ptr_eq(&self.shared, &atom.table) {
+            true => Some(atom.name()),
+            false => None,
+        }
+    }
+
+    fn handle(&self, index: u32) -> InternedAtom {
+        InternedAtom {
+            index,
+            table: self.shared.clone(),
+        }
+    }
+}
+
+impl Default for AtomTable {
+    fn default() -> Self {
+        AtomTable::new()
+    }
+}
+
+impl fmt::Debug for AtomTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AtomTable")
+            .field("len", &self.len())
+            .field("limit", &self.limit())
+            .finish()
+    }
+}
+
+/// Declares functions that return interned atoms from the global table. Each
+/// atom is interned once, the first time its function is called.
+///
+/// ```
+/// use etfpack::{atoms, InternedAtom};
+///
+/// atoms! {
+///     pub ok,
+///     pub error,
+///     gen_call = "$gen_call",
+/// }
+///
+/// assert_eq!(&*ok().name(), "ok");
+/// assert_eq!(ok(), InternedAtom::new("ok").unwrap());
+/// assert_ne!(ok(), error());
+/// assert_eq!(&*gen_call().name(), "$gen_call");
+/// ```
+#[macro_export]
+macro_rules! atoms {
+    ($($(#[$meta:meta])* $vis:vis $name:ident $(= $value:literal)?),* $(,)?) => {$(
+        $(#[$meta])*
+        #[allow(dead_code)]
+        $vis fn $name() -> $crate::InternedAtom {
+            static ATOM: $crate::__private::once_cell::sync::OnceCell<$crate::InternedAtom> =
+                $crate::__private::once_cell::sync::OnceCell::new();
+
+            ATOM.get_or_init(|| {
+                $crate::InternedAtom::new($crate::atoms!(@name $name $($value)?))
+                    .expect("atom table is full")
+            })
+            .clone()
+        }
+    )*};
+    (@name $name:ident) => {
+        stringify!($name)
+    };
+    (@name $name:ident $value:literal) => {
+        $value
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AnyTerm, UnpackOptions};
+
+    atoms! {
+        ok,
+        error,
+        gen_call = "$gen_call",
+    }
+
+    #[test]
+    fn intern() {
+        let table = AtomTable::new();
+        let ok = table.intern("ok").unwrap();
+        let error = table.intern("error").unwrap();
+
+        assert_eq!(table.intern("ok").unwrap(), ok);
+        assert_ne!(ok, error);
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.resolve(&error).as_deref(), Some("error"));
+    }
+
+    #[test]
+    fn separate_tables() {
+        let table = AtomTable::new();
+        table.intern("first").unwrap();
+        let ok = table.intern("ok").unwrap();
+
+        assert_eq!(&*ok.name(), "ok");
+        assert_eq!(ok.to_string(), "ok");
+        assert_eq!(table.clone().lookup("ok"), Some(ok.clone()));
+
+        let other = AtomTable::new();
+        let other_ok = other.intern("ok").unwrap();
+        assert_ne!(ok, other_ok);
+        assert_eq!(other.resolve(&ok), None);
+
+        // Handles keep their table alive.
+        drop(table);
+        assert_eq!(&*ok.name(), "ok");
+    }
+
+    #[test]
+    fn lookup() {
+        let table = AtomTable::new();
+        assert_eq!(table.lookup("ok"), None);
+
+        let ok = table.intern("ok").unwrap();
+        assert_eq!(table.lookup("ok"), Some(ok));
+        assert_eq!(table.len(), 1);
+    }
+
+    #[test]
+    fn limit() {
+        let table = AtomTable::with_limit(2);
+        table.intern("a").unwrap();
+        table.intern("b").unwrap();
+        table.intern("a").unwrap();
+
+        let error = table.intern("c").unwrap_err();
+        assert_eq!(error.to_string(), "Atom table is full (2 atoms)");
+
+        table.set_limit(3);
+        assert!(table.intern("c").is_ok());
+    }
+
+    #[test]
+    fn macro_atoms() {
+        assert_eq!(ok(), ok());
+        assert_ne!(ok(), error());
+        assert_eq!(ok(), InternedAtom::new("ok").unwrap());
+        assert_eq!(&*gen_call().name(), "$gen_call");
+        assert_eq!(error().to_string(), "error");
+    }
+
+    #[test]
+    fn from_atom() {
+        let atom = Atom::from("ok");
+        assert_eq!(InternedAtom::try_from(&atom).unwrap(), ok());
+    }
+
+    #[test]
+    fn unpacking() {
+        let table = AtomTable::with_limit(2);
+        let options = UnpackOptions::new().intern_atoms(table.clone());
+
+        // {ok, error}
+        let data = [
+            131, 104, 2, 119, 2, 111, 107, 119, 5, 101, 114, 114, 111, 114,
+        ];
+        let term = options.unpack(data.to_vec()).unwrap();
+        assert_eq!(table.len(), 2);
+        let interned = |term: &AnyTerm| -> Vec<Option<InternedAtom>> {
+            let elements = term.as_tuple().unwrap();
+            elements
+                .iter()
+                .map(|element| element.as_atom().unwrap().interned().cloned())
+                .collect()
+        };
+        assert_eq!(interned(&term), [table.lookup("ok"), table.lookup("error")]);
+
+        // Interning doesn't change what the atoms compare equal to.
+        let plain = crate::unpack(data.to_vec()).unwrap();
+        assert_eq!(interned(&plain), [None, None]);
+        assert_eq!(term, plain);
+
+        // {ok, other}
+        let data = [
+            131, 104, 2, 119, 2, 111, 107, 119, 5, 111, 116, 104, 101, 114,
+        ];
+        let error = options.unpack(data.to_vec()).unwrap_err();
+        assert_eq!(error.to_string(), "Atom table is full (2 atoms)");
+    }
+}
@@ -0,0 +1,463 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::convert::list_elements;
+use crate::structs::Atom;
+use crate::terms::AnyTerm;
+
+use anyhow::*;
+use flate2::read::ZlibDecoder;
+use std::io::Read;
+use std::path::Path;
+
+const FORM: &[u8; 4] = b"FOR1";
+const BEAM: &[u8; 4] = b"BEAM";
+
+/// A chunk of a BEAM file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chunk {
+    /// The four character chunk ID, like `AtU8` or `LitT`.
+    pub id: [u8; 4],
+    /// The contents of the chunk, without padding.
+    pub data: Vec<u8>,
+}
+
+impl Chunk {
+    /// Returns the chunk ID as a string.
+    pub fn name(&self) -> String {
+        String::from_utf8_lossy(&self.id).into_owned()
+    }
+}
+
+/// A compiled Erlang module, split into its chunks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BeamFile {
+    pub chunks: Vec<Chunk>,
+}
+
+impl BeamFile {
+    /// Reads a BEAM file from a path.
+    pub fn load(path: impl AsRef<Path>) -> Result<BeamFile> {
+        BeamFile::parse(&std::fs::read(path)?)
+    }
+
+    /// Splits the IFF container (`FOR1`, size, `BEAM`) into its chunks.
+    pub fn parse(data: &[u8]) -> Result<BeamFile> {
+        let mut reader = Reader { data, pos: 0 };
+
+        if reader.take(4)? != FORM {
+            return Err(anyhow!("Not a BEAM file: missing FOR1 header"));
+        }
+
+        let size = reader.u32()? as usize;
+        if size > data.len() - reader.pos {
+            return Err(anyhow!("BEAM file is truncated"));
+        }
+
+        let mut reader = Reader {
+            data: &data[..reader.pos + size],
+            pos: reader.pos,
+        };
+
+        if reader.take(4)? != BEAM {
+            return Err(anyhow!("Not a BEAM file: missing BEAM form type"));
+        }
+
+        let mut chunks = Vec::new();
+        while reader.pos < reader.data.len() {
+            let id: [u8; 4] = reader.take(4)?.try_into().unwrap();
+            let size = reader.u32()? as usize;
+            let data = reader
+                .take(size)
+                .with_context(|| format!("Chunk {} is truncated", String::from_utf8_lossy(&id)))?
+                .to_vec();
+
+            // Chunks are padded to a multiple of four bytes, except maybe the
+            // last one.
+            let padding = (4 - size % 4) % 4;
+            reader.pos = (reader.pos + padding).min(reader.data.len());
+
+            chunks.push(Chunk { id, data });
+        }
+
+        Ok(BeamFile { chunks })
+    }
+
+    /// Returns the first chunk with the given ID.
+    pub fn chunk(&self, id: &str) -> Option<&Chunk> {
+        self.chunks.iter().find(|chunk| chunk.id == id.as_bytes())
+    }
+
+    /// Decodes the atom table, from `AtU8` or the older Latin-1 `Atom`. The
+    /// first atom is the module name.
+    pub fn atoms(&self) -> Result<Vec<String>> {
+        let (chunk, is_utf8) = match (self.chunk("AtU8"), self.chunk("Atom")) {
+            (Some(chunk), _) => (chunk, true),
+            (None, Some(chunk)) => (chunk, false),
+            (None, None) => return Err(anyhow!("BEAM file has no atom table")),
+        };
+
+        let mut reader = Reader {
+            data: &chunk.data,
+            pos: 0,
+        };
+
+        // Since OTP 26, a negative count means the lengths are written in
+        // the compact term format, to allow for atoms longer than 255 bytes.
+        let count = reader.u32()? as i32;
+        let is_compact = count < 0;
+
+        let mut atoms = Vec::new();
+        for _ in 0..count.unsigned_abs() {
+            let length = if is_compact {
+                reader.compact_unsigned()?
+            } else {
+                reader.take(1)?[0].into()
+            };
+
+            let name = reader.take(length)?;
+            atoms.push(match is_utf8 {
+                true => String::from_utf8(name.to_vec())?,
+                false => name.iter().map(|&byte| char::from(byte)).collect(),
+            });
+        }
+
+        Ok(atoms)
+    }
+
+    /// Returns the module name, the first atom of the atom table.
+    pub fn module(&self) -> Result<String> {
+        match self.atoms()?.into_iter().next() {
+            Some(module) => Ok(module),
+            None => Err(anyhow!("Atom table is empty")),
+        }
+    }
+
+    /// Returns the encoded terms of the literal table (`LitT`), each starting
+    /// with the format version.
+    pub fn literal_bytes(&self) -> Result<Vec<Vec<u8>>> {
+        let chunk = match self.chunk("LitT") {
+            Some(chunk) => chunk,
+            None => return Ok(Vec::new()),
+        };
+
+        let mut reader = Reader {
+            data: &chunk.data,
+            pos: 0,
+        };
+
+        // A size of zero means the table isn't compressed.
+        let size = reader.u32()? as usize;
+        let table = match size {
+            0 => reader.data[reader.pos..].to_vec(),
+            _ => {
+                // The size comes from the file, so it only sets how much
+                // to inflate, not how much to allocate up front.
+                let mut table = Vec::with_capacity(size.min(chunk.data.len()));
+                ZlibDecoder::new(&reader.data[reader.pos..])
+                    .take(size as u64)
+                    .read_to_end(&mut table)?;
+                table
+            }
+        };
+
+        let mut reader = Reader {
+            data: &table,
+            pos: 0,
+        };
+
+        let count = reader.u32()?;
+        let mut literals = Vec::new();
+        for _ in 0..count {
+            let size = reader.u32()? as usize;
+            literals.push(reader.take(size)?.to_vec());
+        }
+
+        Ok(literals)
+    }
+
+    /// Decodes the literal table. Each literal is decoded on its own, so one
+    /// that can't be decoded doesn't hide the others.
+    pub fn literals(&self) -> Result<Vec<Result<AnyTerm>>> {
+        Ok(self
+            .literal_bytes()?
+            .into_iter()
+            .map(crate::unpack)
+            .collect())
+    }
+
+    /// Decodes the module attributes (`Attr`), a list of `{Name, Values}`
+    /// like `module_info(attributes)`. It is empty if there is no chunk.
+    pub fn attributes(&self) -> Result<Vec<(String, AnyTerm)>> {
+        self.proplist_chunk("Attr")
+    }
+
+    /// Returns the values of every attribute with a name, in order. A
+    /// module can have more than one, like `behaviour`.
+    pub fn attribute(&self, name: &str) -> Result<Vec<AnyTerm>> {
+        let mut values = Vec::new();
+        for (key, value) in self.attributes()? {
+            if key == name {
+                values.extend(list_elements(value)?);
+            }
+        }
+        Ok(values)
+    }
+
+    /// Returns the version of the module, from `-vsn`, or the MD5 of the
+    /// module that the compiler uses when there is no `-vsn`.
+    pub fn vsn(&self) -> Result<Option<AnyTerm>> {
+        Ok(self.attribute("vsn")?.into_iter().next())
+    }
+
+    /// Decodes the compile info (`CInf`), a list of `{Key, Value}` with
+    /// keys like `version`, `options` and `source`. It is empty if there is
+    /// no chunk.
+    pub fn compile_info(&self) -> Result<Vec<(String, AnyTerm)>> {
+        self.proplist_chunk("CInf")
+    }
+
+    /// Decodes the debug info (`Dbgi`), if there is any.
+    pub fn debug_info(&self) -> Result<Option<AnyTerm>> {
+        self.term_chunk("Dbgi")
+    }
+
+    fn proplist_chunk(&self, id: &str) -> Result<Vec<(String, AnyTerm)>> {
+        let term = match self.term_chunk(id)? {
+            Some(term) => term,
+            None => return Ok(Vec::new()),
+        };
+
+        list_elements(term)
+            .and_then(|elements| elements.into_iter().map(property).collect())
+            .with_context(|| format!("Chunk {} is not a list of pairs", id))
+    }
+
+    fn term_chunk(&self, id: &str) -> Result<Option<AnyTerm>> {
+        match self.chunk(id) {
+            Some(chunk) => crate::unpack(chunk.data.clone())
+                .with_context(|| format!("Failed to decode chunk {}", id))
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Reads a `{Key, Value}` pair with an atom key.
+fn property(term: AnyTerm) -> Result<(String, AnyTerm)> {
+    let (key, value): (Atom, AnyTerm) = match term {
+        AnyTerm::Tuple(mut pair) if pair.len() == 2 => {
+            let value = pair.pop().unwrap();
+            (Atom::try_from(pair.pop().unwrap())?, value)
+        }
+        other => return Err(anyhow!("Expected a pair, found {}", other)),
+    };
+    Ok((key.value, value))
+}
+
+/// A cursor over a byte slice.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, num: usize) -> Result<&'a [u8]> {
+        match self.data.get(self.pos..self.pos + num) {
+            Some(bytes) => {
+                self.pos += num;
+                Ok(bytes)
+            }
+            None => Err(anyhow!("Unexpected end of data")),
+        }
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    /// Reads an unsigned (tag 0) value in the compact term format.
+    fn compact_unsigned(&mut self) -> Result<usize> {
+        let first = self.take(1)?[0];
+        if first & 0b111 != 0 {
+            return Err(anyhow!("Expected an unsigned compact term"));
+        }
+
+        if first & 0b1000 == 0 {
+            Ok((first >> 4).into())
+        } else if first & 0b1_0000 == 0 {
+            let second = self.take(1)?[0];
+            Ok((usize::from(first & 0b1110_0000) << 3) | usize::from(second))
+        } else {
+            Err(anyhow!("Compact term is too large"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::BigInt;
+    use flate2::{write::ZlibEncoder, Compression};
+    use std::io::Write;
+
+    fn beam(chunks: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut body = BEAM.to_vec();
+        for (id, data) in chunks {
+            body.extend_from_slice(*id);
+            body.extend((data.len() as u32).to_be_bytes());
+            body.extend(data);
+            body.resize(body.len() + (4 - data.len() % 4) % 4, 0);
+        }
+
+        let mut file = FORM.to_vec();
+        file.extend((body.len() as u32).to_be_bytes());
+        file.extend(body);
+        file
+    }
+
+    fn atom_table(count: i32, atoms: &[&[u8]]) -> Vec<u8> {
+        let mut data = count.to_be_bytes().to_vec();
+        for atom in atoms {
+            data.push(atom.len() as u8);
+            data.extend_from_slice(atom);
+        }
+        data
+    }
+
+    #[test]
+    fn chunks() {
+        let file = beam(&[(b"AtU8", atom_table(1, &[b"hello"])), (b"Code", vec![1, 2])]);
+        let file = BeamFile::parse(&file).unwrap();
+
+        let names: Vec<String> = file.chunks.iter().map(Chunk::name).collect();
+        assert_eq!(names, ["AtU8", "Code"]);
+        assert_eq!(file.chunk("Code").unwrap().data, [1, 2]);
+        assert_eq!(file.module().unwrap(), "hello");
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(BeamFile::parse(b"FOR2").is_err());
+        assert!(BeamFile::parse(b"FOR1\0\0\0\x04JUNK").is_err());
+        assert!(BeamFile::parse(b"FOR1\0\0\0\xffBEAM").is_err());
+    }
+
+    #[test]
+    fn latin1_atoms() {
+        let file = beam(&[(b"Atom", atom_table(2, &[b"m", b"caf\xe9"]))]);
+        let file = BeamFile::parse(&file).unwrap();
+        assert_eq!(file.atoms().unwrap(), ["m", "café"]);
+    }
+
+    #[test]
+    fn compact_atoms() {
+        // A length of 3 is 0011_0000, 300 is 001_01000 0010_1100.
+        let mut data = (-2i32).to_be_bytes().to_vec();
+        data.push(0b0011_0000);
+        data.extend(b"mod");
+        data.extend([0b0010_1000, 0b0010_1100]);
+        data.extend([b'a'; 300]);
+
+        let file = BeamFile::parse(&beam(&[(b"AtU8", data)])).unwrap();
+        let atoms = file.atoms().unwrap();
+        assert_eq!(atoms[0], "mod");
+        assert_eq!(atoms[1].len(), 300);
+    }
+
+    #[test]
+    fn literals() {
+        let mut table = 2u32.to_be_bytes().to_vec();
+        for literal in [&[131, 97, 42][..], &[131, 104, 0]] {
+            table.extend((literal.len() as u32).to_be_bytes());
+            table.extend(literal);
+        }
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&table).unwrap();
+        let mut data = (table.len() as u32).to_be_bytes().to_vec();
+        data.extend(encoder.finish().unwrap());
+
+        let file = BeamFile::parse(&beam(&[(b"LitT", data)])).unwrap();
+        let literals = file.literals().unwrap();
+        assert_eq!(literals.len(), 2);
+        assert_eq!(literals[0].as_ref().unwrap(), &AnyTerm::SmallInt(42));
+        assert_eq!(literals[1].as_ref().unwrap(), &AnyTerm::Tuple(vec![]));
+    }
+
+    #[test]
+    fn uncompressed_literals() {
+        let mut data = vec![0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 2];
+        data.extend([131, 106]);
+
+        let file = BeamFile::parse(&beam(&[(b"LitT", data)])).unwrap();
+        assert_eq!(file.literal_bytes().unwrap(), [vec![131, 106]]);
+    }
+
+    #[test]
+    fn attributes() {
+        // [{vsn, [270544960116169906318588440637845587263]},
+        //  {behaviour, [gen_server]}]
+        let attr = vec![
+            131, 108, 0, 0, 0, 2, 104, 2, 119, 3, 118, 115, 110, 108, 0, 0, 0, 1, 110, 16, 0, 63,
+            197, 168, 175, 28, 76, 45, 82, 76, 221, 91, 62, 98, 16, 137, 203, 106, 104, 2, 119, 9,
+            98, 101, 104, 97, 118, 105, 111, 117, 114, 108, 0, 0, 0, 1, 119, 10, 103, 101, 110, 95,
+            115, 101, 114, 118, 101, 114, 106, 106,
+        ];
+        // [{version, "8.4"}, {options, []}, {source, "/src/m.erl"}]
+        let cinf = vec![
+            131, 108, 0, 0, 0, 3, 104, 2, 119, 7, 118, 101, 114, 115, 105, 111, 110, 107, 0, 3, 56,
+            46, 52, 104, 2, 119, 7, 111, 112, 116, 105, 111, 110, 115, 106, 104, 2, 119, 6, 115,
+            111, 117, 114, 99, 101, 107, 0, 10, 47, 115, 114, 99, 47, 109, 46, 101, 114, 108, 106,
+        ];
+        let file = BeamFile::parse(&beam(&[(b"Attr", attr), (b"CInf", cinf)])).unwrap();
+
+        let vsn = BigInt::from(270544960116169906318588440637845587263u128);
+        assert_eq!(file.vsn().unwrap(), Some(AnyTerm::BigInt(vsn)));
+        let behaviours = file.attribute("behaviour").unwrap();
+        assert!(behaviours[0].is_atom("gen_server"));
+        assert_eq!(file.attributes().unwrap().len(), 2);
+
+        let info = file.compile_info().unwrap();
+        assert_eq!(info[0], ("version".to_string(), AnyTerm::from("8.4")));
+        assert_eq!(info[2].1.as_str(), Some("/src/m.erl"));
+    }
+
+    #[test]
+    fn missing_attributes() {
+        let file = BeamFile::parse(&beam(&[])).unwrap();
+        assert!(file.attributes().unwrap().is_empty());
+        assert_eq!(file.vsn().unwrap(), None);
+        assert!(file.compile_info().unwrap().is_empty());
+
+        let file = beam(&[(b"Attr", vec![131, 119, 2, 111, 107])]);
+        let error = BeamFile::parse(&file).unwrap().attributes().unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "Chunk Attr is not a list of pairs: Expected list, found atom"
+        );
+    }
+
+    #[test]
+    fn oversized_literal_table() {
+        // Claims 4 GiB of literals, but the chunk is tiny.
+        let mut data = u32::MAX.to_be_bytes().to_vec();
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&[0, 0, 0, 0]).unwrap();
+        data.extend(encoder.finish().unwrap());
+
+        let file = BeamFile::parse(&beam(&[(b"LitT", data)])).unwrap();
+        assert!(file.literal_bytes().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,227 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::packet::*;
+use crate::terms::AnyTerm;
+
+use anyhow::*;
+
+/// Decodes and encodes length-prefixed terms on a tokio stream, for use with
+/// `tokio_util::codec::Framed`.
+#[cfg(feature = "tokio")]
+#[derive(Debug, Clone)]
+pub struct TermCodec {
+    header: PacketHeader,
+    max_size: usize,
+}
+
+#[cfg(feature = "tokio")]
+impl TermCodec {
+    pub fn new(header: PacketHeader) -> Self {
+        TermCodec {
+            header,
+            max_size: DEFAULT_MAX_FRAME_SIZE,
+        }
+    }
+
+    /// Sets the largest frame that will be read or written.
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl tokio_util::codec::Decoder for TermCodec {
+    type Item = AnyTerm;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut bytes::BytesMut) -> Result<Option<AnyTerm>> {
+        // Partial frames stay in the buffer until the rest arrives.
+        match self.header.frame_len(src, self.max_size)? {
+            Some(len) => {
+                let frame = src.split_to(len);
+                crate::unpack(frame[self.header.size()..].to_vec()).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl tokio_util::codec::Encoder<AnyTerm> for TermCodec {
+    type Error = Error;
+
+    fn encode(&mut self, item: AnyTerm, dst: &mut bytes::BytesMut) -> Result<()> {
+        let frame = crate::pack(item)?;
+        dst.extend_from_slice(&self.header.frame_header(frame.len(), self.max_size)?);
+        dst.extend_from_slice(&frame);
+        Ok(())
+    }
+}
+
+/// Reads length-prefixed terms from a `futures::AsyncRead`, for runtimes
+/// other than tokio.
+#[cfg(feature = "futures")]
+pub struct AsyncPacketReader<R: futures::AsyncRead + Unpin> {
+    inner: R,
+    header: PacketHeader,
+    max_size: usize,
+    buf: Vec<u8>,
+}
+
+#[cfg(feature = "futures")]
+impl<R: futures::AsyncRead + Unpin> AsyncPacketReader<R> {
+    pub fn new(inner: R, header: PacketHeader) -> Self {
+        AsyncPacketReader {
+            inner,
+            header,
+            max_size: DEFAULT_MAX_FRAME_SIZE,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Sets the largest frame that will be read.
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Returns the underlying reader. Any buffered bytes are lost.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Reads the next frame. Returns None if the stream ended cleanly.
+    ///
+    /// Bytes are buffered in the reader, so dropping the future part way
+    /// through doesn't lose any of the frame.
+    pub async fn read_frame(&mut self) -> Result<Option<Vec<u8>>> {
+        use futures::AsyncReadExt;
+
+        let mut chunk = [0; 4096];
+        loop {
+            if let Some(len) = self.header.frame_len(&self.buf, self.max_size)? {
+                let frame = self.buf[self.header.size()..len].to_vec();
+                self.buf.drain(..len);
+                return Ok(Some(frame));
+            }
+
+            match self.inner.read(&mut chunk).await? {
+                0 if self.buf.is_empty() => return Ok(None),
+                0 => return Err(anyhow!("Stream ended in the middle of a frame")),
+                num => self.buf.extend_from_slice(&chunk[..num]),
+            }
+        }
+    }
+
+    /// Reads the next frame and unpacks it.
+    pub async fn read_term(&mut self) -> Result<Option<AnyTerm>> {
+        match self.read_frame().await? {
+            Some(frame) => Ok(Some(crate::unpack(frame)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::Atom;
+
+    fn frames() -> Vec<u8> {
+        let mut writer = PacketWriter::new(Vec::new(), PacketHeader::Two);
+        writer.write_term(AnyTerm::from(Atom::from("ok"))).unwrap();
+        writer.write_term(AnyTerm::SmallInt(7)).unwrap();
+        writer.into_inner()
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn tokio_codec() {
+        use bytes::BytesMut;
+        use tokio_util::codec::{Decoder, Encoder};
+
+        let data = frames();
+        let mut codec = TermCodec::new(PacketHeader::Two);
+        let mut buf = BytesMut::new();
+
+        // Feed the bytes one at a time, like a slow socket would.
+        let mut terms = Vec::new();
+        for byte in data.iter() {
+            buf.extend_from_slice(&[*byte]);
+            if let Some(term) = codec.decode(&mut buf).unwrap() {
+                terms.push(term);
+            }
+        }
+        assert!(terms[0].is_atom("ok"));
+        assert_eq!(terms[1], AnyTerm::SmallInt(7));
+        assert!(buf.is_empty());
+
+        let mut out = BytesMut::new();
+        for term in terms {
+            codec.encode(term, &mut out).unwrap();
+        }
+        assert_eq!(out[..], data[..]);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn tokio_max_size() {
+        use bytes::BytesMut;
+        use tokio_util::codec::Decoder;
+
+        let mut codec = TermCodec::new(PacketHeader::Four).with_max_size(8);
+        let mut buf = BytesMut::from(&[0, 0, 1, 0][..]);
+        assert!(codec.decode(&mut buf).is_err());
+    }
+
+    #[cfg(feature = "futures")]
+    #[test]
+    fn futures_reader() {
+        use futures::FutureExt;
+        use std::pin::Pin;
+        use std::task::{Context, Poll};
+
+        /// Returns one byte per read.
+        struct Trickle(Vec<u8>);
+
+        impl futures::AsyncRead for Trickle {
+            fn poll_read(
+                mut self: Pin<&mut Self>,
+                _: &mut Context<'_>,
+                buf: &mut [u8],
+            ) -> Poll<std::io::Result<usize>> {
+                if self.0.is_empty() || buf.is_empty() {
+                    return Poll::Ready(std::io::Result::Ok(0));
+                }
+                buf[0] = self.0.remove(0);
+                Poll::Ready(std::io::Result::Ok(1))
+            }
+        }
+
+        let mut reader = AsyncPacketReader::new(Trickle(frames()), PacketHeader::Two);
+        let first = reader.read_term().now_or_never().unwrap().unwrap();
+        assert!(first.unwrap().is_atom("ok"));
+        let second = reader.read_term().now_or_never().unwrap().unwrap();
+        assert_eq!(second, Some(AnyTerm::SmallInt(7)));
+        assert_eq!(reader.read_term().now_or_never().unwrap().unwrap(), None);
+
+        let mut data = frames();
+        data.pop();
+        let mut reader = AsyncPacketReader::new(Trickle(data), PacketHeader::Two);
+        assert!(reader.read_frame().now_or_never().unwrap().is_ok());
+        assert!(reader.read_frame().now_or_never().unwrap().is_err());
+    }
+}
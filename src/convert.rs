@@ -0,0 +1,653 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::structs::*;
+use crate::terms::{AnyTerm, StringPacker, Term};
+
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+use anyhow::*;
+#[cfg(feature = "std")]
+use std::{collections::HashMap, hash::Hash};
+
+const TRUE: &str = "true";
+const FALSE: &str = "false";
+const UNDEFINED: &str = "undefined";
+
+impl From<&str> for Atom {
+    /// Picks the smallest UTF-8 atom encoding that fits the value.
+    fn from(value: &str) -> Self {
+        Atom::from(value.to_string())
+    }
+}
+
+impl From<String> for Atom {
+    fn from(value: String) -> Self {
+        let kind = if value.len() <= u8::MAX.into() {
+            AtomKind::SmallUTF8
+        } else {
+            AtomKind::UTF8
+        };
+
+        Atom::new(kind, value)
+    }
+}
+
+impl From<Atom> for AnyTerm {
+    fn from(value: Atom) -> Self {
+        AnyTerm::Atom(value)
+    }
+}
+
+impl From<Port> for AnyTerm {
+    fn from(value: Port) -> Self {
+        AnyTerm::Port(value)
+    }
+}
+
+impl From<Pid> for AnyTerm {
+    fn from(value: Pid) -> Self {
+        AnyTerm::Pid(value)
+    }
+}
+
+impl From<Reference> for AnyTerm {
+    fn from(value: Reference) -> Self {
+        AnyTerm::Reference(value)
+    }
+}
+
+impl From<BigInt> for AnyTerm {
+    fn from(value: BigInt) -> Self {
+        AnyTerm::BigInt(value)
+    }
+}
+
+impl From<List> for AnyTerm {
+    fn from(value: List) -> Self {
+        AnyTerm::List(value)
+    }
+}
+
+impl From<BitBinary> for AnyTerm {
+    fn from(value: BitBinary) -> Self {
+        AnyTerm::BitBinary(value)
+    }
+}
+
+impl From<bool> for AnyTerm {
+    /// Booleans are the atoms `true` and `false`.
+    fn from(value: bool) -> Self {
+        AnyTerm::Atom(Atom::from(if value { TRUE } else { FALSE }))
+    }
+}
+
+impl From<&str> for AnyTerm {
+    /// Strings become STRING_EXT when they can, like term_to_binary does
+    /// for lists of bytes. Anything else is a list of code points, and the
+    /// empty string is [].
+    fn from(value: &str) -> Self {
+        AnyTerm::from(value.to_string())
+    }
+}
+
+impl From<String> for AnyTerm {
+    fn from(value: String) -> Self {
+        if value.is_empty() {
+            return AnyTerm::Nil;
+        }
+
+        let term = AnyTerm::String(value);
+        if StringPacker::can_pack(&term) {
+            return term;
+        }
+
+        match term {
+            // Code points go up to 0x10FFFF, so they always fit.
+            AnyTerm::String(value) => {
+                AnyTerm::from(value.chars().map(code_point).collect::<Vec<_>>())
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl From<&[u8]> for AnyTerm {
+    /// Byte slices are binaries. A `Vec<u8>` is a list of integers, like
+    /// any other Vec.
+    fn from(value: &[u8]) -> Self {
+        AnyTerm::Binary(value.to_vec())
+    }
+}
+
+impl<T: Into<AnyTerm>> From<Vec<T>> for AnyTerm {
+    /// An empty Vec is [], anything else a proper list.
+    fn from(value: Vec<T>) -> Self {
+        if value.is_empty() {
+            return AnyTerm::Nil;
+        }
+        AnyTerm::List(List::new(value.into_iter().map(Into::into).collect()))
+    }
+}
+
+impl<K: Into<AnyTerm>, V: Into<AnyTerm>> From<BTreeMap<K, V>> for AnyTerm {
+    fn from(value: BTreeMap<K, V>) -> Self {
+        AnyTerm::Map(map_pairs(value))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K: Into<AnyTerm>, V: Into<AnyTerm>> From<HashMap<K, V>> for AnyTerm {
+    fn from(value: HashMap<K, V>) -> Self {
+        AnyTerm::Map(map_pairs(value))
+    }
+}
+
+fn map_pairs<K: Into<AnyTerm>, V: Into<AnyTerm>>(
+    value: impl IntoIterator<Item = (K, V)>,
+) -> Vec<(AnyTerm, AnyTerm)> {
+    value
+        .into_iter()
+        .map(|(key, value)| (key.into(), value.into()))
+        .collect()
+}
+
+impl From<f64> for AnyTerm {
+    fn from(value: f64) -> Self {
+        AnyTerm::Float(value)
+    }
+}
+
+impl From<f32> for AnyTerm {
+    fn from(value: f32) -> Self {
+        AnyTerm::Float(value.into())
+    }
+}
+
+impl<T: Into<AnyTerm>> From<Option<T>> for AnyTerm {
+    /// `None` becomes the atom `undefined`, `Some` is unwrapped.
+    fn from(value: Option<T>) -> Self {
+        match value {
+            Some(value) => value.into(),
+            None => AnyTerm::Atom(Atom::from(UNDEFINED)),
+        }
+    }
+}
+
+/// Integers that always fit into INTEGER_EXT. Like term_to_binary, values
+/// between 0 and 255 are turned into small integers instead.
+macro_rules! from_integer {
+    ($($ty:ty),*) => {$(
+        impl From<$ty> for AnyTerm {
+            fn from(value: $ty) -> Self {
+                match u8::try_from(value) {
+                    Result::Ok(value) => AnyTerm::SmallInt(value),
+                    Err(_) => AnyTerm::Integer(value.into()),
+                }
+            }
+        }
+    )*};
+}
+
+/// Integers that might not fit into INTEGER_EXT, which become bignums when
+/// they don't.
+macro_rules! from_wide_integer {
+    ($wide:ty: $($ty:ty),*) => {$(
+        impl From<$ty> for AnyTerm {
+            fn from(value: $ty) -> Self {
+                if let Result::Ok(value) = u8::try_from(value) {
+                    return AnyTerm::SmallInt(value);
+                }
+
+                match i32::try_from(value) {
+                    Result::Ok(value) => AnyTerm::Integer(value),
+                    Err(_) => AnyTerm::BigInt(BigInt::from(value as $wide)),
+                }
+            }
+        }
+    )*};
+}
+
+from_integer!(u8, i8, u16, i16, i32);
+from_wide_integer!(u128: u32, u64, u128, usize);
+from_wide_integer!(i128: i64, i128, isize);
+
+/// Integers are read from small integers, integers and bignums, as long as
+/// the value fits into the target type.
+macro_rules! integer_from_term {
+    ($($ty:ty),*) => {$(
+        impl TryFrom<AnyTerm> for $ty {
+            type Error = Error;
+
+            fn try_from(value: AnyTerm) -> Result<Self> {
+                let value = match value {
+                    AnyTerm::SmallInt(value) => BigInt::from(i128::from(value)),
+                    AnyTerm::Integer(value) => BigInt::from(i128::from(value)),
+                    AnyTerm::BigInt(value) => value,
+                    other => return Err(mismatch("integer", &other)),
+                };
+
+                let converted = match value.to_i128() {
+                    Some(signed) => <$ty>::try_from(signed).ok(),
+                    None => value.to_u128().and_then(|unsigned| <$ty>::try_from(unsigned).ok()),
+                };
+                converted.ok_or_else(|| {
+                    anyhow!("Integer {} is out of range for {}", value, stringify!($ty))
+                })
+            }
+        }
+    )*};
+}
+
+integer_from_term!(u8, i8, u16, i16, u32, i32, u64, i64, u128, i128, usize, isize);
+
+impl TryFrom<AnyTerm> for f64 {
+    type Error = Error;
+
+    fn try_from(value: AnyTerm) -> Result<Self> {
+        match value {
+            AnyTerm::Float(value) => Ok(value),
+            AnyTerm::LegacyFloat(float) => Ok(float.value),
+            other => Err(mismatch("float", &other)),
+        }
+    }
+}
+
+impl TryFrom<AnyTerm> for f32 {
+    type Error = Error;
+
+    fn try_from(value: AnyTerm) -> Result<Self> {
+        let value = f64::try_from(value)?;
+        let narrowed = value as f32;
+
+        if value.is_finite() && !narrowed.is_finite() {
+            return Err(anyhow!("Float {} is out of range for f32", value));
+        }
+
+        Ok(narrowed)
+    }
+}
+
+impl TryFrom<AnyTerm> for bool {
+    type Error = Error;
+
+    fn try_from(value: AnyTerm) -> Result<Self> {
+        match value {
+            AnyTerm::Atom(atom) if atom.value == TRUE => Ok(true),
+            AnyTerm::Atom(atom) if atom.value == FALSE => Ok(false),
+            AnyTerm::Atom(atom) => Err(anyhow!(
+                "Expected the atom true or false, found the atom {}",
+                atom.value
+            )),
+            other => Err(mismatch("boolean", &other)),
+        }
+    }
+}
+
+impl TryFrom<AnyTerm> for String {
+    type Error = Error;
+
+    /// Reads STRING_EXT, and proper lists of code points.
+    fn try_from(value: AnyTerm) -> Result<Self> {
+        match value {
+            AnyTerm::String(value) => Ok(value),
+            AnyTerm::Nil => Ok(String::new()),
+            AnyTerm::List(list) if list.is_proper() => list
+                .elements
+                .into_iter()
+                .map(|element| {
+                    let code = u32::try_from(element)?;
+                    char::from_u32(code).ok_or_else(|| anyhow!("{} is not a character", code))
+                })
+                .collect(),
+            other => Err(mismatch("string", &other)),
+        }
+    }
+}
+
+impl<T: TryFrom<AnyTerm, Error = Error>> TryFrom<AnyTerm> for Vec<T> {
+    type Error = Error;
+
+    /// Reads proper lists, including strings packed as STRING_EXT.
+    fn try_from(value: AnyTerm) -> Result<Self> {
+        list_elements(value)?.into_iter().map(T::try_from).collect()
+    }
+}
+
+/// Returns the elements of a proper list, including a string packed as
+/// STRING_EXT.
+pub(crate) fn list_elements(value: AnyTerm) -> Result<Vec<AnyTerm>> {
+    match value {
+        AnyTerm::Nil => Ok(Vec::new()),
+        AnyTerm::String(value) => Ok(value
+            .chars()
+            .map(|c| AnyTerm::from(code_point(c)))
+            .collect()),
+        AnyTerm::List(list) if list.is_proper() => Ok(list.elements),
+        AnyTerm::List(_) => Err(anyhow!("Expected a proper list, found an improper one")),
+        other => Err(mismatch("list", &other)),
+    }
+}
+
+impl<K, V> TryFrom<AnyTerm> for BTreeMap<K, V>
+where
+    K: TryFrom<AnyTerm, Error = Error> + Ord,
+    V: TryFrom<AnyTerm, Error = Error>,
+{
+    type Error = Error;
+
+    fn try_from(value: AnyTerm) -> Result<Self> {
+        map_from_term(value)?.collect()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<K, V> TryFrom<AnyTerm> for HashMap<K, V>
+where
+    K: TryFrom<AnyTerm, Error = Error> + Eq + Hash,
+    V: TryFrom<AnyTerm, Error = Error>,
+{
+    type Error = Error;
+
+    fn try_from(value: AnyTerm) -> Result<Self> {
+        map_from_term(value)?.collect()
+    }
+}
+
+fn map_from_term<K, V>(value: AnyTerm) -> Result<impl Iterator<Item = Result<(K, V)>>>
+where
+    K: TryFrom<AnyTerm, Error = Error>,
+    V: TryFrom<AnyTerm, Error = Error>,
+{
+    match value {
+        AnyTerm::Map(pairs) => Ok(pairs
+            .into_iter()
+            .map(|(key, value)| Ok((K::try_from(key)?, V::try_from(value)?)))),
+        other => Err(mismatch("map", &other)),
+    }
+}
+
+/// Tuples convert element by element, and only from tuples of the same
+/// arity.
+macro_rules! tuple_conversions {
+    ($($arity:literal: ($($name:ident),+);)*) => {$(
+        impl<$($name: Into<AnyTerm>),+> From<($($name,)+)> for AnyTerm {
+            #[allow(non_snake_case)]
+            fn from(($($name,)+): ($($name,)+)) -> Self {
+                AnyTerm::Tuple(alloc::vec![$($name.into()),+])
+            }
+        }
+
+        impl<$($name: TryFrom<AnyTerm, Error = Error>),+> TryFrom<AnyTerm> for ($($name,)+) {
+            type Error = Error;
+
+            fn try_from(value: AnyTerm) -> Result<Self> {
+                match value {
+                    AnyTerm::Tuple(elements) if elements.len() == $arity => {
+                        let mut elements = elements.into_iter();
+                        Ok(($($name::try_from(elements.next().unwrap())?,)+))
+                    }
+                    AnyTerm::Tuple(elements) => Err(anyhow!(
+                        "Expected a tuple of {} elements, found {}",
+                        $arity,
+                        elements.len()
+                    )),
+                    other => Err(mismatch("tuple", &other)),
+                }
+            }
+        }
+    )*};
+}
+
+tuple_conversions! {
+    1: (A);
+    2: (A, B);
+    3: (A, B, C);
+    4: (A, B, C, D);
+    5: (A, B, C, D, E);
+    6: (A, B, C, D, E, F);
+    7: (A, B, C, D, E, F, G);
+    8: (A, B, C, D, E, F, G, H);
+    9: (A, B, C, D, E, F, G, H, I);
+    10: (A, B, C, D, E, F, G, H, I, J);
+    11: (A, B, C, D, E, F, G, H, I, J, K);
+    12: (A, B, C, D, E, F, G, H, I, J, K, L);
+}
+
+impl TryFrom<AnyTerm> for Atom {
+    type Error = Error;
+
+    fn try_from(value: AnyTerm) -> Result<Self> {
+        match value {
+            AnyTerm::Atom(value) => Ok(value),
+            other => Err(mismatch("atom", &other)),
+        }
+    }
+}
+
+impl TryFrom<AnyTerm> for Port {
+    type Error = Error;
+
+    fn try_from(value: AnyTerm) -> Result<Self> {
+        match value {
+            AnyTerm::Port(value) => Ok(value),
+            other => Err(mismatch("port", &other)),
+        }
+    }
+}
+
+impl TryFrom<AnyTerm> for Pid {
+    type Error = Error;
+
+    fn try_from(value: AnyTerm) -> Result<Self> {
+        match value {
+            AnyTerm::Pid(value) => Ok(value),
+            other => Err(mismatch("pid", &other)),
+        }
+    }
+}
+
+impl TryFrom<AnyTerm> for Reference {
+    type Error = Error;
+
+    fn try_from(value: AnyTerm) -> Result<Self> {
+        match value {
+            AnyTerm::Reference(value) => Ok(value),
+            other => Err(mismatch("reference", &other)),
+        }
+    }
+}
+
+/// The atom `undefined` is `None`, anything else has to convert into the
+/// inner type. A blanket impl would overlap with `TryFrom<T> for T`.
+macro_rules! option_from_term {
+    ($($ty:ty),*) => {$(
+        impl TryFrom<AnyTerm> for Option<$ty> {
+            type Error = Error;
+
+            fn try_from(value: AnyTerm) -> Result<Self> {
+                match value {
+                    AnyTerm::Atom(atom) if atom.value == UNDEFINED => Ok(None),
+                    other => Ok(Some(<$ty>::try_from(other)?)),
+                }
+            }
+        }
+    )*};
+}
+
+option_from_term!(u8, i8, u16, i16, u32, i32, u64, i64, u128, i128, usize, isize);
+option_from_term!(f32, f64, bool, String, Atom, Port, Pid, Reference);
+
+fn code_point(c: char) -> i32 {
+    u32::from(c) as i32
+}
+
+/// Builds the error for a term that has the wrong type.
+fn mismatch(expected: &str, found: &AnyTerm) -> Error {
+    anyhow!("Expected {}, found {}", expected, found.type_name())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn atom_from_str() {
+        let atom = Atom::from("ok");
+        assert_eq!(atom.kind, AtomKind::SmallUTF8);
+        assert_eq!(atom.value, "ok");
+
+        let atom = Atom::from("a".repeat(300));
+        assert_eq!(atom.kind, AtomKind::UTF8);
+    }
+
+    #[test]
+    fn integers() {
+        assert_eq!(AnyTerm::from(200u8), AnyTerm::SmallInt(200));
+        assert_eq!(AnyTerm::from(200i32), AnyTerm::SmallInt(200));
+        assert_eq!(AnyTerm::from(-1i8), AnyTerm::Integer(-1));
+        assert_eq!(AnyTerm::from(1000u16), AnyTerm::Integer(1000));
+        assert_eq!(AnyTerm::from(7u64), AnyTerm::SmallInt(7));
+        assert_eq!(
+            AnyTerm::from(i64::MAX),
+            AnyTerm::BigInt(BigInt::from(i128::from(i64::MAX)))
+        );
+        assert_eq!(i64::try_from(AnyTerm::from(i64::MIN)).unwrap(), i64::MIN);
+        assert_eq!(u128::try_from(AnyTerm::from(u128::MAX)).unwrap(), u128::MAX);
+        assert!(u64::try_from(AnyTerm::from(-1i64 << 40)).is_err());
+
+        assert_eq!(u8::try_from(AnyTerm::SmallInt(5)).unwrap(), 5);
+        assert_eq!(i64::try_from(AnyTerm::Integer(-5)).unwrap(), -5);
+        assert!(u8::try_from(AnyTerm::Integer(-5)).is_err());
+        assert!(i32::try_from(AnyTerm::Float(1.0)).is_err());
+    }
+
+    #[test]
+    fn floats() {
+        assert_eq!(AnyTerm::from(1.5f64), AnyTerm::Float(1.5));
+        assert_eq!(f32::try_from(AnyTerm::Float(1.5)).unwrap(), 1.5);
+        assert!(f32::try_from(AnyTerm::Float(f64::MAX)).is_err());
+        assert!(f64::try_from(AnyTerm::SmallInt(1)).is_err());
+    }
+
+    #[test]
+    fn booleans() {
+        assert_eq!(AnyTerm::from(true), AnyTerm::Atom(Atom::from("true")));
+        assert!(bool::try_from(AnyTerm::from(true)).unwrap());
+        assert!(!bool::try_from(AnyTerm::from(false)).unwrap());
+        assert!(bool::try_from(AnyTerm::Atom(Atom::from("maybe"))).is_err());
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(AnyTerm::from("hi"), AnyTerm::String("hi".to_string()));
+        assert_eq!(String::try_from(AnyTerm::from("hi")).unwrap(), "hi");
+        assert_eq!(
+            Atom::try_from(AnyTerm::from(Atom::from("hi"))).unwrap(),
+            Atom::from("hi")
+        );
+        assert!(String::try_from(AnyTerm::from(Atom::from("hi"))).is_err());
+
+        // Anything that isn't Latin-1 is a list of code points.
+        assert_eq!(AnyTerm::from("hé"), AnyTerm::String("hé".to_string()));
+        let euro = AnyTerm::from("1€");
+        assert_eq!(
+            euro,
+            AnyTerm::List(List::new(vec![
+                AnyTerm::SmallInt(b'1'),
+                AnyTerm::Integer(0x20AC)
+            ]))
+        );
+        assert!(crate::pack(euro.clone()).is_ok());
+        assert_eq!(String::try_from(euro).unwrap(), "1€");
+        assert_eq!(String::try_from(AnyTerm::Nil).unwrap(), "");
+        assert_eq!(AnyTerm::from(""), AnyTerm::Nil);
+        assert_eq!(AnyTerm::from(String::new()), AnyTerm::Nil);
+    }
+
+    #[test]
+    fn containers() {
+        assert_eq!(AnyTerm::from(Vec::<u8>::new()), AnyTerm::Nil);
+        let list = AnyTerm::from(vec![1u8, 2]);
+        assert_eq!(Vec::<u8>::try_from(list).unwrap(), [1, 2]);
+        let wide = AnyTerm::from(vec![1u64, u64::MAX]);
+        assert_eq!(Vec::<u64>::try_from(wide).unwrap(), [1, u64::MAX]);
+        assert_eq!(Vec::<u8>::try_from(AnyTerm::from("ab")).unwrap(), [97, 98]);
+        assert_eq!(AnyTerm::from(&b"ab"[..]), AnyTerm::Binary(b"ab".to_vec()));
+
+        let improper = AnyTerm::List(List {
+            elements: vec![AnyTerm::SmallInt(1)],
+            tail: alloc::boxed::Box::new(AnyTerm::SmallInt(2)),
+        });
+        assert!(Vec::<u8>::try_from(improper).is_err());
+
+        let mut map = BTreeMap::new();
+        map.insert(1u8, true);
+        let term = AnyTerm::from(map.clone());
+        assert_eq!(
+            term,
+            AnyTerm::Map(vec![(AnyTerm::SmallInt(1), AnyTerm::from(true))])
+        );
+        assert_eq!(BTreeMap::<u8, bool>::try_from(term).unwrap(), map);
+
+        #[cfg(feature = "std")]
+        {
+            let map: HashMap<String, u8> = [("a".to_string(), 1)].into_iter().collect();
+            let term = AnyTerm::from(map.clone());
+            assert_eq!(HashMap::<String, u8>::try_from(term).unwrap(), map);
+        }
+    }
+
+    #[test]
+    fn tuples() {
+        let term = AnyTerm::from((Atom::from("ok"), 1u8));
+        assert_eq!(
+            term,
+            AnyTerm::Tuple(vec![Atom::from("ok").into(), AnyTerm::SmallInt(1)])
+        );
+        let (atom, value) = <(Atom, u8)>::try_from(term.clone()).unwrap();
+        assert_eq!((atom.value.as_str(), value), ("ok", 1));
+
+        let error = <(Atom, u8, u8)>::try_from(term).unwrap_err();
+        assert_eq!(error.to_string(), "Expected a tuple of 3 elements, found 2");
+
+        let twelve = AnyTerm::from((
+            1u8, 2u8, 3u8, 4u8, 5u8, 6u8, 7u8, 8u8, 9u8, 10u8, 11u8, 12u8,
+        ));
+        let back = <(u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8)>::try_from(twelve).unwrap();
+        assert_eq!(back.11, 12);
+    }
+
+    #[test]
+    fn options() {
+        let undefined = AnyTerm::Atom(Atom::from("undefined"));
+        assert_eq!(AnyTerm::from(None::<i32>), undefined);
+        assert_eq!(AnyTerm::from(Some(1000)), AnyTerm::Integer(1000));
+        assert_eq!(Option::<i32>::try_from(undefined).unwrap(), None);
+        assert_eq!(
+            Option::<i32>::try_from(AnyTerm::Integer(1000)).unwrap(),
+            Some(1000)
+        );
+    }
+
+    #[test]
+    fn error_message() {
+        let error = String::try_from(AnyTerm::Float(1.0)).unwrap_err();
+        assert_eq!(error.to_string(), "Expected string, found float");
+    }
+}
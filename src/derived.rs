@@ -0,0 +1,108 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+// Support for the code that the IntoTerm and FromTerm derives generate.
+// Everything here writes straight into the buffer, without an AnyTerm.
+
+use crate::terms::*;
+
+use alloc::{string::String, vec::Vec};
+use anyhow::*;
+
+pub fn write_atom(buf: &mut Vec<u8>, name: &str) -> Result<()> {
+    AtomPacker::write_utf8(buf, name)
+}
+
+pub fn write_binary(buf: &mut Vec<u8>, value: &str) -> Result<()> {
+    BinaryPacker::write_slice(buf, value.as_bytes())
+}
+
+pub fn write_tuple_header(buf: &mut Vec<u8>, arity: usize) -> Result<()> {
+    TuplePacker::write_header(buf, arity)
+}
+
+pub fn write_map_header(buf: &mut Vec<u8>, arity: usize) -> Result<()> {
+    MapPacker::write_header(buf, arity)
+}
+
+/// Reads a string from a UTF-8 binary.
+pub fn read_binary(term: AnyTerm) -> Result<String> {
+    match term {
+        AnyTerm::Binary(bytes) => Ok(String::from_utf8(bytes)?),
+        other => Err(anyhow!("Expected binary, found {}", other.type_name())),
+    }
+}
+
+/// Reads the values of the fields of a struct from a map with atom keys,
+/// in the order of the names. Keys that aren't fields are ignored.
+pub fn map_fields(term: AnyTerm, names: &[&str], type_name: &str) -> Result<Vec<AnyTerm>> {
+    let pairs = match term {
+        AnyTerm::Map(pairs) => pairs,
+        other => {
+            return Err(anyhow!(
+                "Expected a map for {}, found {}",
+                type_name,
+                other.type_name()
+            ))
+        }
+    };
+
+    let mut values: Vec<Option<AnyTerm>> = names.iter().map(|_| None).collect();
+    for (key, value) in pairs {
+        let field = key
+            .as_atom_str()
+            .and_then(|key| names.iter().position(|name| *name == key));
+        if let Some(field) = field {
+            values[field] = Some(value);
+        }
+    }
+
+    values
+        .into_iter()
+        .zip(names)
+        .map(|(value, name)| {
+            value.ok_or_else(|| anyhow!("Missing field {} of {}", name, type_name))
+        })
+        .collect()
+}
+
+/// Reads the values of the fields of a struct from a record, a tuple with
+/// the record name first.
+pub fn record_fields(
+    term: AnyTerm,
+    record: &str,
+    count: usize,
+    type_name: &str,
+) -> Result<Vec<AnyTerm>> {
+    match term {
+        AnyTerm::Tuple(mut elements)
+            if elements.len() == count + 1 && elements[0].is_atom(record) =>
+        {
+            elements.remove(0);
+            Ok(elements)
+        }
+        other => Err(anyhow!(
+            "Expected a {} record with {} fields for {}, found {}",
+            record,
+            count,
+            type_name,
+            other
+        )),
+    }
+}
+
+/// Says which field couldn't be read.
+pub fn field<T>(value: Result<T>, name: &str, type_name: &str) -> Result<T> {
+    value.with_context(|| alloc::format!("In field {} of {}", name, type_name))
+}
@@ -0,0 +1,582 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::lazy::TermIndex;
+use crate::terms::AnyTerm;
+
+use anyhow::*;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+
+const LOG_MAGIC: [u8; 4] = [1, 2, 3, 4];
+const OPENED: [u8; 4] = [6, 7, 8, 9];
+const CLOSED: [u8; 4] = [99, 88, 77, 11];
+const BIG_MAGIC: [u8; 4] = [98, 87, 76, 65];
+const OLD_MAGIC: [u8; 4] = [12, 33, 44, 55];
+/// Items at least this large have an MD5 of their size after the magic.
+const MIN_MD5_ITEM: usize = 65528;
+const MD5_SIZE: usize = 16;
+/// The version of wrap log index files.
+const INDEX_VERSION: u8 = 2;
+
+/// Reads the items of a disk_log file in the internal format, like a halt
+/// log or one file of a wrap log. Each item is a packed term.
+pub struct DiskLogReader<R: Read> {
+    inner: R,
+    closed: bool,
+    /// The offset of the next item in the file.
+    pos: usize,
+}
+
+impl DiskLogReader<BufReader<File>> {
+    /// Opens a disk_log file.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        DiskLogReader::new(BufReader::new(File::open(path)?))
+            .with_context(|| format!("Failed to open {}", path.display()))
+    }
+}
+
+impl<R: Read> DiskLogReader<R> {
+    /// Reads and checks the file header.
+    pub fn new(mut inner: R) -> Result<Self> {
+        let mut header = [0; 8];
+        inner
+            .read_exact(&mut header)
+            .map_err(|_| anyhow!("Not a disk_log file: header is too short"))?;
+
+        if header[..4] != LOG_MAGIC {
+            return Err(anyhow!("Not a disk_log file: bad magic"));
+        }
+
+        let closed = match header[4..].try_into().unwrap() {
+            OPENED => false,
+            CLOSED => true,
+            _ => return Err(anyhow!("Not a disk_log file: bad open/closed marker")),
+        };
+
+        Ok(DiskLogReader {
+            inner,
+            closed,
+            pos: header.len(),
+        })
+    }
+
+    /// Returns true if the log was closed properly. Logs that weren't can
+    /// end with a partly written item.
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Reads the next item, as packed bytes. Returns None at the end of the
+    /// log.
+    pub fn read_item(&mut self) -> Result<Option<Vec<u8>>> {
+        let start = self.pos;
+        let mut header = [0; 8];
+        match self.fill(&mut header)? {
+            0 => return Ok(None),
+            8 => {}
+            _ => return Err(self.truncated(start)),
+        }
+
+        let size = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+        match header[4..].try_into().unwrap() {
+            BIG_MAGIC if size >= MIN_MD5_ITEM => {
+                // The MD5 only protects the size, so it isn't checked.
+                let mut md5 = [0; MD5_SIZE];
+                self.fill_item(&mut md5, start)?;
+            }
+            BIG_MAGIC | OLD_MAGIC => {}
+            _ => return Err(anyhow!("Bad item header at byte {}", start)),
+        }
+
+        // The size comes from the file, so the item grows as it's read rather
+        // than being allocated up front.
+        let mut item = Vec::new();
+        (&mut self.inner).take(size as u64).read_to_end(&mut item)?;
+        self.pos += item.len();
+        match item.len() == size {
+            true => Ok(Some(item)),
+            false => Err(self.truncated(start)),
+        }
+    }
+
+    /// Reads the next item and unpacks it.
+    pub fn read_term(&mut self) -> Result<Option<AnyTerm>> {
+        match self.read_item()? {
+            Some(item) => Ok(Some(crate::unpack(item)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn fill_item(&mut self, buf: &mut [u8], start: usize) -> Result<()> {
+        match self.fill(buf)? == buf.len() {
+            true => Ok(()),
+            false => Err(self.truncated(start)),
+        }
+    }
+
+    fn truncated(&self, start: usize) -> Error {
+        match self.closed {
+            true => anyhow!("Log ends in the middle of the item at byte {}", start),
+            false => anyhow!(
+                "Log ends in the middle of the item at byte {}; it wasn't closed properly",
+                start
+            ),
+        }
+    }
+
+    /// Fills as much of the buffer as the file has, returning how much.
+    fn fill(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let mut read = 0;
+        while read < buf.len() {
+            match self.inner.read(&mut buf[read..]) {
+                Result::Ok(0) => break,
+                Result::Ok(num) => read += num,
+                Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(error) => return Err(error.into()),
+            }
+        }
+        self.pos += read;
+        Ok(read)
+    }
+}
+
+impl<R: Read> Iterator for DiskLogReader<R> {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_item().transpose()
+    }
+}
+
+/// Returns the files of a wrap log, oldest first, using its index file.
+/// The base is the name the log was opened with; its files are `base.1`,
+/// `base.2` and so on, and `base.idx` says which one is being written.
+pub fn wrap_log_files(base: impl AsRef<Path>) -> Result<Vec<PathBuf>> {
+    let base = base.as_ref();
+    let index = std::fs::read(with_suffix(base, "idx"))
+        .with_context(|| format!("Failed to read the index of {}", base.display()))?;
+    let current = current_file(&index)?;
+
+    // The files after the current one are older, if the log has wrapped.
+    let mut older = Vec::new();
+    let mut number = current + 1;
+    loop {
+        let path = with_suffix(base, &number.to_string());
+        if !path.exists() {
+            break;
+        }
+        older.push(path);
+        number += 1;
+    }
+
+    older.extend((1..=current).map(|number| with_suffix(base, &number.to_string())));
+    Ok(older)
+}
+
+/// Reads the number of the current file from a wrap log index, which has
+/// had a few layouts over the years.
+fn current_file(index: &[u8]) -> Result<u32> {
+    let current = match index {
+        [0, 0, 0, 0, 0, INDEX_VERSION, a, b, c, d, ..] => u32::from_be_bytes([*a, *b, *c, *d]),
+        [0, a, b, c, d, ..] => u32::from_be_bytes([*a, *b, *c, *d]),
+        [current, ..] => u32::from(*current),
+        [] => 0,
+    };
+
+    match current {
+        0 => Err(anyhow!("Wrap log index has no current file")),
+        current => Ok(current),
+    }
+}
+
+fn with_suffix(base: &Path, suffix: &str) -> PathBuf {
+    let mut path = base.as_os_str().to_owned();
+    path.push(".");
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+/// Reads the items of a wrap log, from the oldest file to the newest.
+pub struct WrapLogReader {
+    files: std::vec::IntoIter<PathBuf>,
+    current: Option<DiskLogReader<BufReader<File>>>,
+}
+
+impl WrapLogReader {
+    /// Opens a wrap log by the name it was opened with.
+    pub fn open(base: impl AsRef<Path>) -> Result<Self> {
+        Ok(WrapLogReader {
+            files: wrap_log_files(base)?.into_iter(),
+            current: None,
+        })
+    }
+
+    /// Reads the next item, as packed bytes. Returns None after the last
+    /// item of the newest file.
+    pub fn read_item(&mut self) -> Result<Option<Vec<u8>>> {
+        loop {
+            if let Some(reader) = self.current.as_mut() {
+                if let Some(item) = reader.read_item()? {
+                    return Ok(Some(item));
+                }
+            }
+
+            match self.files.next() {
+                Some(path) => self.current = Some(DiskLogReader::open(path)?),
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+impl Iterator for WrapLogReader {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_item().transpose()
+    }
+}
+
+/// The table info at the start of an ets:tab2file dump.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableInfo {
+    pub name: String,
+    /// set, ordered_set, bag or duplicate_bag.
+    pub kind: String,
+    /// The position of the key in each object, starting from 1.
+    pub keypos: usize,
+    /// The number of objects in the table when it was dumped, if recorded.
+    pub size: Option<usize>,
+    /// The whole header, a packed list of {Key, Value} tuples.
+    pub header: Vec<u8>,
+}
+
+impl TableInfo {
+    fn parse(header: Vec<u8>) -> Result<Self> {
+        let index = TermIndex::new(&header)?;
+        let root = index.root();
+        if !root.is_list() {
+            return Err(anyhow!("Table info should be a list"));
+        }
+
+        let field = |name: &str| -> Option<AnyTerm> {
+            root.elements()
+                .filter(|element| element.is_tuple() && element.len() == 2)
+                .find(|element| {
+                    element
+                        .get(0)
+                        .and_then(|key| key.unpack().ok())
+                        .is_some_and(|key| key.is_atom(name))
+                })
+                .and_then(|element| element.get(1)?.unpack().ok())
+        };
+
+        let name = field("name")
+            .and_then(|name| Some(name.as_atom_str()?.to_string()))
+            .ok_or_else(|| anyhow!("Table info has no name"))?;
+        let kind = field("type")
+            .and_then(|kind| Some(kind.as_atom_str()?.to_string()))
+            .ok_or_else(|| anyhow!("Table info has no type"))?;
+        let keypos = field("keypos")
+            .and_then(|keypos| usize::try_from(keypos.as_integer()?).ok())
+            .ok_or_else(|| anyhow!("Table info has no keypos"))?;
+        let size = field("size").and_then(|size| usize::try_from(size.as_integer()?).ok());
+
+        Ok(TableInfo {
+            name,
+            kind,
+            keypos,
+            size,
+            header,
+        })
+    }
+}
+
+/// Reads a table dumped with ets:tab2file: the table info, then each
+/// object as a packed tuple.
+pub struct Tab2FileReader<R: Read> {
+    log: DiskLogReader<R>,
+    info: TableInfo,
+    footer: Option<Vec<u8>>,
+}
+
+impl Tab2FileReader<BufReader<File>> {
+    /// Opens a tab2file dump.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Tab2FileReader::from_log(DiskLogReader::open(path)?)
+    }
+}
+
+impl<R: Read> Tab2FileReader<R> {
+    /// Reads and checks the file and table headers.
+    pub fn new(inner: R) -> Result<Self> {
+        Tab2FileReader::from_log(DiskLogReader::new(inner)?)
+    }
+
+    fn from_log(mut log: DiskLogReader<R>) -> Result<Self> {
+        let header = log
+            .read_item()?
+            .ok_or_else(|| anyhow!("Table dump has no table info"))?;
+
+        Ok(Tab2FileReader {
+            log,
+            info: TableInfo::parse(header).context("Failed to read table info")?,
+            footer: None,
+        })
+    }
+
+    pub fn info(&self) -> &TableInfo {
+        &self.info
+    }
+
+    /// Returns the footer that ends dumps made with extended info, like
+    /// `[{count, N}]`, once the objects have all been read.
+    pub fn footer(&self) -> Option<&[u8]> {
+        self.footer.as_deref()
+    }
+
+    /// Reads the next object, as a packed tuple. Returns None after the
+    /// last one.
+    pub fn read_object(&mut self) -> Result<Option<Vec<u8>>> {
+        if self.footer.is_some() {
+            return Ok(None);
+        }
+
+        match self.log.read_item()? {
+            // Objects are tuples, and the footer is a list.
+            Some(item) if matches!(item.get(1), Some(104 | 105)) => Ok(Some(item)),
+            Some(item) => {
+                self.footer = Some(item);
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Reads the next object and unpacks it.
+    pub fn read_term(&mut self) -> Result<Option<AnyTerm>> {
+        match self.read_object()? {
+            Some(object) => Ok(Some(crate::unpack(object)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<R: Read> Iterator for Tab2FileReader<R> {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_object().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::Encoder;
+
+    fn log(closed: bool, items: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = LOG_MAGIC.to_vec();
+        data.extend(if closed { CLOSED } else { OPENED });
+        for item in items {
+            data.extend((item.len() as u32).to_be_bytes());
+            data.extend(BIG_MAGIC);
+            if item.len() >= MIN_MD5_ITEM {
+                data.extend([0; MD5_SIZE]);
+            }
+            data.extend(item);
+        }
+        data
+    }
+
+    fn packed(term: impl Into<AnyTerm>) -> Vec<u8> {
+        crate::pack(term.into()).unwrap()
+    }
+
+    #[test]
+    fn halt_log() {
+        let big = packed("a".repeat(MIN_MD5_ITEM));
+        let data = log(true, &[packed(1), packed(crate::Atom::from("ok")), big]);
+
+        let mut reader = DiskLogReader::new(data.as_slice()).unwrap();
+        assert!(reader.is_closed());
+        assert_eq!(reader.read_term().unwrap(), Some(AnyTerm::SmallInt(1)));
+        assert!(reader.read_term().unwrap().unwrap().is_atom("ok"));
+        assert_eq!(reader.read_item().unwrap().unwrap().len(), MIN_MD5_ITEM + 4);
+        assert_eq!(reader.read_item().unwrap(), None);
+    }
+
+    #[test]
+    fn old_items() {
+        let mut data = log(false, &[]);
+        data.extend([0, 0, 0, 3]);
+        data.extend(OLD_MAGIC);
+        data.extend([131, 97, 5]);
+
+        let reader = DiskLogReader::new(data.as_slice()).unwrap();
+        assert!(!reader.is_closed());
+        let items: Vec<Vec<u8>> = reader.map(Result::unwrap).collect();
+        assert_eq!(items, [vec![131, 97, 5]]);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(DiskLogReader::new(&[1, 2, 3][..]).is_err());
+        assert!(DiskLogReader::new(&[1, 2, 3, 4, 0, 0, 0, 0][..]).is_err());
+
+        let mut data = log(false, &[packed(1)]);
+        data.pop();
+        let mut reader = DiskLogReader::new(data.as_slice()).unwrap();
+        assert_eq!(
+            reader.read_item().unwrap_err().to_string(),
+            "Log ends in the middle of the item at byte 8; it wasn't closed properly"
+        );
+
+        // The size is far more than the file has, which is only found out
+        // as the item is read.
+        let mut data = log(false, &[packed(1)]);
+        data[8..12].copy_from_slice(&u32::MAX.to_be_bytes());
+        let mut reader = DiskLogReader::new(data.as_slice()).unwrap();
+        assert!(reader.read_item().is_err());
+
+        let mut data = log(true, &[packed(1)]);
+        data[12] = 0;
+        let mut reader = DiskLogReader::new(data.as_slice()).unwrap();
+        assert_eq!(
+            reader.read_item().unwrap_err().to_string(),
+            "Bad item header at byte 8"
+        );
+    }
+
+    #[test]
+    fn index_layouts() {
+        assert_eq!(
+            current_file(&[0, 0, 0, 0, 0, 2, 0, 0, 0, 3, 9, 9]).unwrap(),
+            3
+        );
+        assert_eq!(current_file(&[0, 0, 0, 0, 4, 9]).unwrap(), 4);
+        assert_eq!(current_file(&[5, 9, 9]).unwrap(), 5);
+        assert!(current_file(&[]).is_err());
+    }
+
+    #[test]
+    fn wrap_log() {
+        let dir = std::env::temp_dir().join(format!("etfpack-wrap-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let base = dir.join("events");
+
+        // File 2 is being written, so file 3 holds the oldest items.
+        std::fs::write(with_suffix(&base, "idx"), [0, 0, 0, 0, 0, 2, 0, 0, 0, 2]).unwrap();
+        std::fs::write(with_suffix(&base, "1"), log(true, &[packed(2)])).unwrap();
+        std::fs::write(with_suffix(&base, "2"), log(false, &[packed(3)])).unwrap();
+        std::fs::write(with_suffix(&base, "3"), log(true, &[packed(1)])).unwrap();
+
+        let terms: Vec<AnyTerm> = WrapLogReader::open(&base)
+            .unwrap()
+            .map(|item| crate::unpack(item.unwrap()).unwrap())
+            .collect();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            terms,
+            [
+                AnyTerm::SmallInt(1),
+                AnyTerm::SmallInt(2),
+                AnyTerm::SmallInt(3)
+            ]
+        );
+    }
+
+    fn object(key: &str, value: i32) -> Vec<u8> {
+        let mut encoder = Encoder::new(Vec::new());
+        encoder.tuple(2).unwrap();
+        encoder.atom(key).unwrap();
+        encoder.value(value).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn tab2file() {
+        // [{name, users}, {type, set}, {protection, protected},
+        //  {named_table, false}, {keypos, 1}, {size, 2}]
+        let mut encoder = Encoder::new(Vec::new());
+        encoder.list_header(6).unwrap();
+        for (key, value) in [
+            ("name", "users"),
+            ("type", "set"),
+            ("protection", "protected"),
+            ("named_table", "false"),
+        ] {
+            encoder.tuple(2).unwrap();
+            encoder.atom(key).unwrap();
+            encoder.atom(value).unwrap();
+        }
+        for (key, value) in [("keypos", 1), ("size", 2)] {
+            encoder.tuple(2).unwrap();
+            encoder.atom(key).unwrap();
+            encoder.value(value).unwrap();
+        }
+        encoder.nil().unwrap();
+        let header = encoder.finish().unwrap();
+
+        // [{count, 2}]
+        let mut encoder = Encoder::new(Vec::new());
+        encoder.list_header(1).unwrap();
+        encoder.tuple(2).unwrap();
+        encoder.atom("count").unwrap();
+        encoder.value(2).unwrap();
+        encoder.nil().unwrap();
+        let footer = encoder.finish().unwrap();
+
+        let data = log(
+            true,
+            &[
+                header.clone(),
+                object("joe", 1),
+                object("mike", 2),
+                footer.clone(),
+            ],
+        );
+        let mut reader = Tab2FileReader::new(data.as_slice()).unwrap();
+        assert_eq!(
+            reader.info(),
+            &TableInfo {
+                name: "users".to_string(),
+                kind: "set".to_string(),
+                keypos: 1,
+                size: Some(2),
+                header: header.clone(),
+            }
+        );
+
+        let objects: Vec<Vec<u8>> = reader.by_ref().map(Result::unwrap).collect();
+        assert_eq!(objects, [object("joe", 1), object("mike", 2)]);
+        assert_eq!(reader.footer(), Some(footer.as_slice()));
+
+        let items = [header, object("joe", 1), footer];
+        let data = log(true, &items);
+        let mut reader = Tab2FileReader::new(data.as_slice()).unwrap();
+        let joe = reader.read_term().unwrap().unwrap();
+        let joe = joe.as_tuple().unwrap();
+        assert!(joe[0].is_atom("joe"));
+        assert_eq!(joe[1], AnyTerm::SmallInt(1));
+        assert_eq!(reader.read_term().unwrap(), None);
+
+        let data = log(true, &[packed(1)]);
+        assert!(Tab2FileReader::new(data.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,202 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::structs::*;
+use crate::terms::AnyTerm;
+
+use alloc::format;
+use core::fmt::{self, Display, Formatter, Write};
+
+/// Words that have to be quoted to be read back as atoms.
+const RESERVED: [&str; 29] = [
+    "after", "and", "andalso", "band", "begin", "bnot", "bor", "bsl", "bsr", "bxor", "case",
+    "catch", "cond", "div", "else", "end", "fun", "if", "let", "maybe", "not", "of", "or",
+    "orelse", "receive", "rem", "try", "when", "xor",
+];
+
+/// Formats terms in Erlang syntax, the way the shell prints them.
+impl Display for AnyTerm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            AnyTerm::SmallInt(value) => write!(f, "{}", value),
+            AnyTerm::Integer(value) => write!(f, "{}", value),
+            AnyTerm::Float(value) => write_float(f, *value),
+            AnyTerm::LegacyFloat(float) => write_float(f, float.value),
+            AnyTerm::BigInt(value) => write!(f, "{}", value),
+            AnyTerm::Port(port) => write!(f, "#Port<{}.{}>", port.node, port.id),
+            AnyTerm::Pid(pid) => write!(f, "<{}.{}.{}>", pid.node, pid.id, pid.serial),
+            AnyTerm::Reference(reference) => {
+                write!(f, "#Ref<{}", reference.node)?;
+                for word in reference.id.iter().rev() {
+                    write!(f, ".{}", word)?;
+                }
+                f.write_char('>')
+            }
+            AnyTerm::Atom(atom) => write!(f, "{}", atom),
+            AnyTerm::String(value) => write_quoted(f, value, '"'),
+            AnyTerm::Nil => f.write_str("[]"),
+            AnyTerm::List(list) => {
+                f.write_char('[')?;
+                write_separated(f, &list.elements)?;
+                if !list.is_proper() {
+                    write!(f, "|{}", list.tail)?;
+                }
+                f.write_char(']')
+            }
+            AnyTerm::Tuple(elements) => {
+                f.write_char('{')?;
+                write_separated(f, elements)?;
+                f.write_char('}')
+            }
+            AnyTerm::Map(pairs) => {
+                f.write_str("#{")?;
+                for (i, (key, value)) in pairs.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{} => {}", key, value)?;
+                }
+                f.write_char('}')
+            }
+            AnyTerm::Binary(bytes) => write_binary(f, bytes, None),
+            AnyTerm::BitBinary(binary) => write_binary(f, &binary.bytes, Some(binary.bits)),
+        }
+    }
+}
+
+fn write_separated(f: &mut Formatter<'_>, elements: &[AnyTerm]) -> fmt::Result {
+    for (i, element) in elements.iter().enumerate() {
+        if i > 0 {
+            f.write_char(',')?;
+        }
+        write!(f, "{}", element)?;
+    }
+    Ok(())
+}
+
+/// Writes the bytes of a binary, with the size of the last one if it isn't
+/// a whole byte. Only the used bits of the last byte are shown, like
+/// `<<1,7:3>>`.
+fn write_binary(f: &mut Formatter<'_>, bytes: &[u8], bits: Option<u8>) -> fmt::Result {
+    f.write_str("<<")?;
+    for (i, byte) in bytes.iter().enumerate() {
+        if i > 0 {
+            f.write_char(',')?;
+        }
+        match bits {
+            Some(bits) if i == bytes.len() - 1 && bits < 8 => {
+                write!(f, "{}:{}", byte >> (8 - bits), bits)?
+            }
+            _ => write!(f, "{}", byte)?,
+        }
+    }
+    f.write_str(">>")
+}
+
+/// Formats atoms in Erlang syntax, quoting them if needed.
+impl Display for Atom {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut chars = self.value.chars();
+        let is_bare = chars.next().is_some_and(|c| c.is_ascii_lowercase())
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '@')
+            && !RESERVED.contains(&self.value.as_str());
+
+        if is_bare {
+            f.write_str(&self.value)
+        } else {
+            write_quoted(f, &self.value, '\'')
+        }
+    }
+}
+
+/// Erlang floats always have a fraction, even with an exponent (`1.0e30`).
+fn write_float(f: &mut Formatter<'_>, value: f64) -> fmt::Result {
+    let text = format!("{:?}", value);
+    match text.split_once('e') {
+        Some((mantissa, exponent)) if !mantissa.contains('.') => {
+            write!(f, "{}.0e{}", mantissa, exponent)
+        }
+        _ => f.write_str(&text),
+    }
+}
+
+fn write_quoted(f: &mut Formatter<'_>, value: &str, quote: char) -> fmt::Result {
+    f.write_char(quote)?;
+    for c in value.chars() {
+        match c {
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            '\\' => f.write_str("\\\\")?,
+            c if c == quote => write!(f, "\\{}", c)?,
+            c if c.is_control() => write!(f, "\\x{{{:X}}}", u32::from(c))?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char(quote)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn atoms() {
+        assert_eq!(Atom::from("ok").to_string(), "ok");
+        assert_eq!(Atom::from("node@host").to_string(), "node@host");
+        assert_eq!(Atom::from("Elixir.Date").to_string(), "'Elixir.Date'");
+        assert_eq!(Atom::from("end").to_string(), "'end'");
+        assert_eq!(Atom::from("it's").to_string(), "'it\\'s'");
+        assert_eq!(Atom::from("").to_string(), "''");
+    }
+
+    #[test]
+    fn terms() {
+        assert_eq!(AnyTerm::SmallInt(5).to_string(), "5");
+        assert_eq!(AnyTerm::Integer(-5).to_string(), "-5");
+        assert_eq!(AnyTerm::Float(1.5).to_string(), "1.5");
+        assert_eq!(AnyTerm::Float(1.0).to_string(), "1.0");
+        assert_eq!(AnyTerm::Float(1e30).to_string(), "1.0e30");
+        assert_eq!(
+            AnyTerm::String("a\"b\n".to_string()).to_string(),
+            "\"a\\\"b\\n\""
+        );
+    }
+
+    #[test]
+    fn containers() {
+        let list = AnyTerm::List(List::new(vec![AnyTerm::SmallInt(1), AnyTerm::Nil]));
+        let tuple = AnyTerm::Tuple(vec![Atom::from("ok").into(), list]);
+        assert_eq!(tuple.to_string(), "{ok,[1,[]]}");
+
+        let improper = List {
+            elements: vec![AnyTerm::SmallInt(1)],
+            tail: Box::new(AnyTerm::SmallInt(2)),
+        };
+        assert_eq!(AnyTerm::List(improper).to_string(), "[1|2]");
+
+        let map = AnyTerm::Map(vec![(Atom::from("a").into(), AnyTerm::Binary(vec![1, 2]))]);
+        assert_eq!(map.to_string(), "#{a => <<1,2>>}");
+
+        let bits = BitBinary {
+            bytes: vec![1, 0xE0],
+            bits: 3,
+        };
+        assert_eq!(AnyTerm::BitBinary(bits).to_string(), "<<1,7:3>>");
+        assert_eq!(
+            AnyTerm::BigInt(BigInt::from(-(1i128 << 100))).to_string(),
+            "-1267650600228229401496703205376"
+        );
+    }
+}
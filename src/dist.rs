@@ -0,0 +1,516 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::packing::FORMAT_VERSION;
+use crate::structs::{Atom, Pid, Reference};
+use crate::terms::AnyTerm;
+
+use alloc::{vec, vec::Vec};
+use anyhow::*;
+
+const SMALL_TUPLE_EXT: u8 = 104;
+const LARGE_TUPLE_EXT: u8 = 105;
+const SMALL_INTEGER_EXT: u8 = 97;
+
+/// The operation of a distribution control message, the first element of
+/// the control tuple.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ControlOp {
+    Link = 1,
+    Send = 2,
+    Exit = 3,
+    Unlink = 4,
+    NodeLink = 5,
+    RegSend = 6,
+    GroupLeader = 7,
+    Exit2 = 8,
+    SendTt = 12,
+    ExitTt = 13,
+    RegSendTt = 16,
+    Exit2Tt = 18,
+    MonitorP = 19,
+    DemonitorP = 20,
+    MonitorPExit = 21,
+    SendSender = 22,
+    SendSenderTt = 23,
+    PayloadExit = 24,
+    PayloadExitTt = 25,
+    PayloadExit2 = 26,
+    PayloadExit2Tt = 27,
+    PayloadMonitorPExit = 28,
+    SpawnRequest = 29,
+    SpawnRequestTt = 30,
+    SpawnReply = 31,
+    SpawnReplyTt = 32,
+    AliasSend = 33,
+    AliasSendTt = 34,
+    UnlinkId = 35,
+    UnlinkIdAck = 36,
+}
+
+impl ControlOp {
+    /// Returns the number of elements in the control tuple, including the
+    /// operation itself.
+    pub fn arity(&self) -> usize {
+        match self {
+            ControlOp::NodeLink => 1,
+            ControlOp::Link
+            | ControlOp::Send
+            | ControlOp::Unlink
+            | ControlOp::GroupLeader
+            | ControlOp::SendSender
+            | ControlOp::PayloadExit
+            | ControlOp::PayloadExit2
+            | ControlOp::AliasSend => 3,
+            ControlOp::Exit
+            | ControlOp::RegSend
+            | ControlOp::Exit2
+            | ControlOp::SendTt
+            | ControlOp::MonitorP
+            | ControlOp::DemonitorP
+            | ControlOp::SendSenderTt
+            | ControlOp::PayloadExitTt
+            | ControlOp::PayloadExit2Tt
+            | ControlOp::PayloadMonitorPExit
+            | ControlOp::AliasSendTt
+            | ControlOp::UnlinkId
+            | ControlOp::UnlinkIdAck => 4,
+            ControlOp::ExitTt
+            | ControlOp::RegSendTt
+            | ControlOp::Exit2Tt
+            | ControlOp::MonitorPExit
+            | ControlOp::SpawnReply => 5,
+            ControlOp::SpawnRequest | ControlOp::SpawnReplyTt => 6,
+            ControlOp::SpawnRequestTt => 7,
+        }
+    }
+
+    /// Returns true if the control message is followed by a message term:
+    /// the message itself, an exit reason, or the argument list of a spawn.
+    pub fn has_message(&self) -> bool {
+        matches!(
+            self,
+            ControlOp::Send
+                | ControlOp::RegSend
+                | ControlOp::SendTt
+                | ControlOp::RegSendTt
+                | ControlOp::SendSender
+                | ControlOp::SendSenderTt
+                | ControlOp::PayloadExit
+                | ControlOp::PayloadExitTt
+                | ControlOp::PayloadExit2
+                | ControlOp::PayloadExit2Tt
+                | ControlOp::PayloadMonitorPExit
+                | ControlOp::SpawnRequest
+                | ControlOp::SpawnRequestTt
+                | ControlOp::AliasSend
+                | ControlOp::AliasSendTt
+        )
+    }
+}
+
+impl TryFrom<u8> for ControlOp {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        let op = match value {
+            1 => ControlOp::Link,
+            2 => ControlOp::Send,
+            3 => ControlOp::Exit,
+            4 => ControlOp::Unlink,
+            5 => ControlOp::NodeLink,
+            6 => ControlOp::RegSend,
+            7 => ControlOp::GroupLeader,
+            8 => ControlOp::Exit2,
+            12 => ControlOp::SendTt,
+            13 => ControlOp::ExitTt,
+            16 => ControlOp::RegSendTt,
+            18 => ControlOp::Exit2Tt,
+            19 => ControlOp::MonitorP,
+            20 => ControlOp::DemonitorP,
+            21 => ControlOp::MonitorPExit,
+            22 => ControlOp::SendSender,
+            23 => ControlOp::SendSenderTt,
+            24 => ControlOp::PayloadExit,
+            25 => ControlOp::PayloadExitTt,
+            26 => ControlOp::PayloadExit2,
+            27 => ControlOp::PayloadExit2Tt,
+            28 => ControlOp::PayloadMonitorPExit,
+            29 => ControlOp::SpawnRequest,
+            30 => ControlOp::SpawnRequestTt,
+            31 => ControlOp::SpawnReply,
+            32 => ControlOp::SpawnReplyTt,
+            33 => ControlOp::AliasSend,
+            34 => ControlOp::AliasSendTt,
+            35 => ControlOp::UnlinkId,
+            36 => ControlOp::UnlinkIdAck,
+            _ => return Err(anyhow!("Unknown control message operation {}", value)),
+        };
+        Ok(op)
+    }
+}
+
+/// Reads the operation of an encoded control message without decoding the
+/// rest of it, and checks that the tuple has the right arity for it.
+pub fn peek_control_op(data: &[u8]) -> Result<ControlOp> {
+    let (arity, rest) = match data {
+        [FORMAT_VERSION, SMALL_TUPLE_EXT, arity, rest @ ..] => (usize::from(*arity), rest),
+        [FORMAT_VERSION, LARGE_TUPLE_EXT, a, b, c, d, rest @ ..] => {
+            (u32::from_be_bytes([*a, *b, *c, *d]) as usize, rest)
+        }
+        [FORMAT_VERSION, ..] => return Err(anyhow!("Control message is not a tuple")),
+        _ => return Err(anyhow!("Format version mismatch!")),
+    };
+
+    let op = match rest {
+        [SMALL_INTEGER_EXT, op, ..] => ControlOp::try_from(*op)?,
+        _ => return Err(anyhow!("Control message has no operation")),
+    };
+
+    if arity != op.arity() {
+        return Err(anyhow!(
+            "Expected {} elements in a {:?} control message, found {}",
+            op.arity(),
+            op,
+            arity
+        ));
+    }
+
+    Ok(op)
+}
+
+/// Converts the elements of control tuples.
+trait Element: Sized {
+    fn from_element(term: AnyTerm) -> Result<Self>;
+    fn into_element(self) -> Result<AnyTerm>;
+}
+
+impl Element for AnyTerm {
+    fn from_element(term: AnyTerm) -> Result<Self> {
+        Ok(term)
+    }
+
+    fn into_element(self) -> Result<AnyTerm> {
+        Ok(self)
+    }
+}
+
+macro_rules! element {
+    ($($ty:ty),*) => {$(
+        impl Element for $ty {
+            fn from_element(term: AnyTerm) -> Result<Self> {
+                <$ty>::try_from(term)
+            }
+
+            fn into_element(self) -> Result<AnyTerm> {
+                Ok(AnyTerm::from(self))
+            }
+        }
+    )*};
+}
+
+element!(Atom, Pid, Reference, u32, u64);
+
+macro_rules! control_messages {
+    ($($(#[$doc:meta])* $name:ident { $($field:ident: $ty:ty),* $(,)? },)*) => {
+        /// A distribution control message. The variants match ControlOp,
+        /// with the other elements of the control tuple as fields.
+        #[derive(Debug, Clone, PartialEq)]
+        pub enum ControlMessage {
+            $($(#[$doc])* $name { $($field: $ty),* },)*
+        }
+
+        impl ControlMessage {
+            pub fn op(&self) -> ControlOp {
+                match self {
+                    $(ControlMessage::$name { .. } => ControlOp::$name,)*
+                }
+            }
+        }
+
+        impl TryFrom<AnyTerm> for ControlMessage {
+            type Error = Error;
+
+            fn try_from(value: AnyTerm) -> Result<Self> {
+                let elements = match value {
+                    AnyTerm::Tuple(elements) => elements,
+                    other => return Err(anyhow!("Expected a control tuple, found {}", other.type_name())),
+                };
+                let op = match elements.first().and_then(AnyTerm::as_integer) {
+                    Some(op) => ControlOp::try_from(u8::try_from(op)?)?,
+                    None => return Err(anyhow!("Control message has no operation")),
+                };
+                if elements.len() != op.arity() {
+                    return Err(anyhow!(
+                        "Expected {} elements in a {:?} control message, found {}",
+                        op.arity(),
+                        op,
+                        elements.len()
+                    ));
+                }
+
+                #[allow(unused_mut, unused_variables)]
+                let mut elements = elements.into_iter().skip(1);
+                match op {
+                    $(ControlOp::$name => Ok(ControlMessage::$name {
+                        $($field: <$ty>::from_element(elements.next().unwrap()).with_context(|| {
+                            alloc::format!("In {} of a {:?} control message", stringify!($field), op)
+                        })?,)*
+                    }),)*
+                }
+            }
+        }
+
+        impl TryFrom<ControlMessage> for AnyTerm {
+            type Error = Error;
+
+            fn try_from(value: ControlMessage) -> Result<Self> {
+                let op = value.op() as u8;
+                match value {
+                    $(ControlMessage::$name { $($field),* } => {
+                        Ok(AnyTerm::Tuple(vec![AnyTerm::from(op), $($field.into_element()?),*]))
+                    })*
+                }
+            }
+        }
+    };
+}
+
+control_messages! {
+    Link { from: Pid, to: Pid },
+    Send { unused: AnyTerm, to: Pid },
+    Exit { from: Pid, to: Pid, reason: AnyTerm },
+    Unlink { from: Pid, to: Pid },
+    NodeLink {},
+    RegSend { from: Pid, unused: AnyTerm, to_name: Atom },
+    GroupLeader { from: Pid, to: Pid },
+    Exit2 { from: Pid, to: Pid, reason: AnyTerm },
+    SendTt { unused: AnyTerm, to: Pid, trace_token: AnyTerm },
+    ExitTt { from: Pid, to: Pid, trace_token: AnyTerm, reason: AnyTerm },
+    RegSendTt { from: Pid, unused: AnyTerm, to_name: Atom, trace_token: AnyTerm },
+    Exit2Tt { from: Pid, to: Pid, trace_token: AnyTerm, reason: AnyTerm },
+    /// `to_proc` is a pid or a registered name.
+    MonitorP { from: Pid, to_proc: AnyTerm, reference: Reference },
+    DemonitorP { from: Pid, to_proc: AnyTerm, reference: Reference },
+    MonitorPExit { from_proc: AnyTerm, to: Pid, reference: Reference, reason: AnyTerm },
+    SendSender { from: Pid, to: Pid },
+    SendSenderTt { from: Pid, to: Pid, trace_token: AnyTerm },
+    PayloadExit { from: Pid, to: Pid },
+    PayloadExitTt { from: Pid, to: Pid, trace_token: AnyTerm },
+    PayloadExit2 { from: Pid, to: Pid },
+    PayloadExit2Tt { from: Pid, to: Pid, trace_token: AnyTerm },
+    PayloadMonitorPExit { from_proc: AnyTerm, to: Pid, reference: Reference },
+    /// `mfa` is `{Module, Function, Arity}`; the arguments are the message.
+    SpawnRequest {
+        req_id: Reference,
+        from: Pid,
+        group_leader: Pid,
+        mfa: AnyTerm,
+        options: AnyTerm,
+    },
+    SpawnRequestTt {
+        req_id: Reference,
+        from: Pid,
+        group_leader: Pid,
+        mfa: AnyTerm,
+        options: AnyTerm,
+        trace_token: AnyTerm,
+    },
+    SpawnReply { req_id: Reference, to: Pid, flags: u32, result: AnyTerm },
+    SpawnReplyTt {
+        req_id: Reference,
+        to: Pid,
+        flags: u32,
+        result: AnyTerm,
+        trace_token: AnyTerm,
+    },
+    AliasSend { from: Pid, alias: Reference },
+    AliasSendTt { from: Pid, alias: Reference, trace_token: AnyTerm },
+    UnlinkId { id: u64, from: Pid, to: Pid },
+    UnlinkIdAck { id: u64, from: Pid, to: Pid },
+}
+
+/// A control message and the message term after it, which only some
+/// operations have (see ControlOp::has_message).
+#[derive(Debug, Clone, PartialEq)]
+pub struct DistMessage {
+    pub control: ControlMessage,
+    pub message: Option<AnyTerm>,
+}
+
+impl DistMessage {
+    /// Decodes a control message and its message term, each packed with
+    /// its own format version, like the payload of a distribution packet
+    /// after the pass-through byte.
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let (control, used) = crate::unpack_prefix(data)?;
+        let control = ControlMessage::try_from(control)?;
+        let rest = &data[used..];
+
+        let message = if control.op().has_message() {
+            if rest.is_empty() {
+                return Err(anyhow!(
+                    "A {:?} control message needs a message",
+                    control.op()
+                ));
+            }
+            let (message, used) = crate::unpack_prefix(rest)?;
+            if used != rest.len() {
+                return Err(anyhow!(
+                    "{} bytes left after the message",
+                    rest.len() - used
+                ));
+            }
+            Some(message)
+        } else if !rest.is_empty() {
+            return Err(anyhow!(
+                "{} bytes left after a {:?} control message, which has no message",
+                rest.len(),
+                control.op()
+            ));
+        } else {
+            None
+        };
+
+        Ok(DistMessage { control, message })
+    }
+
+    /// Encodes the control message and its message term, which must be
+    /// there exactly when the operation has one.
+    pub fn encode(self) -> Result<Vec<u8>> {
+        let op = self.control.op();
+        let mut data = crate::pack(self.control.try_into()?)?;
+        match (self.message, op.has_message()) {
+            (Some(message), true) => data.extend(crate::pack(message)?),
+            (None, false) => {}
+            (Some(_), false) => return Err(anyhow!("A {:?} control message has no message", op)),
+            (None, true) => return Err(anyhow!("A {:?} control message needs a message", op)),
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn peek() {
+        // {6, From, '', to}, with the rest left out.
+        let data = [131, 104, 4, 97, 6, 88];
+        let op = peek_control_op(&data).unwrap();
+        assert_eq!(op, ControlOp::RegSend);
+        assert!(op.has_message());
+
+        let data = [131, 104, 3, 97, 1];
+        assert!(!peek_control_op(&data).unwrap().has_message());
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(peek_control_op(&[131, 97, 2]).is_err());
+        assert!(peek_control_op(&[131, 104, 3, 97, 99]).is_err());
+
+        let error = peek_control_op(&[131, 104, 2, 97, 2]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Expected 3 elements in a Send control message, found 2"
+        );
+    }
+
+    fn pid(id: u32) -> Pid {
+        Pid::new(Atom::from("a@host"), id, 0, 1)
+    }
+
+    #[test]
+    fn round_trip() {
+        let messages = [
+            DistMessage {
+                control: ControlMessage::RegSend {
+                    from: pid(1),
+                    unused: Atom::from("").into(),
+                    to_name: Atom::from("logger"),
+                },
+                message: Some(AnyTerm::from("hello")),
+            },
+            DistMessage {
+                control: ControlMessage::Link {
+                    from: pid(1),
+                    to: pid(2),
+                },
+                message: None,
+            },
+            DistMessage {
+                control: ControlMessage::NodeLink {},
+                message: None,
+            },
+            DistMessage {
+                control: ControlMessage::UnlinkId {
+                    id: u64::MAX,
+                    from: pid(1),
+                    to: pid(2),
+                },
+                message: None,
+            },
+            DistMessage {
+                control: ControlMessage::SpawnReply {
+                    req_id: Reference::new(Atom::from("a@host"), 1, vec![1, 2, 3]),
+                    to: pid(3),
+                    flags: 0,
+                    result: pid(4).into(),
+                },
+                message: None,
+            },
+        ];
+
+        for message in messages {
+            let data = message.clone().encode().unwrap();
+            assert_eq!(peek_control_op(&data).unwrap(), message.control.op());
+            assert_eq!(DistMessage::decode(&data).unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn decode_errors() {
+        let send = ControlMessage::Send {
+            unused: Atom::from("").into(),
+            to: pid(1),
+        };
+        let control = crate::pack(send.clone().try_into().unwrap()).unwrap();
+        let error = DistMessage::decode(&control).unwrap_err();
+        assert_eq!(error.to_string(), "A Send control message needs a message");
+
+        let mut data = control.clone();
+        data.extend(crate::pack(AnyTerm::Nil).unwrap());
+        data.push(0);
+        let error = DistMessage::decode(&data).unwrap_err();
+        assert_eq!(error.to_string(), "1 bytes left after the message");
+
+        let error = DistMessage {
+            control: send,
+            message: None,
+        }
+        .encode()
+        .unwrap_err();
+        assert_eq!(error.to_string(), "A Send control message needs a message");
+
+        // {1, not_a_pid, To}
+        let link = AnyTerm::Tuple(vec![1.into(), Atom::from("x").into(), pid(2).into()]);
+        let error = ControlMessage::try_from(link).unwrap_err();
+        assert_eq!(
+            format!("{:#}", error),
+            "In from of a Link control message: Expected pid, found atom"
+        );
+    }
+}
@@ -0,0 +1,794 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::structs::*;
+use crate::terms::AnyTerm;
+
+use alloc::{format, string::String, vec::Vec};
+use anyhow::*;
+
+const STRUCT_KEY: &str = "__struct__";
+const ELIXIR_PREFIX: &str = "Elixir.";
+const CALENDAR_ISO: &str = "Elixir.Calendar.ISO";
+
+/// An Elixir struct: a map with a `__struct__` key naming its module.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ElixirStruct {
+    /// The module atom, including the `Elixir.` prefix.
+    pub module: String,
+    /// The other keys of the map, in order.
+    pub fields: Vec<(String, AnyTerm)>,
+}
+
+impl ElixirStruct {
+    /// Creates a struct with no fields. The module is given the way it is
+    /// written in Elixir, like `Date` or `MyApp.User`.
+    pub fn new(module: &str) -> Self {
+        ElixirStruct {
+            module: format!("{}{}", ELIXIR_PREFIX, module),
+            fields: Vec::new(),
+        }
+    }
+
+    /// Returns the module name the way it is written in Elixir.
+    pub fn name(&self) -> &str {
+        self.module
+            .strip_prefix(ELIXIR_PREFIX)
+            .unwrap_or(&self.module)
+    }
+
+    /// Returns the value of a field, if it is set.
+    pub fn get(&self, field: &str) -> Option<&AnyTerm> {
+        self.fields
+            .iter()
+            .find(|(name, _)| name == field)
+            .map(|(_, value)| value)
+    }
+
+    /// Sets the value of a field, replacing the old value if there was one.
+    pub fn set(&mut self, field: impl Into<String>, value: impl Into<AnyTerm>) {
+        let field = field.into();
+        let value = value.into();
+
+        match self.fields.iter_mut().find(|(name, _)| *name == field) {
+            Some((_, old)) => *old = value,
+            None => self.fields.push((field, value)),
+        }
+    }
+
+    /// Reads a struct from the key-value pairs of its map. Every key has to
+    /// be an atom, and `__struct__` has to be one of them.
+    pub fn from_pairs(pairs: Vec<(AnyTerm, AnyTerm)>) -> Result<Self> {
+        let mut module = None;
+        let mut fields = Vec::new();
+
+        for (key, value) in pairs {
+            let key = match key {
+                AnyTerm::Atom(key) => key.value,
+                other => {
+                    return Err(anyhow!(
+                        "Expected atom keys in a struct, found {}",
+                        other.type_name()
+                    ))
+                }
+            };
+
+            if key == STRUCT_KEY {
+                module = Some(Atom::try_from(value)?.value);
+            } else {
+                fields.push((key, value));
+            }
+        }
+
+        match module {
+            Some(module) => Ok(ElixirStruct { module, fields }),
+            None => Err(anyhow!("Map has no {} key", STRUCT_KEY)),
+        }
+    }
+
+    /// Writes the struct as the key-value pairs of its map, starting with
+    /// `__struct__`.
+    pub fn to_pairs(self) -> Vec<(AnyTerm, AnyTerm)> {
+        let module = (
+            Atom::from(STRUCT_KEY).into(),
+            Atom::from(self.module).into(),
+        );
+        let fields = self
+            .fields
+            .into_iter()
+            .map(|(key, value)| (Atom::from(key).into(), value));
+
+        core::iter::once(module).chain(fields).collect()
+    }
+
+    /// Checks the module and returns a required field.
+    fn field<T: TryFrom<AnyTerm, Error = Error>>(&self, module: &str, field: &str) -> Result<T> {
+        self.raw_field(module, field).and_then(|value| {
+            T::try_from(value.clone())
+                .with_context(|| format!("Invalid field {} of %{}{{}}", field, module))
+        })
+    }
+
+    /// Checks the module and returns a required field that is a UTF-8
+    /// binary, like the strings of Elixir.
+    fn binary_field(&self, module: &str, field: &str) -> Result<String> {
+        match self.raw_field(module, field)? {
+            AnyTerm::Binary(bytes) => String::from_utf8(bytes.clone())
+                .with_context(|| format!("Invalid field {} of %{}{{}}", field, module)),
+            other => Err(anyhow!(
+                "Invalid field {} of %{}{{}}: expected binary, found {}",
+                field,
+                module,
+                other.type_name()
+            )),
+        }
+    }
+
+    fn raw_field(&self, module: &str, field: &str) -> Result<&AnyTerm> {
+        if self.name() != module {
+            return Err(anyhow!(
+                "Expected %{}{{}}, found %{}{{}}",
+                module,
+                self.name()
+            ));
+        }
+
+        self.get(field)
+            .ok_or_else(|| anyhow!("%{}{{}} has no field {}", module, field))
+    }
+
+    /// Checks that the struct uses the ISO calendar.
+    fn check_calendar(&self, module: &str) -> Result<()> {
+        let calendar: Atom = self.field(module, "calendar")?;
+        if calendar.value != CALENDAR_ISO {
+            return Err(anyhow!("Unsupported calendar {}", calendar.value));
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<AnyTerm> for ElixirStruct {
+    type Error = Error;
+
+    fn try_from(value: AnyTerm) -> Result<Self> {
+        match value {
+            AnyTerm::Map(pairs) => ElixirStruct::from_pairs(pairs),
+            other => Err(anyhow!("Expected map, found {}", other.type_name())),
+        }
+    }
+}
+
+impl From<ElixirStruct> for AnyTerm {
+    fn from(value: ElixirStruct) -> Self {
+        AnyTerm::Map(value.to_pairs())
+    }
+}
+
+/// The types below convert to and from map terms through ElixirStruct.
+macro_rules! term_conversions {
+    ($($ty:ty),*) => {$(
+        impl TryFrom<AnyTerm> for $ty {
+            type Error = Error;
+
+            fn try_from(value: AnyTerm) -> Result<Self> {
+                <$ty>::try_from(ElixirStruct::try_from(value)?)
+            }
+        }
+
+        impl From<$ty> for AnyTerm {
+            fn from(value: $ty) -> Self {
+                ElixirStruct::from(value).into()
+            }
+        }
+    )*};
+}
+
+/// A `%Date{}` in the ISO calendar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Date {
+    pub year: i32,
+    pub month: u8,
+    pub day: u8,
+}
+
+impl TryFrom<ElixirStruct> for Date {
+    type Error = Error;
+
+    fn try_from(value: ElixirStruct) -> Result<Self> {
+        value.check_calendar("Date")?;
+        Date::read(&value, "Date")
+    }
+}
+
+impl From<Date> for ElixirStruct {
+    fn from(value: Date) -> Self {
+        let mut result = ElixirStruct::new("Date");
+        result.set("calendar", Atom::from(CALENDAR_ISO));
+        value.write(&mut result);
+        result
+    }
+}
+
+impl Date {
+    /// Returns the number of days in a month of a year, or 0 if there is
+    /// no such month.
+    pub fn days_in_month(year: i32, month: u8) -> u8 {
+        let leap =
+            year.rem_euclid(4) == 0 && (year.rem_euclid(100) != 0 || year.rem_euclid(400) == 0);
+        match month {
+            1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+            4 | 6 | 9 | 11 => 30,
+            2 if leap => 29,
+            2 => 28,
+            _ => 0,
+        }
+    }
+
+    /// Reads the date fields of a struct, which other structs share.
+    fn read(value: &ElixirStruct, module: &str) -> Result<Self> {
+        let date = Date {
+            year: value.field(module, "year")?,
+            month: value.field(module, "month")?,
+            day: value.field(module, "day")?,
+        };
+
+        if date.day == 0 || date.day > Date::days_in_month(date.year, date.month) {
+            return Err(anyhow!(
+                "Invalid date {}-{}-{} in %{}{{}}",
+                date.year,
+                date.month,
+                date.day,
+                module
+            ));
+        }
+        Ok(date)
+    }
+
+    fn write(self, result: &mut ElixirStruct) {
+        result.set("day", self.day);
+        result.set("month", self.month);
+        result.set("year", self.year);
+    }
+}
+
+/// A `%Time{}` in the ISO calendar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Time {
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    /// The microseconds, and how many digits of them are shown (0 to 6).
+    pub microsecond: (u32, u8),
+}
+
+impl TryFrom<ElixirStruct> for Time {
+    type Error = Error;
+
+    fn try_from(value: ElixirStruct) -> Result<Self> {
+        value.check_calendar("Time")?;
+        Time::read(&value, "Time")
+    }
+}
+
+impl From<Time> for ElixirStruct {
+    fn from(value: Time) -> Self {
+        let mut result = ElixirStruct::new("Time");
+        result.set("calendar", Atom::from(CALENDAR_ISO));
+        value.write(&mut result);
+        result
+    }
+}
+
+impl Time {
+    /// Reads the time fields of a struct, which other structs share.
+    fn read(value: &ElixirStruct, module: &str) -> Result<Self> {
+        let time = Time {
+            hour: value.field(module, "hour")?,
+            minute: value.field(module, "minute")?,
+            second: value.field(module, "second")?,
+            microsecond: value.field(module, "microsecond")?,
+        };
+
+        let (microsecond, precision) = time.microsecond;
+        if time.hour > 23
+            || time.minute > 59
+            || time.second > 59
+            || microsecond > 999_999
+            || precision > 6
+        {
+            return Err(anyhow!(
+                "Invalid time {}:{}:{}.{} in %{}{{}}",
+                time.hour,
+                time.minute,
+                time.second,
+                microsecond,
+                module
+            ));
+        }
+        Ok(time)
+    }
+
+    fn write(self, result: &mut ElixirStruct) {
+        result.set("hour", self.hour);
+        result.set("microsecond", self.microsecond);
+        result.set("minute", self.minute);
+        result.set("second", self.second);
+    }
+}
+
+/// A `%NaiveDateTime{}` in the ISO calendar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NaiveDateTime {
+    pub date: Date,
+    pub time: Time,
+}
+
+impl TryFrom<ElixirStruct> for NaiveDateTime {
+    type Error = Error;
+
+    fn try_from(value: ElixirStruct) -> Result<Self> {
+        value.check_calendar("NaiveDateTime")?;
+        Ok(NaiveDateTime {
+            date: Date::read(&value, "NaiveDateTime")?,
+            time: Time::read(&value, "NaiveDateTime")?,
+        })
+    }
+}
+
+impl From<NaiveDateTime> for ElixirStruct {
+    fn from(value: NaiveDateTime) -> Self {
+        let mut result = ElixirStruct::new("NaiveDateTime");
+        result.set("calendar", Atom::from(CALENDAR_ISO));
+        value.date.write(&mut result);
+        value.time.write(&mut result);
+        result
+    }
+}
+
+/// A `%DateTime{}` in the ISO calendar, with its time zone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DateTime {
+    pub date: Date,
+    pub time: Time,
+    /// Like `Etc/UTC` or `Europe/Berlin`.
+    pub time_zone: String,
+    /// Like `UTC` or `CET`.
+    pub zone_abbr: String,
+    /// The offset from UTC in seconds, without daylight saving.
+    pub utc_offset: i32,
+    /// The daylight saving offset in seconds.
+    pub std_offset: i32,
+}
+
+impl TryFrom<ElixirStruct> for DateTime {
+    type Error = Error;
+
+    fn try_from(value: ElixirStruct) -> Result<Self> {
+        value.check_calendar("DateTime")?;
+        Ok(DateTime {
+            date: Date::read(&value, "DateTime")?,
+            time: Time::read(&value, "DateTime")?,
+            time_zone: value.binary_field("DateTime", "time_zone")?,
+            zone_abbr: value.binary_field("DateTime", "zone_abbr")?,
+            utc_offset: value.field("DateTime", "utc_offset")?,
+            std_offset: value.field("DateTime", "std_offset")?,
+        })
+    }
+}
+
+impl From<DateTime> for ElixirStruct {
+    fn from(value: DateTime) -> Self {
+        let mut result = ElixirStruct::new("DateTime");
+        result.set("calendar", Atom::from(CALENDAR_ISO));
+        value.date.write(&mut result);
+        value.time.write(&mut result);
+        result.set("std_offset", value.std_offset);
+        result.set("time_zone", value.time_zone.as_bytes());
+        result.set("utc_offset", value.utc_offset);
+        result.set("zone_abbr", value.zone_abbr.as_bytes());
+        result
+    }
+}
+
+/// A `%MapSet{}`, which keeps its elements as the keys of a map.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapSet {
+    pub elements: Vec<AnyTerm>,
+}
+
+impl TryFrom<ElixirStruct> for MapSet {
+    type Error = Error;
+
+    fn try_from(value: ElixirStruct) -> Result<Self> {
+        match value.raw_field("MapSet", "map")? {
+            AnyTerm::Map(pairs) => Ok(MapSet {
+                elements: pairs.iter().map(|(key, _)| key.clone()).collect(),
+            }),
+            other => Err(anyhow!(
+                "Invalid field map of %MapSet{{}}: expected map, found {}",
+                other.type_name()
+            )),
+        }
+    }
+}
+
+impl From<MapSet> for ElixirStruct {
+    fn from(value: MapSet) -> Self {
+        let map = value
+            .elements
+            .into_iter()
+            .map(|element| (element, AnyTerm::Nil))
+            .collect();
+
+        let mut result = ElixirStruct::new("MapSet");
+        result.set("map", AnyTerm::Map(map));
+        // Elixir still declares the field, although it no longer reads it.
+        result.set("version", 2);
+        result
+    }
+}
+
+/// A `%Range{}`, like `1..10//2`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Range {
+    pub first: i32,
+    pub last: i32,
+    pub step: i32,
+}
+
+impl TryFrom<ElixirStruct> for Range {
+    type Error = Error;
+
+    /// Ranges from before Elixir 1.12 have no step, which then depends on
+    /// the direction of the range.
+    fn try_from(value: ElixirStruct) -> Result<Self> {
+        let first = value.field("Range", "first")?;
+        let last = value.field("Range", "last")?;
+        let step = match value.get("step") {
+            Some(_) => value.field("Range", "step")?,
+            None if last < first => -1,
+            None => 1,
+        };
+
+        Ok(Range { first, last, step })
+    }
+}
+
+impl From<Range> for ElixirStruct {
+    fn from(value: Range) -> Self {
+        let mut result = ElixirStruct::new("Range");
+        result.set("first", value.first);
+        result.set("last", value.last);
+        result.set("step", value.step);
+        result
+    }
+}
+
+term_conversions!(Date, Time, NaiveDateTime, DateTime, MapSet, Range);
+
+/// The coefficient of a `%Decimal{}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Coefficient {
+    /// Coefficients that don't fit into a u128 can't be read.
+    Finite(u128),
+    /// The atom `inf`.
+    Infinity,
+    /// The atom `NaN`.
+    NaN,
+}
+
+/// A `%Decimal{}` from the decimal library, which is `sign * coef * 10 ^ exp`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decimal {
+    /// Either 1 or -1.
+    pub sign: i8,
+    pub coef: Coefficient,
+    pub exp: i32,
+}
+
+impl TryFrom<ElixirStruct> for Decimal {
+    type Error = Error;
+
+    fn try_from(value: ElixirStruct) -> Result<Self> {
+        let sign = value.field("Decimal", "sign")?;
+        if sign != 1 && sign != -1 {
+            return Err(anyhow!("Invalid sign {} of %Decimal{{}}", sign));
+        }
+
+        let coef = match value.get("coef") {
+            Some(AnyTerm::Atom(atom)) if atom.value == "inf" => Coefficient::Infinity,
+            Some(AnyTerm::Atom(atom)) if atom.value == "NaN" => Coefficient::NaN,
+            _ => Coefficient::Finite(value.field("Decimal", "coef")?),
+        };
+
+        Ok(Decimal {
+            sign,
+            coef,
+            exp: value.field("Decimal", "exp")?,
+        })
+    }
+}
+
+impl TryFrom<Decimal> for ElixirStruct {
+    type Error = Error;
+
+    fn try_from(value: Decimal) -> Result<Self> {
+        let coef = match value.coef {
+            Coefficient::Finite(coef) => AnyTerm::from(coef),
+            Coefficient::Infinity => AnyTerm::Atom(Atom::from("inf")),
+            Coefficient::NaN => AnyTerm::Atom(Atom::from("NaN")),
+        };
+
+        let mut result = ElixirStruct::new("Decimal");
+        result.set("coef", coef);
+        result.set("exp", value.exp);
+        result.set("sign", value.sign);
+        Ok(result)
+    }
+}
+
+impl TryFrom<AnyTerm> for Decimal {
+    type Error = Error;
+
+    fn try_from(value: AnyTerm) -> Result<Self> {
+        Decimal::try_from(ElixirStruct::try_from(value)?)
+    }
+}
+
+impl TryFrom<Decimal> for AnyTerm {
+    type Error = Error;
+
+    fn try_from(value: Decimal) -> Result<Self> {
+        Ok(ElixirStruct::try_from(value)?.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn atom(value: &str) -> AnyTerm {
+        AnyTerm::Atom(Atom::from(value))
+    }
+
+    #[test]
+    fn from_pairs() {
+        let value = ElixirStruct::from_pairs(vec![
+            (atom("__struct__"), atom("Elixir.MyApp.User")),
+            (atom("id"), AnyTerm::from(42)),
+        ])
+        .unwrap();
+
+        assert_eq!(value.name(), "MyApp.User");
+        assert_eq!(value.get("id"), Some(&AnyTerm::from(42)));
+        assert_eq!(value.fields.len(), 1);
+    }
+
+    #[test]
+    fn from_pairs_invalid() {
+        let error = ElixirStruct::from_pairs(vec![(atom("id"), AnyTerm::from(1))]).unwrap_err();
+        assert_eq!(error.to_string(), "Map has no __struct__ key");
+
+        let error =
+            ElixirStruct::from_pairs(vec![(AnyTerm::from("id"), AnyTerm::from(1))]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Expected atom keys in a struct, found string"
+        );
+    }
+
+    #[test]
+    fn to_pairs() {
+        let mut value = ElixirStruct::new("MyApp.User");
+        value.set("id", 42);
+
+        assert_eq!(
+            value.to_pairs(),
+            vec![
+                (atom("__struct__"), atom("Elixir.MyApp.User")),
+                (atom("id"), AnyTerm::from(42)),
+            ]
+        );
+    }
+
+    #[test]
+    fn date() {
+        let date = Date {
+            year: 2022,
+            month: 7,
+            day: 14,
+        };
+
+        let value = ElixirStruct::from(date);
+        assert_eq!(value.module, "Elixir.Date");
+        assert_eq!(value.get("calendar"), Some(&atom("Elixir.Calendar.ISO")));
+        assert_eq!(Date::try_from(value).unwrap(), date);
+    }
+
+    #[test]
+    fn date_invalid() {
+        let mut value = ElixirStruct::from(Date {
+            year: 2022,
+            month: 7,
+            day: 14,
+        });
+        value.set("month", 1000);
+
+        let error = Date::try_from(value.clone()).unwrap_err();
+        assert_eq!(error.to_string(), "Invalid field month of %Date{}");
+
+        value.set("month", 13);
+        let error = Date::try_from(value.clone()).unwrap_err();
+        assert_eq!(error.to_string(), "Invalid date 2022-13-14 in %Date{}");
+
+        value.set("month", 2);
+        value.set("day", 0);
+        assert!(Date::try_from(value.clone()).is_err());
+        value.set("day", 29);
+        assert!(Date::try_from(value.clone()).is_err());
+        value.set("year", 2024);
+        assert!(Date::try_from(value).is_ok());
+
+        let error = Date::try_from(ElixirStruct::new("Time")).unwrap_err();
+        assert_eq!(error.to_string(), "Expected %Date{}, found %Time{}");
+    }
+
+    #[test]
+    fn range() {
+        let range = Range {
+            first: 1,
+            last: 10,
+            step: 2,
+        };
+        assert_eq!(Range::try_from(ElixirStruct::from(range)).unwrap(), range);
+
+        let mut legacy = ElixirStruct::new("Range");
+        legacy.set("first", 10);
+        legacy.set("last", 1);
+        assert_eq!(Range::try_from(legacy).unwrap().step, -1);
+    }
+
+    #[test]
+    fn decimal() {
+        let decimal = Decimal {
+            sign: -1,
+            coef: Coefficient::Finite(12345),
+            exp: -2,
+        };
+        let value = ElixirStruct::try_from(decimal).unwrap();
+        assert_eq!(Decimal::try_from(value).unwrap(), decimal);
+
+        let nan = Decimal {
+            sign: 1,
+            coef: Coefficient::NaN,
+            exp: 0,
+        };
+        let value = ElixirStruct::try_from(nan).unwrap();
+        assert_eq!(value.get("coef"), Some(&atom("NaN")));
+        assert_eq!(Decimal::try_from(value).unwrap(), nan);
+
+        let huge = Decimal {
+            sign: 1,
+            coef: Coefficient::Finite(u128::MAX),
+            exp: 0,
+        };
+        let value = ElixirStruct::try_from(huge).unwrap();
+        assert!(matches!(value.get("coef"), Some(AnyTerm::BigInt(_))));
+        assert_eq!(Decimal::try_from(value).unwrap(), huge);
+    }
+
+    fn time() -> Time {
+        Time {
+            hour: 23,
+            minute: 59,
+            second: 1,
+            microsecond: (5000, 3),
+        }
+    }
+
+    #[test]
+    fn terms() {
+        let date = Date {
+            year: 2022,
+            month: 7,
+            day: 14,
+        };
+        let term = AnyTerm::from(date);
+        assert_eq!(
+            term.as_map().unwrap()[0],
+            (atom("__struct__"), atom("Elixir.Date"))
+        );
+        assert_eq!(Date::try_from(term).unwrap(), date);
+
+        let error = Date::try_from(AnyTerm::Nil).unwrap_err();
+        assert_eq!(error.to_string(), "Expected map, found list");
+    }
+
+    #[test]
+    fn times() {
+        let term = AnyTerm::from(time());
+        assert_eq!(Time::try_from(term).unwrap(), time());
+
+        let mut value = ElixirStruct::from(time());
+        value.set("hour", 24);
+        let error = Time::try_from(value).unwrap_err();
+        assert_eq!(error.to_string(), "Invalid time 24:59:1.5000 in %Time{}");
+
+        let mut value = ElixirStruct::from(time());
+        value.set("microsecond", (5000, 7));
+        assert!(Time::try_from(value).is_err());
+
+        let naive = NaiveDateTime {
+            date: Date {
+                year: 2000,
+                month: 2,
+                day: 29,
+            },
+            time: time(),
+        };
+        let value = ElixirStruct::from(naive);
+        assert_eq!(value.get("year"), Some(&AnyTerm::from(2000)));
+        assert_eq!(value.get("minute"), Some(&AnyTerm::from(59)));
+        assert_eq!(
+            NaiveDateTime::try_from(AnyTerm::from(value)).unwrap(),
+            naive
+        );
+    }
+
+    #[test]
+    fn date_time() {
+        let date_time = DateTime {
+            date: Date {
+                year: 2022,
+                month: 12,
+                day: 31,
+            },
+            time: time(),
+            time_zone: "Europe/Berlin".to_string(),
+            zone_abbr: "CET".to_string(),
+            utc_offset: 3600,
+            std_offset: 0,
+        };
+
+        let value = ElixirStruct::from(date_time.clone());
+        assert_eq!(value.get("zone_abbr"), Some(&AnyTerm::from(&b"CET"[..])));
+        assert_eq!(DateTime::try_from(value).unwrap(), date_time);
+
+        let mut value = ElixirStruct::from(date_time);
+        value.set("time_zone", atom("utc"));
+        let error = DateTime::try_from(value).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid field time_zone of %DateTime{}: expected binary, found atom"
+        );
+    }
+
+    #[test]
+    fn map_set() {
+        let set = MapSet {
+            elements: vec![AnyTerm::from(1), atom("a")],
+        };
+
+        let term = AnyTerm::from(set.clone());
+        let map = ElixirStruct::try_from(term.clone()).unwrap();
+        assert_eq!(
+            map.get("map"),
+            Some(&AnyTerm::Map(vec![
+                (AnyTerm::from(1), AnyTerm::Nil),
+                (atom("a"), AnyTerm::Nil),
+            ]))
+        );
+        assert_eq!(MapSet::try_from(term).unwrap(), set);
+    }
+}
@@ -0,0 +1,272 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::packing::FORMAT_VERSION;
+use crate::structs::Atom;
+use crate::traits::IntoTerm;
+
+use anyhow::*;
+use std::io::Write;
+
+const SMALL_TUPLE_EXT: u8 = 104;
+const LARGE_TUPLE_EXT: u8 = 105;
+const NIL_EXT: u8 = 106;
+const LIST_EXT: u8 = 108;
+const BINARY_EXT: u8 = 109;
+const MAP_EXT: u8 = 116;
+
+/// A container that still needs terms.
+#[derive(Debug)]
+struct Open {
+    kind: &'static str,
+    /// The number of terms still needed, including the tail of a list.
+    left: usize,
+}
+
+/// Writes a term piece by piece, so that large terms never have to be built
+/// in memory. Containers are written as a header with their size, then
+/// their contents, and are closed once they have all of them.
+///
+/// Writes go straight to the writer, so wrapping it in a BufWriter is a
+/// good idea. After an error, the output should be thrown away.
+pub struct Encoder<W: Write> {
+    inner: W,
+    stack: Vec<Open>,
+    started: bool,
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(inner: W) -> Self {
+        Encoder {
+            inner,
+            stack: Vec::new(),
+            started: false,
+        }
+    }
+
+    /// Starts a tuple. The next arity terms are its elements.
+    pub fn tuple(&mut self, arity: usize) -> Result<()> {
+        self.begin()?;
+        match u8::try_from(arity) {
+            Result::Ok(arity) => self.inner.write_all(&[SMALL_TUPLE_EXT, arity])?,
+            Err(_) => self.header(LARGE_TUPLE_EXT, arity)?,
+        }
+        self.open("tuple", arity);
+        Ok(())
+    }
+
+    /// Starts a list. The next len terms are its elements, and the one after
+    /// that is its tail, which is nil for proper lists. Empty lists are just
+    /// nil.
+    pub fn list_header(&mut self, len: usize) -> Result<()> {
+        if len == 0 {
+            return Err(anyhow!("Empty lists have to be written as nil"));
+        }
+
+        self.begin()?;
+        self.header(LIST_EXT, len)?;
+        self.open("list", len + 1);
+        Ok(())
+    }
+
+    /// Starts a map. The next pairs * 2 terms are its keys and values,
+    /// alternating.
+    pub fn map_header(&mut self, pairs: usize) -> Result<()> {
+        let terms = pairs
+            .checked_mul(2)
+            .ok_or_else(|| anyhow!("Map of {} pairs is too large", pairs))?;
+
+        self.begin()?;
+        self.header(MAP_EXT, pairs)?;
+        self.open("map", terms);
+        Ok(())
+    }
+
+    /// Writes [], which also ends proper lists.
+    pub fn nil(&mut self) -> Result<()> {
+        self.begin()?;
+        self.inner.write_all(&[NIL_EXT])?;
+        self.end();
+        Ok(())
+    }
+
+    /// Writes an atom, with the smallest UTF-8 encoding that fits.
+    pub fn atom(&mut self, name: &str) -> Result<()> {
+        self.value(Atom::from(name))
+    }
+
+    /// Writes a binary.
+    pub fn binary(&mut self, bytes: &[u8]) -> Result<()> {
+        self.begin()?;
+        self.header(BINARY_EXT, bytes.len())?;
+        self.inner.write_all(bytes)?;
+        self.end();
+        Ok(())
+    }
+
+    /// Writes any value that can be a term, like an integer or an AnyTerm.
+    pub fn value<T: IntoTerm>(&mut self, value: T) -> Result<()> {
+        // Packed first, so a value that can't be packed leaves no trace.
+        let mut buf = Vec::new();
+        value.write_term(&mut buf)?;
+
+        self.begin()?;
+        self.inner.write_all(&buf)?;
+        self.end();
+        Ok(())
+    }
+
+    /// Checks that the term is complete and returns the writer.
+    pub fn finish(mut self) -> Result<W> {
+        if !self.started {
+            return Err(anyhow!("No term was written"));
+        }
+
+        if let Some(open) = self.stack.last() {
+            return Err(anyhow!(
+                "{} containers are still open; the innermost {} needs {} more terms",
+                self.stack.len(),
+                open.kind,
+                open.left
+            ));
+        }
+
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+
+    /// Called before each term, to check that one is expected.
+    fn begin(&mut self) -> Result<()> {
+        if !self.started {
+            self.inner.write_all(&[FORMAT_VERSION])?;
+            self.started = true;
+        } else if self.stack.is_empty() {
+            return Err(anyhow!("The term is already complete"));
+        }
+
+        if let Some(open) = self.stack.last_mut() {
+            open.left -= 1;
+        }
+        Ok(())
+    }
+
+    /// Called after each complete term, to close the containers it filled.
+    fn end(&mut self) {
+        while let Some(Open { left: 0, .. }) = self.stack.last() {
+            self.stack.pop();
+        }
+    }
+
+    fn open(&mut self, kind: &'static str, left: usize) {
+        self.stack.push(Open { kind, left });
+        self.end();
+    }
+
+    fn header(&mut self, tag: u8, len: usize) -> Result<()> {
+        let len = u32::try_from(len).map_err(|_| anyhow!("Length {} is too large", len))?;
+        self.inner.write_all(&[tag])?;
+        self.inner.write_all(&len.to_be_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::terms::AnyTerm;
+    use crate::validate::validate;
+
+    #[test]
+    fn nested() -> Result<()> {
+        // {ok, [1, <<"hi">>], #{}}
+        let mut encoder = Encoder::new(Vec::new());
+        encoder.tuple(3)?;
+        encoder.atom("ok")?;
+        encoder.list_header(2)?;
+        encoder.value(1)?;
+        encoder.binary(b"hi")?;
+        encoder.nil()?;
+        encoder.map_header(0)?;
+        let data = encoder.finish()?;
+
+        assert_eq!(
+            data,
+            [
+                131, 104, 3, 119, 2, 111, 107, 108, 0, 0, 0, 2, 97, 1, 109, 0, 0, 0, 2, 104, 105,
+                106, 116, 0, 0, 0, 0
+            ]
+        );
+        assert!(validate(&data).is_valid());
+        Ok(())
+    }
+
+    #[test]
+    fn scalar() -> Result<()> {
+        let mut encoder = Encoder::new(Vec::new());
+        encoder.value(AnyTerm::Float(1.5))?;
+        assert!(encoder.value(2).is_err());
+        assert_eq!(encoder.finish()?, crate::pack(AnyTerm::Float(1.5))?);
+        Ok(())
+    }
+
+    #[test]
+    fn counts() -> Result<()> {
+        let mut encoder = Encoder::new(Vec::new());
+        encoder.tuple(2)?;
+        encoder.tuple(1)?;
+        encoder.nil()?;
+        let error = encoder.finish().err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "1 containers are still open; the innermost tuple needs 1 more terms"
+        );
+
+        let mut encoder = Encoder::new(Vec::new());
+        encoder.list_header(1)?;
+        encoder.value(1)?;
+        encoder.nil()?;
+        assert!(encoder.nil().is_err());
+
+        assert!(Encoder::new(Vec::new()).list_header(0).is_err());
+        assert!(Encoder::new(Vec::new()).finish().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn large_list() -> Result<()> {
+        let len = 100_000;
+        let mut encoder = Encoder::new(Vec::new());
+        encoder.list_header(len)?;
+        for i in 0..len {
+            encoder.tuple(2)?;
+            encoder.value(i as i32)?;
+            encoder.atom("row")?;
+        }
+        encoder.nil()?;
+
+        let data = encoder.finish()?;
+        assert!(validate(&data).is_valid());
+        Ok(())
+    }
+
+    #[test]
+    fn unpackable_values() -> Result<()> {
+        let mut encoder = Encoder::new(Vec::new());
+        encoder.tuple(1)?;
+        assert!(encoder.value(AnyTerm::String("€".into())).is_err());
+        encoder.value(1)?;
+        assert_eq!(encoder.finish()?, [131, 104, 1, 97, 1]);
+        Ok(())
+    }
+}
@@ -0,0 +1,547 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::terms::AnyTerm;
+
+use alloc::{vec, vec::Vec};
+use anyhow::*;
+
+// The same constants and mixing function as make_hash2 in the emulator's
+// utils.c, so that the results match erlang:phash2.
+const HCONST: u32 = 0x9e3779b9;
+const HCONST_2: u32 = HCONST.wrapping_mul(2);
+const HCONST_3: u32 = HCONST.wrapping_mul(3);
+const HCONST_4: u32 = HCONST.wrapping_mul(4);
+const HCONST_5: u32 = HCONST.wrapping_mul(5);
+const HCONST_6: u32 = HCONST.wrapping_mul(6);
+const HCONST_7: u32 = HCONST.wrapping_mul(7);
+const HCONST_9: u32 = HCONST.wrapping_mul(9);
+const HCONST_10: u32 = HCONST.wrapping_mul(10);
+const HCONST_11: u32 = HCONST.wrapping_mul(11);
+const HCONST_12: u32 = HCONST.wrapping_mul(12);
+const HCONST_13: u32 = HCONST.wrapping_mul(13);
+const HCONST_15: u32 = HCONST.wrapping_mul(15);
+const HCONST_16: u32 = HCONST.wrapping_mul(16);
+const HCONST_19: u32 = HCONST.wrapping_mul(19);
+
+/// The hash of [] when it is the whole term.
+const NIL_HASH: u32 = 3468870702;
+const NIL_DEF: u32 = 2;
+
+fn mix(mut a: u32, mut b: u32, mut c: u32) -> (u32, u32, u32) {
+    a = a.wrapping_sub(b).wrapping_sub(c) ^ (c >> 13);
+    b = b.wrapping_sub(c).wrapping_sub(a) ^ (a << 8);
+    c = c.wrapping_sub(a).wrapping_sub(b) ^ (b >> 13);
+    a = a.wrapping_sub(b).wrapping_sub(c) ^ (c >> 12);
+    b = b.wrapping_sub(c).wrapping_sub(a) ^ (a << 16);
+    c = c.wrapping_sub(a).wrapping_sub(b) ^ (b >> 5);
+    a = a.wrapping_sub(b).wrapping_sub(c) ^ (c >> 3);
+    b = b.wrapping_sub(c).wrapping_sub(a) ^ (a << 10);
+    c = c.wrapping_sub(a).wrapping_sub(b) ^ (b >> 15);
+    (a, b, c)
+}
+
+/// The atom table's hash (hashpjw over the name), which phash2 uses as it
+/// is for a lone atom.
+fn atom_hash(name: &str) -> u32 {
+    let bytes = name.as_bytes();
+    let mut hash: u32 = 0;
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let mut byte = bytes[i];
+        i += 1;
+
+        // Latin-1 characters are hashed as their Latin-1 byte, so that hashes
+        // didn't change when atoms became UTF-8.
+        if i < bytes.len() && byte & 0xFE == 0xC2 && bytes[i] & 0xC0 == 0x80 {
+            byte = (byte << 6) | (bytes[i] & 0x3F);
+            i += 1;
+        }
+
+        hash = (hash << 4).wrapping_add(byte.into());
+        let high = hash & 0xf000_0000;
+        if high != 0 {
+            hash ^= high >> 24;
+            hash ^= high;
+        }
+    }
+
+    hash
+}
+
+/// Bob Jenkins' lookup2 hash, which binaries are hashed with.
+fn block_hash(bytes: &[u8], initial: u32) -> u32 {
+    let word = |chunk: &[u8]| {
+        chunk
+            .iter()
+            .rev()
+            .fold(0u32, |word, &byte| (word << 8) | u32::from(byte))
+    };
+
+    let (mut a, mut b, mut c) = (HCONST, HCONST, initial);
+    let mut chunks = bytes.chunks_exact(12);
+    for chunk in &mut chunks {
+        a = a.wrapping_add(word(&chunk[..4]));
+        b = b.wrapping_add(word(&chunk[4..8]));
+        c = c.wrapping_add(word(&chunk[8..]));
+        (a, b, c) = mix(a, b, c);
+    }
+
+    // The lowest byte of c is taken by the length.
+    let rest = chunks.remainder();
+    c = c.wrapping_add(bytes.len() as u32);
+    a = a.wrapping_add(word(&rest[..rest.len().min(4)]));
+    b = b.wrapping_add(word(
+        rest.get(4..).map_or(&[], |rest| &rest[..rest.len().min(4)]),
+    ));
+    c = c.wrapping_add(word(rest.get(8..).unwrap_or(&[])) << 8);
+    mix(a, b, c).2
+}
+
+/// What is left to hash, kept on a stack rather than hashing containers
+/// recursively.
+enum Step<'a> {
+    Term(&'a AnyTerm),
+    /// The rest of a list, from some element on.
+    List(&'a [AnyTerm], &'a AnyTerm),
+    /// The end of one key and value of a map.
+    Pair,
+    /// The end of a map, with the hashes from before it.
+    MapEnd {
+        hash: u32,
+        pairs: u32,
+    },
+}
+
+/// Follows make_hash2 in the emulator, which hashes the parts of a term in
+/// order into one running hash. Only maps are hashed out of order, by XORing
+/// the hashes of their pairs together.
+struct Hasher {
+    hash: u32,
+    /// The XOR of the pairs of the innermost map so far.
+    pairs: u32,
+}
+
+impl Hasher {
+    fn add_2(&mut self, first: u32, second: u32, constant: u32) {
+        let a = constant.wrapping_add(first);
+        let b = constant.wrapping_add(second);
+        self.hash = mix(a, b, self.hash).2;
+    }
+
+    fn add(&mut self, value: u32, constant: u32) {
+        self.add_2(value, 0, constant);
+    }
+
+    fn integer(&mut self, value: i64) {
+        // Integers that don't fit in 28 bits are hashed as bignums.
+        if !(-(1 << 27)..(1 << 27)).contains(&value) {
+            return self.bignum(value < 0, &value.unsigned_abs().to_le_bytes());
+        }
+
+        // Negative numbers are mixed twice, like the emulator does.
+        if value < 0 {
+            self.add(value.unsigned_abs() as u32, HCONST);
+        }
+        self.add(value as u32, HCONST);
+    }
+
+    /// Hashes a bignum by the 32-bit halves of each 64-bit digit of its
+    /// magnitude, least significant first.
+    fn bignum(&mut self, negative: bool, digits: &[u8]) {
+        let len = digits
+            .iter()
+            .rposition(|&digit| digit != 0)
+            .map_or(0, |last| last + 1);
+        let digits = &digits[..len];
+        if len <= 4 {
+            let magnitude = digits
+                .iter()
+                .rev()
+                .fold(0i64, |value, &digit| (value << 8) | i64::from(digit));
+            let value = if negative { -magnitude } else { magnitude };
+            if (-(1 << 27)..(1 << 27)).contains(&value) {
+                return self.integer(value);
+            }
+        }
+
+        let constant = if negative { HCONST_10 } else { HCONST_11 };
+        for digit in digits.chunks(8) {
+            let digit = digit
+                .iter()
+                .rev()
+                .fold(0u64, |value, &byte| (value << 8) | u64::from(byte));
+            self.add_2(digit as u32, (digit >> 32) as u32, constant);
+        }
+    }
+
+    fn float(&mut self, value: f64) {
+        // 0.0 and -0.0 hash the same.
+        let value = if value == 0.0 { 0.0 } else { value };
+        let bits = value.to_bits();
+        self.add_2((bits >> 32) as u32, bits as u32, HCONST_12);
+    }
+
+    fn atom(&mut self, name: &str) {
+        match self.hash {
+            0 => self.hash = atom_hash(name),
+            _ => self.add(atom_hash(name), HCONST_3),
+        }
+    }
+
+    fn nil(&mut self) {
+        match self.hash {
+            0 => self.hash = NIL_HASH,
+            _ => self.add(NIL_DEF, HCONST_2),
+        }
+    }
+
+    /// Hashes the bytes of a bitstring, then the bits of its last byte
+    /// that aren't a whole byte.
+    fn binary(&mut self, bytes: &[u8], bits: u8) {
+        let initial = HCONST_13.wrapping_add(self.hash);
+        let (whole, last) = match (bits, bytes.split_last()) {
+            (1..=7, Some((&last, whole))) => (whole, Some(last)),
+            _ => (bytes, None),
+        };
+        if whole.is_empty() && last.is_none() {
+            self.hash = initial;
+            return;
+        }
+
+        self.hash = block_hash(whole, initial);
+        if let Some(last) = last {
+            self.add_2(bits.into(), u32::from(last >> (8 - bits)), HCONST_15);
+        }
+    }
+
+    /// Hashes the bytes at the start of a list four at a time, which is the
+    /// same for a string and the list of its characters.
+    fn bytes(&mut self, bytes: impl Iterator<Item = u8>) {
+        let mut word = 0;
+        let mut count = 0;
+        for byte in bytes {
+            word = (word << 8) | u32::from(byte);
+            count += 1;
+            if count == 4 {
+                self.add(word, HCONST_4);
+                word = 0;
+                count = 0;
+            }
+        }
+        if count > 0 {
+            self.add(word, HCONST_4);
+        }
+    }
+
+    fn string(&mut self, value: &str) {
+        let mut rest = value;
+        loop {
+            let end = rest
+                .find(|c: char| u32::from(c) > 0xFF)
+                .unwrap_or(rest.len());
+            self.bytes(rest[..end].chars().map(|c| c as u8));
+
+            let mut chars = rest[end..].chars();
+            match chars.next() {
+                Some(c) => self.integer(u32::from(c).into()),
+                None => return self.nil(),
+            }
+            rest = chars.as_str();
+        }
+    }
+
+    fn list<'a>(&mut self, elements: &'a [AnyTerm], tail: &'a AnyTerm, stack: &mut Vec<Step<'a>>) {
+        let byte = |element: &AnyTerm| u8::try_from(element.as_integer()?).ok();
+        let count = elements
+            .iter()
+            .take_while(|element| byte(element).is_some())
+            .count();
+        self.bytes(elements[..count].iter().filter_map(byte));
+
+        match elements[count..].split_first() {
+            Some((element, rest)) => {
+                stack.push(Step::List(rest, tail));
+                stack.push(Step::Term(element));
+            }
+            None => stack.push(Step::Term(tail)),
+        }
+    }
+
+    fn term<'a>(&mut self, term: &'a AnyTerm, stack: &mut Vec<Step<'a>>) {
+        match term {
+            AnyTerm::SmallInt(value) => self.integer((*value).into()),
+            AnyTerm::Integer(value) => self.integer((*value).into()),
+            AnyTerm::BigInt(value) => self.bignum(value.negative, &value.digits),
+            AnyTerm::Float(value) => self.float(*value),
+            AnyTerm::LegacyFloat(float) => self.float(float.value),
+            AnyTerm::Atom(atom) => self.atom(&atom.value),
+            AnyTerm::String(value) => self.string(value),
+            AnyTerm::Nil => self.nil(),
+            AnyTerm::List(list) => self.list(&list.elements, &list.tail, stack),
+            AnyTerm::Tuple(elements) => {
+                self.add(elements.len() as u32, HCONST_9);
+                stack.extend(elements.iter().rev().map(Step::Term));
+            }
+            AnyTerm::Map(pairs) => {
+                self.add(pairs.len() as u32, HCONST_16);
+                if pairs.is_empty() {
+                    return;
+                }
+
+                stack.push(Step::MapEnd {
+                    hash: self.hash,
+                    pairs: self.pairs,
+                });
+                self.hash = 0;
+                self.pairs = 0;
+                for (key, value) in pairs.iter().rev() {
+                    stack.push(Step::Pair);
+                    stack.push(Step::Term(value));
+                    stack.push(Step::Term(key));
+                }
+            }
+            AnyTerm::Binary(bytes) => self.binary(bytes, 0),
+            AnyTerm::BitBinary(binary) => self.binary(&binary.bytes, binary.bits),
+            // Only the number of each identifier is hashed, and only the
+            // first word of a reference.
+            AnyTerm::Pid(pid) => self.add(pid.id, HCONST_5),
+            AnyTerm::Port(port) => self.add_2(port.id as u32, (port.id >> 32) as u32, HCONST_6),
+            AnyTerm::Reference(reference) => {
+                self.add(reference.id.first().copied().unwrap_or(0), HCONST_7)
+            }
+        }
+    }
+}
+
+/// Hashes a term like erlang:phash2/2, returning a value in 0..range. The
+/// range goes up to 2^32; erlang:phash2/1 is the same as a range of 2^27.
+pub fn phash2(term: &AnyTerm, range: u64) -> Result<u32> {
+    if range == 0 || range > 1 << 32 {
+        return Err(anyhow!("Range must be between 1 and 2^32, not {}", range));
+    }
+
+    let mut hasher = Hasher { hash: 0, pairs: 0 };
+    let mut stack = vec![Step::Term(term)];
+    while let Some(step) = stack.pop() {
+        match step {
+            Step::Term(term) => hasher.term(term, &mut stack),
+            Step::List(elements, tail) => hasher.list(elements, tail, &mut stack),
+            Step::Pair => {
+                hasher.pairs ^= hasher.hash;
+                hasher.hash = 0;
+            }
+            Step::MapEnd { hash, pairs } => {
+                let xor = hasher.pairs;
+                hasher.hash = hash;
+                hasher.add(xor, HCONST_19);
+                hasher.pairs = pairs;
+            }
+        }
+    }
+
+    Ok((u64::from(hasher.hash) % range) as u32)
+}
+
+/// Computes the CRC-32 of some bytes, like erlang:crc32/1.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// Computes the CRC-32 of a packed term, like
+/// `erlang:crc32(term_to_binary(Term))`.
+pub fn term_crc32(term: &AnyTerm) -> Result<u32> {
+    Ok(crc32(&crate::pack(term.clone())?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::{Atom, List};
+
+    const FULL: u64 = 1 << 32;
+
+    fn hash(term: impl Into<AnyTerm>) -> u32 {
+        phash2(&term.into(), FULL).unwrap()
+    }
+
+    #[test]
+    fn otp_vectors() {
+        // From the phash2 test in OTP's hash_SUITE, with a range of 2^32.
+        assert_eq!(hash(AnyTerm::Nil), 3468870702);
+        assert_eq!(hash(""), 3468870702);
+        assert_eq!(hash(Atom::from("abc")), 26499);
+        assert_eq!(hash(Atom::from("abd")), 26500);
+        assert_eq!(hash(Atom::from("åäö")), 62518);
+        assert_eq!(hash(Atom::from("😃")), 1026307);
+        let runes: String = (5792..=5872).filter_map(char::from_u32).collect();
+        assert_eq!(hash(Atom::from(runes.as_str())), 241561024);
+
+        assert_eq!(hash(AnyTerm::SmallInt(0)), 3175731469);
+        assert_eq!(hash(AnyTerm::SmallInt(1)), 539485162);
+        assert_eq!(hash(AnyTerm::Integer(-1)), 1117813597);
+        assert_eq!(hash(AnyTerm::Integer(1 << 20)), 1477815345);
+        assert_eq!(hash(AnyTerm::Integer(-(1 << 20))), 3076904293);
+        assert_eq!(hash(AnyTerm::from(1u64 << 32)), 2108323275);
+        assert_eq!(hash(AnyTerm::Float(0.0)), 423528920);
+
+        assert_eq!(hash(AnyTerm::Binary(vec![])), 147926629);
+        assert_eq!(hash(AnyTerm::Binary(vec![0])), 2914887855);
+        assert_eq!(hash(AnyTerm::Binary(vec![0; 4])), 2014511533);
+        assert_eq!(hash(AnyTerm::Binary(b"abc".to_vec())), 1306188027);
+        let digits = b"12345678901234567890".to_vec();
+        assert_eq!(hash(AnyTerm::Binary(digits)), 3021061640);
+        let long: Vec<u8> = (0..500).flat_map(|_| 32..128).collect();
+        assert_eq!(hash(AnyTerm::Binary(long)), 2644086993);
+        let seven_bits = crate::BitBinary {
+            bytes: vec![0],
+            bits: 7,
+        };
+        assert_eq!(hash(AnyTerm::BitBinary(seven_bits)), 1055790816);
+        assert_eq!(hash(AnyTerm::Tuple(vec![])), 221703996);
+    }
+
+    #[test]
+    fn model_vectors() {
+        // hash_SUITE has no vectors for these, so they were checked against
+        // a separate model of make_hash2 written from the emulator's C.
+        let atom = |name: &str| AnyTerm::from(Atom::from(name));
+        let list = |elements: Vec<AnyTerm>| AnyTerm::List(List::new(elements));
+        let ints = |values: &[i32]| list(values.iter().map(|&value| value.into()).collect());
+
+        assert_eq!(hash(list(vec![atom("abc")])), 3112446404);
+        assert_eq!(hash(ints(&[1, 2, 3])), 4186538188);
+        let improper = List {
+            elements: vec![atom("a")],
+            tail: Box::new(atom("b")),
+        };
+        assert_eq!(hash(AnyTerm::List(improper)), 2356411656);
+        let mixed = list(vec![1.into(), 300.into(), AnyTerm::Nil]);
+        assert_eq!(hash(mixed), 147311059);
+        assert_eq!(
+            hash(AnyTerm::Tuple(vec![atom("a"), AnyTerm::Nil])),
+            475258904
+        );
+
+        let map = AnyTerm::Map(vec![(atom("a"), 1.into()), (atom("b"), ints(&[2]))]);
+        assert_eq!(hash(AnyTerm::Map(vec![(atom("a"), 1.into())])), 1401795262);
+        assert_eq!(hash(map), 1579473383);
+        let inner = AnyTerm::Map(vec![(1.into(), AnyTerm::Tuple(vec![]))]);
+        assert_eq!(hash(AnyTerm::Tuple(vec![inner, atom("x")])), 418546456);
+
+        assert_eq!(hash(2.5), 2784981260);
+        assert_eq!(hash(-1.5), 1758584130);
+        assert_eq!(hash(1.0e123), 3623720220);
+
+        assert_eq!(hash(-(1i64 << 32)), 2586067094);
+        assert_eq!(hash(-(1i128 << 64) - 5), 2798261862);
+        assert_eq!(hash(-(1i64 << 28)), 3694550089);
+        assert_eq!(hash(1u128 << 70), 1070946125);
+
+        let node = || Atom::from("nonode@nohost");
+        let pid = crate::Pid {
+            kind: crate::PidKind::NewPid,
+            node: node(),
+            id: 5,
+            serial: 0,
+            creation: 0,
+        };
+        assert_eq!(hash(AnyTerm::Pid(pid)), 1742111205);
+        let port = |id| {
+            AnyTerm::Port(crate::Port {
+                kind: crate::PortKind::V4,
+                node: node(),
+                id,
+                creation: 0,
+            })
+        };
+        assert_eq!(hash(port(7)), 3725428046);
+        assert_eq!(hash(port((1 << 32) + 7)), 3671940937);
+        let reference = crate::Reference {
+            kind: crate::ReferenceKind::NewerReference,
+            node: node(),
+            creation: 0,
+            id: vec![122, 0, 0],
+        };
+        assert_eq!(hash(AnyTerm::Reference(reference)), 1103127778);
+    }
+
+    #[test]
+    fn equal_terms() {
+        let small = phash2(&AnyTerm::SmallInt(5), FULL).unwrap();
+        assert_eq!(phash2(&AnyTerm::Integer(5), FULL).unwrap(), small);
+
+        let legacy = Atom::new(crate::AtomKind::SmallLegacy, "abc".to_string());
+        assert_eq!(phash2(&legacy.into(), FULL).unwrap(), 26499);
+
+        let zero = phash2(&AnyTerm::Float(0.0), FULL).unwrap();
+        assert_eq!(phash2(&AnyTerm::Float(-0.0), FULL).unwrap(), zero);
+
+        // Erlang only has one kind of integer, and strings are lists.
+        let big = AnyTerm::BigInt(crate::BigInt::from(-(1i128 << 27)));
+        assert_eq!(hash(big), hash(AnyTerm::Integer(-(1 << 27))));
+        let chars = |elements: Vec<AnyTerm>| AnyTerm::List(List::new(elements));
+        let list = chars(vec![
+            AnyTerm::SmallInt(b'a'),
+            AnyTerm::Integer(0x3b1),
+            AnyTerm::Integer(0xe9),
+            AnyTerm::SmallInt(b'z'),
+        ]);
+        assert_eq!(hash("aαéz"), hash(list));
+
+        // Maps hash the same whatever order their pairs are in.
+        let pairs = vec![
+            (Atom::from("a").into(), AnyTerm::from("x")),
+            (AnyTerm::Tuple(vec![]), AnyTerm::Binary(vec![1, 2])),
+            (AnyTerm::SmallInt(1), chars(vec![AnyTerm::Nil])),
+        ];
+        let reversed = pairs.iter().rev().cloned().collect();
+        let map = AnyTerm::Map(pairs);
+        assert_eq!(hash(map.clone()), hash(AnyTerm::Map(reversed)));
+        assert_ne!(hash(map.clone()), hash(AnyTerm::Map(vec![])));
+
+        let nested = AnyTerm::Tuple(vec![map.clone(), chars(vec![map])]);
+        assert_ne!(hash(nested.clone()), hash(AnyTerm::Tuple(vec![])));
+    }
+
+    #[test]
+    fn range() {
+        let term = AnyTerm::SmallInt(0);
+        assert_eq!(
+            phash2(&term, 1 << 27).unwrap(),
+            3175731469 & ((1 << 27) - 1)
+        );
+        assert_eq!(phash2(&term, 1).unwrap(), 0);
+        assert!(phash2(&term, 0).is_err());
+        assert!(phash2(&term, FULL + 1).is_err());
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+        assert_eq!(crc32(b""), 0);
+
+        let term = AnyTerm::SmallInt(1);
+        assert_eq!(term_crc32(&term).unwrap(), crc32(&[131, 97, 1]));
+    }
+}
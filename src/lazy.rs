@@ -0,0 +1,617 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#[cfg(feature = "std")]
+use crate::atom_table::{AtomTable, InternedAtom};
+use crate::packing::{unpack_buf, COMPRESSED, FORMAT_VERSION};
+use crate::terms::AnyTerm;
+#[cfg(feature = "std")]
+use crate::terms::{AtomPacker, Term};
+use crate::traits::FromTerm;
+#[cfg(feature = "std")]
+use crate::utils::read_bytes;
+use crate::utils::ReadBuf;
+use crate::validate::{header, index, skip_term, Container, Layout, STRIDE};
+use crate::visit::{visit, visit_term, Visitor};
+use crate::UnpackOptions;
+
+use alloc::vec::Vec;
+use anyhow::*;
+
+const NEW_FLOAT_EXT: u8 = 70;
+const BIT_BINARY_EXT: u8 = 77;
+const INTEGER_EXT: u8 = 98;
+const SMALL_TUPLE_EXT: u8 = 104;
+const LARGE_TUPLE_EXT: u8 = 105;
+const NIL_EXT: u8 = 106;
+const LIST_EXT: u8 = 108;
+const BINARY_EXT: u8 = 109;
+const LARGE_BIG_EXT: u8 = 111;
+const MAP_EXT: u8 = 116;
+const ATOM_UTF8_EXT: u8 = 118;
+
+/// An index of where every subterm of some packed data starts and ends, so
+/// that parts of it can be found and unpacked without unpacking the rest.
+///
+/// Building the index checks the data like validate. Only large tuples, lists
+/// and maps are kept, with where every sixteenth element starts, so it's
+/// usually much smaller than the data, which is borrowed, not copied.
+pub struct TermIndex<'a> {
+    data: &'a [u8],
+    layout: Layout,
+}
+
+impl<'a> TermIndex<'a> {
+    /// Indexes packed data. Compressed data has to be decompressed first.
+    pub fn new(data: &'a [u8]) -> Result<Self> {
+        if let Some(&[FORMAT_VERSION, COMPRESSED]) = data.get(..2) {
+            return Err(anyhow!("Compressed terms have to be decompressed first"));
+        }
+
+        let (report, layout) = index(data);
+        if !report.is_valid() {
+            return Err(anyhow!("Invalid term: {}", report));
+        }
+
+        Ok(TermIndex { data, layout })
+    }
+
+    /// Returns the whole term.
+    pub fn root(&self) -> TermHandle<'_, 'a> {
+        // After the format version.
+        self.handle(1)
+    }
+
+    /// Returns the number of subterms, including the whole term.
+    pub fn count(&self) -> usize {
+        self.layout.terms
+    }
+
+    fn handle(&self, start: usize) -> TermHandle<'_, 'a> {
+        let (first, children) = header(self.data, start);
+        // Containers that weren't kept only have a few subterms to skip.
+        let end = match children {
+            0 => first,
+            _ => match self.container(start) {
+                Some(container) => container.end,
+                None => skip_term(self.data, start).unwrap_or(first),
+            },
+        };
+
+        TermHandle {
+            index: self,
+            start,
+            end,
+            first,
+            children,
+        }
+    }
+
+    fn container(&self, start: usize) -> Option<&Container> {
+        let containers = &self.layout.containers;
+        let found = containers.binary_search_by_key(&start, |container| container.start);
+        containers.get(found.ok()?)
+    }
+}
+
+/// A subterm of an indexed term, which is only unpacked when asked to be.
+#[derive(Clone, Copy)]
+pub struct TermHandle<'i, 'a> {
+    index: &'i TermIndex<'a>,
+    start: usize,
+    end: usize,
+    /// Where the first child starts.
+    first: usize,
+    /// The number of children: elements of tuples and lists (including the
+    /// tail), keys and values of maps, and free variables of funs.
+    children: usize,
+}
+
+impl<'i, 'a> TermHandle<'i, 'a> {
+    /// Returns the tag of the subterm, like 104 for SMALL_TUPLE_EXT.
+    pub fn tag(&self) -> u8 {
+        self.index.data[self.start]
+    }
+
+    /// Returns the offset of the subterm in the data.
+    pub fn offset(&self) -> usize {
+        self.start
+    }
+
+    /// Returns the packed subterm, without a format version.
+    pub fn bytes(&self) -> &'a [u8] {
+        &self.index.data[self.start..self.end]
+    }
+
+    /// Returns the packed subterm with a format version, as unpack expects.
+    pub fn to_packed(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(self.bytes().len() + 1);
+        data.push(FORMAT_VERSION);
+        data.extend_from_slice(self.bytes());
+        data
+    }
+
+    /// Returns true if the subterm is a tuple.
+    pub fn is_tuple(&self) -> bool {
+        matches!(self.tag(), SMALL_TUPLE_EXT | LARGE_TUPLE_EXT)
+    }
+
+    /// Returns true if the subterm is a list, proper or not, other than [].
+    pub fn is_list(&self) -> bool {
+        self.tag() == LIST_EXT
+    }
+
+    /// Returns true if the subterm is [].
+    pub fn is_nil(&self) -> bool {
+        self.tag() == NIL_EXT
+    }
+
+    /// Returns true if the subterm is a map.
+    pub fn is_map(&self) -> bool {
+        self.tag() == MAP_EXT
+    }
+
+    /// Returns the number of elements of a tuple or list, not counting the
+    /// tail, or the number of pairs in a map. Other terms have none.
+    pub fn len(&self) -> usize {
+        match self.tag() {
+            SMALL_TUPLE_EXT | LARGE_TUPLE_EXT => self.children,
+            LIST_EXT => self.children - 1,
+            MAP_EXT => self.children / 2,
+            _ => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the nth direct child in the data, starting from the closest
+    /// mark before it and skipping over the children in between.
+    fn child(&self, n: usize) -> Option<Self> {
+        if n >= self.children {
+            return None;
+        }
+
+        let mut start = match n / STRIDE {
+            0 => self.first,
+            mark => {
+                let container = self.index.container(self.start)?;
+                self.index.layout.marks[container.marks + mark - 1]
+            }
+        };
+        for _ in 0..n % STRIDE {
+            start = self.index.handle(start).end;
+        }
+        Some(self.index.handle(start))
+    }
+
+    /// Returns an element of a tuple or list.
+    pub fn get(&self, n: usize) -> Option<Self> {
+        match self.tag() {
+            SMALL_TUPLE_EXT | LARGE_TUPLE_EXT | LIST_EXT if n < self.len() => self.child(n),
+            _ => None,
+        }
+    }
+
+    /// Returns the tail of a list, which is [] for proper lists.
+    pub fn tail(&self) -> Option<Self> {
+        match self.tag() {
+            LIST_EXT => self.child(self.len()),
+            _ => None,
+        }
+    }
+
+    /// Returns the elements of a tuple or list, not counting the tail.
+    pub fn elements(&self) -> impl Iterator<Item = TermHandle<'i, 'a>> {
+        let len = match self.tag() {
+            SMALL_TUPLE_EXT | LARGE_TUPLE_EXT | LIST_EXT => self.len(),
+            _ => 0,
+        };
+        self.children().take(len)
+    }
+
+    /// Returns the keys and values of a map.
+    pub fn entries(&self) -> impl Iterator<Item = (TermHandle<'i, 'a>, TermHandle<'i, 'a>)> {
+        let len = match self.tag() {
+            MAP_EXT => self.len(),
+            _ => 0,
+        };
+
+        let mut children = self.children();
+        (0..len).filter_map(move |_| Some((children.next()?, children.next()?)))
+    }
+
+    fn children(&self) -> impl Iterator<Item = TermHandle<'i, 'a>> {
+        let index = self.index;
+        let mut start = self.first;
+        (0..self.children).map(move |_| {
+            let child = index.handle(start);
+            start = child.end;
+            child
+        })
+    }
+
+    /// Returns the value for a key of a map. Keys are compared by their
+    /// packed bytes, once atoms, integers, floats and strings are written the
+    /// same way however they were packed. Keys that can't be packed or read
+    /// never match.
+    pub fn get_key(&self, key: &AnyTerm) -> Option<Self> {
+        let key = crate::pack(key.clone())
+            .and_then(|key| normalized(&key))
+            .ok()?;
+        self.entries()
+            .find(|(candidate, _)| candidate.normalizes_to(&key))
+            .map(|(_, value)| value)
+    }
+
+    /// Returns true if the subterm normalizes to the given bytes. Its own
+    /// normalized bytes are compared as they're written, not kept.
+    fn normalizes_to(&self, normalized: &[u8]) -> bool {
+        let mut writer = Normalizer(Output::Compare {
+            expected: normalized,
+            pos: Some(0),
+        });
+        visit_term(self.bytes(), &mut writer).is_ok()
+            && matches!(writer.0, Output::Compare { pos, .. } if pos == Some(normalized.len()))
+    }
+
+    /// Unpacks the subterm.
+    pub fn unpack(&self) -> Result<AnyTerm> {
+        let mut buf = ReadBuf::new(self.bytes());
+        unpack_buf(&mut buf, &UnpackOptions::new())
+    }
+
+    /// Interns an atom in a table, without copying its name out of the data
+    /// unless the atom is new, so that it can be compared cheaply.
+    #[cfg(feature = "std")]
+    pub fn intern(&self, table: &AtomTable) -> Result<InternedAtom> {
+        let mut buf = ReadBuf::new(self.bytes());
+        let fb = read_bytes(&mut buf, 1)?[0];
+        if !AtomPacker::can_unpack(&fb) {
+            return Err(anyhow!("Expected an atom, found tag {}", fb));
+        }
+
+        let (_, name) = AtomPacker::read_name(&mut buf, fb)?;
+        table.intern(&name)
+    }
+
+    /// Unpacks the subterm into a value.
+    pub fn unpack_value<T: FromTerm>(&self) -> Result<T> {
+        T::from_term(self.unpack()?)
+    }
+}
+
+/// Packed data written again with one encoding for each kind of term, so
+/// that equal terms have equal bytes. It's only for comparing, and can't be
+/// unpacked: integers are always eight bytes, strings become lists, and every
+/// length is four bytes.
+fn normalized(data: &[u8]) -> Result<Vec<u8>> {
+    let mut writer = Normalizer(Output::Bytes(Vec::new()));
+    visit(data, &mut writer)?;
+    match writer.0 {
+        Output::Bytes(bytes) => Ok(bytes),
+        Output::Compare { .. } => unreachable!(),
+    }
+}
+
+/// Where a Normalizer writes.
+enum Output<'a> {
+    Bytes(Vec<u8>),
+    /// Bytes that were already normalized, which are checked against what's
+    /// written. The position is None once something different was written.
+    Compare {
+        expected: &'a [u8],
+        pos: Option<usize>,
+    },
+}
+
+struct Normalizer<'a>(Output<'a>);
+
+impl Normalizer<'_> {
+    fn write(&mut self, bytes: &[u8]) {
+        match &mut self.0 {
+            Output::Bytes(output) => output.extend_from_slice(bytes),
+            Output::Compare { expected, pos } => {
+                *pos = pos
+                    .filter(|&start| expected[start..].starts_with(bytes))
+                    .map(|start| start + bytes.len());
+            }
+        }
+    }
+
+    fn header(&mut self, tag: u8, len: usize) {
+        self.write(&[tag]);
+        self.write(&(len as u32).to_be_bytes());
+    }
+
+    fn bytes(&mut self, tag: u8, bytes: &[u8]) {
+        self.header(tag, bytes.len());
+        self.write(bytes);
+    }
+}
+
+impl Visitor for Normalizer<'_> {
+    fn integer(&mut self, value: i64) -> Result<()> {
+        self.write(&[INTEGER_EXT]);
+        self.write(&value.to_be_bytes());
+        Ok(())
+    }
+
+    fn big_integer(&mut self, negative: bool, digits: &[u8]) -> Result<()> {
+        let len = digits
+            .iter()
+            .rposition(|&digit| digit != 0)
+            .map_or(0, |last| last + 1);
+        self.bytes(LARGE_BIG_EXT, &digits[..len]);
+        self.write(&[negative.into()]);
+        Ok(())
+    }
+
+    fn float(&mut self, value: f64) -> Result<()> {
+        self.write(&[NEW_FLOAT_EXT]);
+        self.write(&value.to_be_bytes());
+        Ok(())
+    }
+
+    fn atom(&mut self, name: &str) -> Result<()> {
+        self.bytes(ATOM_UTF8_EXT, name.as_bytes());
+        Ok(())
+    }
+
+    fn string(&mut self, bytes: &[u8]) -> Result<()> {
+        self.begin_list(bytes.len())?;
+        for &byte in bytes {
+            self.integer(byte.into())?;
+        }
+        self.end_list(true)
+    }
+
+    fn binary(&mut self, bytes: &[u8]) -> Result<()> {
+        self.bytes(BINARY_EXT, bytes);
+        Ok(())
+    }
+
+    fn bitstring(&mut self, bytes: &[u8], bits: u8) -> Result<()> {
+        self.bytes(BIT_BINARY_EXT, bytes);
+        self.write(&[bits]);
+        Ok(())
+    }
+
+    fn begin_tuple(&mut self, arity: usize) -> Result<()> {
+        self.header(LARGE_TUPLE_EXT, arity);
+        Ok(())
+    }
+
+    /// [] is written as just its tail.
+    fn begin_list(&mut self, len: usize) -> Result<()> {
+        if len > 0 {
+            self.header(LIST_EXT, len);
+        }
+        Ok(())
+    }
+
+    fn end_list(&mut self, proper: bool) -> Result<()> {
+        if proper {
+            self.write(&[NIL_EXT]);
+        }
+        Ok(())
+    }
+
+    fn begin_map(&mut self, pairs: usize) -> Result<()> {
+        self.header(MAP_EXT, pairs);
+        Ok(())
+    }
+
+    fn other(&mut self, _: u8, bytes: &[u8]) -> Result<()> {
+        self.write(bytes);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::{Atom, List};
+
+    // #{name => "joe", tags => [1, {2, ok}], 'E' => 300}
+    const DATA: [u8; 49] = [
+        131, 116, 0, 0, 0, 3, 119, 4, 110, 97, 109, 101, 107, 0, 3, 106, 111, 101, 119, 4, 116, 97,
+        103, 115, 108, 0, 0, 0, 2, 97, 1, 104, 2, 97, 2, 119, 2, 111, 107, 106, 100, 0, 1, 69, 98,
+        0, 0, 1, 44,
+    ];
+
+    #[test]
+    fn navigate() {
+        let index = TermIndex::new(&DATA).unwrap();
+        let root = index.root();
+        assert!(root.is_map());
+        assert_eq!(root.len(), 3);
+        assert_eq!(index.count(), 12);
+
+        let name = root.get_key(&Atom::from("name").into()).unwrap();
+        assert_eq!(name.unpack().unwrap(), AnyTerm::from("joe"));
+
+        let tags = root.get_key(&Atom::from("tags").into()).unwrap();
+        assert!(tags.is_list());
+        assert_eq!(tags.len(), 2);
+        assert!(tags.tail().unwrap().is_nil());
+        assert_eq!(tags.get(0).unwrap().unpack_value::<i32>().unwrap(), 1);
+        assert!(tags.get(2).is_none());
+
+        let tuple = tags.get(1).unwrap();
+        assert!(tuple.is_tuple());
+        assert_eq!(tuple.offset(), 31);
+        assert_eq!(tuple.bytes(), [104, 2, 97, 2, 119, 2, 111, 107]);
+        let elements: Vec<AnyTerm> = tuple.elements().map(|e| e.unpack().unwrap()).collect();
+        assert_eq!(elements, [AnyTerm::SmallInt(2), Atom::from("ok").into()]);
+        assert_eq!(tuple.unpack().unwrap(), AnyTerm::Tuple(elements));
+
+        // The key was packed as ATOM_EXT, and the value as INTEGER_EXT.
+        let legacy = root.get_key(&Atom::from("E").into()).unwrap();
+        assert_eq!(legacy.unpack().unwrap(), AnyTerm::Integer(300));
+        assert!(root.get_key(&Atom::from("missing").into()).is_none());
+    }
+
+    #[test]
+    fn sub_terms() {
+        let index = TermIndex::new(&DATA).unwrap();
+        let keys: Vec<String> = index
+            .root()
+            .entries()
+            .map(|(key, _)| key.unpack().unwrap().to_string())
+            .collect();
+        assert_eq!(keys, ["name", "tags", "'E'"]);
+
+        let name = index.root().entries().next().unwrap().1;
+        assert_eq!(name.to_packed(), [131, 107, 0, 3, 106, 111, 101]);
+        assert_eq!(
+            crate::unpack(name.to_packed()).unwrap(),
+            AnyTerm::from("joe")
+        );
+        assert!(name.get(0).is_none());
+        assert!(name.is_empty());
+    }
+
+    #[test]
+    fn invalid() {
+        let mut data = DATA.to_vec();
+        data.pop();
+        let error = TermIndex::new(&data).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Invalid term: /2/value at byte 45: Data ends 1 bytes too soon"
+        );
+
+        assert!(TermIndex::new(&[131, COMPRESSED, 0, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn container_keys() {
+        // #{{a, 1} => 1, "ab" => 2, <<"ab">> => 3, [a | b] => 4}, as Erlang
+        // packs it.
+        let data = [
+            131, 116, 0, 0, 0, 4, 104, 2, 119, 1, 97, 97, 1, 97, 1, 107, 0, 2, 97, 98, 97, 2, 109,
+            0, 0, 0, 2, 97, 98, 97, 3, 108, 0, 0, 0, 1, 119, 1, 97, 119, 1, 98, 97, 4,
+        ];
+        let index = TermIndex::new(&data).unwrap();
+        let root = index.root();
+        let get = |key: AnyTerm| root.get_key(&key).map(|value| value.unpack().unwrap());
+
+        let legacy = |name: &str| AnyTerm::Atom(Atom::new(crate::AtomKind::Legacy, name.into()));
+        let tuple = AnyTerm::Tuple(vec![legacy("a"), AnyTerm::Integer(1)]);
+        assert_eq!(get(tuple), Some(AnyTerm::SmallInt(1)));
+
+        let chars = vec![AnyTerm::SmallInt(97), AnyTerm::Integer(98)];
+        assert_eq!(
+            get(AnyTerm::List(List::new(chars))),
+            Some(AnyTerm::SmallInt(2))
+        );
+        assert_eq!(get(AnyTerm::from("ab")), Some(AnyTerm::SmallInt(2)));
+        assert_eq!(
+            get(AnyTerm::Binary(b"ab".to_vec())),
+            Some(AnyTerm::SmallInt(3))
+        );
+
+        let improper = List {
+            elements: vec![Atom::from("a").into()],
+            tail: Box::new(legacy("b")),
+        };
+        assert_eq!(get(AnyTerm::List(improper)), Some(AnyTerm::SmallInt(4)));
+        assert_eq!(
+            get(AnyTerm::Tuple(vec![legacy("a"), AnyTerm::Integer(2)])),
+            None
+        );
+        assert_eq!(get(AnyTerm::from("a")), None);
+    }
+
+    #[test]
+    fn large_map() {
+        let pairs = 100_000u32;
+        let mut data = vec![131, 116];
+        data.extend(pairs.to_be_bytes());
+        for i in 0..pairs {
+            data.extend([98]);
+            data.extend(i.to_be_bytes());
+            data.extend([104, 1, 97, (i % 256) as u8]);
+        }
+
+        let index = TermIndex::new(&data).unwrap();
+        let value = index.root().get_key(&AnyTerm::Integer(99_999)).unwrap();
+        assert_eq!(
+            value.get(0).unwrap().unpack().unwrap(),
+            AnyTerm::SmallInt(159)
+        );
+
+        // Only the map is kept, with a mark for every sixteenth key or value.
+        assert_eq!(index.count(), 300_001);
+        assert_eq!(index.layout.containers.len(), 1);
+        assert_eq!(index.layout.marks.len(), 12_499);
+    }
+
+    #[test]
+    fn random_access() {
+        // [0, {1}, 2, {3}, ...] with a tuple of 20 elements at the end.
+        let len = 1000u32;
+        let mut data = vec![131, 108];
+        data.extend((len + 1).to_be_bytes());
+        for i in 0..len {
+            match i % 2 {
+                0 => data.extend([98]),
+                _ => data.extend([104, 1, 98]),
+            }
+            data.extend(i.to_be_bytes());
+        }
+        data.extend([104, 20]);
+        data.extend([97, 7].repeat(20));
+        data.push(106);
+
+        let index = TermIndex::new(&data).unwrap();
+        let root = index.root();
+        assert_eq!(root.len(), 1001);
+        assert_eq!(index.layout.containers.len(), 2);
+        for i in (0..len).rev() {
+            let element = root.get(i as usize).unwrap();
+            let value = match i % 2 {
+                0 => element,
+                _ => element.get(0).unwrap(),
+            };
+            assert_eq!(value.unpack_value::<u32>().unwrap(), i);
+        }
+
+        let tuple = root.get(1000).unwrap();
+        assert_eq!(tuple.len(), 20);
+        assert_eq!(tuple.bytes().len(), 42);
+        assert_eq!(
+            tuple.get(19).unwrap().unpack().unwrap(),
+            AnyTerm::SmallInt(7)
+        );
+        assert!(root.tail().unwrap().is_nil());
+        assert_eq!(root.elements().count(), 1001);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn intern() {
+        let table = AtomTable::new();
+        let index = TermIndex::new(&DATA).unwrap();
+        let tags = index.root().entries().nth(1).unwrap().0;
+
+        let atom = tags.intern(&table).unwrap();
+        assert_eq!(table.lookup("tags"), Some(atom));
+
+        let error = index.root().intern(&table).unwrap_err();
+        assert_eq!(error.to_string(), "Expected an atom, found tag 116");
+    }
+}
@@ -1,49 +1,539 @@
 //! etfpack - a serializer/deserializer for the Erlang Term Format.
 //! Written in Rust, to be embedded into other languages.
-//! Copyright 2022 andre4ik3
-//!
-//! Licensed under the Apache License, Version 2.0 (the "License");
-//! you may not use this file except in compliance with the License.
-//! You may obtain a copy of the License at
-//!
-//!     http://www.apache.org/licenses/LICENSE-2.0
-//!
-//! Unless required by applicable law or agreed to in writing, software
-//! distributed under the License is distributed on an "AS IS" BASIS,
-//! WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
-//! See the License for the specific language governing permissions and
-//! limitations under the License.
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
 
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "proptest")]
+mod arbitrary;
+#[cfg(feature = "std")]
+mod atom_table;
+#[cfg(feature = "std")]
+mod beam;
+#[cfg(any(feature = "tokio", feature = "futures"))]
+mod codec;
+mod convert;
+mod derived;
+#[cfg(feature = "std")]
+mod disk_log;
+mod display;
+mod dist;
+mod elixir;
+#[cfg(feature = "std")]
+mod encoder;
+mod hash;
+mod lazy;
+mod limits;
+#[cfg(feature = "std")]
+mod packet;
 mod packing;
+mod path;
+mod policy;
+mod record;
+#[cfg(feature = "std")]
+mod runtime;
+mod sequence;
 mod structs;
+mod syntax;
 mod terms;
+mod traits;
 mod utils;
+mod validate;
+mod visit;
+
+#[cfg(feature = "proptest")]
+pub use crate::arbitrary::TermConfig;
+#[cfg(feature = "std")]
+pub use crate::atom_table::*;
+#[cfg(feature = "std")]
+pub use crate::beam::*;
+#[cfg(any(feature = "tokio", feature = "futures"))]
+pub use crate::codec::*;
+#[cfg(feature = "std")]
+pub use crate::disk_log::*;
+pub use crate::dist::*;
+pub use crate::elixir::*;
+#[cfg(feature = "std")]
+pub use crate::encoder::*;
+pub use crate::hash::*;
+pub use crate::lazy::*;
+pub use crate::limits::*;
+#[cfg(feature = "std")]
+pub use crate::packet::*;
+pub use crate::path::Key;
+pub use crate::policy::*;
+pub use crate::record::*;
+#[cfg(feature = "std")]
+pub use crate::runtime::*;
+pub use crate::sequence::*;
+pub use crate::structs::*;
+pub use crate::syntax::parse_term;
+pub use crate::terms::AnyTerm;
+pub use crate::traits::*;
+pub use crate::validate::*;
+pub use crate::visit::*;
+
+#[cfg(feature = "derive")]
+pub use etfpack_derive::{FromTerm, IntoTerm};
+
+/// Used by the derive and atoms! macros, not part of the public API.
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::derived::*;
+    pub use alloc::{string::String, vec::Vec};
+    pub use anyhow;
+    #[cfg(feature = "std")]
+    pub use once_cell;
+}
 
 use crate::packing::*;
-use crate::terms::AnyTerm;
 
+use alloc::{vec, vec::Vec};
 use anyhow::*;
-use std::io::{BufReader, BufWriter, Cursor};
-use utils::{read_bytes, write_bytes};
+use utils::{read_bytes, write_bytes, ReadBuf};
 
 /// Packs a term into bytes.
 /// TODO: make the result type concrete with a custom error type
 pub fn pack(data: AnyTerm) -> Result<Vec<u8>> {
-    let mut buf = BufWriter::new(Cursor::new(Vec::<u8>::new()));
+    let mut buf = Vec::new();
     write_bytes(&mut buf, vec![FORMAT_VERSION])?;
     pack_buf(&mut buf, data)?;
-    Ok(buf.into_inner()?.into_inner())
+    Ok(buf)
 }
 
-/// Unpacks some bytes into a term.
+/// Returns the number of bytes pack would produce for the term, without
+/// packing it, like erlang:external_size/1. Fails if pack would fail.
+pub fn encoded_size(data: &AnyTerm) -> Result<usize> {
+    Ok(1 + term_size(data)?)
+}
+
+/// Returns the most bytes compress can produce for packed data of the given
+/// size. The exact size depends on the data, so this is an upper bound,
+/// based on zlib's compressBound.
+pub fn max_compressed_size(encoded_size: usize) -> usize {
+    let term = encoded_size.saturating_sub(1);
+    let zlib = term + (term >> 12) + (term >> 14) + (term >> 25) + 13;
+    // Format version, COMPRESSED and the uncompressed size.
+    2 + 4 + zlib
+}
+
+/// Unpacks some bytes into a term. Bytes after the term are an error, see
+/// unpack_prefix for reading a term from the start of some bytes. Terms
+/// nested deeper than DEFAULT_MAX_DEPTH are rejected.
 /// TODO: make the result type concrete with a custom error type
 pub fn unpack(data: Vec<u8>) -> Result<AnyTerm> {
-    let mut buf = BufReader::new(Cursor::new(data));
-    let version = read_bytes(&mut buf, 1)?[0];
+    UnpackOptions::new().unpack(data)
+}
+
+/// Unpacks the term at the start of some bytes, returning it and the number
+/// of bytes it used, like binary_to_term with the used option.
+pub fn unpack_prefix(data: &[u8]) -> Result<(AnyTerm, usize)> {
+    UnpackOptions::new().unpack_prefix(data)
+}
+
+/// Options for unpacking, for when the defaults of unpack don't fit.
+#[derive(Debug, Clone, Default)]
+pub struct UnpackOptions {
+    lossless: bool,
+    /// Whether strict mode is off, so that it is on by default.
+    lenient: bool,
+    limits: DecodeLimits,
+    policy: Option<SafePolicy>,
+    #[cfg(feature = "std")]
+    atoms: Option<AtomTable>,
+}
+
+impl UnpackOptions {
+    pub fn new() -> Self {
+        UnpackOptions::default()
+    }
+
+    /// In lossless mode, terms remember their exact wire encoding, so that
+    /// packing them again gives back the same bytes. Old text floats are
+    /// unpacked as LegacyFloat instead of Float, and compressed data or
+    /// trailing bytes are errors, since they can't be reproduced. So are
+    /// large tuples and bignums that would fit into the small encoding.
+    pub fn lossless(mut self, lossless: bool) -> Self {
+        self.lossless = lossless;
+        self
+    }
+
+    pub fn is_lossless(&self) -> bool {
+        self.lossless
+    }
+
+    /// In strict mode, which is the default, unpack fails if there are
+    /// bytes after the term instead of ignoring them.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.lenient = !strict;
+        self
+    }
+
+    pub fn is_strict(&self) -> bool {
+        !self.lenient
+    }
+
+    /// Sets limits for untrusted input. Terms that go over them fail with a
+    /// LimitExceeded error before anything is allocated for them. By
+    /// default only the depth is limited.
+    pub fn limits(mut self, limits: DecodeLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn decode_limits(&self) -> &DecodeLimits {
+        &self.limits
+    }
+
+    /// Only accepts terms that the policy allows. Other terms fail with a
+    /// PolicyViolation error that says where the offending term is.
+    pub fn safe(mut self, policy: SafePolicy) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    pub fn safe_policy(&self) -> Option<&SafePolicy> {
+        self.policy.as_ref()
+    }
 
-    if version != FORMAT_VERSION {
-        return Err(anyhow!("Format version mismatch!"));
+    /// Interns every unpacked atom in a table, so that its limit caps how
+    /// many different atoms untrusted input can bring in. Terms with atoms
+    /// that don't fit fail to unpack.
+    #[cfg(feature = "std")]
+    pub fn intern_atoms(mut self, table: AtomTable) -> Self {
+        self.atoms = Some(table);
+        self
     }
 
-    Ok(unpack_buf(&mut buf)?)
+    #[cfg(feature = "std")]
+    pub fn atom_table(&self) -> Option<&AtomTable> {
+        self.atoms.as_ref()
+    }
+
+    /// Unpacks some bytes into a term.
+    pub fn unpack(&self, data: Vec<u8>) -> Result<AnyTerm> {
+        let (term, used) = self.unpack_prefix(&data)?;
+
+        let remaining = data.len() - used;
+        if (self.is_strict() || self.lossless) && remaining > 0 {
+            return Err(anyhow!("{} bytes left after the term", remaining));
+        }
+
+        Ok(term)
+    }
+
+    /// Unpacks the term at the start of some bytes, returning it and the
+    /// number of bytes it used, including the format version.
+    pub fn unpack_prefix(&self, data: &[u8]) -> Result<(AnyTerm, usize)> {
+        match data.get(..2) {
+            Some(&[FORMAT_VERSION, COMPRESSED]) if self.lossless => {
+                Err(anyhow!("Compressed terms can't be unpacked losslessly"))
+            }
+            #[cfg(feature = "std")]
+            Some(&[FORMAT_VERSION, COMPRESSED]) => {
+                let max_size = self.limits.max_decompressed_size;
+                let (term, used) = decompress_term_prefix(&data[2..], max_size)?;
+                self.check(&term, 0)?;
+
+                let mut buf = ReadBuf::new(&term);
+                let unpacked = unpack_buf(&mut buf, self)?;
+
+                if self.is_strict() && buf.remaining() > 0 {
+                    return Err(anyhow!(
+                        "{} bytes left after the compressed term",
+                        buf.remaining()
+                    ));
+                }
+
+                Ok((unpacked, 2 + used))
+            }
+            #[cfg(not(feature = "std"))]
+            Some(&[FORMAT_VERSION, COMPRESSED]) => {
+                Err(anyhow!("Compressed terms need the std feature"))
+            }
+            _ => {
+                let mut buf = ReadBuf::new(data);
+                let version = read_bytes(&mut buf, 1)?[0];
+
+                if version != FORMAT_VERSION {
+                    return Err(anyhow!("Format version mismatch!"));
+                }
+
+                self.check(data, 1)?;
+                let term = unpack_buf(&mut buf, self)?;
+                Ok((term, buf.position()))
+            }
+        }
+    }
+
+    /// Checks the limits and policy before anything is unpacked.
+    fn check(&self, data: &[u8], start: usize) -> Result<()> {
+        if self.limits.only_depth() && self.policy.is_none() {
+            return Ok(());
+        }
+        crate::validate::check_options(data, start, &self.limits, self.policy.as_ref())
+    }
+
+    /// Returns an iterator over the terms in some bytes, one after the
+    /// other.
+    pub fn terms<'a>(&self, data: &'a [u8]) -> Terms<'a> {
+        Terms::new(data, self.clone())
+    }
+}
+
+/// Compresses packed bytes with zlib, like term_to_binary's compressed
+/// option. The level goes from 0 (none) to 9 (best).
+#[cfg(feature = "std")]
+pub fn compress(data: Vec<u8>, level: u32) -> Result<Vec<u8>> {
+    match data.get(..2) {
+        Some(&[FORMAT_VERSION, COMPRESSED]) => Ok(data),
+        Some(&[FORMAT_VERSION, _]) => {
+            let mut result = vec![FORMAT_VERSION, COMPRESSED];
+            result.extend(compress_term(&data[1..], level)?);
+            Ok(result)
+        }
+        _ => Err(anyhow!("Format version mismatch!")),
+    }
+}
+
+/// Decompresses packed bytes. Bytes that aren't compressed are returned as
+/// they are.
+#[cfg(feature = "std")]
+pub fn decompress(data: Vec<u8>) -> Result<Vec<u8>> {
+    match data.get(..2) {
+        Some(&[FORMAT_VERSION, COMPRESSED]) => {
+            let mut result = vec![FORMAT_VERSION];
+            result.extend(decompress_term(&data[2..])?);
+            Ok(result)
+        }
+        Some(&[FORMAT_VERSION, _]) => Ok(data),
+        _ => Err(anyhow!("Format version mismatch!")),
+    }
+}
+
+/// Packs a value into bytes, writing it directly instead of through AnyTerm.
+pub fn pack_value<T: IntoTerm>(data: T) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    write_bytes(&mut buf, vec![FORMAT_VERSION])?;
+    data.write_term(&mut buf)?;
+    Ok(buf)
+}
+
+/// Unpacks some bytes into a value.
+pub fn unpack_value<T: FromTerm>(data: Vec<u8>) -> Result<T> {
+    T::from_term(unpack(data)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let term = AnyTerm::from(Atom::from("hello"));
+        assert_eq!(unpack(pack(term.clone()).unwrap()).unwrap(), term);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn compressed() {
+        let term = AnyTerm::from("a".repeat(100));
+        let packed = pack(term.clone()).unwrap();
+        let compressed = compress(packed.clone(), 9).unwrap();
+
+        assert_eq!(compressed[..2], [FORMAT_VERSION, COMPRESSED]);
+        assert!(compressed.len() < packed.len());
+        assert_eq!(decompress(compressed.clone()).unwrap(), packed);
+        assert_eq!(unpack(compressed).unwrap(), term);
+    }
+
+    #[test]
+    fn sizes() {
+        let node = Atom::from("node@host");
+        let terms = [
+            AnyTerm::SmallInt(1),
+            AnyTerm::Integer(-1),
+            AnyTerm::BigInt(BigInt::from(1i128 << 100)),
+            AnyTerm::Float(1.5),
+            AnyTerm::LegacyFloat(LegacyFloat::from(1.5)),
+            AnyTerm::from(Atom::from("hello")),
+            AnyTerm::from(Atom::from("a".repeat(300))),
+            AnyTerm::from("hello"),
+            AnyTerm::from("héllo wörld"),
+            AnyTerm::Port(Port::new(node.clone(), 1, 2)),
+            AnyTerm::Pid(Pid::new(node.clone(), 1, 2, 3)),
+            AnyTerm::Reference(Reference::new(node, 1, vec![1, 2, 3])),
+            AnyTerm::Nil,
+            AnyTerm::List(List::new(vec![AnyTerm::SmallInt(1), AnyTerm::Nil])),
+            AnyTerm::Tuple(vec![AnyTerm::SmallInt(1), AnyTerm::Tuple(vec![])]),
+            AnyTerm::Map(vec![(AnyTerm::Nil, AnyTerm::Binary(vec![1, 2]))]),
+        ];
+        let options = UnpackOptions::new().lossless(true);
+        for term in terms {
+            let packed = pack(term.clone()).unwrap();
+            assert_eq!(encoded_size(&term).unwrap(), packed.len());
+            assert_eq!(options.unpack(packed).unwrap(), term);
+        }
+
+        assert!(encoded_size(&AnyTerm::String("€".to_string())).is_err());
+        let nested = AnyTerm::Tuple(vec![AnyTerm::String("€".to_string())]);
+        assert!(encoded_size(&nested).is_err());
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let nested = |depth: usize| {
+            let mut data = vec![FORMAT_VERSION];
+            for _ in 0..depth {
+                data.extend([108, 0, 0, 0, 1]);
+            }
+            data.push(106);
+            data.extend(core::iter::repeat(106).take(depth));
+            data
+        };
+
+        // Terms at the default depth can be packed, printed, compared and
+        // dropped like any other.
+        let data = nested(DEFAULT_MAX_DEPTH);
+        let term = unpack(data.clone()).unwrap();
+        assert_eq!(pack(term.clone()).unwrap(), data);
+        assert!(!format!("{}", term).is_empty());
+        drop(term);
+
+        let error = unpack(nested(DEFAULT_MAX_DEPTH + 1)).unwrap_err();
+        let exceeded = error.downcast_ref::<LimitExceeded>().unwrap();
+        assert_eq!(exceeded.limit, Limit::Depth);
+
+        // Without limits, nested lists are unpacked without recursing.
+        let depth = 100_000;
+        let options = UnpackOptions::new().limits(DecodeLimits::unlimited());
+        let mut term = options.unpack(nested(depth)).unwrap();
+        let mut found = 0;
+        while let AnyTerm::List(list) = term {
+            term = list.elements.into_iter().next().unwrap();
+            found += 1;
+        }
+        assert_eq!(found, depth);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn compressed_size() {
+        // Random text barely compresses, and still fits the bound.
+        let mut state = 1u32;
+        let value: String = (0..60000)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                char::from(b'a' + (state >> 16) as u8 % 26)
+            })
+            .collect();
+        let packed = pack(AnyTerm::from(value)).unwrap();
+        for level in [0, 1, 6, 9] {
+            let compressed = compress(packed.clone(), level).unwrap();
+            assert!(compressed.len() <= max_compressed_size(packed.len()));
+        }
+    }
+
+    #[test]
+    fn lossless() {
+        let mut data = vec![FORMAT_VERSION, 99];
+        data.extend(b"1.234");
+        data.resize(33, 0);
+
+        assert_eq!(unpack(data.clone()).unwrap(), AnyTerm::Float(1.234));
+        assert_eq!(pack(unpack(data.clone()).unwrap()).unwrap()[1], 70);
+
+        let options = UnpackOptions::new().lossless(true);
+        let term = options.unpack(data.clone()).unwrap();
+        assert_eq!(term.as_float(), Some(1.234));
+        assert_eq!(pack(term).unwrap(), data);
+
+        // Lossless mode rejects trailing bytes even when strict mode is off.
+        data.push(0);
+        assert!(UnpackOptions::new()
+            .strict(false)
+            .unpack(data.clone())
+            .is_ok());
+        assert!(options.clone().strict(false).unpack(data).is_err());
+
+        #[cfg(feature = "std")]
+        {
+            let compressed = compress(pack(AnyTerm::SmallInt(1)).unwrap(), 6).unwrap();
+            assert!(options.unpack(compressed).is_err());
+        }
+    }
+
+    #[test]
+    fn lossless_every_tag() {
+        let node = [119, 3, b'a', b'@', b'b'];
+        let legacy_node = [100, 0, 3, b'a', b'@', b'b'];
+        let mut terms: Vec<Vec<u8>> = vec![
+            [&[70][..], &1.5f64.to_be_bytes()].concat(),
+            vec![77, 0, 0, 0, 2, 3, 1, 0xE0],
+            [&[88][..], &node, &[0, 0, 0, 80, 0, 0, 0, 1, 0, 0, 0, 2]].concat(),
+            [&[89][..], &node, &[0, 0, 0, 5, 0, 0, 0, 2]].concat(),
+            [
+                &[90, 0, 2][..],
+                &node,
+                &[0, 0, 0, 2, 0, 0, 0, 7, 0, 0, 0, 8],
+            ]
+            .concat(),
+            vec![97, 200],
+            vec![98, 0, 0, 0, 5],
+            [&[99][..], b"1.50000000000000000000e+00", &[0; 5]].concat(),
+            vec![100, 0, 7, b'f', b'o', b'o', b'_', b'b', b'a', b'r'],
+            vec![100, 0, 2, 0xC3, 0xA9],
+            [&[101][..], &legacy_node, &[0, 0, 0, 7, 2]].concat(),
+            [&[102][..], &legacy_node, &[0, 0, 0, 5, 2]].concat(),
+            [&[103][..], &legacy_node, &[0, 0, 0, 80, 0, 0, 0, 1, 2]].concat(),
+            vec![104, 2, 97, 1, 106],
+            [&[105, 0, 0, 1, 0][..], &[106; 256]].concat(),
+            vec![106],
+            [&[107, 0, 11][..], b"hello world"].concat(),
+            vec![107, 0, 2, 0xE9, 0xFF],
+            vec![108, 0, 0, 0, 1, 97, 1, 97, 2],
+            vec![108, 0, 0, 0, 0, 106],
+            vec![109, 0, 0, 0, 2, 0, 255],
+            vec![110, 2, 1, 0, 1],
+            vec![110, 2, 0, 0, 0],
+            [&[111, 0, 0, 1, 0, 0][..], &[1; 256]].concat(),
+            [&[114, 0, 1][..], &legacy_node, &[2, 0, 0, 0, 7]].concat(),
+            vec![115, 3, b'a', 0xE9, b'b'],
+            vec![116, 0, 0, 0, 2, 106, 97, 1, 97, 1, 106],
+            vec![118, 0, 2, 0xC3, 0xA9],
+            vec![119, 2, 0xC3, 0xA9],
+            [&[120][..], &node, &[0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 2]].concat(),
+        ];
+        for term in terms.iter_mut() {
+            term.insert(0, FORMAT_VERSION);
+        }
+
+        let options = UnpackOptions::new().lossless(true);
+        for data in terms {
+            let term = options.unpack(data.clone()).unwrap();
+            assert_eq!(pack(term).unwrap(), data);
+        }
+
+        // Encodings that pack differently can't be unpacked losslessly.
+        let large_tuple = vec![FORMAT_VERSION, 105, 0, 0, 0, 1, 106];
+        let large_big = vec![FORMAT_VERSION, 111, 0, 0, 0, 1, 0, 1];
+        for data in [large_tuple, large_big] {
+            assert!(options.unpack(data.clone()).is_err());
+            assert!(unpack(data).is_ok());
+        }
+    }
 }
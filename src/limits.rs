@@ -0,0 +1,247 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use core::fmt::{self, Display, Formatter};
+
+/// The nesting depth allowed by default. Packing, printing, comparing and
+/// dropping a term all recurse, so much deeper terms can overflow the stack.
+pub const DEFAULT_MAX_DEPTH: usize = 512;
+
+/// Limits on what decoding will accept, for input that can't be trusted.
+/// Every limit is checked before anything is allocated for it. The default
+/// only limits the depth, to DEFAULT_MAX_DEPTH.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeLimits {
+    /// How deeply containers can be nested. 0 allows no containers.
+    pub max_depth: usize,
+    /// Roughly how many bytes decoding can allocate: the size of every
+    /// term, plus the contents of strings, atoms and binaries.
+    pub max_allocation: usize,
+    /// The most elements or bytes in a single tuple, list, map, string or
+    /// binary.
+    pub max_length: usize,
+    /// The most atoms in a term, counting repeats.
+    pub max_atoms: usize,
+    /// The largest size a compressed term can have once decompressed.
+    pub max_decompressed_size: usize,
+}
+
+impl DecodeLimits {
+    /// No limits at all.
+    pub fn unlimited() -> Self {
+        DecodeLimits {
+            max_depth: usize::MAX,
+            max_allocation: usize::MAX,
+            max_length: usize::MAX,
+            max_atoms: usize::MAX,
+            max_decompressed_size: usize::MAX,
+        }
+    }
+
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn with_max_allocation(mut self, max_allocation: usize) -> Self {
+        self.max_allocation = max_allocation;
+        self
+    }
+
+    pub fn with_max_length(mut self, max_length: usize) -> Self {
+        self.max_length = max_length;
+        self
+    }
+
+    pub fn with_max_atoms(mut self, max_atoms: usize) -> Self {
+        self.max_atoms = max_atoms;
+        self
+    }
+
+    pub fn with_max_decompressed_size(mut self, max_decompressed_size: usize) -> Self {
+        self.max_decompressed_size = max_decompressed_size;
+        self
+    }
+
+    pub(crate) fn max(&self, limit: Limit) -> usize {
+        match limit {
+            Limit::Depth => self.max_depth,
+            Limit::Allocation => self.max_allocation,
+            Limit::Length => self.max_length,
+            Limit::Atoms => self.max_atoms,
+            Limit::DecompressedSize => self.max_decompressed_size,
+        }
+    }
+
+    /// Returns an error if the value is over the limit.
+    pub(crate) fn check(&self, limit: Limit, value: usize) -> Result<(), LimitExceeded> {
+        let max = self.max(limit);
+        match value > max {
+            true => Err(LimitExceeded { limit, value, max }),
+            false => Ok(()),
+        }
+    }
+
+    /// Whether only the depth is limited. Unpacking checks the depth
+    /// itself, so nothing else needs to look at the input first.
+    pub(crate) fn only_depth(&self) -> bool {
+        *self == DecodeLimits::unlimited().with_max_depth(self.max_depth)
+    }
+}
+
+impl Default for DecodeLimits {
+    fn default() -> Self {
+        DecodeLimits::unlimited().with_max_depth(DEFAULT_MAX_DEPTH)
+    }
+}
+
+/// One of the limits in DecodeLimits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Depth,
+    Allocation,
+    Length,
+    Atoms,
+    DecompressedSize,
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Limit::Depth => "nesting depth",
+            Limit::Allocation => "allocation",
+            Limit::Length => "length",
+            Limit::Atoms => "atom count",
+            Limit::DecompressedSize => "decompressed size",
+        })
+    }
+}
+
+/// The error when input goes over one of its DecodeLimits. It can be found
+/// in an anyhow error with downcast_ref.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LimitExceeded {
+    pub limit: Limit,
+    /// The value that was too large, or the first one found to be.
+    pub value: usize,
+    pub max: usize,
+}
+
+impl Display for LimitExceeded {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Term goes over the {} limit: {} is more than {}",
+            self.limit, self.value, self.max
+        )
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for LimitExceeded {}
+
+#[cfg(not(feature = "std"))]
+impl core::error::Error for LimitExceeded {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AnyTerm, UnpackOptions};
+
+    fn limited(limits: DecodeLimits, data: &[u8]) -> LimitExceeded {
+        let error = UnpackOptions::new()
+            .limits(limits)
+            .unpack(data.to_vec())
+            .unwrap_err();
+        error.downcast_ref::<LimitExceeded>().unwrap().clone()
+    }
+
+    #[test]
+    fn lengths() {
+        // A list that claims 4 billion elements, in 8 bytes.
+        let data = [131, 108, 255, 255, 255, 255, 97, 1];
+        let exceeded = limited(DecodeLimits::unlimited().with_max_length(1000), &data);
+        assert_eq!(
+            exceeded,
+            LimitExceeded {
+                limit: Limit::Length,
+                value: u32::MAX as usize,
+                max: 1000,
+            }
+        );
+        assert_eq!(
+            exceeded.to_string(),
+            "Term goes over the length limit: 4294967295 is more than 1000"
+        );
+
+        let data = [131, 107, 0, 5, 104, 101, 108, 108, 111];
+        let options = UnpackOptions::new().limits(DecodeLimits::unlimited().with_max_length(5));
+        assert_eq!(
+            options.unpack(data.to_vec()).unwrap(),
+            AnyTerm::from("hello")
+        );
+        let exceeded = limited(DecodeLimits::unlimited().with_max_length(4), &data);
+        assert_eq!(exceeded.limit, Limit::Length);
+    }
+
+    #[test]
+    fn depth_and_atoms() {
+        let mut data = vec![131];
+        for _ in 0..50 {
+            data.extend([104, 1]);
+        }
+        data.push(106);
+        let exceeded = limited(DecodeLimits::unlimited().with_max_depth(10), &data);
+        assert_eq!((exceeded.limit, exceeded.value), (Limit::Depth, 11));
+
+        // {a, b, c}
+        let data = [131, 104, 3, 119, 1, 97, 119, 1, 98, 119, 1, 99];
+        let exceeded = limited(DecodeLimits::unlimited().with_max_atoms(2), &data);
+        assert_eq!((exceeded.limit, exceeded.value), (Limit::Atoms, 3));
+    }
+
+    #[test]
+    fn allocation() {
+        let mut data = vec![131, 109, 0, 1, 0, 0];
+        data.resize(data.len() + 65536, 0);
+        let exceeded = limited(DecodeLimits::unlimited().with_max_allocation(1024), &data);
+        assert_eq!(exceeded.limit, Limit::Allocation);
+        assert_eq!(exceeded.max, 1024);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn decompressed_size() {
+        let packed = crate::pack(AnyTerm::from("a".repeat(10_000))).unwrap();
+        let compressed = crate::compress(packed, 9).unwrap();
+        assert!(compressed.len() < 100);
+
+        let exceeded = limited(
+            DecodeLimits::unlimited().with_max_decompressed_size(1000),
+            &compressed,
+        );
+        assert_eq!(exceeded.value, 10_003);
+
+        let options = UnpackOptions::new().limits(DecodeLimits::unlimited().with_max_length(10));
+        assert!(options.unpack(compressed).is_err());
+    }
+
+    #[test]
+    fn default_depth() {
+        let data = crate::pack(AnyTerm::from("a".repeat(100))).unwrap();
+        assert!(DecodeLimits::default().only_depth());
+        assert_eq!(DecodeLimits::default().max_depth, DEFAULT_MAX_DEPTH);
+        assert!(UnpackOptions::new().unpack(data).is_ok());
+    }
+}
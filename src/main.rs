@@ -0,0 +1,720 @@
+// etfpack - inspect and convert Erlang Term Format data from the shell.
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use etfpack::{compress, decompress, pack, parse_term, unpack, validate, AnyTerm, Atom};
+
+use anyhow::*;
+use std::io::{Read, Write};
+
+const USAGE: &str = "\
+Usage: etfpack <command> [options] [file]
+
+Reads from the file, or from stdin if no file is given.
+
+Commands:
+    decode      Print an encoded term
+    encode      Encode a term
+    compress    Compress an encoded term
+    decompress  Decompress an encoded term
+    validate    Check that the input is a valid encoded term
+
+Options:
+    --from <format>   Input format. For encode: erlang (default) or json.
+                      Otherwise: raw (default), hex or base64.
+    --to <format>     Output format. For decode: erlang (default) or json.
+                      Otherwise: raw (default), hex or base64.
+    --level <0-9>     Compression level for compress (default 6).
+    -h, --help        Print this help.
+";
+
+/// How deeply JSON can be nested, to keep the recursion off the end of the
+/// stack.
+const MAX_JSON_DEPTH: usize = 512;
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// How bytes are written as text.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Bytes {
+    Raw,
+    Hex,
+    Base64,
+}
+
+/// How terms are written as text.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Syntax {
+    Erlang,
+    Json,
+}
+
+struct Options {
+    command: String,
+    from: Option<String>,
+    to: Option<String>,
+    level: u32,
+    file: Option<String>,
+}
+
+fn main() {
+    if let Err(error) = run(std::env::args().skip(1).collect()) {
+        eprintln!("etfpack: {:#}", error);
+        std::process::exit(1);
+    }
+}
+
+fn run(args: Vec<String>) -> Result<()> {
+    let options = match parse_args(args)? {
+        Some(options) => options,
+        None => {
+            print!("{}", USAGE);
+            return Ok(());
+        }
+    };
+
+    let input = read_input(options.file.as_deref())?;
+
+    match options.command.as_str() {
+        "decode" => {
+            let data = bytes_from(&input, bytes_format(options.from.as_deref())?)?;
+            let term = unpack(data)?;
+            let text = match syntax_format(options.to.as_deref())? {
+                Syntax::Erlang => format!("{}.", term),
+                Syntax::Json => to_json(&term)?,
+            };
+            println!("{}", text);
+        }
+        "encode" => {
+            let text = std::str::from_utf8(&input)?;
+            let term = match syntax_format(options.from.as_deref())? {
+                Syntax::Erlang => parse_term(text)?,
+                Syntax::Json => from_json(text)?,
+            };
+            write_output(pack(term)?, bytes_format(options.to.as_deref())?)?;
+        }
+        "compress" | "decompress" => {
+            let data = bytes_from(&input, bytes_format(options.from.as_deref())?)?;
+            let data = match options.command.as_str() {
+                "compress" => compress(data, options.level)?,
+                _ => decompress(data)?,
+            };
+            write_output(data, bytes_format(options.to.as_deref())?)?;
+        }
+        "validate" => {
+            let data = bytes_from(&input, bytes_format(options.from.as_deref())?)?;
+            let report = validate(&data);
+            if !report.is_valid() {
+                return Err(anyhow!("{}", report));
+            }
+            println!("ok");
+        }
+        other => return Err(anyhow!("Unknown command {}, see --help", other)),
+    }
+
+    Ok(())
+}
+
+/// Returns None if help was asked for.
+fn parse_args(args: Vec<String>) -> Result<Option<Options>> {
+    let mut args = args.into_iter();
+    let mut options = Options {
+        command: String::new(),
+        from: None,
+        to: None,
+        level: 6,
+        file: None,
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .ok_or_else(|| anyhow!("Missing value for {}", name))
+        };
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "--from" => options.from = Some(value("--from")?),
+            "--to" => options.to = Some(value("--to")?),
+            "--level" => {
+                options.level = value("--level")?.parse()?;
+                if options.level > 9 {
+                    return Err(anyhow!("Compression level must be between 0 and 9"));
+                }
+            }
+            _ if arg.starts_with("--") => return Err(anyhow!("Unknown option {}", arg)),
+            _ if options.command.is_empty() => options.command = arg,
+            _ if options.file.is_none() => options.file = Some(arg),
+            _ => return Err(anyhow!("Unexpected argument {}", arg)),
+        }
+    }
+
+    if options.command.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(options))
+}
+
+fn bytes_format(name: Option<&str>) -> Result<Bytes> {
+    match name {
+        None | Some("raw") => Ok(Bytes::Raw),
+        Some("hex") => Ok(Bytes::Hex),
+        Some("base64") => Ok(Bytes::Base64),
+        Some(other) => Err(anyhow!(
+            "Unknown format {}, expected raw, hex or base64",
+            other
+        )),
+    }
+}
+
+fn syntax_format(name: Option<&str>) -> Result<Syntax> {
+    match name {
+        None | Some("erlang") => Ok(Syntax::Erlang),
+        Some("json") => Ok(Syntax::Json),
+        Some(other) => Err(anyhow!("Unknown format {}, expected erlang or json", other)),
+    }
+}
+
+fn read_input(file: Option<&str>) -> Result<Vec<u8>> {
+    match file {
+        Some("-") | None => {
+            let mut input = Vec::new();
+            std::io::stdin().read_to_end(&mut input)?;
+            Ok(input)
+        }
+        Some(path) => std::fs::read(path).with_context(|| format!("Failed to read {}", path)),
+    }
+}
+
+fn write_output(data: Vec<u8>, format: Bytes) -> Result<()> {
+    let mut stdout = std::io::stdout();
+    match format {
+        Bytes::Raw => stdout.write_all(&data)?,
+        Bytes::Hex => writeln!(stdout, "{}", to_hex(&data))?,
+        Bytes::Base64 => writeln!(stdout, "{}", to_base64(&data))?,
+    }
+    Ok(stdout.flush()?)
+}
+
+fn bytes_from(input: &[u8], format: Bytes) -> Result<Vec<u8>> {
+    match format {
+        Bytes::Raw => Ok(input.to_vec()),
+        Bytes::Hex => from_hex(std::str::from_utf8(input)?),
+        Bytes::Base64 => from_base64(std::str::from_utf8(input)?),
+    }
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Accepts upper and lower case, and ignores whitespace.
+fn from_hex(text: &str) -> Result<Vec<u8>> {
+    let digits: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+    if digits.len() % 2 != 0 {
+        return Err(anyhow!("Hex input has an odd number of digits"));
+    }
+
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let pair: String = pair.iter().collect();
+            u8::from_str_radix(&pair, 16).map_err(|_| anyhow!("Invalid hex byte {}", pair))
+        })
+        .collect()
+}
+
+fn to_base64(data: &[u8]) -> String {
+    let mut result = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let group = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                let index = (group >> (18 - 6 * i)) & 0x3f;
+                result.push(BASE64[index as usize] as char);
+            } else {
+                result.push('=');
+            }
+        }
+    }
+    result
+}
+
+/// Ignores whitespace, and doesn't require padding.
+fn from_base64(text: &str) -> Result<Vec<u8>> {
+    let mut result = Vec::new();
+    let mut group = 0u32;
+    let mut bits = 0;
+
+    for c in text.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+        let value = match BASE64.iter().position(|&b| b as char == c) {
+            Some(value) => value as u32,
+            None => return Err(anyhow!("Invalid base64 character {:?}", c)),
+        };
+
+        group = (group << 6) | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            result.push((group >> bits) as u8);
+            group &= (1 << bits) - 1;
+        }
+    }
+
+    Ok(result)
+}
+
+/// Follows OTP's json module: the atoms true, false and null are JSON
+/// literals, other atoms and UTF-8 binaries are strings, lists are arrays
+/// and maps are objects.
+fn to_json(term: &AnyTerm) -> Result<String> {
+    let mut result = String::new();
+    write_json(&mut result, term, 0)?;
+    Ok(result)
+}
+
+fn write_json(result: &mut String, term: &AnyTerm, depth: usize) -> Result<()> {
+    if depth > MAX_JSON_DEPTH {
+        return Err(anyhow!("Term is nested too deeply for JSON"));
+    }
+
+    match term {
+        AnyTerm::Nil => result.push_str("[]"),
+        AnyTerm::List(list) if list.is_proper() => {
+            result.push('[');
+            for (i, element) in list.elements.iter().enumerate() {
+                if i != 0 {
+                    result.push(',');
+                }
+                write_json(result, element, depth + 1)?;
+            }
+            result.push(']');
+        }
+        AnyTerm::List(_) => return Err(anyhow!("An improper list can't be written as JSON")),
+        AnyTerm::Map(pairs) => {
+            result.push('{');
+            for (i, (key, value)) in pairs.iter().enumerate() {
+                if i != 0 {
+                    result.push(',');
+                }
+                result.push_str(&json_key(key)?);
+                result.push(':');
+                write_json(result, value, depth + 1)?;
+            }
+            result.push('}');
+        }
+        scalar => result.push_str(&scalar_json(scalar)?),
+    }
+    Ok(())
+}
+
+/// Object keys are strings in JSON, so atoms, integers and binaries are
+/// written as their text.
+fn json_key(key: &AnyTerm) -> Result<String> {
+    match key {
+        AnyTerm::Atom(atom) => Ok(json_string(&atom.value)),
+        AnyTerm::SmallInt(_) | AnyTerm::Integer(_) | AnyTerm::BigInt(_) => {
+            Ok(json_string(&scalar_json(key)?))
+        }
+        AnyTerm::String(_) | AnyTerm::Binary(_) => scalar_json(key),
+        other => Err(anyhow!(
+            "A {} can't be a JSON object key",
+            other.type_name()
+        )),
+    }
+}
+
+fn scalar_json(term: &AnyTerm) -> Result<String> {
+    Ok(match term {
+        AnyTerm::SmallInt(value) => value.to_string(),
+        AnyTerm::Integer(value) => value.to_string(),
+        AnyTerm::Float(value) if value.is_finite() => format!("{:?}", value),
+        AnyTerm::Float(value) => return Err(anyhow!("{} can't be written as JSON", value)),
+        AnyTerm::LegacyFloat(float) => return scalar_json(&AnyTerm::Float(float.value)),
+        AnyTerm::Atom(atom) if ["true", "false", "null"].contains(&atom.value.as_str()) => {
+            atom.value.clone()
+        }
+        AnyTerm::Atom(atom) => json_string(&atom.value),
+        AnyTerm::String(value) => json_string(value),
+        AnyTerm::BigInt(value) => value.to_string(),
+        AnyTerm::Binary(bytes) => match std::str::from_utf8(bytes) {
+            Result::Ok(value) => json_string(value),
+            Err(_) => {
+                return Err(anyhow!(
+                    "A binary that isn't UTF-8 can't be written as JSON"
+                ))
+            }
+        },
+        other => return Err(anyhow!("A {} can't be written as JSON", other.type_name())),
+    })
+}
+
+fn json_string(value: &str) -> String {
+    let mut result = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if c.is_control() => result.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+/// Reads JSON. Arrays become lists and objects maps. Strings, also as
+/// object keys, become UTF-8 binaries, like json:decode/1 in OTP.
+fn from_json(text: &str) -> Result<AnyTerm> {
+    let mut parser = JsonParser { text, pos: 0 };
+    let term = parser.value(0)?;
+    parser.skip_whitespace();
+    if parser.pos != text.len() {
+        return Err(anyhow!("Unexpected text after the JSON value"));
+    }
+    Ok(term)
+}
+
+struct JsonParser<'a> {
+    text: &'a str,
+    /// The byte offset of the next character.
+    pos: usize,
+}
+
+impl<'a> JsonParser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.pos = self.text.len() - self.rest().trim_start().len();
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.rest().starts_with(c);
+        if found {
+            self.pos += c.len_utf8();
+        }
+        found
+    }
+
+    fn value(&mut self, depth: usize) -> Result<AnyTerm> {
+        if depth > MAX_JSON_DEPTH {
+            return Err(anyhow!("JSON is nested too deeply"));
+        }
+
+        self.skip_whitespace();
+        if self.eat('[') {
+            let elements = self.sequence(']', |parser| parser.value(depth + 1))?;
+            return Ok(AnyTerm::from(elements));
+        }
+        if self.eat('{') {
+            let pairs = self.sequence('}', |parser| {
+                parser.skip_whitespace();
+                let key = parser.string()?;
+                parser.skip_whitespace();
+                if !parser.eat(':') {
+                    return Err(anyhow!("Expected : after a JSON object key"));
+                }
+                Ok((key, parser.value(depth + 1)?))
+            })?;
+            return Ok(AnyTerm::Map(pairs));
+        }
+
+        match self.rest().chars().next() {
+            None => Err(anyhow!("Expected a JSON value")),
+            Some('"') => self.string(),
+            Some(_) => self.scalar(),
+        }
+    }
+
+    /// Reads items separated by commas, up to the closing character.
+    fn sequence<T>(
+        &mut self,
+        close: char,
+        mut item: impl FnMut(&mut Self) -> Result<T>,
+    ) -> Result<Vec<T>> {
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.eat(close) {
+            return Ok(items);
+        }
+
+        loop {
+            items.push(item(self)?);
+            self.skip_whitespace();
+            if self.eat(close) {
+                return Ok(items);
+            }
+            if !self.eat(',') {
+                return Err(anyhow!("Expected , or {} in JSON", close));
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<AnyTerm> {
+        let rest = self.rest();
+        if !rest.starts_with('"') {
+            return Err(anyhow!("Expected a JSON string"));
+        }
+
+        let mut escaped = false;
+        let end = rest
+            .char_indices()
+            .skip(1)
+            .find(|&(_, c)| {
+                let end = c == '"' && !escaped;
+                escaped = c == '\\' && !escaped;
+                end
+            })
+            .map(|(i, _)| i + 1)
+            .ok_or_else(|| anyhow!("Unterminated JSON string"))?;
+
+        let value = json_unquote(&rest[..end])?;
+        self.pos += end;
+        Ok(AnyTerm::Binary(value.into_bytes()))
+    }
+
+    fn scalar(&mut self) -> Result<AnyTerm> {
+        let rest = self.rest();
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || "+-.".contains(c)))
+            .unwrap_or(rest.len());
+        let token = &rest[..len];
+        self.pos += len;
+
+        match token {
+            "" => Err(anyhow!(
+                "Unexpected {} in JSON",
+                rest.chars().next().unwrap()
+            )),
+            "true" | "false" | "null" => Ok(AnyTerm::Atom(Atom::from(token))),
+            _ if !is_json_number(token) => Err(anyhow!("Invalid JSON value {}", token)),
+            _ if token.contains(['.', 'e', 'E']) => Ok(AnyTerm::Float(token.parse()?)),
+            _ => Ok(AnyTerm::from(token.parse::<i128>()?)),
+        }
+    }
+}
+
+/// Checks a number against JSON's grammar, which is stricter than Rust's:
+/// no leading `+` or zeros, and digits on both sides of the point.
+fn is_json_number(token: &str) -> bool {
+    let digits =
+        |text: &str| text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+
+    let mut rest = token.strip_prefix('-').unwrap_or(token);
+    match digits(rest) {
+        0 => return false,
+        1 => {}
+        _ if rest.starts_with('0') => return false,
+        _ => {}
+    }
+    rest = &rest[digits(rest)..];
+
+    if let Some(fraction) = rest.strip_prefix('.') {
+        if digits(fraction) == 0 {
+            return false;
+        }
+        rest = &fraction[digits(fraction)..];
+    }
+    if let Some(exponent) = rest.strip_prefix(['e', 'E']) {
+        let exponent = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        if digits(exponent) == 0 {
+            return false;
+        }
+        rest = &exponent[digits(exponent)..];
+    }
+    rest.is_empty()
+}
+
+fn json_unquote(text: &str) -> Result<String> {
+    let inner = text
+        .strip_prefix('"')
+        .and_then(|text| text.strip_suffix('"'))
+        .filter(|_| text.len() >= 2)
+        .ok_or_else(|| anyhow!("Unterminated JSON string"))?;
+
+    let mut result = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some('t') => result.push('\t'),
+            Some('b') => result.push('\u{8}'),
+            Some('f') => result.push('\u{c}'),
+            Some('u') => {
+                let mut code = json_code_unit(&mut chars)?;
+                // Characters outside the BMP are written as surrogate pairs.
+                if (0xd800..0xdc00).contains(&code) {
+                    if chars.next() != Some('\\') || chars.next() != Some('u') {
+                        return Err(anyhow!("Unpaired surrogate in JSON string"));
+                    }
+                    let low = json_code_unit(&mut chars)?;
+                    code = 0x10000 + ((code - 0xd800) << 10) + (low.wrapping_sub(0xdc00) & 0x3ff);
+                }
+                result.push(char::from_u32(code).ok_or_else(|| anyhow!("Invalid JSON escape"))?);
+            }
+            Some(c @ ('"' | '\\' | '/')) => result.push(c),
+            _ => return Err(anyhow!("Invalid JSON escape")),
+        }
+    }
+
+    Ok(result)
+}
+
+fn json_code_unit(chars: &mut std::str::Chars) -> Result<u32> {
+    let digits: String = chars.take(4).collect();
+    u32::from_str_radix(&digits, 16).map_err(|_| anyhow!("Invalid JSON escape \\u{}", digits))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex() {
+        assert_eq!(to_hex(&[131, 97, 1]), "836101");
+        assert_eq!(from_hex("83 61\n0A").unwrap(), [131, 97, 10]);
+        assert!(from_hex("836").is_err());
+        assert!(from_hex("zz").is_err());
+    }
+
+    #[test]
+    fn base64() {
+        for data in [&b""[..], b"f", b"fo", b"foo", b"foob", b"fooba", b"foobar"] {
+            assert_eq!(from_base64(&to_base64(data)).unwrap(), data);
+        }
+        assert_eq!(to_base64(b"foob"), "Zm9vYg==");
+        assert_eq!(from_base64("Zm9v\nYg").unwrap(), b"foob");
+        assert!(from_base64("Zm9v!").is_err());
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(to_json(&AnyTerm::SmallInt(1)).unwrap(), "1");
+        assert_eq!(to_json(&AnyTerm::Float(1.5)).unwrap(), "1.5");
+        assert_eq!(to_json(&AnyTerm::Atom(Atom::from("null"))).unwrap(), "null");
+        assert_eq!(to_json(&AnyTerm::Atom(Atom::from("ok"))).unwrap(), "\"ok\"");
+        assert_eq!(
+            to_json(&AnyTerm::String("a\"b".to_string())).unwrap(),
+            "\"a\\\"b\""
+        );
+
+        assert_eq!(from_json(" 1000 ").unwrap(), AnyTerm::Integer(1000));
+        assert_eq!(from_json("-1.5e1").unwrap(), AnyTerm::Float(-15.0));
+        assert_eq!(
+            from_json("true").unwrap(),
+            AnyTerm::Atom(Atom::from("true"))
+        );
+        assert_eq!(
+            from_json("\"a\\n\\u00e9\\ud83d\\ude00\"").unwrap(),
+            AnyTerm::Binary("a\né😀".as_bytes().to_vec())
+        );
+        assert_eq!(
+            from_json("\"a\\\"b\"").unwrap(),
+            AnyTerm::Binary(b"a\"b".to_vec())
+        );
+        assert_eq!(from_json("\"\"").unwrap(), AnyTerm::Binary(vec![]));
+        assert!(from_json("\"open").is_err());
+        assert!(from_json("1 2").is_err());
+
+        assert_eq!(from_json("-0").unwrap(), AnyTerm::SmallInt(0));
+        assert_eq!(from_json("0.5E+2").unwrap(), AnyTerm::Float(50.0));
+        for text in [
+            "+1", "+1.5", "01", "1.", ".5", "-", "1e", "1.5e+", "inf", "NaN", "1-2",
+        ] {
+            assert!(from_json(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn json_containers() {
+        let text = r#"{"user": {"id": 7, "tags": ["a", true, 1.5]}, "empty": []}"#;
+        let term = from_json(text).unwrap();
+        let binary = |text: &str| AnyTerm::Binary(text.as_bytes().to_vec());
+        assert_eq!(
+            term,
+            AnyTerm::Map(vec![
+                (
+                    binary("user"),
+                    AnyTerm::Map(vec![
+                        (binary("id"), AnyTerm::SmallInt(7)),
+                        (
+                            binary("tags"),
+                            AnyTerm::from(vec![
+                                binary("a"),
+                                AnyTerm::Atom(Atom::from("true")),
+                                AnyTerm::Float(1.5),
+                            ])
+                        ),
+                    ])
+                ),
+                (binary("empty"), AnyTerm::Nil),
+            ])
+        );
+        assert_eq!(
+            to_json(&term).unwrap(),
+            r#"{"user":{"id":7,"tags":["a",true,1.5]},"empty":[]}"#
+        );
+
+        let map = AnyTerm::Map(vec![
+            (
+                AnyTerm::Atom(Atom::from("ok")),
+                AnyTerm::Binary(b"yes".to_vec()),
+            ),
+            (AnyTerm::SmallInt(1), AnyTerm::Nil),
+        ]);
+        assert_eq!(to_json(&map).unwrap(), r#"{"ok":"yes","1":[]}"#);
+
+        assert!(from_json("[1,]").is_err());
+        assert!(from_json("{\"a\" 1}").is_err());
+        assert!(from_json(&"[".repeat(1000)).is_err());
+        assert!(to_json(&AnyTerm::Tuple(vec![])).is_err());
+        assert!(to_json(&AnyTerm::Binary(vec![0xff])).is_err());
+    }
+
+    #[test]
+    fn args() {
+        let args = |args: &[&str]| parse_args(args.iter().map(|a| a.to_string()).collect());
+
+        let options = args(&["decode", "--from", "hex", "x.bin"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(options.command, "decode");
+        assert_eq!(options.from.as_deref(), Some("hex"));
+        assert_eq!(options.file.as_deref(), Some("x.bin"));
+
+        assert!(args(&[]).unwrap().is_none());
+        assert!(args(&["decode", "--help"]).unwrap().is_none());
+        assert!(args(&["decode", "--from"]).is_err());
+        assert!(args(&["compress", "--level", "10"]).is_err());
+        assert!(args(&["decode", "a", "b"]).is_err());
+    }
+}
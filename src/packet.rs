@@ -0,0 +1,292 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::terms::AnyTerm;
+
+use anyhow::*;
+use std::io::{ErrorKind, Read, Write};
+
+/// The default maximum frame size, 64 MiB.
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+
+/// The length header of a packet, as set with `{packet, N}` on the Erlang
+/// side of the port.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketHeader {
+    /// `{packet, 1}`
+    One,
+    /// `{packet, 2}`
+    Two,
+    /// `{packet, 4}`
+    Four,
+}
+
+impl PacketHeader {
+    /// Returns the size of the header in bytes.
+    pub fn size(&self) -> usize {
+        match self {
+            PacketHeader::One => 1,
+            PacketHeader::Two => 2,
+            PacketHeader::Four => 4,
+        }
+    }
+
+    /// Returns the largest frame the header can describe.
+    pub fn max_len(&self) -> usize {
+        match self {
+            PacketHeader::One => u8::MAX.into(),
+            PacketHeader::Two => u16::MAX.into(),
+            PacketHeader::Four => u32::MAX.try_into().unwrap_or(usize::MAX),
+        }
+    }
+
+    fn encode(&self, len: usize) -> Vec<u8> {
+        // The caller checks len against max_len.
+        let bytes = (len as u32).to_be_bytes();
+        bytes[4 - self.size()..].to_vec()
+    }
+
+    fn decode(&self, bytes: &[u8]) -> usize {
+        bytes
+            .iter()
+            .fold(0usize, |len, &byte| (len << 8) | usize::from(byte))
+    }
+
+    /// Returns the header for a frame of the given length, checking it
+    /// against the maximum frame size.
+    pub(crate) fn frame_header(&self, len: usize, max_size: usize) -> Result<Vec<u8>> {
+        let max = max_size.min(self.max_len());
+        if len > max {
+            return Err(too_large(len, max));
+        }
+        Ok(self.encode(len))
+    }
+
+    /// Returns the length of the frame at the start of the buffer, header
+    /// included, or None if the frame isn't complete yet.
+    #[cfg(any(feature = "tokio", feature = "futures"))]
+    pub(crate) fn frame_len(&self, buf: &[u8], max_size: usize) -> Result<Option<usize>> {
+        let header = match buf.get(..self.size()) {
+            Some(header) => header,
+            None => return Ok(None),
+        };
+
+        let len = self.decode(header);
+        if len > max_size {
+            return Err(too_large(len, max_size));
+        }
+
+        let total = self.size() + len;
+        Ok((buf.len() >= total).then_some(total))
+    }
+}
+
+impl TryFrom<u8> for PacketHeader {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        match value {
+            1 => Ok(PacketHeader::One),
+            2 => Ok(PacketHeader::Two),
+            4 => Ok(PacketHeader::Four),
+            _ => Err(anyhow!(
+                "Packet header must be 1, 2 or 4 bytes, not {}",
+                value
+            )),
+        }
+    }
+}
+
+/// Reads length-prefixed packets, like the ones an Erlang port sends.
+pub struct PacketReader<R: Read> {
+    inner: R,
+    header: PacketHeader,
+    max_size: usize,
+}
+
+impl<R: Read> PacketReader<R> {
+    pub fn new(inner: R, header: PacketHeader) -> Self {
+        PacketReader {
+            inner,
+            header,
+            max_size: DEFAULT_MAX_FRAME_SIZE,
+        }
+    }
+
+    /// Sets the largest frame that will be read. Larger frames are an error,
+    /// and nothing is allocated for them.
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Returns the underlying reader.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Reads the next frame. Returns None if the stream ended cleanly, which
+    /// is what happens when the Erlang side closes the port.
+    pub fn read_frame(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut header = vec![0; self.header.size()];
+        if !self.fill(&mut header)? {
+            return Ok(None);
+        }
+
+        let len = self.header.decode(&header);
+        if len > self.max_size {
+            return Err(too_large(len, self.max_size));
+        }
+
+        let mut frame = vec![0; len];
+        if !self.fill(&mut frame)? && len > 0 {
+            return Err(anyhow!("Stream ended in the middle of a frame"));
+        }
+
+        Ok(Some(frame))
+    }
+
+    /// Reads the next frame and unpacks it.
+    pub fn read_term(&mut self) -> Result<Option<AnyTerm>> {
+        match self.read_frame()? {
+            Some(frame) => Ok(Some(crate::unpack(frame)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Fills the buffer. Returns false if the stream ended before anything
+    /// was read, and fails if it ended part way through.
+    fn fill(&mut self, buf: &mut [u8]) -> Result<bool> {
+        let mut read = 0;
+        while read < buf.len() {
+            match self.inner.read(&mut buf[read..]) {
+                Result::Ok(0) if read == 0 => return Ok(false),
+                Result::Ok(0) => return Err(anyhow!("Stream ended in the middle of a frame")),
+                Result::Ok(num) => read += num,
+                Err(error) if error.kind() == ErrorKind::Interrupted => {}
+                Err(error) => return Err(error.into()),
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Writes length-prefixed packets, like the ones an Erlang port expects.
+pub struct PacketWriter<W: Write> {
+    inner: W,
+    header: PacketHeader,
+    max_size: usize,
+}
+
+impl<W: Write> PacketWriter<W> {
+    pub fn new(inner: W, header: PacketHeader) -> Self {
+        PacketWriter {
+            inner,
+            header,
+            max_size: DEFAULT_MAX_FRAME_SIZE,
+        }
+    }
+
+    /// Sets the largest frame that will be written.
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+
+    /// Writes a frame and flushes it, so the Erlang side sees it right away.
+    pub fn write_frame(&mut self, frame: &[u8]) -> Result<()> {
+        let header = self.header.frame_header(frame.len(), self.max_size)?;
+        self.inner.write_all(&header)?;
+        self.inner.write_all(frame)?;
+        self.inner.flush()?;
+        Ok(())
+    }
+
+    /// Packs a term and writes it as a frame.
+    pub fn write_term(&mut self, term: AnyTerm) -> Result<()> {
+        self.write_frame(&crate::pack(term)?)
+    }
+}
+
+fn too_large(len: usize, max: usize) -> Error {
+    anyhow!(
+        "Frame of {} bytes is larger than the maximum of {}",
+        len,
+        max
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::Atom;
+    use std::io::Cursor;
+
+    #[test]
+    fn round_trip() {
+        for header in [PacketHeader::One, PacketHeader::Two, PacketHeader::Four] {
+            let mut writer = PacketWriter::new(Vec::new(), header);
+            writer.write_term(AnyTerm::from(Atom::from("ok"))).unwrap();
+            writer.write_frame(&[]).unwrap();
+            writer.write_term(AnyTerm::SmallInt(1)).unwrap();
+
+            let data = writer.into_inner();
+            assert_eq!(data[..header.size()], header.encode(5)[..]);
+
+            let mut reader = PacketReader::new(Cursor::new(data), header);
+            assert!(reader.read_term().unwrap().unwrap().is_atom("ok"));
+            assert_eq!(reader.read_frame().unwrap(), Some(vec![]));
+            assert_eq!(reader.read_term().unwrap(), Some(AnyTerm::SmallInt(1)));
+            assert_eq!(reader.read_frame().unwrap(), None);
+        }
+    }
+
+    #[test]
+    fn header_sizes() {
+        assert_eq!(PacketHeader::Two.encode(258), [1, 2]);
+        assert_eq!(PacketHeader::Four.encode(258), [0, 0, 1, 2]);
+        assert_eq!(PacketHeader::Four.decode(&[0, 0, 1, 2]), 258);
+        assert_eq!(PacketHeader::try_from(4).unwrap(), PacketHeader::Four);
+        assert!(PacketHeader::try_from(3).is_err());
+    }
+
+    #[test]
+    fn truncated() {
+        let mut reader = PacketReader::new(Cursor::new(vec![0, 0]), PacketHeader::Four);
+        assert!(reader.read_frame().is_err());
+
+        let mut reader = PacketReader::new(Cursor::new(vec![0, 3, 1]), PacketHeader::Two);
+        assert!(reader.read_frame().is_err());
+    }
+
+    #[test]
+    fn max_size() {
+        let data = vec![255, 255, 255, 255];
+        let mut reader = PacketReader::new(Cursor::new(data), PacketHeader::Four).with_max_size(16);
+        let error = reader.read_frame().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Frame of 4294967295 bytes is larger than the maximum of 16"
+        );
+
+        let mut writer = PacketWriter::new(Vec::new(), PacketHeader::One);
+        assert!(writer.write_frame(&[0; 256]).is_err());
+        assert!(writer.into_inner().is_empty());
+    }
+}
@@ -1,43 +1,314 @@
-//! Written in Rust, to be embedded into other languages.
-//! Copyright 2022 andre4ik3
-//!
-//! Licensed under the Apache License, Version 2.0 (the "License");
-//! you may not use this file except in compliance with the License.
-//! You may obtain a copy of the License at
-//!
-//!     http://www.apache.org/licenses/LICENSE-2.0
-//!
-//! Unless required by applicable law or agreed to in writing, software
-//! distributed under the License is distributed on an "AS IS" BASIS,
-//! WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
-//! See the License for the specific language governing permissions and
-//! limitations under the License.
-
-use crate::{terms::*, utils::*};
+// Written in Rust, to be embedded into other languages.
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
 
+#[cfg(feature = "std")]
+use crate::limits::DecodeLimits;
+use crate::limits::Limit;
+use crate::{
+    structs::{Atom, List},
+    terms::*,
+    utils::*,
+    UnpackOptions,
+};
+
+use alloc::{boxed::Box, vec::Vec};
 use anyhow::*;
-use std::io::{BufReader, BufWriter, Cursor};
+#[cfg(feature = "std")]
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+#[cfg(feature = "std")]
+use std::io::{Read, Write};
 
 pub const FORMAT_VERSION: u8 = 131;
+pub const COMPRESSED: u8 = 80;
 
 /// Internal function that operates on a buf writer.
-pub fn pack_buf(_: &mut BufWriter<Cursor<Vec<u8>>>, _: AnyTerm) -> Result<()> {
-    todo!()
+pub fn pack_buf(buf: &mut Vec<u8>, data: AnyTerm) -> Result<()> {
+    if StringPacker::can_pack(&data) {
+        if let AnyTerm::String(value) = data {
+            return StringPacker::pack(value, buf);
+        }
+    }
+
+    match data {
+        AnyTerm::SmallInt(value) => SmallIntPacker::pack(value, buf),
+        AnyTerm::Integer(value) => IntegerPacker::pack(value, buf),
+        AnyTerm::BigInt(value) => BigIntPacker::pack(value, buf),
+        AnyTerm::Float(value) => FloatPacker::pack(value, buf),
+        AnyTerm::LegacyFloat(value) => LegacyFloatPacker::pack(value, buf),
+        AnyTerm::Atom(value) => AtomPacker::pack(value, buf),
+        AnyTerm::String(_) => Err(anyhow!("String is not Latin-1, or is too long")),
+        AnyTerm::Port(value) => PortPacker::pack(value, buf),
+        AnyTerm::Pid(value) => PidPacker::pack(value, buf),
+        AnyTerm::Reference(value) => ReferencePacker::pack(value, buf),
+        AnyTerm::Nil => NilPacker::pack((), buf),
+        AnyTerm::List(value) => ListPacker::pack(value, buf),
+        AnyTerm::Tuple(value) => TuplePacker::pack(value, buf),
+        AnyTerm::Map(value) => MapPacker::pack(value, buf),
+        AnyTerm::Binary(value) => BinaryPacker::pack(value, buf),
+        AnyTerm::BitBinary(value) => BitBinaryPacker::pack(value, buf),
+    }
+}
+
+/// Returns the number of bytes pack_buf would write for the term.
+pub fn term_size(data: &AnyTerm) -> Result<usize> {
+    if StringPacker::can_pack(data) {
+        if let AnyTerm::String(value) = data {
+            return StringPacker::size(value);
+        }
+    }
+
+    match data {
+        AnyTerm::SmallInt(value) => SmallIntPacker::size(value),
+        AnyTerm::Integer(value) => IntegerPacker::size(value),
+        AnyTerm::BigInt(value) => BigIntPacker::size(value),
+        AnyTerm::Float(value) => FloatPacker::size(value),
+        AnyTerm::LegacyFloat(value) => LegacyFloatPacker::size(value),
+        AnyTerm::Atom(value) => AtomPacker::size(value),
+        AnyTerm::String(_) => Err(anyhow!("String is not Latin-1, or is too long")),
+        AnyTerm::Port(value) => PortPacker::size(value),
+        AnyTerm::Pid(value) => PidPacker::size(value),
+        AnyTerm::Reference(value) => ReferencePacker::size(value),
+        AnyTerm::Nil => NilPacker::size(&()),
+        AnyTerm::List(value) => ListPacker::size(value),
+        AnyTerm::Tuple(value) => TuplePacker::size(value),
+        AnyTerm::Map(value) => MapPacker::size(value),
+        AnyTerm::Binary(value) => BinaryPacker::size(value),
+        AnyTerm::BitBinary(value) => BitBinaryPacker::size(value),
+    }
 }
 
 /// Internal function that operates on a buf reader.
-pub fn unpack_buf(buf: &mut BufReader<Cursor<Vec<u8>>>) -> Result<AnyTerm> {
+pub fn unpack_buf(buf: &mut ReadBuf<'_>, options: &UnpackOptions) -> Result<AnyTerm> {
     let fb = read_bytes(buf, 1)?[0];
+    unpack_tagged(buf, fb, options)
+}
+
+/// A container that is being unpacked, with the elements read so far.
+enum Frame {
+    Tuple(Vec<AnyTerm>, usize),
+    List(Vec<AnyTerm>, usize),
+    /// A list whose elements have all been read, waiting for its tail.
+    Tail(Vec<AnyTerm>),
+    Map(Vec<(AnyTerm, AnyTerm)>, Option<AnyTerm>, usize),
+}
+
+/// Unpacks the term whose first byte has already been read. Containers are
+/// kept on a stack instead of recursing, so deeply nested terms can't
+/// overflow the call stack.
+pub fn unpack_tagged(
+    buf: &mut ReadBuf<'_>,
+    mut fb: u8,
+    options: &UnpackOptions,
+) -> Result<AnyTerm> {
+    let mut stack = Vec::new();
+
+    loop {
+        // Every element takes at least one byte, so the remaining bytes cap
+        // how much is worth reserving.
+        let mut term = if TuplePacker::can_unpack(&fb) {
+            let arity = TuplePacker::read_arity(buf, fb, options.is_lossless())?;
+            let elements = Vec::with_capacity(arity.min(buf.remaining()));
+            stack.push(Frame::Tuple(elements, arity));
+            None
+        } else if ListPacker::can_unpack(&fb) {
+            let length = ListPacker::read_length(buf)?;
+            let elements = Vec::with_capacity(length.min(buf.remaining()));
+            stack.push(Frame::List(elements, length));
+            None
+        } else if MapPacker::can_unpack(&fb) {
+            let arity = MapPacker::read_arity(buf)?;
+            let pairs = Vec::with_capacity(arity.min(buf.remaining() / 2));
+            stack.push(Frame::Map(pairs, None, arity));
+            None
+        } else {
+            Some(unpack_scalar(buf, fb, options)?)
+        };
+        if term.is_none() {
+            options
+                .decode_limits()
+                .check(Limit::Depth, stack.len())
+                .map_err(Error::new)?;
+        }
+
+        loop {
+            // Add the finished term to the container it is in.
+            match (stack.last_mut(), term.take()) {
+                (None, Some(term)) => return Ok(term),
+                (Some(Frame::Tuple(elements, _) | Frame::List(elements, _)), Some(element)) => {
+                    elements.push(element)
+                }
+                (Some(Frame::Map(pairs, key, _)), Some(element)) => match key.take() {
+                    Some(key) => pairs.push((key, element)),
+                    None => *key = Some(element),
+                },
+                (Some(Frame::Tail(_)), Some(tail)) => {
+                    if let Some(Frame::Tail(elements)) = stack.pop() {
+                        term = Some(AnyTerm::List(List {
+                            elements,
+                            tail: Box::new(tail),
+                        }));
+                    }
+                    continue;
+                }
+                (_, None) => {}
+            }
+
+            // Then see if that container is done.
+            let frame = stack.last_mut().unwrap();
+            match frame {
+                Frame::Tuple(elements, arity) if elements.len() == *arity => {
+                    term = Some(AnyTerm::Tuple(core::mem::take(elements)));
+                    stack.pop();
+                }
+                Frame::Map(pairs, None, arity) if pairs.len() == *arity => {
+                    term = Some(AnyTerm::Map(core::mem::take(pairs)));
+                    stack.pop();
+                }
+                Frame::List(elements, length) if elements.len() == *length => {
+                    *frame = Frame::Tail(core::mem::take(elements));
+                    break;
+                }
+                _ => break,
+            }
+        }
+
+        fb = read_bytes(buf, 1)?[0];
+    }
+}
 
-    if SmallIntPacker::can_unpack(&fb) {
+fn unpack_scalar(buf: &mut ReadBuf<'_>, fb: u8, options: &UnpackOptions) -> Result<AnyTerm> {
+    if options.is_lossless() && LegacyFloatPacker::can_unpack(&fb) {
+        Ok(AnyTerm::LegacyFloat(LegacyFloatPacker::unpack(buf, fb)?))
+    } else if SmallIntPacker::can_unpack(&fb) {
         Ok(AnyTerm::SmallInt(SmallIntPacker::unpack(buf, fb)?))
     } else if IntegerPacker::can_unpack(&fb) {
         Ok(AnyTerm::Integer(IntegerPacker::unpack(buf, fb)?))
+    } else if BigIntPacker::can_unpack(&fb) {
+        let value = BigIntPacker::unpack(buf, fb)?;
+        if options.is_lossless() && !BigIntPacker::is_canonical(&value, fb) {
+            return Err(anyhow!(
+                "A large bignum of {} digits can't be unpacked losslessly",
+                value.digits.len()
+            ));
+        }
+        Ok(AnyTerm::BigInt(value))
     } else if FloatPacker::can_unpack(&fb) {
         Ok(AnyTerm::Float(FloatPacker::unpack(buf, fb)?))
     } else if AtomPacker::can_unpack(&fb) {
-        Ok(AnyTerm::Atom(AtomPacker::unpack(buf, fb)?))
+        let (kind, value) = AtomPacker::read_name(buf, fb)?;
+        let atom = Atom::new(kind, value.into_owned());
+        #[cfg(feature = "std")]
+        let atom = match options.atom_table() {
+            Some(table) => Atom {
+                interned: Some(table.intern(&atom.value)?),
+                ..atom
+            },
+            None => atom,
+        };
+        Ok(AnyTerm::Atom(atom))
+    } else if StringPacker::can_unpack(&fb) {
+        Ok(AnyTerm::String(StringPacker::unpack(buf, fb)?))
+    } else if NilPacker::can_unpack(&fb) {
+        NilPacker::unpack(buf, fb)?;
+        Ok(AnyTerm::Nil)
+    } else if BinaryPacker::can_unpack(&fb) {
+        Ok(AnyTerm::Binary(BinaryPacker::unpack(buf, fb)?))
+    } else if BitBinaryPacker::can_unpack(&fb) {
+        Ok(AnyTerm::BitBinary(BitBinaryPacker::unpack(buf, fb)?))
+    } else if PidPacker::can_unpack(&fb) {
+        Ok(AnyTerm::Pid(PidPacker::unpack(buf, fb)?))
+    } else if PortPacker::can_unpack(&fb) {
+        Ok(AnyTerm::Port(PortPacker::unpack(buf, fb)?))
+    } else if ReferencePacker::can_unpack(&fb) {
+        Ok(AnyTerm::Reference(ReferencePacker::unpack(buf, fb)?))
     } else {
         Err(anyhow!("Unknown first byte"))
     }
 }
+
+/// Compresses the encoded term that follows the format version, producing
+/// the data that follows COMPRESSED: the uncompressed size, then zlib data.
+#[cfg(feature = "std")]
+pub fn compress_term(term: &[u8], level: u32) -> Result<Vec<u8>> {
+    let size = u32::try_from(term.len())?;
+    let mut encoder = ZlibEncoder::new(size.to_be_bytes().to_vec(), Compression::new(level));
+    encoder.write_all(term)?;
+    Ok(encoder.finish()?)
+}
+
+/// The opposite of compress_term.
+#[cfg(feature = "std")]
+pub fn decompress_term(data: &[u8]) -> Result<Vec<u8>> {
+    Ok(decompress_term_prefix(data, usize::MAX)?.0)
+}
+
+/// Like decompress_term, but also returns how many bytes of data the
+/// compressed term used, since more data can follow it. Terms larger than
+/// max_size fail before they are decompressed.
+#[cfg(feature = "std")]
+pub fn decompress_term_prefix(data: &[u8], max_size: usize) -> Result<(Vec<u8>, usize)> {
+    if data.len() < 4 {
+        return Err(anyhow!("Compressed term is missing its size"));
+    }
+
+    let size = u32::from_be_bytes(data[..4].try_into().unwrap());
+    DecodeLimits::unlimited()
+        .with_max_decompressed_size(max_size)
+        .check(Limit::DecompressedSize, size as usize)?;
+    let mut term = Vec::new();
+    // Reading one byte more than the size makes the decoder read to the end
+    // of the zlib stream, checksum included.
+    let mut decoder = ZlibDecoder::new(&data[4..]);
+    (&mut decoder)
+        .take(u64::from(size) + 1)
+        .read_to_end(&mut term)?;
+
+    if term.len() != size as usize {
+        return Err(anyhow!(
+            "Compressed term should be {} bytes, but is {}",
+            size,
+            term.len()
+        ));
+    }
+
+    Ok((term, 4 + decoder.total_in() as usize))
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compression() {
+        let term = [107, 0, 5, 72, 101, 108, 108, 111];
+        let mut compressed = compress_term(&term, 6).unwrap();
+        assert_eq!(compressed[..4], [0, 0, 0, 8]);
+        assert_eq!(decompress_term(&compressed).unwrap(), term);
+
+        let len = compressed.len();
+        compressed.extend([1, 2, 3]);
+        let (decompressed, used) = decompress_term_prefix(&compressed, usize::MAX).unwrap();
+        assert_eq!(decompressed, term);
+        assert_eq!(used, len);
+    }
+
+    #[test]
+    fn decompression_size_mismatch() {
+        let mut compressed = compress_term(&[97, 1], 6).unwrap();
+        compressed[3] = 3;
+        assert!(decompress_term(&compressed).is_err());
+        assert!(decompress_term(&[0, 0]).is_err());
+    }
+}
@@ -0,0 +1,373 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::terms::{char_term, AnyTerm};
+
+use alloc::{
+    borrow::Cow,
+    string::{String, ToString},
+    vec,
+};
+use anyhow::*;
+use core::fmt::{self, Display, Formatter};
+
+/// One step of a path into a term; see AnyTerm::at.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Key {
+    /// An atom key of a map or proplist, however the atom is packed.
+    Atom(String),
+    /// An integer key of a map or proplist, however the integer is packed.
+    Integer(i128),
+    /// A position in a tuple or list, starting at 0.
+    Index(usize),
+    /// Any other key of a map or proplist, compared by value like `=:=`,
+    /// so integers, atoms and strings match however they are packed.
+    Term(AnyTerm),
+}
+
+impl Key {
+    pub fn atom(name: &str) -> Self {
+        Key::Atom(name.to_string())
+    }
+
+    pub fn integer(value: impl Into<i128>) -> Self {
+        Key::Integer(value.into())
+    }
+
+    pub fn index(n: usize) -> Self {
+        Key::Index(n)
+    }
+
+    pub fn term(term: impl Into<AnyTerm>) -> Self {
+        Key::Term(term.into())
+    }
+
+    /// Returns true if a map or proplist key is this key.
+    fn matches(&self, term: &AnyTerm) -> bool {
+        match self {
+            Key::Atom(name) => term.as_atom_str() == Some(name),
+            Key::Integer(value) => integer(term) == Some(*value),
+            Key::Index(_) => false,
+            Key::Term(key) => key.same_value(term),
+        }
+    }
+}
+
+impl Display for Key {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Key::Atom(name) => f.write_str(name),
+            Key::Integer(value) => write!(f, "{}", value),
+            Key::Index(n) => write!(f, "{}", n),
+            Key::Term(term) => write!(f, "{}", term),
+        }
+    }
+}
+
+fn integer(term: &AnyTerm) -> Option<i128> {
+    match term {
+        AnyTerm::SmallInt(value) => Some((*value).into()),
+        AnyTerm::Integer(value) => Some((*value).into()),
+        AnyTerm::BigInt(value) => value.to_i128(),
+        _ => None,
+    }
+}
+
+impl AnyTerm {
+    /// Looks up a key in a map, or the value of a `{Key, Value}` pair in a
+    /// proplist. Bare atoms in a proplist aren't found, since they have no
+    /// value to borrow.
+    pub fn get(&self, key: &Key) -> Option<&AnyTerm> {
+        match self {
+            AnyTerm::Map(pairs) => pairs.iter().find(|(k, _)| key.matches(k)).map(|(_, v)| v),
+            AnyTerm::List(list) => list.elements.iter().find_map(|element| match element {
+                AnyTerm::Tuple(pair) if pair.len() == 2 && key.matches(&pair[0]) => Some(&pair[1]),
+                _ => None,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Returns the element at a position in a tuple or list, starting at 0.
+    /// The elements of strings packed as STRING_EXT are made on the fly.
+    pub fn index(&self, n: usize) -> Option<Cow<'_, AnyTerm>> {
+        match self {
+            AnyTerm::Tuple(elements) => elements.get(n).map(Cow::Borrowed),
+            AnyTerm::List(list) => list.elements.get(n).map(Cow::Borrowed),
+            AnyTerm::String(value) => value.chars().nth(n).map(|c| Cow::Owned(char_term(c))),
+            _ => None,
+        }
+    }
+
+    /// Follows a path of keys and indexes, like
+    /// `term.at(&[Key::atom("user"), Key::atom("id")])`. Errors say where
+    /// along the path the lookup failed, and what was there.
+    pub fn at(&self, path: &[Key]) -> Result<Cow<'_, AnyTerm>> {
+        let mut term = Cow::Borrowed(self);
+        for (depth, key) in path.iter().enumerate() {
+            let location = Location(&path[..depth]);
+            term = match term {
+                Cow::Borrowed(term) => term.step(key, location)?,
+                Cow::Owned(term) => Cow::Owned(term.step(key, location)?.into_owned()),
+            };
+        }
+        Ok(term)
+    }
+
+    fn step(&self, key: &Key, location: Location<'_>) -> Result<Cow<'_, AnyTerm>> {
+        let lists = matches!(self, AnyTerm::List(_) | AnyTerm::Nil | AnyTerm::String(_));
+        match key {
+            Key::Index(n) if lists || matches!(self, AnyTerm::Tuple(_)) => self
+                .index(*n)
+                .ok_or_else(|| anyhow!("Index {} is out of range at {}", n, location)),
+            Key::Index(_) => Err(anyhow!(
+                "Expected a tuple or list at {}, found {}",
+                location,
+                self.type_name()
+            )),
+            _ if lists || matches!(self, AnyTerm::Map(_)) => self
+                .get(key)
+                .map(Cow::Borrowed)
+                .ok_or_else(|| anyhow!("Key {} not found at {}", key, location)),
+            _ => Err(anyhow!(
+                "Expected a map or proplist at {}, found {}",
+                location,
+                self.type_name()
+            )),
+        }
+    }
+
+    /// Compares terms by value, like `=:=`. Integers and atoms match however
+    /// they are packed, and strings match lists of the same integers.
+    pub(crate) fn same_value(&self, other: &AnyTerm) -> bool {
+        let mut pending = vec![(self, other)];
+        while let Some((a, b)) = pending.pop() {
+            let same = match (a, b) {
+                (AnyTerm::Atom(a), AnyTerm::Atom(b)) => a.value == b.value,
+                (AnyTerm::String(a), AnyTerm::String(b)) => a == b,
+                (AnyTerm::String(value), other) | (other, AnyTerm::String(value)) => {
+                    match other.as_list() {
+                        Some(elements) => value
+                            .chars()
+                            .map(|c| i128::from(u32::from(c)))
+                            .map(Some)
+                            .eq(elements.iter().map(integer)),
+                        None => false,
+                    }
+                }
+                (AnyTerm::List(a), AnyTerm::List(b)) if a.elements.len() == b.elements.len() => {
+                    pending.extend(a.elements.iter().zip(&b.elements));
+                    pending.push((&a.tail, &b.tail));
+                    true
+                }
+                (AnyTerm::List(list), AnyTerm::Nil) | (AnyTerm::Nil, AnyTerm::List(list)) => {
+                    list.elements.is_empty() && list.is_proper()
+                }
+                (AnyTerm::Tuple(a), AnyTerm::Tuple(b)) if a.len() == b.len() => {
+                    pending.extend(a.iter().zip(b));
+                    true
+                }
+                // Maps are equal whatever order their pairs are in.
+                (AnyTerm::Map(a), AnyTerm::Map(b)) => {
+                    a.len() == b.len()
+                        && a.iter().all(|(key, value)| {
+                            b.iter().any(|(other_key, other_value)| {
+                                key.same_value(other_key) && value.same_value(other_value)
+                            })
+                        })
+                }
+                // Identifiers are the same whatever kind they are packed as.
+                (AnyTerm::Pid(a), AnyTerm::Pid(b)) => {
+                    (&a.node.value, a.id, a.serial, a.creation)
+                        == (&b.node.value, b.id, b.serial, b.creation)
+                }
+                (AnyTerm::Port(a), AnyTerm::Port(b)) => {
+                    (&a.node.value, a.id, a.creation) == (&b.node.value, b.id, b.creation)
+                }
+                (AnyTerm::Reference(a), AnyTerm::Reference(b)) => {
+                    (&a.node.value, &a.id, a.creation) == (&b.node.value, &b.id, b.creation)
+                }
+                (AnyTerm::BigInt(a), AnyTerm::BigInt(b)) => {
+                    let significant = |digits: &[u8]| {
+                        let len = digits
+                            .iter()
+                            .rposition(|digit| *digit != 0)
+                            .map_or(0, |i| i + 1);
+                        digits[..len].to_vec()
+                    };
+                    (a.negative == b.negative || significant(&a.digits).is_empty())
+                        && significant(&a.digits) == significant(&b.digits)
+                }
+                _ => match (integer(a), integer(b), a.as_float(), b.as_float()) {
+                    (Some(a), Some(b), _, _) => a == b,
+                    (_, _, Some(a), Some(b)) => a == b,
+                    _ => a == b,
+                },
+            };
+            if !same {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Shows a path like /user/id, or / for the root.
+#[derive(Clone, Copy)]
+struct Location<'p>(&'p [Key]);
+
+impl Display for Location<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.0.is_empty() {
+            return f.write_str("/");
+        }
+        for key in self.0 {
+            write!(f, "/{}", key)?;
+        }
+        Result::Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::{Atom, BigInt};
+    use alloc::vec;
+
+    fn atom(name: &str) -> AnyTerm {
+        AnyTerm::Atom(Atom::from(name))
+    }
+
+    // #{user => #{id => 7, tags => [{role, admin}]}, 1 => {a, b}}
+    fn payload() -> AnyTerm {
+        let tags = AnyTerm::from(vec![AnyTerm::from((atom("role"), atom("admin")))]);
+        let user = AnyTerm::Map(vec![
+            (atom("id"), AnyTerm::SmallInt(7)),
+            (atom("tags"), tags),
+        ]);
+        AnyTerm::Map(vec![
+            (atom("user"), user),
+            (AnyTerm::Integer(1), AnyTerm::from((atom("a"), atom("b")))),
+        ])
+    }
+
+    #[test]
+    fn lookup() {
+        let term = payload();
+        let id = term.at(&[Key::atom("user"), Key::atom("id")]).unwrap();
+        assert_eq!(id.as_integer(), Some(7));
+
+        let role = [Key::atom("user"), Key::atom("tags"), Key::atom("role")];
+        assert!(term.at(&role).unwrap().is_atom("admin"));
+
+        let b = term.at(&[Key::integer(1), Key::index(1)]).unwrap();
+        assert!(b.is_atom("b"));
+        assert_eq!(*term.at(&[]).unwrap(), term);
+
+        // Atoms match however they are packed.
+        let legacy = AnyTerm::Map(vec![(
+            AnyTerm::Atom(Atom::new(
+                crate::structs::AtomKind::Legacy,
+                "user".to_string(),
+            )),
+            AnyTerm::Nil,
+        )]);
+        assert_eq!(legacy.get(&Key::atom("user")), Some(&AnyTerm::Nil));
+    }
+
+    #[test]
+    fn strings() {
+        // "ab" packed as STRING_EXT is the list [97, 98].
+        let term = AnyTerm::Tuple(vec![AnyTerm::String("ab".to_string())]);
+        let b = term.at(&[Key::index(0), Key::index(1)]).unwrap();
+        assert_eq!(*b, AnyTerm::SmallInt(98));
+        assert_eq!(
+            term.at(&[Key::index(0), Key::index(2)])
+                .unwrap_err()
+                .to_string(),
+            "Index 2 is out of range at /0"
+        );
+        assert_eq!(
+            term.at(&[Key::index(0), Key::index(0), Key::index(0)])
+                .unwrap_err()
+                .to_string(),
+            "Expected a tuple or list at /0/0, found integer"
+        );
+    }
+
+    #[test]
+    fn term_keys() {
+        let list = AnyTerm::from(vec![AnyTerm::SmallInt(97), AnyTerm::SmallInt(98)]);
+        let legacy = AnyTerm::Atom(Atom::new(
+            crate::structs::AtomKind::Legacy,
+            "ok".to_string(),
+        ));
+        let map = AnyTerm::Map(vec![
+            (
+                AnyTerm::Tuple(vec![AnyTerm::SmallInt(1), legacy]),
+                atom("tuple"),
+            ),
+            (list, atom("list")),
+            (
+                AnyTerm::Map(vec![(atom("a"), AnyTerm::Nil), (atom("b"), AnyTerm::Nil)]),
+                atom("map"),
+            ),
+        ]);
+
+        let tuple = Key::term(AnyTerm::Tuple(vec![AnyTerm::Integer(1), atom("ok")]));
+        assert!(map.get(&tuple).unwrap().is_atom("tuple"));
+        let string = Key::term(AnyTerm::String("ab".to_string()));
+        assert!(map.get(&string).unwrap().is_atom("list"));
+        let swapped = AnyTerm::Map(vec![(atom("b"), AnyTerm::Nil), (atom("a"), AnyTerm::Nil)]);
+        assert!(map.get(&Key::term(swapped)).unwrap().is_atom("map"));
+        assert_eq!(
+            map.get(&Key::term(AnyTerm::String("abc".to_string()))),
+            None
+        );
+        assert_eq!(map.get(&Key::term(AnyTerm::Float(1.0))), None);
+
+        assert!(AnyTerm::String(String::new()).same_value(&AnyTerm::Nil));
+        assert!(
+            AnyTerm::BigInt(BigInt::from(-(1i128 << 100))).same_value(&AnyTerm::BigInt(BigInt {
+                negative: true,
+                digits: [vec![0; 12], vec![16, 0, 0]].concat(),
+            }))
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let term = payload();
+        for (path, error) in [
+            (
+                vec![Key::atom("user"), Key::atom("name")],
+                "Key name not found at /user",
+            ),
+            (
+                vec![Key::atom("user"), Key::atom("id"), Key::atom("x")],
+                "Expected a map or proplist at /user/id, found integer",
+            ),
+            (
+                vec![Key::atom("user"), Key::index(0)],
+                "Expected a tuple or list at /user, found map",
+            ),
+            (
+                vec![Key::integer(1), Key::index(2)],
+                "Index 2 is out of range at /1",
+            ),
+        ] {
+            assert_eq!(term.at(&path).unwrap_err().to_string(), error);
+        }
+    }
+}
@@ -0,0 +1,225 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#[cfg(feature = "std")]
+use crate::atom_table::AtomTable;
+use crate::validate::Problem;
+
+use alloc::{collections::BTreeSet, string::String};
+use core::fmt::{self, Display, Formatter};
+
+/// What unpack accepts from peers that can't be trusted, like the safe
+/// option of binary_to_term. Funs and exports are always rejected, since
+/// they run code once an Erlang node decodes them. The default allows
+/// everything else.
+#[derive(Debug, Clone)]
+pub struct SafePolicy {
+    identifiers: bool,
+    /// Atoms that are allowed, if only some are.
+    atoms: Option<BTreeSet<String>>,
+    #[cfg(feature = "std")]
+    table: Option<AtomTable>,
+}
+
+impl SafePolicy {
+    pub fn new() -> Self {
+        SafePolicy::default()
+    }
+
+    /// Whether pids, ports and references are allowed. They are by
+    /// default.
+    pub fn allow_identifiers(mut self, allow: bool) -> Self {
+        self.identifiers = allow;
+        self
+    }
+
+    pub fn allows_identifiers(&self) -> bool {
+        self.identifiers
+    }
+
+    /// Adds atoms to the allowlist. Once there is an allowlist or a table,
+    /// other atoms are rejected.
+    pub fn allow_atoms<I, S>(mut self, atoms: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.atoms
+            .get_or_insert_with(BTreeSet::new)
+            .extend(atoms.into_iter().map(Into::into));
+        self
+    }
+
+    /// Also allows the atoms that are already in a table, like
+    /// `AtomTable::global().clone()`. The policy shares the table, so atoms
+    /// interned later are allowed too, but it never adds any.
+    #[cfg(feature = "std")]
+    pub fn existing_atoms(mut self, table: AtomTable) -> Self {
+        self.table = Some(table);
+        self
+    }
+
+    /// Returns true if the policy allows an atom.
+    pub fn allows_atom(&self, name: &str) -> bool {
+        #[cfg(feature = "std")]
+        if let Some(table) = &self.table {
+            if table.lookup(name).is_some() {
+                return true;
+            }
+        }
+
+        match &self.atoms {
+            Some(atoms) => atoms.contains(name),
+            #[cfg(feature = "std")]
+            None => self.table.is_none(),
+            #[cfg(not(feature = "std"))]
+            None => true,
+        }
+    }
+}
+
+impl Default for SafePolicy {
+    fn default() -> Self {
+        SafePolicy {
+            identifiers: true,
+            atoms: None,
+            #[cfg(feature = "std")]
+            table: None,
+        }
+    }
+}
+
+/// The error when a term breaks its SafePolicy, with where the offending
+/// term is. It can be found in an anyhow error with downcast_ref.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyViolation(pub Problem);
+
+impl Display for PolicyViolation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "Term breaks the safe policy: {}", self.0)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for PolicyViolation {}
+
+#[cfg(not(feature = "std"))]
+impl core::error::Error for PolicyViolation {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AnyTerm, Atom, UnpackOptions};
+
+    /// {ok, Term}
+    fn tagged(term: &[u8]) -> Vec<u8> {
+        let mut data = vec![131, 104, 2, 119, 2, b'o', b'k'];
+        data.extend(term);
+        data
+    }
+
+    fn violation(policy: SafePolicy, data: &[u8]) -> PolicyViolation {
+        let error = UnpackOptions::new()
+            .safe(policy)
+            .unpack(data.to_vec())
+            .unwrap_err();
+        error.downcast_ref::<PolicyViolation>().unwrap().clone()
+    }
+
+    #[test]
+    fn executable() {
+        let export = tagged(&[113, 119, 1, b'm', 119, 1, b'f', 97, 1]);
+        let found = violation(SafePolicy::new(), &export);
+        assert_eq!(
+            found.to_string(),
+            "Term breaks the safe policy: /1 at byte 7: Exports are not allowed"
+        );
+
+        // Without a policy, the export gets as far as the decoder.
+        let error = UnpackOptions::new().unpack(export).unwrap_err();
+        assert!(error.downcast_ref::<PolicyViolation>().is_none());
+    }
+
+    #[test]
+    fn identifiers() {
+        let pid = tagged(&[88, 119, 1, b'n', 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1]);
+        let term = UnpackOptions::new()
+            .safe(SafePolicy::new())
+            .unpack(pid.clone())
+            .unwrap();
+        assert!(term.as_tuple().unwrap()[1].as_pid().is_some());
+
+        let found = violation(SafePolicy::new().allow_identifiers(false), &pid);
+        assert_eq!((found.0.path.as_str(), found.0.offset), ("/1", 7));
+        assert_eq!(found.0.message, "Pids are not allowed");
+    }
+
+    #[test]
+    fn atoms() {
+        let policy = SafePolicy::new().allow_atoms(["ok"]);
+        let options = UnpackOptions::new().safe(policy.clone());
+        assert_eq!(
+            options.unpack(vec![131, 119, 2, b'o', b'k']).unwrap(),
+            AnyTerm::from(Atom::from("ok"))
+        );
+        assert!(options.unpack(vec![131, 100, 0, 2, b'o', b'k']).is_ok());
+
+        let found = violation(
+            policy.clone(),
+            &tagged(&[119, 5, b'e', b'r', b'r', b'o', b'r']),
+        );
+        assert_eq!(
+            found.0.to_string(),
+            "/1 at byte 7: Atom 'error' is not allowed"
+        );
+
+        // Latin-1 atoms are compared by their characters.
+        let found = violation(policy.clone(), &[131, 100, 0, 1, 0xE9]);
+        assert_eq!(found.0.message, "Atom 'é' is not allowed");
+
+        // The check reads ATOM_EXT bytes as Latin-1, just like unpacking.
+        let data = [131, 100, 0, 2, 0xC3, 0xA9];
+        let found = violation(policy.clone(), &data);
+        assert_eq!(found.0.message, "Atom 'Ã©' is not allowed");
+        let options = UnpackOptions::new().safe(policy.clone().allow_atoms(["Ã©"]));
+        let atom = options.unpack(data.to_vec()).unwrap();
+        assert_eq!(atom.as_atom_str(), Some("Ã©"));
+
+        // Node names are atoms too.
+        let pid = [131, 88, 119, 1, b'n', 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1];
+        let found = violation(policy, &pid);
+        assert_eq!((found.0.path.as_str(), found.0.offset), ("/", 2));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn existing_atoms() {
+        let table = AtomTable::new();
+        table.intern("known").unwrap();
+        let policy = SafePolicy::new().existing_atoms(table.clone());
+
+        let options = UnpackOptions::new().safe(policy.clone());
+        assert!(options
+            .unpack(crate::pack(Atom::from("known").into()).unwrap())
+            .is_ok());
+
+        let unknown = crate::pack(Atom::from("unknown").into()).unwrap();
+        let found = violation(policy.allow_atoms(["other"]), &unknown);
+        assert_eq!(found.0.message, "Atom 'unknown' is not allowed");
+        assert_eq!(table.len(), 1);
+
+        table.intern("unknown").unwrap();
+        assert!(options.unpack(unknown).is_ok());
+    }
+}
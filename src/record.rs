@@ -0,0 +1,583 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::structs::*;
+use crate::syntax::*;
+use crate::terms::AnyTerm;
+
+use alloc::{
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use anyhow::*;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+const UNDEFINED: &str = "undefined";
+
+/// A field of a record definition.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordField {
+    /// The name of the field.
+    pub name: String,
+    /// The default value, if one was given and it is a literal that can be
+    /// represented as a term.
+    pub default: Option<AnyTerm>,
+    /// The default value as it was written in the source, if one was given.
+    pub default_source: Option<String>,
+}
+
+/// A record definition, as declared with `-record(name, {...}).`
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordSchema {
+    /// The name of the record, which is also the tag atom of its tuples.
+    pub name: String,
+    /// The fields of the record, in declaration order.
+    pub fields: Vec<RecordField>,
+}
+
+/// A record value, with its fields keyed by name.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    /// The name of the record.
+    pub name: String,
+    /// The fields that have been set, in insertion order.
+    pub fields: Vec<(String, AnyTerm)>,
+}
+
+impl Record {
+    /// Creates a record with no fields set.
+    pub fn new(name: impl Into<String>) -> Self {
+        Record {
+            name: name.into(),
+            fields: Vec::new(),
+        }
+    }
+
+    /// Returns the value of a field, if it is set.
+    pub fn get(&self, field: &str) -> Option<&AnyTerm> {
+        self.fields
+            .iter()
+            .find(|(name, _)| name == field)
+            .map(|(_, value)| value)
+    }
+
+    /// Sets the value of a field, replacing the old value if there was one.
+    pub fn set(&mut self, field: impl Into<String>, value: impl Into<AnyTerm>) {
+        let field = field.into();
+        let value = value.into();
+
+        match self.fields.iter_mut().find(|(name, _)| *name == field) {
+            Some((_, old)) => *old = value,
+            None => self.fields.push((field, value)),
+        }
+    }
+}
+
+impl RecordSchema {
+    /// Parses every record definition in the contents of a .hrl (or .erl)
+    /// file. Everything that isn't a record definition is skipped.
+    pub fn from_hrl(source: &str) -> Result<Vec<RecordSchema>> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let mut records = Vec::new();
+
+        while parser.pos < parser.tokens.len() {
+            if parser.at_record() {
+                records.push(parser.record()?);
+            } else {
+                parser.pos += 1;
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Reads and parses a .hrl file.
+    #[cfg(feature = "std")]
+    pub fn load_hrl(path: impl AsRef<Path>) -> Result<Vec<RecordSchema>> {
+        RecordSchema::from_hrl(&std::fs::read_to_string(path)?)
+    }
+
+    /// Returns the arity of the record's tuples, including the tag.
+    pub fn arity(&self) -> usize {
+        self.fields.len() + 1
+    }
+
+    /// Creates a record with every field set to its default value. Fields
+    /// without a default are `undefined`, like in Erlang.
+    pub fn new_record(&self) -> Result<Record> {
+        let mut record = Record::new(self.name.clone());
+        for field in &self.fields {
+            record.set(field.name.clone(), self.default_of(field)?);
+        }
+        Ok(record)
+    }
+
+    /// Reads a record from the elements of its tuple, starting with the tag.
+    pub fn from_elements(&self, elements: Vec<AnyTerm>) -> Result<Record> {
+        if elements.len() != self.arity() {
+            return Err(anyhow!(
+                "Record {} has arity {}, found {} elements",
+                self.name,
+                self.arity(),
+                elements.len()
+            ));
+        }
+
+        let mut elements = elements.into_iter();
+        match elements.next() {
+            Some(AnyTerm::Atom(tag)) if tag.value == self.name => {}
+            Some(AnyTerm::Atom(tag)) => {
+                return Err(anyhow!(
+                    "Expected record {}, found record {}",
+                    self.name,
+                    tag.value
+                ))
+            }
+            Some(other) => {
+                return Err(anyhow!(
+                    "Expected the atom {} as the record tag, found {}",
+                    self.name,
+                    other.type_name()
+                ))
+            }
+            None => unreachable!(),
+        }
+
+        let fields = self
+            .fields
+            .iter()
+            .map(|field| field.name.clone())
+            .zip(elements)
+            .collect();
+
+        Ok(Record {
+            name: self.name.clone(),
+            fields,
+        })
+    }
+
+    /// Writes a record as the elements of its tuple, starting with the tag.
+    /// Fields that aren't set are filled in with their default values.
+    pub fn to_elements(&self, record: Record) -> Result<Vec<AnyTerm>> {
+        if record.name != self.name {
+            return Err(anyhow!(
+                "Expected record {}, found record {}",
+                self.name,
+                record.name
+            ));
+        }
+
+        if let Some((name, _)) = record
+            .fields
+            .iter()
+            .find(|(name, _)| !self.fields.iter().any(|field| field.name == *name))
+        {
+            return Err(anyhow!("Record {} has no field {}", self.name, name));
+        }
+
+        let mut elements = vec![AnyTerm::Atom(Atom::from(self.name.clone()))];
+        for field in &self.fields {
+            match record.get(&field.name) {
+                Some(value) => elements.push(value.clone()),
+                None => elements.push(self.default_of(field)?),
+            }
+        }
+
+        Ok(elements)
+    }
+
+    /// Reads a record from its tuple, checking its tag and arity.
+    pub fn from_term(&self, term: AnyTerm) -> Result<Record> {
+        match term {
+            AnyTerm::Tuple(elements) => self.from_elements(elements),
+            other => Err(anyhow!(
+                "Expected record {} as a tuple, found {}",
+                self.name,
+                other.type_name()
+            )),
+        }
+    }
+
+    /// Writes a record as its tuple. Fields that aren't set are filled in
+    /// with their default values.
+    pub fn to_term(&self, record: Record) -> Result<AnyTerm> {
+        Ok(AnyTerm::Tuple(self.to_elements(record)?))
+    }
+
+    fn default_of(&self, field: &RecordField) -> Result<AnyTerm> {
+        match (&field.default, &field.default_source) {
+            (Some(value), _) => Ok(value.clone()),
+            (None, None) => Ok(AnyTerm::Atom(Atom::from(UNDEFINED))),
+            (None, Some(source)) => Err(anyhow!(
+                "Default value of {}.{} can't be represented: {}",
+                self.name,
+                field.name,
+                source
+            )),
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<Spanned>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|spanned| &spanned.token)
+    }
+
+    fn line(&self) -> usize {
+        match self.tokens.get(self.pos).or_else(|| self.tokens.last()) {
+            Some(spanned) => spanned.line,
+            None => 1,
+        }
+    }
+
+    fn at_record(&self) -> bool {
+        let starts_form = self.pos == 0 || self.tokens[self.pos - 1].token == Token::Punct(".");
+        let next = |offset: usize| self.tokens.get(self.pos + offset).map(|s| &s.token);
+
+        starts_form
+            && next(0) == Some(&Token::Punct("-"))
+            && next(1) == Some(&Token::Atom("record".to_string()))
+            && next(2) == Some(&Token::Punct("("))
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<()> {
+        match self.peek() {
+            Some(Token::Punct(found)) if *found == punct => {
+                self.pos += 1;
+                Ok(())
+            }
+            _ => Err(anyhow!("line {}: expected {}", self.line(), punct)),
+        }
+    }
+
+    fn atom(&mut self) -> Result<String> {
+        match self.peek() {
+            Some(Token::Atom(name)) => {
+                let name = name.clone();
+                self.pos += 1;
+                Ok(name)
+            }
+            _ => Err(anyhow!("line {}: expected an atom", self.line())),
+        }
+    }
+
+    /// Parses `-record(name, {field, field = default, field :: type}).`
+    fn record(&mut self) -> Result<RecordSchema> {
+        self.expect("-")?;
+        self.atom()?;
+        self.expect("(")?;
+        let name = self.atom()?;
+        self.expect(",")?;
+        self.expect("{")?;
+
+        let mut fields = Vec::new();
+        if self.peek() == Some(&Token::Punct("}")) {
+            self.pos += 1;
+        } else {
+            loop {
+                fields.push(self.field()?);
+                match self.peek() {
+                    Some(Token::Punct(",")) => self.pos += 1,
+                    Some(Token::Punct("}")) => {
+                        self.pos += 1;
+                        break;
+                    }
+                    _ => return Err(anyhow!("line {}: expected , or }}", self.line())),
+                }
+            }
+        }
+
+        self.expect(")")?;
+        self.expect(".")?;
+        Ok(RecordSchema { name, fields })
+    }
+
+    fn field(&mut self) -> Result<RecordField> {
+        let name = self.atom()?;
+        let mut field = RecordField {
+            name,
+            default: None,
+            default_source: None,
+        };
+
+        if self.peek() == Some(&Token::Punct("=")) {
+            self.pos += 1;
+            let expression = self.expression(true)?;
+            let source: Vec<&str> = expression.iter().map(|s| s.source.as_str()).collect();
+            field.default = literal(&expression);
+            field.default_source = Some(source.join(" "));
+        }
+
+        if self.peek() == Some(&Token::Punct("::")) {
+            self.pos += 1;
+            self.expression(false)?;
+        }
+
+        Ok(field)
+    }
+
+    /// Collects the tokens up to the next top-level , } or :: of the record.
+    /// Types can contain `fun()` without a matching `end`, so `blocks` is only
+    /// set for value expressions.
+    fn expression(&mut self, blocks: bool) -> Result<Vec<&Spanned>> {
+        let start = self.pos;
+        let mut depth = 0usize;
+
+        loop {
+            let opens_fun = blocks
+                && self.peek() == Some(&Token::Atom("fun".to_string()))
+                && self.tokens.get(self.pos + 1).map(|s| &s.token) == Some(&Token::Punct("("));
+
+            match self.peek() {
+                None => return Err(anyhow!("line {}: unexpected end of input", self.line())),
+                Some(Token::Punct("," | "}" | "::")) if depth == 0 => break,
+                Some(Token::Punct("(" | "[" | "{" | "<<")) => depth += 1,
+                Some(Token::Punct(")" | "]" | "}" | ">>")) => depth = depth.saturating_sub(1),
+                Some(Token::Atom(_)) if opens_fun => depth += 1,
+                Some(Token::Atom(keyword)) if blocks && is_block_start(keyword) => depth += 1,
+                Some(Token::Atom(keyword)) if blocks && keyword == "end" => {
+                    depth = depth.saturating_sub(1)
+                }
+                _ => {}
+            }
+            self.pos += 1;
+        }
+
+        if start == self.pos {
+            return Err(anyhow!("line {}: expected an expression", self.line()));
+        }
+
+        Ok(self.tokens[start..self.pos].iter().collect())
+    }
+}
+
+/// Keywords that open a block closed by `end`. `fun` is handled separately,
+/// since references like `fun foo/1` don't have one.
+fn is_block_start(keyword: &str) -> bool {
+    matches!(
+        keyword,
+        "begin" | "case" | "if" | "receive" | "try" | "maybe"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HRL: &str = r#"
+%% A user record.
+-record(user, {id, name, email = undefined}).
+
+-define(DEFAULT_PORT, 4369).
+-record(node, {
+    host = "localhost" :: string(),
+    port = 16#1111 :: inet:port_number(),
+    weight = -1.5,
+    tags = [] :: [atom()],
+    opts = #{a => 1, b => 2},
+    callback = fun(X) -> X, ok end :: fun(),
+    'Quoted' = 'hello world'
+}).
+-record(empty, {}).
+"#;
+
+    fn schemas() -> Vec<RecordSchema> {
+        RecordSchema::from_hrl(HRL).unwrap()
+    }
+
+    #[test]
+    fn parse() {
+        let schemas = schemas();
+        assert_eq!(schemas.len(), 3);
+
+        let user = &schemas[0];
+        assert_eq!(user.name, "user");
+        assert_eq!(user.arity(), 4);
+        assert_eq!(user.fields[0].name, "id");
+        assert_eq!(user.fields[0].default, None);
+        assert_eq!(
+            user.fields[2].default,
+            Some(AnyTerm::Atom(Atom::from("undefined")))
+        );
+
+        let node = &schemas[1];
+        let defaults: Vec<_> = node.fields.iter().map(|f| f.default.clone()).collect();
+        assert_eq!(
+            defaults,
+            vec![
+                Some(AnyTerm::String("localhost".to_string())),
+                Some(AnyTerm::Integer(4369)),
+                Some(AnyTerm::Float(-1.5)),
+                Some(AnyTerm::Nil),
+                Some(AnyTerm::Map(vec![
+                    (Atom::from("a").into(), AnyTerm::SmallInt(1)),
+                    (Atom::from("b").into(), AnyTerm::SmallInt(2)),
+                ])),
+                None,
+                Some(AnyTerm::Atom(Atom::from("hello world"))),
+            ]
+        );
+        assert_eq!(node.fields[6].name, "Quoted");
+        assert_eq!(
+            node.fields[5].default_source.as_deref(),
+            Some("fun ( X ) -> X , ok end")
+        );
+
+        assert!(schemas[2].fields.is_empty());
+    }
+
+    #[test]
+    fn parse_error() {
+        let error = RecordSchema::from_hrl("-record(user, {id\n name}).").unwrap_err();
+        assert_eq!(error.to_string(), "line 2: expected , or }");
+    }
+
+    #[test]
+    fn from_elements() {
+        let user = &schemas()[0];
+        let record = user
+            .from_elements(vec![
+                AnyTerm::from(Atom::from("user")),
+                AnyTerm::from(42),
+                AnyTerm::from("bob"),
+                AnyTerm::from(Atom::from("undefined")),
+            ])
+            .unwrap();
+
+        assert_eq!(record.get("id"), Some(&AnyTerm::SmallInt(42)));
+        assert_eq!(record.get("name"), Some(&AnyTerm::from("bob")));
+        assert_eq!(record.get("missing"), None);
+    }
+
+    #[test]
+    fn from_elements_invalid() {
+        let user = &schemas()[0];
+
+        let error = user.from_elements(vec![AnyTerm::from(1)]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Record user has arity 4, found 1 elements"
+        );
+
+        let elements = vec![
+            AnyTerm::from(Atom::from("group")),
+            AnyTerm::from(1),
+            AnyTerm::from(2),
+            AnyTerm::from(3),
+        ];
+        let error = user.from_elements(elements).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Expected record user, found record group"
+        );
+    }
+
+    #[test]
+    fn to_elements() {
+        let user = &schemas()[0];
+        let mut record = Record::new("user");
+        record.set("name", "bob");
+
+        assert_eq!(
+            user.to_elements(record).unwrap(),
+            vec![
+                AnyTerm::from(Atom::from("user")),
+                AnyTerm::from(Atom::from("undefined")),
+                AnyTerm::from("bob"),
+                AnyTerm::from(Atom::from("undefined")),
+            ]
+        );
+
+        let mut record = Record::new("user");
+        record.set("age", 30);
+        let error = user.to_elements(record).unwrap_err();
+        assert_eq!(error.to_string(), "Record user has no field age");
+    }
+
+    #[test]
+    fn terms() {
+        let user = &schemas()[0];
+        let term = AnyTerm::Tuple(vec![
+            AnyTerm::from(Atom::from("user")),
+            AnyTerm::from(42),
+            AnyTerm::from("bob"),
+            AnyTerm::from(Atom::from("undefined")),
+        ]);
+        let record = user.from_term(term.clone()).unwrap();
+        assert_eq!(record.get("name"), Some(&AnyTerm::from("bob")));
+        assert_eq!(user.to_term(record).unwrap(), term);
+
+        let error = user
+            .from_term(AnyTerm::from(Atom::from("user")))
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Expected record user as a tuple, found atom"
+        );
+
+        let group = AnyTerm::Tuple(vec![
+            AnyTerm::from(Atom::from("group")),
+            AnyTerm::from(1),
+            AnyTerm::from(2),
+            AnyTerm::from(3),
+        ]);
+        let error = user.from_term(group).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Expected record user, found record group"
+        );
+    }
+
+    #[test]
+    fn container_defaults() {
+        let hrl = "-record(pair, {value = {1, ok}, items = [a | b], meta = #{}}).";
+        let pair = &RecordSchema::from_hrl(hrl).unwrap()[0];
+        let ok = AnyTerm::from(Atom::from("ok"));
+
+        assert_eq!(
+            pair.to_term(Record::new("pair")).unwrap(),
+            AnyTerm::Tuple(vec![
+                AnyTerm::from(Atom::from("pair")),
+                AnyTerm::Tuple(vec![AnyTerm::SmallInt(1), ok]),
+                AnyTerm::List(List {
+                    elements: vec![AnyTerm::from(Atom::from("a"))],
+                    tail: Box::new(AnyTerm::from(Atom::from("b"))),
+                }),
+                AnyTerm::Map(vec![]),
+            ])
+        );
+    }
+
+    #[test]
+    fn unrepresentable_default() {
+        let node = &schemas()[1];
+        let error = node.new_record().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Default value of node.callback can't be represented: fun ( X ) -> X , ok end"
+        );
+
+        let mut record = Record::new("node");
+        record.set("callback", Atom::from("none"));
+        assert_eq!(node.to_elements(record).unwrap().len(), node.arity());
+    }
+}
@@ -0,0 +1,422 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::convert::list_elements;
+use crate::packet::*;
+use crate::structs::Atom;
+use crate::terms::AnyTerm;
+
+use anyhow::*;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+/// A request from the Erlang side, once the protocol has decoded it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    /// Identifies the request in the response, like the Ref of a call.
+    pub id: Option<AnyTerm>,
+    /// The name of the handler to call.
+    pub function: String,
+    /// The arguments for the handler.
+    pub args: Vec<AnyTerm>,
+}
+
+/// The response to a request, before the protocol encodes it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    /// The ID of the request. None if the request couldn't be decoded.
+    pub id: Option<AnyTerm>,
+    /// The handler's result, or the reason it failed.
+    pub result: std::result::Result<AnyTerm, String>,
+}
+
+/// The shape of requests and responses on the wire.
+pub trait Protocol {
+    /// This function should turn a decoded term into a request.
+    fn decode_request(&self, term: AnyTerm) -> Result<Request>;
+
+    /// This function should turn a response into the term to send back.
+    fn encode_response(&self, response: Response) -> Result<AnyTerm>;
+
+    /// This function should find the ID of a request that couldn't be
+    /// decoded, so the error can still be sent back to the caller.
+    fn request_id(&self, _term: &AnyTerm) -> Option<AnyTerm> {
+        None
+    }
+}
+
+/// The `{call, Ref, Fun, Args}` protocol, where Fun is an atom and Args a
+/// list. Results are sent back as `{reply, Ref, Result}` and failures as
+/// `{error, Ref, Reason}`, with the reason as a binary. Ref is `undefined`
+/// if the request wasn't a call at all.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CallProtocol;
+
+impl Protocol for CallProtocol {
+    fn decode_request(&self, term: AnyTerm) -> Result<Request> {
+        let [tag, id, function, args] = call_elements(term)?;
+        if tag.as_atom_str() != Some("call") {
+            return Err(anyhow!("Expected a call, found {}", tag));
+        }
+
+        Ok(Request {
+            id: Some(id),
+            function: Atom::try_from(function)?.value,
+            args: list_elements(args)?,
+        })
+    }
+
+    fn encode_response(&self, response: Response) -> Result<AnyTerm> {
+        let id = response
+            .id
+            .unwrap_or_else(|| Atom::from("undefined").into());
+        Ok(match response.result {
+            Result::Ok(result) => (Atom::from("reply"), id, result).into(),
+            Err(reason) => (Atom::from("error"), id, reason.as_bytes()).into(),
+        })
+    }
+
+    fn request_id(&self, term: &AnyTerm) -> Option<AnyTerm> {
+        match term.as_tuple()? {
+            [tag, id, _, _] if tag.as_atom_str() == Some("call") => Some(id.clone()),
+            _ => None,
+        }
+    }
+}
+
+fn call_elements(term: AnyTerm) -> Result<[AnyTerm; 4]> {
+    match term {
+        AnyTerm::Tuple(elements) if elements.len() == 4 => Ok(elements.try_into().unwrap()),
+        other => Err(anyhow!(
+            "Expected {{call, Ref, Fun, Args}}, found {}",
+            other
+        )),
+    }
+}
+
+type Handler = Box<dyn FnMut(Vec<AnyTerm>) -> Result<AnyTerm>>;
+
+/// Serves requests from Erlang in a port program: reads each frame, decodes
+/// the request, calls the handler registered for it and writes a response.
+pub struct PortRuntime<P: Protocol> {
+    protocol: P,
+    header: PacketHeader,
+    max_size: usize,
+    handlers: HashMap<String, Handler>,
+}
+
+impl<P: Protocol> PortRuntime<P> {
+    pub fn new(protocol: P, header: PacketHeader) -> Self {
+        PortRuntime {
+            protocol,
+            header,
+            max_size: DEFAULT_MAX_FRAME_SIZE,
+            handlers: HashMap::new(),
+        }
+    }
+
+    /// Sets the largest frame that will be read or written.
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Registers the handler for a function name, replacing the old one.
+    pub fn register<F>(&mut self, function: &str, handler: F) -> &mut Self
+    where
+        F: FnMut(Vec<AnyTerm>) -> Result<AnyTerm> + 'static,
+    {
+        self.handlers
+            .insert(function.to_string(), Box::new(handler));
+        self
+    }
+
+    /// Serves requests from stdin until the Erlang side closes the port.
+    pub fn run_stdio(&mut self) -> Result<()> {
+        let stdin = std::io::stdin();
+        let stdout = std::io::stdout();
+        self.run(stdin.lock(), stdout.lock())
+    }
+
+    /// Serves requests until the input ends. Problems with a single request,
+    /// including a panicking handler or a result that can't be sent, are
+    /// sent back as error responses; only broken framing, I/O errors or a
+    /// protocol that can't encode errors stop the loop.
+    pub fn run(&mut self, input: impl Read, output: impl Write) -> Result<()> {
+        let mut reader = PacketReader::new(input, self.header).with_max_size(self.max_size);
+        let mut writer = PacketWriter::new(output, self.header).with_max_size(self.max_size);
+
+        while let Some(frame) = reader.read_frame()? {
+            let response = self.handle(frame);
+            let id = response.id.clone();
+            let frame = match self.encode(response) {
+                Result::Ok(frame) => frame,
+                Err(error) => self.encode(Response {
+                    id,
+                    result: Err(format!("Invalid response: {:#}", error)),
+                })?,
+            };
+            writer.write_frame(&frame)?;
+        }
+
+        Ok(())
+    }
+
+    /// Packs a response into a frame, checking that it isn't too large.
+    fn encode(&self, response: Response) -> Result<Vec<u8>> {
+        let frame = crate::pack(self.protocol.encode_response(response)?)?;
+        self.header.frame_header(frame.len(), self.max_size)?;
+        Ok(frame)
+    }
+
+    fn handle(&mut self, frame: Vec<u8>) -> Response {
+        let term = match crate::unpack(frame) {
+            Result::Ok(term) => term,
+            Err(error) => {
+                return Response {
+                    id: None,
+                    result: Err(format!("Invalid request: {:#}", error)),
+                }
+            }
+        };
+
+        let id = self.protocol.request_id(&term);
+        let request = match self.protocol.decode_request(term) {
+            Result::Ok(request) => request,
+            Err(error) => {
+                return Response {
+                    id,
+                    result: Err(format!("Invalid request: {:#}", error)),
+                }
+            }
+        };
+
+        let handler = match self.handlers.get_mut(&request.function) {
+            Some(handler) => handler,
+            None => {
+                return Response {
+                    id: request.id,
+                    result: Err(format!("Unknown function {}", request.function)),
+                }
+            }
+        };
+
+        // The response is only written once the handler returns, so a panic
+        // can't leave half a frame on the stream.
+        let args = request.args;
+        let result = match catch_unwind(AssertUnwindSafe(|| handler(args))) {
+            Result::Ok(Result::Ok(term)) => Result::Ok(term),
+            Result::Ok(Err(error)) => Err(format!("{:#}", error)),
+            Err(panic) => Err(format!("Handler panicked: {}", panic_message(&panic))),
+        };
+
+        Response {
+            id: request.id,
+            result,
+        }
+    }
+}
+
+fn panic_message(panic: &Box<dyn std::any::Any + Send>) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::Atom;
+    use std::io::Cursor;
+
+    /// Requests are a bare atom naming the function, and the response is
+    /// the result, or an atom with the reason for errors.
+    struct AtomProtocol;
+
+    impl Protocol for AtomProtocol {
+        fn decode_request(&self, term: AnyTerm) -> Result<Request> {
+            let function = Atom::try_from(term)?.value;
+            Ok(Request {
+                id: None,
+                function,
+                args: Vec::new(),
+            })
+        }
+
+        fn encode_response(&self, response: Response) -> Result<AnyTerm> {
+            Ok(match response.result {
+                Result::Ok(term) => term,
+                Err(reason) => atom(&reason),
+            })
+        }
+    }
+
+    fn serve(requests: &[AnyTerm]) -> Vec<AnyTerm> {
+        let mut input = PacketWriter::new(Vec::new(), PacketHeader::Four);
+        for request in requests {
+            input.write_term(request.clone()).unwrap();
+        }
+
+        let mut runtime = PortRuntime::new(AtomProtocol, PacketHeader::Four);
+        let mut count = 0;
+        runtime
+            .register("ping", |_| Ok(Atom::from("pong").into()))
+            .register("count", move |_| {
+                count += 1;
+                Ok(AnyTerm::from(count))
+            })
+            .register("fail", |_| Err(anyhow!("it broke")))
+            .register("crash", |_| panic!("boom"));
+
+        let mut output = Vec::new();
+        runtime
+            .run(Cursor::new(input.into_inner()), &mut output)
+            .unwrap();
+
+        let mut reader = PacketReader::new(Cursor::new(output), PacketHeader::Four);
+        let mut responses = Vec::new();
+        while let Some(term) = reader.read_term().unwrap() {
+            responses.push(term);
+        }
+        responses
+    }
+
+    fn atom(value: &str) -> AnyTerm {
+        AnyTerm::Atom(Atom::from(value))
+    }
+
+    #[test]
+    fn dispatch() {
+        let responses = serve(&[atom("ping"), atom("count"), atom("count")]);
+        assert_eq!(
+            responses,
+            [atom("pong"), AnyTerm::SmallInt(1), AnyTerm::SmallInt(2)]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let responses = serve(&[
+            atom("fail"),
+            atom("missing"),
+            AnyTerm::SmallInt(1),
+            atom("ping"),
+        ]);
+
+        assert_eq!(responses[0], atom("it broke"));
+        assert_eq!(responses[1], atom("Unknown function missing"));
+        assert_eq!(
+            responses[2],
+            atom("Invalid request: Expected atom, found integer")
+        );
+        assert_eq!(responses[3], atom("pong"));
+    }
+
+    #[test]
+    fn panics() {
+        let responses = serve(&[atom("crash"), atom("ping")]);
+        assert_eq!(responses, [atom("Handler panicked: boom"), atom("pong")]);
+    }
+
+    fn call(id: u8, function: &str, args: Vec<AnyTerm>) -> AnyTerm {
+        (atom("call"), id, atom(function), args).into()
+    }
+
+    fn serve_calls(requests: &[AnyTerm]) -> Vec<AnyTerm> {
+        let mut input = PacketWriter::new(Vec::new(), PacketHeader::Two);
+        for request in requests {
+            input.write_term(request.clone()).unwrap();
+        }
+
+        let mut runtime = PortRuntime::new(CallProtocol, PacketHeader::Two).with_max_size(100);
+        runtime
+            .register("add", |args| {
+                let [a, b]: [i32; 2] = Vec::<i32>::try_from(AnyTerm::from(args))?
+                    .try_into()
+                    .map_err(|_| anyhow!("Expected 2 arguments"))?;
+                Ok(AnyTerm::from(a + b))
+            })
+            .register("big", |_| Ok(AnyTerm::Binary(vec![0; 200])))
+            .register("euro", |_| Ok(AnyTerm::String("€".to_string())));
+
+        let mut output = Vec::new();
+        runtime
+            .run(Cursor::new(input.into_inner()), &mut output)
+            .unwrap();
+
+        let mut reader = PacketReader::new(Cursor::new(output), PacketHeader::Two);
+        let mut responses = Vec::new();
+        while let Some(term) = reader.read_term().unwrap() {
+            responses.push(term);
+        }
+        responses
+    }
+
+    fn error(id: AnyTerm, reason: &str) -> AnyTerm {
+        (atom("error"), id, reason.as_bytes()).into()
+    }
+
+    #[test]
+    fn calls() {
+        let args = vec![AnyTerm::from(1), AnyTerm::from(2)];
+        let responses = serve_calls(&[
+            call(1, "add", args),
+            call(2, "add", Vec::new()),
+            (atom("call"), 3, 4, AnyTerm::Nil).into(),
+            atom("ping"),
+        ]);
+
+        assert_eq!(
+            responses,
+            [
+                (atom("reply"), 1, 3).into(),
+                error(2.into(), "Expected 2 arguments"),
+                error(3.into(), "Invalid request: Expected atom, found integer"),
+                error(
+                    atom("undefined"),
+                    "Invalid request: Expected {call, Ref, Fun, Args}, found ping"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn unsendable_results() {
+        let responses = serve_calls(&[
+            call(1, "big", Vec::new()),
+            call(2, "euro", Vec::new()),
+            call(3, "add", vec![AnyTerm::from(2), AnyTerm::from(2)]),
+        ]);
+
+        assert_eq!(
+            responses,
+            [
+                error(
+                    1.into(),
+                    "Invalid response: Frame of 217 bytes is larger than the maximum of 100"
+                ),
+                error(
+                    2.into(),
+                    "Invalid response: String is not Latin-1, or is too long"
+                ),
+                (atom("reply"), 3, 4).into(),
+            ]
+        );
+    }
+}
@@ -0,0 +1,381 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+#[cfg(feature = "std")]
+use crate::limits::Limit;
+use crate::terms::AnyTerm;
+#[cfg(feature = "std")]
+use crate::validate::TermScan;
+use crate::UnpackOptions;
+
+use alloc::format;
+use anyhow::*;
+#[cfg(feature = "std")]
+use flate2::{Decompress, FlushDecompress, Status};
+#[cfg(feature = "std")]
+use std::io::{ErrorKind, Read};
+
+/// An iterator over terms packed one after the other in some bytes. It
+/// stops after the first error, since the next term can't be found.
+pub struct Terms<'a> {
+    data: &'a [u8],
+    options: UnpackOptions,
+    pos: usize,
+}
+
+impl<'a> Terms<'a> {
+    pub(crate) fn new(data: &'a [u8], options: UnpackOptions) -> Self {
+        Terms {
+            data,
+            options,
+            pos: 0,
+        }
+    }
+
+    /// Returns the number of bytes used by the terms so far.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+}
+
+impl<'a> Iterator for Terms<'a> {
+    type Item = Result<AnyTerm>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos == self.data.len() {
+            return None;
+        }
+
+        match self.options.unpack_prefix(&self.data[self.pos..]) {
+            Result::Ok((term, used)) => {
+                self.pos += used;
+                Some(Ok(term))
+            }
+            Err(error) => {
+                let error = error.context(format!("Failed to unpack term at byte {}", self.pos));
+                self.pos = self.data.len();
+                Some(Err(error))
+            }
+        }
+    }
+}
+
+/// Returns an iterator over terms packed one after the other in some bytes.
+pub fn terms(data: &[u8]) -> Terms<'_> {
+    UnpackOptions::new().terms(data)
+}
+
+/// How far a TermReader got looking for the end of a term.
+#[cfg(feature = "std")]
+enum Scan {
+    Plain(TermScan),
+    /// Compressed terms are inflated as they arrive, and the output is
+    /// thrown away until the end is found.
+    Compressed {
+        size: usize,
+        inflater: Decompress,
+    },
+}
+
+/// Reads terms packed one after the other from a reader, like a file
+/// written with repeated term_to_binary calls.
+#[cfg(feature = "std")]
+pub struct TermReader<R: Read> {
+    inner: R,
+    options: UnpackOptions,
+    max_size: usize,
+    buf: Vec<u8>,
+    /// Bytes at the start of buf that were already unpacked.
+    used: usize,
+    /// How far the search for the end of the next term got.
+    scan: Option<Scan>,
+    eof: bool,
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> TermReader<R> {
+    pub fn new(inner: R) -> Self {
+        TermReader {
+            inner,
+            options: UnpackOptions::new(),
+            max_size: crate::DEFAULT_MAX_FRAME_SIZE,
+            buf: Vec::new(),
+            used: 0,
+            scan: None,
+            eof: false,
+        }
+    }
+
+    /// Sets the options used to unpack each term.
+    pub fn with_options(mut self, options: UnpackOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Sets the largest term that will be read, in bytes. Larger terms are
+    /// an error, rather than being buffered until they end.
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Reads the next term. Returns None if the reader ended between terms.
+    pub fn read_term(&mut self) -> Result<Option<AnyTerm>> {
+        loop {
+            if self.used == self.buf.len() && self.eof {
+                return Ok(None);
+            }
+
+            let end = self.term_end()?;
+            let pending = &self.buf[self.used..];
+            if end.unwrap_or(pending.len()) > self.max_size {
+                return Err(anyhow!(
+                    "Term is larger than the maximum of {} bytes",
+                    self.max_size
+                ));
+            }
+
+            if let Some(end) = end {
+                let term = self.options.unpack_prefix(&pending[..end])?.0;
+                self.used += end;
+                self.scan = None;
+                return Ok(Some(term));
+            }
+
+            if self.eof {
+                return Err(anyhow!("Reader ended in the middle of a term"));
+            }
+            self.fill()?;
+        }
+    }
+
+    /// Returns where the first pending term ends, or None if more data is
+    /// needed to tell. Carries on from where the last call stopped.
+    fn term_end(&mut self) -> Result<Option<usize>> {
+        let data = &self.buf[self.used..];
+        let scan = match (&mut self.scan, data.get(..2)) {
+            (Some(scan), _) => scan,
+            (None, None) => return Ok(None),
+            (None, Some(&[crate::packing::FORMAT_VERSION, crate::packing::COMPRESSED])) => {
+                let Some(size) = data.get(2..6) else {
+                    return Ok(None);
+                };
+                // The compressed size isn't written down, so the only way to
+                // find the end is to decompress it.
+                let size = u32::from_be_bytes(size.try_into().unwrap()) as usize;
+                self.options
+                    .decode_limits()
+                    .check(Limit::DecompressedSize, size)?;
+                self.scan.insert(Scan::Compressed {
+                    size,
+                    inflater: Decompress::new(true),
+                })
+            }
+            (None, Some(&[crate::packing::FORMAT_VERSION, _])) => {
+                self.scan.insert(Scan::Plain(TermScan::new(1)))
+            }
+            (None, Some(_)) => return Err(anyhow!("Format version mismatch!")),
+        };
+
+        match scan {
+            Scan::Plain(scan) => scan.scan(data),
+            Scan::Compressed { size, inflater } => {
+                let mut out = [0; 8192];
+                loop {
+                    let input = &data[6 + inflater.total_in() as usize..];
+                    let before = (inflater.total_in(), inflater.total_out());
+                    let status = inflater.decompress(input, &mut out, FlushDecompress::None)?;
+
+                    if inflater.total_out() > *size as u64 {
+                        return Err(anyhow!(
+                            "Compressed term should be {} bytes, but is longer",
+                            size
+                        ));
+                    }
+                    if status == Status::StreamEnd {
+                        return Ok(Some(6 + inflater.total_in() as usize));
+                    }
+                    if before == (inflater.total_in(), inflater.total_out()) {
+                        return Ok(None);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reads more data, dropping what was already unpacked.
+    fn fill(&mut self) -> Result<()> {
+        self.buf.drain(..self.used);
+        self.used = 0;
+
+        let mut chunk = [0; 8192];
+        loop {
+            match self.inner.read(&mut chunk) {
+                Result::Ok(0) => self.eof = true,
+                Result::Ok(num) => self.buf.extend_from_slice(&chunk[..num]),
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error.into()),
+            }
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: Read> Iterator for TermReader<R> {
+    type Item = Result<AnyTerm>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_term().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::Atom;
+
+    fn concatenated() -> Vec<u8> {
+        let mut data = crate::pack(AnyTerm::SmallInt(1)).unwrap();
+        data.extend(crate::pack(Atom::from("ok").into()).unwrap());
+        data.extend(crate::pack(AnyTerm::from("a".repeat(200))).unwrap());
+        data
+    }
+
+    #[test]
+    fn prefix() {
+        let data = concatenated();
+        assert_eq!(
+            crate::unpack_prefix(&data).unwrap(),
+            (AnyTerm::SmallInt(1), 3)
+        );
+        let error = crate::unpack(data.clone()).unwrap_err();
+        assert_eq!(error.to_string(), "209 bytes left after the term");
+        assert!(crate::unpack(vec![131, 97, 1]).is_ok());
+
+        let lenient = UnpackOptions::new().strict(false);
+        assert_eq!(lenient.unpack(data).unwrap(), AnyTerm::SmallInt(1));
+    }
+
+    #[test]
+    fn iterate() {
+        let data = concatenated();
+        let mut iter = terms(&data);
+        assert_eq!(iter.next().unwrap().unwrap(), AnyTerm::SmallInt(1));
+        assert!(iter.next().unwrap().unwrap().is_atom("ok"));
+        assert_eq!(iter.next().unwrap().unwrap().as_str().unwrap().len(), 200);
+        assert!(iter.next().is_none());
+        assert_eq!(iter.position(), data.len());
+
+        let mut data = data;
+        data.extend([131, 42]);
+        let results: Vec<Result<AnyTerm>> = terms(&data).collect();
+        assert_eq!(results.len(), 4);
+        assert_eq!(
+            results[3].as_ref().unwrap_err().to_string(),
+            "Failed to unpack term at byte 212"
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn compressed() {
+        let mut data =
+            crate::compress(crate::pack(AnyTerm::from("b".repeat(100))).unwrap(), 9).unwrap();
+        let len = data.len();
+        data.extend([131, 97, 7]);
+
+        let (term, used) = crate::unpack_prefix(&data).unwrap();
+        assert_eq!(term.as_str().unwrap().len(), 100);
+        assert_eq!(used, len);
+        assert_eq!(terms(&data).count(), 2);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn reader() {
+        /// Returns a few bytes at a time, like a slow socket.
+        struct Trickle(std::io::Cursor<Vec<u8>>);
+
+        impl Read for Trickle {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let len = buf.len().min(3);
+                self.0.read(&mut buf[..len])
+            }
+        }
+
+        let mut data = concatenated();
+        data.extend(
+            crate::compress(crate::pack(AnyTerm::from("c".repeat(50))).unwrap(), 6).unwrap(),
+        );
+
+        let reader = TermReader::new(Trickle(std::io::Cursor::new(data.clone())));
+        let terms: Vec<AnyTerm> = reader.map(Result::unwrap).collect();
+        assert_eq!(terms.len(), 4);
+        assert_eq!(terms[3].as_str().unwrap().len(), 50);
+
+        data.pop();
+        let mut reader = TermReader::new(data.as_slice());
+        for _ in 0..3 {
+            assert!(reader.read_term().unwrap().is_some());
+        }
+        assert!(reader.read_term().is_err());
+
+        let data = concatenated();
+        let mut reader = TermReader::new(data.as_slice()).with_max_size(16);
+        assert!(reader.read_term().unwrap().is_some());
+        assert!(reader.read_term().unwrap().is_some());
+        assert!(reader.read_term().is_err());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn large_terms() {
+        /// Returns at most a kilobyte at a time, then fails, so that reading
+        /// past the last term is an error.
+        struct Chunks<'a>(&'a [u8]);
+
+        impl Read for Chunks<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                if self.0.is_empty() {
+                    return Err(ErrorKind::ConnectionReset.into());
+                }
+                let len = buf.len().min(1024).min(self.0.len());
+                buf[..len].copy_from_slice(&self.0[..len]);
+                self.0 = &self.0[len..];
+                Result::Ok(len)
+            }
+        }
+
+        let list = AnyTerm::List(crate::structs::List {
+            elements: (0..500_000).map(AnyTerm::Integer).collect(),
+            tail: Box::new(AnyTerm::Nil),
+        });
+        let list = crate::pack(list).unwrap();
+        let binary = crate::pack(AnyTerm::Binary(vec![7; 4 << 20])).unwrap();
+        let compressed = crate::compress(list.clone(), 6).unwrap();
+
+        let data = [list.as_slice(), &binary, &compressed].concat();
+        let mut reader = TermReader::new(Chunks(&data));
+        let list = reader.read_term().unwrap().unwrap();
+        assert_eq!(list.as_list().unwrap()[499_999], AnyTerm::Integer(499_999));
+        let binary = reader.read_term().unwrap().unwrap();
+        assert_eq!(binary.as_binary().unwrap().len(), 4 << 20);
+        assert_eq!(reader.read_term().unwrap().unwrap(), list);
+        assert!(reader.read_term().is_err());
+
+        let mut reader = TermReader::new(Chunks(&compressed[..compressed.len() - 1]));
+        assert!(reader.read_term().is_err());
+    }
+}
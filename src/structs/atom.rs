@@ -1,21 +1,25 @@
-//! Copyright 2022 andre4ik3
-//!
-//! Licensed under the Apache License, Version 2.0 (the "License");
-//! you may not use this file except in compliance with the License.
-//! You may obtain a copy of the License at
-//!
-//!     http://www.apache.org/licenses/LICENSE-2.0
-//!
-//! Unless required by applicable law or agreed to in writing, software
-//! distributed under the License is distributed on an "AS IS" BASIS,
-//! WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
-//! See the License for the specific language governing permissions and
-//! limitations under the License.
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
 
+#[cfg(feature = "std")]
+use crate::atom_table::InternedAtom;
+
+use alloc::string::String;
 use serde::{Deserialize, Serialize};
 
 /// The possible types of an atom.
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum AtomKind {
     /// ATOM_UTF8_EXT
     UTF8,
@@ -28,10 +32,41 @@ pub enum AtomKind {
 }
 
 /// Represents an atom value.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Atom {
     /// The kind of the atom.
     pub kind: AtomKind,
     /// The value of the atom, as a string.
     pub value: String,
+    /// The atom's handle, if it was interned while unpacking.
+    #[cfg(feature = "std")]
+    #[serde(skip)]
+    pub(crate) interned: Option<InternedAtom>,
+}
+
+impl Atom {
+    /// Creates an atom that is packed as the given kind.
+    pub fn new(kind: AtomKind, value: String) -> Self {
+        Atom {
+            kind,
+            value,
+            #[cfg(feature = "std")]
+            interned: None,
+        }
+    }
+
+    /// Returns the atom's handle in the table it was interned in while
+    /// unpacking; see UnpackOptions::intern_atoms.
+    #[cfg(feature = "std")]
+    pub fn interned(&self) -> Option<&InternedAtom> {
+        self.interned.as_ref()
+    }
+}
+
+/// Atoms are equal if they have the same kind and value, whether or not
+/// they were interned.
+impl PartialEq for Atom {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind && self.value == other.value
+    }
 }
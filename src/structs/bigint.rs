@@ -0,0 +1,117 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::{vec, vec::Vec};
+use core::fmt::{self, Display, Formatter};
+use serde::{Deserialize, Serialize};
+
+/// Represents an integer that is packed as a bignum (SMALL_BIG_EXT or
+/// LARGE_BIG_EXT).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BigInt {
+    /// True if the integer is negative.
+    pub negative: bool,
+    /// The magnitude of the integer, least significant byte first.
+    pub digits: Vec<u8>,
+}
+
+impl BigInt {
+    /// Returns the value, if it fits into an i128.
+    pub fn to_i128(&self) -> Option<i128> {
+        let magnitude = self.to_magnitude()?;
+        match self.negative {
+            true if magnitude == i128::MIN.unsigned_abs() => Some(i128::MIN),
+            true => i128::try_from(magnitude).ok().map(|value| -value),
+            false => i128::try_from(magnitude).ok(),
+        }
+    }
+
+    /// Returns the value, if it fits into a u128.
+    pub fn to_u128(&self) -> Option<u128> {
+        match self.to_magnitude()? {
+            0 => Some(0),
+            _ if self.negative => None,
+            magnitude => Some(magnitude),
+        }
+    }
+
+    fn to_magnitude(&self) -> Option<u128> {
+        let digits = self.significant_digits();
+        if digits.len() > 16 {
+            return None;
+        }
+
+        Some(
+            digits
+                .iter()
+                .rev()
+                .fold(0, |value, &digit| (value << 8) | u128::from(digit)),
+        )
+    }
+
+    /// The digits without the zeros at the most significant end.
+    pub(crate) fn significant_digits(&self) -> &[u8] {
+        let length = self.digits.iter().rposition(|&digit| digit != 0);
+        &self.digits[..length.map_or(0, |length| length + 1)]
+    }
+}
+
+impl From<u128> for BigInt {
+    fn from(value: u128) -> Self {
+        let length = (16 - value.leading_zeros() as usize / 8).max(1);
+        BigInt {
+            negative: false,
+            digits: value.to_le_bytes()[..length].to_vec(),
+        }
+    }
+}
+
+impl From<i128> for BigInt {
+    fn from(value: i128) -> Self {
+        BigInt {
+            negative: value < 0,
+            ..BigInt::from(value.unsigned_abs())
+        }
+    }
+}
+
+/// Formats the integer in decimal.
+impl Display for BigInt {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        // Divides the magnitude by 10 until nothing is left, most significant
+        // digit first.
+        let mut magnitude: Vec<u8> = self.significant_digits().iter().rev().copied().collect();
+        let mut decimal = vec![];
+        while !magnitude.is_empty() {
+            let mut remainder = 0u16;
+            for digit in magnitude.iter_mut() {
+                let value = (remainder << 8) | u16::from(*digit);
+                *digit = (value / 10) as u8;
+                remainder = value % 10;
+            }
+            decimal.push(b'0' + remainder as u8);
+            let zeros = magnitude.iter().take_while(|&&digit| digit == 0).count();
+            magnitude.drain(..zeros);
+        }
+
+        if decimal.is_empty() {
+            return f.write_str("0");
+        }
+        if self.negative {
+            f.write_str("-")?;
+        }
+        decimal.reverse();
+        f.write_str(core::str::from_utf8(&decimal).unwrap())
+    }
+}
@@ -0,0 +1,26 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
+/// Represents a bitstring whose length isn't a whole number of bytes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct BitBinary {
+    /// The bytes of the bitstring. Only the high bits of the last byte are
+    /// used.
+    pub bytes: Vec<u8>,
+    /// How many bits of the last byte are used, from 1 to 8.
+    pub bits: u8,
+}
@@ -0,0 +1,44 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use alloc::format;
+use serde::{Deserialize, Serialize};
+
+/// Represents a float in the old text format (FLOAT_EXT), with the text kept
+/// exactly as it was written.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LegacyFloat {
+    /// The value of the float.
+    pub value: f64,
+    /// The text of the float, padded with NUL bytes.
+    pub text: [u8; 31],
+}
+
+/// Formats the float the way Erlang does, with `%.20e`.
+impl From<f64> for LegacyFloat {
+    fn from(value: f64) -> Self {
+        let formatted = format!("{:.20e}", value);
+        let formatted = match formatted.split_once('e') {
+            Some((mantissa, exponent)) => {
+                format!("{}e{:+03}", mantissa, exponent.parse::<i32>().unwrap())
+            }
+            None => formatted,
+        };
+
+        let mut text = [0; 31];
+        let len = formatted.len().min(text.len());
+        text[..len].copy_from_slice(&formatted.as_bytes()[..len]);
+        LegacyFloat { value, text }
+    }
+}
@@ -0,0 +1,41 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::terms::AnyTerm;
+use alloc::{boxed::Box, vec::Vec};
+
+/// Represents a list with at least one element. Proper lists end with
+/// AnyTerm::Nil; anything else as the tail makes the list improper.
+#[derive(Debug, Clone, PartialEq)]
+pub struct List {
+    /// The elements of the list.
+    pub elements: Vec<AnyTerm>,
+    /// What the last element points to.
+    pub tail: Box<AnyTerm>,
+}
+
+impl List {
+    /// Creates a proper list.
+    pub fn new(elements: Vec<AnyTerm>) -> Self {
+        List {
+            elements,
+            tail: Box::new(AnyTerm::Nil),
+        }
+    }
+
+    /// Returns true if the list ends with [].
+    pub fn is_proper(&self) -> bool {
+        *self.tail == AnyTerm::Nil
+    }
+}
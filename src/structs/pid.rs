@@ -0,0 +1,53 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::Atom;
+use serde::{Deserialize, Serialize};
+
+/// The possible encodings of a pid.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PidKind {
+    /// PID_EXT (deprecated)
+    Pid,
+    /// NEW_PID_EXT
+    NewPid,
+}
+
+/// Represents a process identifier.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Pid {
+    /// The kind of the pid.
+    pub kind: PidKind,
+    /// The originating node, encoded as an atom.
+    pub node: Atom,
+    /// The ID of the process.
+    pub id: u32,
+    /// The serial, which is bumped when the ID is reused.
+    pub serial: u32,
+    /// All pids from the same node incarnation share the same Creation value.
+    pub creation: u32,
+}
+
+impl Pid {
+    /// Creates a pid that is packed as NEW_PID_EXT.
+    pub fn new(node: Atom, id: u32, serial: u32, creation: u32) -> Self {
+        Pid {
+            kind: PidKind::NewPid,
+            node,
+            id,
+            serial,
+            creation,
+        }
+    }
+}
@@ -1,23 +1,36 @@
-//! Copyright 2022 andre4ik3
-//!
-//! Licensed under the Apache License, Version 2.0 (the "License");
-//! you may not use this file except in compliance with the License.
-//! You may obtain a copy of the License at
-//!
-//!     http://www.apache.org/licenses/LICENSE-2.0
-//!
-//! Unless required by applicable law or agreed to in writing, software
-//! distributed under the License is distributed on an "AS IS" BASIS,
-//! WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
-//! See the License for the specific language governing permissions and
-//! limitations under the License.
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
 
 use super::Atom;
 use serde::{Deserialize, Serialize};
 
+/// The possible encodings of a port.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PortKind {
+    /// PORT_EXT (deprecated)
+    Port,
+    /// NEW_PORT_EXT
+    NewPort,
+    /// V4_PORT_EXT
+    V4,
+}
+
 /// Represents a port value.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Port {
+    /// The kind of the port.
+    pub kind: PortKind,
     /// The originating node, encoded as an atom.
     pub node: Atom,
     /// The ID of the port.
@@ -25,3 +38,20 @@ pub struct Port {
     /// All ports from the same node incarnation share the same Creation value.
     pub creation: u32,
 }
+
+impl Port {
+    /// Picks NEW_PORT_EXT, unless the ID needs the 64 bits of V4_PORT_EXT.
+    pub fn new(node: Atom, id: u64, creation: u32) -> Self {
+        let kind = match u32::try_from(id) {
+            Ok(_) => PortKind::NewPort,
+            Err(_) => PortKind::V4,
+        };
+
+        Port {
+            kind,
+            node,
+            id,
+            creation,
+        }
+    }
+}
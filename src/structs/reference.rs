@@ -0,0 +1,54 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::Atom;
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
+/// The possible encodings of a reference.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ReferenceKind {
+    /// REFERENCE_EXT (deprecated), with a single ID word.
+    Reference,
+    /// NEW_REFERENCE_EXT, with an 8-bit creation.
+    NewReference,
+    /// NEWER_REFERENCE_EXT
+    NewerReference,
+}
+
+/// Represents a reference value.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Reference {
+    /// The kind of the reference.
+    pub kind: ReferenceKind,
+    /// The originating node, encoded as an atom.
+    pub node: Atom,
+    /// All references from the same node incarnation share the same
+    /// Creation value.
+    pub creation: u32,
+    /// The ID words, which together make the reference unique.
+    pub id: Vec<u32>,
+}
+
+impl Reference {
+    /// Creates a reference that is packed as NEWER_REFERENCE_EXT.
+    pub fn new(node: Atom, creation: u32, id: Vec<u32>) -> Self {
+        Reference {
+            kind: ReferenceKind::NewerReference,
+            node,
+            creation,
+            id,
+        }
+    }
+}
@@ -0,0 +1,642 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::limits::DEFAULT_MAX_DEPTH;
+use crate::structs::*;
+use crate::terms::AnyTerm;
+
+use alloc::{boxed::Box, string::String, vec::Vec};
+use anyhow::*;
+
+/// Parses a single term written in Erlang syntax, like `ok`, `-12`,
+/// `"text"` or `{ok, [1, 2], #{a => <<"x">>}}`. A trailing `.` is allowed.
+pub fn parse_term(source: &str) -> Result<AnyTerm> {
+    let mut tokens = tokenize(source)?;
+    if let Some(Spanned {
+        token: Token::Punct("."),
+        ..
+    }) = tokens.last()
+    {
+        tokens.pop();
+    }
+
+    let tokens: Vec<&Spanned> = tokens.iter().collect();
+    match literal(&tokens) {
+        Some(term) => Ok(term),
+        None if tokens.is_empty() => Err(anyhow!("Expected a term")),
+        None => Err(anyhow!("Unsupported term: {}", source.trim())),
+    }
+}
+
+/// A token of Erlang source code.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Token {
+    Atom(String),
+    Var(String),
+    Integer(i128),
+    Float(f64),
+    String(String),
+    Punct(&'static str),
+}
+
+/// Multi-character punctuation, longest first so that it wins.
+const PUNCTUATION: [&str; 19] = [
+    "=:=", "=/=", "...", "::", "=>", ":=", "<<", ">>", "..", "->", "<-", "<=", "||", "==", "=<",
+    ">=", "/=", "++", "--",
+];
+
+const SINGLE_PUNCTUATION: &str = "()[]{}<>,.;:=+-*/!?#|";
+
+/// A token along with the line it starts on and its source text.
+pub(crate) struct Spanned {
+    pub token: Token,
+    pub line: usize,
+    pub source: String,
+}
+
+pub(crate) fn tokenize(source: &str) -> Result<Vec<Spanned>> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+    let mut line = 1;
+
+    while pos < chars.len() {
+        let c = chars[pos];
+        let start = pos;
+
+        let token = if c == '\n' {
+            line += 1;
+            pos += 1;
+            continue;
+        } else if c.is_whitespace() {
+            pos += 1;
+            continue;
+        } else if c == '%' {
+            while pos < chars.len() && chars[pos] != '\n' {
+                pos += 1;
+            }
+            continue;
+        } else if c.is_ascii_lowercase() {
+            let name = take_while(&chars, &mut pos, is_name_char);
+            Token::Atom(name)
+        } else if c.is_ascii_uppercase() || c == '_' {
+            let name = take_while(&chars, &mut pos, is_name_char);
+            Token::Var(name)
+        } else if c == '\'' {
+            Token::Atom(quoted(&chars, &mut pos, '\'', line)?)
+        } else if c == '"' {
+            Token::String(quoted(&chars, &mut pos, '"', line)?)
+        } else if c == '$' {
+            pos += 1;
+            let c = match chars.get(pos) {
+                Some('\\') => escape(&chars, &mut pos, line)?,
+                Some(&c) => {
+                    pos += 1;
+                    c
+                }
+                None => return Err(anyhow!("line {}: unexpected end of input", line)),
+            };
+            Token::Integer(u32::from(c).into())
+        } else if c.is_ascii_digit() {
+            number(&chars, &mut pos, line)?
+        } else if let Some(punct) = PUNCTUATION
+            .iter()
+            .find(|punct| chars[pos..].starts_with(&punct.chars().collect::<Vec<_>>()))
+        {
+            pos += punct.len();
+            Token::Punct(punct)
+        } else if let Some(index) = SINGLE_PUNCTUATION.find(c) {
+            pos += 1;
+            Token::Punct(&SINGLE_PUNCTUATION[index..index + 1])
+        } else {
+            return Err(anyhow!("line {}: unexpected character {:?}", line, c));
+        };
+
+        let source: String = chars[start..pos].iter().collect();
+        tokens.push(Spanned {
+            token,
+            line,
+            source: source.clone(),
+        });
+        line += source.matches('\n').count();
+    }
+
+    Ok(tokens)
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '@'
+}
+
+fn take_while(chars: &[char], pos: &mut usize, predicate: fn(char) -> bool) -> String {
+    let start = *pos;
+    while *pos < chars.len() && predicate(chars[*pos]) {
+        *pos += 1;
+    }
+    chars[start..*pos].iter().collect()
+}
+
+/// Reads a quoted atom or string, starting at the opening quote.
+fn quoted(chars: &[char], pos: &mut usize, quote: char, line: usize) -> Result<String> {
+    let mut value = String::new();
+    *pos += 1;
+
+    loop {
+        match chars.get(*pos) {
+            Some(&c) if c == quote => {
+                *pos += 1;
+                return Ok(value);
+            }
+            Some('\\') => value.push(escape(chars, pos, line)?),
+            Some(&c) => {
+                value.push(c);
+                *pos += 1;
+            }
+            None => return Err(anyhow!("line {}: unterminated {}", line, quote)),
+        }
+    }
+}
+
+/// Reads an escape sequence, starting at the backslash.
+fn escape(chars: &[char], pos: &mut usize, line: usize) -> Result<char> {
+    *pos += 1;
+    let c = match chars.get(*pos) {
+        Some(&c) => c,
+        None => return Err(anyhow!("line {}: unexpected end of input", line)),
+    };
+    *pos += 1;
+
+    let code = match c {
+        'n' => '\n'.into(),
+        't' => '\t'.into(),
+        'r' => '\r'.into(),
+        's' => ' '.into(),
+        'e' => 0x1b,
+        'b' => 0x08,
+        'f' => 0x0c,
+        'v' => 0x0b,
+        'd' => 0x7f,
+        // Up to three octal digits, like \0 or \101.
+        '0'..='7' => {
+            let octal = |c: char| c.is_digit(8);
+            let mut text = String::from(c);
+            while text.len() < 3 && chars.get(*pos).copied().is_some_and(octal) {
+                text.push(chars[*pos]);
+                *pos += 1;
+            }
+            u32::from_str_radix(&text, 8)?
+        }
+        // Two hex digits like \x41, or any number of them like \x{1F600}.
+        'x' => {
+            let text = match chars.get(*pos) {
+                Some('{') => {
+                    *pos += 1;
+                    let text = take_while(chars, pos, |c| c.is_ascii_hexdigit());
+                    if chars.get(*pos) != Some(&'}') {
+                        return Err(anyhow!("line {}: unterminated \\x{{", line));
+                    }
+                    *pos += 1;
+                    text
+                }
+                _ => {
+                    let end = (*pos + 2).min(chars.len());
+                    let text: String = chars[*pos..end].iter().collect();
+                    *pos = end;
+                    text
+                }
+            };
+            match u32::from_str_radix(&text, 16) {
+                Result::Ok(code) => code,
+                Err(_) => return Err(anyhow!("line {}: invalid escape \\x{}", line, text)),
+            }
+        }
+        // Control characters, like \^C.
+        '^' => match chars.get(*pos) {
+            Some(&c) => {
+                *pos += 1;
+                u32::from(c) & 0x1f
+            }
+            None => return Err(anyhow!("line {}: unexpected end of input", line)),
+        },
+        other => other.into(),
+    };
+
+    match char::from_u32(code) {
+        Some(c) => Ok(c),
+        None => Err(anyhow!("line {}: invalid character {:#x}", line, code)),
+    }
+}
+
+/// Reads an integer (optionally with a base, like 16#ff) or a float.
+fn number(chars: &[char], pos: &mut usize, line: usize) -> Result<Token> {
+    let digits = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let start = *pos;
+    let whole = take_while(chars, pos, |c| c.is_ascii_digit() || c == '_').replace('_', "");
+
+    if chars.get(*pos) == Some(&'#') {
+        *pos += 1;
+        let base = match whole.parse::<u32>() {
+            Result::Ok(base @ 2..=36) => base,
+            _ => return Err(anyhow!("line {}: invalid base {}", line, whole)),
+        };
+        let value = take_while(chars, pos, digits).replace('_', "");
+        return match i128::from_str_radix(&value, base) {
+            Result::Ok(value) => Ok(Token::Integer(value)),
+            Err(_) => Err(anyhow!("line {}: invalid integer {}#{}", line, base, value)),
+        };
+    }
+
+    let is_fraction =
+        chars.get(*pos) == Some(&'.') && chars.get(*pos + 1).is_some_and(|c| c.is_ascii_digit());
+    if !is_fraction {
+        return Ok(Token::Integer(whole.parse()?));
+    }
+
+    *pos += 1;
+    take_while(chars, pos, |c| c.is_ascii_digit() || c == '_');
+    if let Some('e' | 'E') = chars.get(*pos) {
+        *pos += 1;
+        if let Some('+' | '-') = chars.get(*pos) {
+            *pos += 1;
+        }
+        take_while(chars, pos, |c| c.is_ascii_digit());
+    }
+
+    let text: String = chars[start..*pos].iter().collect();
+    Ok(Token::Float(text.replace('_', "").parse()?))
+}
+
+/// Turns an expression into a term, if it is a literal that
+/// AnyTerm can represent.
+pub(crate) fn literal(tokens: &[&Spanned]) -> Option<AnyTerm> {
+    let tokens: Vec<&Token> = tokens.iter().map(|spanned| &spanned.token).collect();
+    let mut parser = Literal {
+        tokens: &tokens,
+        pos: 0,
+    };
+    let term = parser.term(0)?;
+    match parser.pos == tokens.len() {
+        true => Some(term),
+        false => None,
+    }
+}
+
+/// Reads a literal term from tokens. Everything returns None for anything
+/// that isn't a literal, or is nested deeper than DEFAULT_MAX_DEPTH.
+struct Literal<'a> {
+    tokens: &'a [&'a Token],
+    pos: usize,
+}
+
+impl<'a> Literal<'a> {
+    fn next(&mut self) -> Option<&'a Token> {
+        let token = self.tokens.get(self.pos)?;
+        self.pos += 1;
+        Some(token)
+    }
+
+    /// Skips the punctuation if it is next.
+    fn eat(&mut self, punct: &str) -> bool {
+        match self.tokens.get(self.pos) {
+            Some(Token::Punct(found)) if *found == punct => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn term(&mut self, depth: usize) -> Option<AnyTerm> {
+        if depth > DEFAULT_MAX_DEPTH {
+            return None;
+        }
+
+        Some(match self.next()? {
+            Token::Atom(value) => AnyTerm::Atom(Atom::from(value.clone())),
+            Token::String(value) => AnyTerm::from(self.string(value)),
+            Token::Float(value) => AnyTerm::Float(*value),
+            Token::Integer(value) => AnyTerm::from(*value),
+            Token::Punct("-") => match self.next()? {
+                Token::Float(value) => AnyTerm::Float(-*value),
+                Token::Integer(value) => AnyTerm::from(-*value),
+                _ => return None,
+            },
+            Token::Punct("[") => self.list(depth)?,
+            Token::Punct("{") => AnyTerm::Tuple(self.elements("}", depth)?),
+            Token::Punct("#") if self.eat("{") => self.map(depth)?,
+            Token::Punct("<<") => self.binary()?,
+            _ => return None,
+        })
+    }
+
+    /// Adjacent strings are joined, like `"ab" "cd"`.
+    fn string(&mut self, first: &str) -> String {
+        let mut value = String::from(first);
+        while let Some(Token::String(next)) = self.tokens.get(self.pos) {
+            value.push_str(next);
+            self.pos += 1;
+        }
+        value
+    }
+
+    /// Reads elements separated by commas, up to the closing punctuation.
+    fn elements(&mut self, close: &str, depth: usize) -> Option<Vec<AnyTerm>> {
+        let mut elements = Vec::new();
+        if self.eat(close) {
+            return Some(elements);
+        }
+        loop {
+            elements.push(self.term(depth + 1)?);
+            if self.eat(close) {
+                return Some(elements);
+            }
+            if !self.eat(",") {
+                return None;
+            }
+        }
+    }
+
+    /// Reads a list after its `[`, which can have a tail like `[1|2]`.
+    fn list(&mut self, depth: usize) -> Option<AnyTerm> {
+        if self.eat("]") {
+            return Some(AnyTerm::Nil);
+        }
+
+        let mut elements = Vec::new();
+        let tail = loop {
+            elements.push(self.term(depth + 1)?);
+            if self.eat("]") {
+                break AnyTerm::Nil;
+            }
+            if self.eat("|") {
+                let tail = self.term(depth + 1)?;
+                if !self.eat("]") {
+                    return None;
+                }
+                break tail;
+            }
+            if !self.eat(",") {
+                return None;
+            }
+        };
+
+        // `[1|[2]]` is the same list as `[1,2]`.
+        let tail = match tail {
+            AnyTerm::List(list) => {
+                elements.extend(list.elements);
+                *list.tail
+            }
+            tail => tail,
+        };
+        Some(AnyTerm::List(List {
+            elements,
+            tail: Box::new(tail),
+        }))
+    }
+
+    /// Reads a map after its `#{`.
+    fn map(&mut self, depth: usize) -> Option<AnyTerm> {
+        let mut pairs = Vec::new();
+        if self.eat("}") {
+            return Some(AnyTerm::Map(pairs));
+        }
+        loop {
+            let key = self.term(depth + 1)?;
+            if !self.eat("=>") {
+                return None;
+            }
+            pairs.push((key, self.term(depth + 1)?));
+            if self.eat("}") {
+                return Some(AnyTerm::Map(pairs));
+            }
+            if !self.eat(",") {
+                return None;
+            }
+        }
+    }
+
+    /// Reads a binary after its `<<`. Segments are integers, which can have
+    /// a size in bits up to 128 like `7:3`, or strings, which are Latin-1
+    /// unless they are marked `/utf8`.
+    fn binary(&mut self) -> Option<AnyTerm> {
+        let mut bytes = Vec::new();
+        let mut bits = 0usize;
+        let mut push = |value: i128, size: usize| {
+            for i in (0..size).rev() {
+                if bits % 8 == 0 {
+                    bytes.push(0);
+                }
+                let bit = (value >> i.min(127)) as u8 & 1;
+                *bytes.last_mut().unwrap() |= bit << (7 - bits % 8);
+                bits += 1;
+            }
+        };
+
+        if !self.eat(">>") {
+            loop {
+                match self.next()? {
+                    Token::String(value) => {
+                        let value = self.string(value);
+                        if self.eat("/") {
+                            match self.next()? {
+                                Token::Atom(kind) if kind == "utf8" => {}
+                                _ => return None,
+                            }
+                            value.bytes().for_each(|byte| push(byte.into(), 8));
+                        } else {
+                            for c in value.chars() {
+                                push(u8::try_from(c).ok()?.into(), 8);
+                            }
+                        }
+                    }
+                    token => {
+                        let value = match token {
+                            Token::Integer(value) => *value,
+                            Token::Punct("-") => match self.next()? {
+                                Token::Integer(value) => -*value,
+                                _ => return None,
+                            },
+                            _ => return None,
+                        };
+                        let size = match self.eat(":") {
+                            true => match self.next()? {
+                                Token::Integer(size @ 0..=128) => *size as usize,
+                                _ => return None,
+                            },
+                            false => 8,
+                        };
+                        push(value, size);
+                    }
+                }
+                if self.eat(">>") {
+                    break;
+                }
+                if !self.eat(",") {
+                    return None;
+                }
+            }
+        }
+
+        Some(match bits % 8 {
+            0 => AnyTerm::Binary(bytes),
+            used => AnyTerm::BitBinary(BitBinary {
+                bytes,
+                bits: used as u8,
+            }),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_term("ok.").unwrap(), AnyTerm::Atom(Atom::from("ok")));
+        assert_eq!(
+            parse_term("'hello world'").unwrap(),
+            AnyTerm::Atom(Atom::from("hello world"))
+        );
+        assert_eq!(parse_term(" -12 ").unwrap(), AnyTerm::Integer(-12));
+        assert_eq!(parse_term("16#ff").unwrap(), AnyTerm::SmallInt(255));
+        assert_eq!(parse_term("$a").unwrap(), AnyTerm::SmallInt(97));
+        assert_eq!(parse_term("1.5e3").unwrap(), AnyTerm::Float(1500.0));
+        assert_eq!(
+            parse_term("\"a\\nb\"").unwrap(),
+            AnyTerm::String("a\nb".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert_eq!(parse_term("").unwrap_err().to_string(), "Expected a term");
+        for text in [
+            "f(1)",
+            "X",
+            "{a, b",
+            "[1|2|3]",
+            "#{a}",
+            "<<256:x>>",
+            "<<\"\u{1F600}\">>",
+        ] {
+            assert_eq!(
+                parse_term(text).unwrap_err().to_string(),
+                format!("Unsupported term: {}", text)
+            );
+        }
+        assert!(parse_term("'open").is_err());
+    }
+
+    #[test]
+    fn parse_containers() {
+        let ok = || AnyTerm::Atom(Atom::from("ok"));
+        assert_eq!(
+            parse_term("{ok, [1, 2], <<\"x\">>}").unwrap(),
+            AnyTerm::Tuple(vec![
+                ok(),
+                AnyTerm::List(List::new(vec![AnyTerm::SmallInt(1), AnyTerm::SmallInt(2)])),
+                AnyTerm::Binary(b"x".to_vec()),
+            ])
+        );
+        assert_eq!(parse_term("[]").unwrap(), AnyTerm::Nil);
+        assert_eq!(parse_term("{}").unwrap(), AnyTerm::Tuple(vec![]));
+        assert_eq!(parse_term("#{}").unwrap(), AnyTerm::Map(vec![]));
+        assert_eq!(
+            parse_term("#{ok => -1}").unwrap(),
+            AnyTerm::Map(vec![(ok(), AnyTerm::Integer(-1))])
+        );
+        assert_eq!(
+            parse_term("[1|[2|ok]]").unwrap(),
+            AnyTerm::List(List {
+                elements: vec![AnyTerm::SmallInt(1), AnyTerm::SmallInt(2)],
+                tail: Box::new(ok()),
+            })
+        );
+        assert_eq!(
+            parse_term("\"ab\" \"c\"").unwrap(),
+            AnyTerm::String("abc".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_binaries() {
+        assert_eq!(parse_term("<<>>").unwrap(), AnyTerm::Binary(vec![]));
+        assert_eq!(
+            parse_term("<<1, -1, 256:16, \"é\", \"é\"/utf8>>").unwrap(),
+            AnyTerm::Binary(vec![1, 255, 1, 0, 0xE9, 0xC3, 0xA9])
+        );
+        assert_eq!(
+            parse_term("<<1,7:3>>").unwrap(),
+            AnyTerm::BitBinary(BitBinary {
+                bytes: vec![1, 0xE0],
+                bits: 3,
+            })
+        );
+    }
+
+    #[test]
+    fn parse_escapes() {
+        assert_eq!(
+            parse_term("\"\\x41\\x{E9}\\101\\^C\\d\"").unwrap(),
+            AnyTerm::String("AéA\x03\x7f".to_string())
+        );
+        assert!(parse_term("\"\\x{41\"").is_err());
+        assert!(parse_term("\"\\x{D800}\"").is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        // What decode prints can be encoded again.
+        let term = AnyTerm::Tuple(vec![
+            AnyTerm::Atom(Atom::from("ok")),
+            AnyTerm::Atom(Atom::from("hello world")),
+            AnyTerm::Integer(-100_000),
+            AnyTerm::Float(-1.5e-30),
+            AnyTerm::String("a\"b\n\x01".to_string()),
+            AnyTerm::Nil,
+            AnyTerm::List(List {
+                elements: vec![AnyTerm::SmallInt(1), AnyTerm::Tuple(vec![])],
+                tail: Box::new(AnyTerm::Binary(vec![0, 255])),
+            }),
+            AnyTerm::Map(vec![(
+                AnyTerm::Binary(b"key".to_vec()),
+                AnyTerm::BitBinary(BitBinary {
+                    bytes: vec![0xFF, 0xA0],
+                    bits: 4,
+                }),
+            )]),
+        ]);
+        let data = crate::pack(term).unwrap();
+        let text = format!("{}.", crate::unpack(data.clone()).unwrap());
+        assert_eq!(crate::pack(parse_term(&text).unwrap()).unwrap(), data);
+    }
+
+    #[test]
+    fn parse_bases() {
+        assert_eq!(parse_term("2#101").unwrap(), AnyTerm::SmallInt(5));
+        assert_eq!(parse_term("36#z").unwrap(), AnyTerm::SmallInt(35));
+        for (text, error) in [
+            ("0#1", "line 1: invalid base 0"),
+            ("1#0", "line 1: invalid base 1"),
+            ("37#1", "line 1: invalid base 37"),
+            ("99#1", "line 1: invalid base 99"),
+            ("99999999999#1", "line 1: invalid base 99999999999"),
+            ("2#102", "line 1: invalid integer 2#102"),
+            ("16#", "line 1: invalid integer 16#"),
+        ] {
+            assert_eq!(parse_term(text).unwrap_err().to_string(), error);
+        }
+    }
+}
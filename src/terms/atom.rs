@@ -1,20 +1,21 @@
-//! Copyright 2022 andre4ik3
-//!
-//! Licensed under the Apache License, Version 2.0 (the "License");
-//! you may not use this file except in compliance with the License.
-//! You may obtain a copy of the License at
-//!
-//!     http://www.apache.org/licenses/LICENSE-2.0
-//!
-//! Unless required by applicable law or agreed to in writing, software
-//! distributed under the License is distributed on an "AS IS" BASIS,
-//! WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
-//! See the License for the specific language governing permissions and
-//! limitations under the License.
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
 
 use super::*;
 use crate::utils::*;
 
+use alloc::{borrow::Cow, vec};
 use anyhow::*;
 
 const ATOM_UTF8_EXT: u8 = 118;
@@ -24,24 +25,10 @@ const SMALL_ATOM_EXT: u8 = 115;
 
 pub struct AtomPacker;
 impl Term<Atom> for AtomPacker {
-    fn pack(data: Atom, buf: &mut BufWriter<Cursor<Vec<u8>>>) -> Result<()> {
-        let is_ascii = data.value.chars().all(char::is_alphanumeric);
-        let max_length: usize = match data.kind {
-            AtomKind::SmallUTF8 | AtomKind::SmallLegacy => u8::MAX.into(),
-            AtomKind::UTF8 | AtomKind::Legacy => u16::MAX.into(),
-        };
-
-        // Check if the string can actually be parsed...
-        if !is_ascii {
-            if let AtomKind::Legacy | AtomKind::SmallLegacy = data.kind {
-                return Err(anyhow!("String not ASCII"));
-            };
-        }
-
-        // ... and whether or not it can fit.
-        if data.value.len() > max_length {
-            return Err(anyhow!("String too large"));
-        }
+    fn pack(data: Atom, buf: &mut Vec<u8>) -> Result<()> {
+        // Check that the atom can be packed.
+        AtomPacker::size(&data)?;
+        let max_length = max_length(data.kind);
 
         // Work out the first byte ("Term ID") to write.
         let first_byte = match data.kind {
@@ -51,56 +38,59 @@ impl Term<Atom> for AtomPacker {
             AtomKind::SmallLegacy => SMALL_ATOM_EXT,
         };
 
+        // Legacy atoms are Latin-1, with one byte per char.
+        let bytes = match data.kind {
+            AtomKind::UTF8 | AtomKind::SmallUTF8 => data.value.into_bytes(),
+            AtomKind::Legacy | AtomKind::SmallLegacy => latin1_bytes(&data.value).unwrap(),
+        };
+
         // Then work out the length. This shouldn't panic because length was
         // already checked above.
         let length: Vec<u8> = {
             if max_length == u8::MAX.into() {
-                vec![u8::try_from(data.value.len()).unwrap()]
+                vec![u8::try_from(bytes.len()).unwrap()]
             } else {
-                u16::try_from(data.value.len())?.to_be_bytes().to_vec()
+                u16::try_from(bytes.len())?.to_be_bytes().to_vec()
             }
         };
 
         write_bytes(buf, vec![first_byte])?;
         write_bytes(buf, length)?;
-        write_bytes(buf, data.value.as_bytes().to_vec())?;
+        write_bytes(buf, bytes)?;
 
         Ok(())
     }
 
-    fn unpack(buf: &mut BufReader<Cursor<Vec<u8>>>, fb: u8) -> Result<Atom> {
-        let length: Result<usize> = match fb {
-            ATOM_EXT | ATOM_UTF8_EXT => Ok(2),
-            SMALL_ATOM_EXT | SMALL_ATOM_UTF8_EXT => Ok(1),
-            _ => Err(anyhow!("Unknown first byte")),
-        };
-
-        let kind = match fb {
-            ATOM_UTF8_EXT => AtomKind::UTF8,
-            SMALL_ATOM_UTF8_EXT => AtomKind::SmallUTF8,
-            ATOM_EXT => AtomKind::Legacy,
-            SMALL_ATOM_EXT => AtomKind::SmallLegacy,
-            _ => unreachable!(),
-        };
+    fn unpack(buf: &mut ReadBuf<'_>, fb: u8) -> Result<Atom> {
+        let (kind, value) = AtomPacker::read_name(buf, fb)?;
+        Ok(Atom::new(kind, value.into_owned()))
+    }
 
-        let length = read_bytes(buf, length?)?;
-        let length = match length.len() {
-            1 => u16::from(length[0]),
-            2 => u16::from_be_bytes([length[0], length[1]]),
-            _ => unreachable!(),
+    fn size(data: &Atom) -> Result<usize> {
+        let max_length = max_length(data.kind);
+
+        // Check if the name can be encoded...
+        let length = match data.kind {
+            AtomKind::UTF8 | AtomKind::SmallUTF8 => data.value.len(),
+            AtomKind::Legacy | AtomKind::SmallLegacy => {
+                if !data.value.chars().all(|c| u32::from(c) <= 0xFF) {
+                    return Err(anyhow!("Atom {} is not Latin-1", data.value));
+                }
+                data.value.chars().count()
+            }
         };
 
-        let value = read_bytes(buf, length.into())?;
-        let value = String::from_utf8(value)?;
+        // ... and whether or not it can fit.
+        if length > max_length {
+            return Err(anyhow!("Atom is too large"));
+        }
 
-        Ok(Atom { kind, value })
+        let length_size = if max_length == u8::MAX.into() { 1 } else { 2 };
+        Ok(1 + length_size + length)
     }
 
     fn can_pack(data: &AnyTerm) -> bool {
-        match data {
-            &AnyTerm::Atom(_) => true,
-            _ => false,
-        }
+        matches!(data, AnyTerm::Atom(_))
     }
 
     fn can_unpack(first_byte: &u8) -> bool {
@@ -111,6 +101,60 @@ impl Term<Atom> for AtomPacker {
     }
 }
 
+impl AtomPacker {
+    /// Writes a UTF-8 atom without building an Atom, picking the encoding
+    /// that Atom::from would.
+    pub(crate) fn write_utf8(buf: &mut Vec<u8>, name: &str) -> Result<()> {
+        match u8::try_from(name.len()) {
+            Result::Ok(length) => write_bytes(buf, vec![SMALL_ATOM_UTF8_EXT, length])?,
+            Err(_) => {
+                let length = u16::try_from(name.len()).map_err(|_| anyhow!("Atom is too large"))?;
+                write_bytes(buf, vec![ATOM_UTF8_EXT])?;
+                write_bytes(buf, length.to_be_bytes().to_vec())?;
+            }
+        }
+        buf.extend_from_slice(name.as_bytes());
+        Ok(())
+    }
+
+    /// Reads an atom without copying its name, unless it is Latin-1 with
+    /// characters that aren't ASCII.
+    pub(crate) fn read_name<'a>(buf: &mut ReadBuf<'a>, fb: u8) -> Result<(AtomKind, Cow<'a, str>)> {
+        let kind = match fb {
+            ATOM_UTF8_EXT => AtomKind::UTF8,
+            SMALL_ATOM_UTF8_EXT => AtomKind::SmallUTF8,
+            ATOM_EXT => AtomKind::Legacy,
+            SMALL_ATOM_EXT => AtomKind::SmallLegacy,
+            _ => return Err(anyhow!("Unknown first byte")),
+        };
+
+        let length = match kind {
+            AtomKind::SmallUTF8 | AtomKind::SmallLegacy => read_bytes(buf, 1)?[0].into(),
+            AtomKind::UTF8 | AtomKind::Legacy => {
+                u16::from_be_bytes(buf.take(2)?.try_into().unwrap())
+            }
+        };
+
+        let value = buf.take(length.into())?;
+        let value = match kind {
+            AtomKind::UTF8 | AtomKind::SmallUTF8 => Cow::Borrowed(core::str::from_utf8(value)?),
+            AtomKind::Legacy | AtomKind::SmallLegacy => match value.is_ascii() {
+                true => Cow::Borrowed(core::str::from_utf8(value)?),
+                false => Cow::Owned(value.iter().map(|&byte| char::from(byte)).collect()),
+            },
+        };
+
+        Ok((kind, value))
+    }
+}
+
+fn max_length(kind: AtomKind) -> usize {
+    match kind {
+        AtomKind::SmallUTF8 | AtomKind::SmallLegacy => u8::MAX.into(),
+        AtomKind::UTF8 | AtomKind::Legacy => u16::MAX.into(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -123,21 +167,17 @@ mod tests {
 
     #[test]
     fn pack_atom() {
-        let mut buf = BufWriter::new(Cursor::new(Vec::<u8>::new()));
+        let mut buf = Vec::new();
 
-        let atom = Atom {
-            kind: AtomKind::Legacy,
-            value: VALUE.to_string(),
-        };
+        let atom = Atom::new(AtomKind::Legacy, VALUE.to_string());
 
         AtomPacker::pack(atom, &mut buf).unwrap();
-        let buf = buf.into_inner().unwrap().into_inner();
         assert_eq!(buf, PACKED_ATOM);
     }
 
     #[test]
     fn unpack_atom() {
-        let mut buf = BufReader::new(Cursor::new(PACKED_ATOM.to_vec()));
+        let mut buf = ReadBuf::new(&PACKED_ATOM);
         let fb = read_bytes(&mut buf, 1).unwrap()[0];
         let atom = AtomPacker::unpack(&mut buf, fb).unwrap();
         assert_eq!(atom.kind, AtomKind::Legacy);
@@ -146,21 +186,17 @@ mod tests {
 
     #[test]
     fn pack_atom_utf8() {
-        let mut buf = BufWriter::new(Cursor::new(Vec::<u8>::new()));
+        let mut buf = Vec::new();
 
-        let atom = Atom {
-            kind: AtomKind::UTF8,
-            value: VALUE.to_string(),
-        };
+        let atom = Atom::new(AtomKind::UTF8, VALUE.to_string());
 
         AtomPacker::pack(atom, &mut buf).unwrap();
-        let buf = buf.into_inner().unwrap().into_inner();
         assert_eq!(buf, PACKED_ATOM_UTF8);
     }
 
     #[test]
     fn unpack_atom_utf8() {
-        let mut buf = BufReader::new(Cursor::new(PACKED_ATOM_UTF8.to_vec()));
+        let mut buf = ReadBuf::new(&PACKED_ATOM_UTF8);
         let fb = read_bytes(&mut buf, 1).unwrap()[0];
         let atom = AtomPacker::unpack(&mut buf, fb).unwrap();
         assert_eq!(atom.kind, AtomKind::UTF8);
@@ -169,21 +205,17 @@ mod tests {
 
     #[test]
     fn pack_small_atom() {
-        let mut buf = BufWriter::new(Cursor::new(Vec::<u8>::new()));
+        let mut buf = Vec::new();
 
-        let atom = Atom {
-            kind: AtomKind::SmallLegacy,
-            value: VALUE.to_string(),
-        };
+        let atom = Atom::new(AtomKind::SmallLegacy, VALUE.to_string());
 
         AtomPacker::pack(atom, &mut buf).unwrap();
-        let buf = buf.into_inner().unwrap().into_inner();
         assert_eq!(buf, PACKED_SMALL_ATOM);
     }
 
     #[test]
     fn unpack_small_atom() {
-        let mut buf = BufReader::new(Cursor::new(PACKED_SMALL_ATOM.to_vec()));
+        let mut buf = ReadBuf::new(&PACKED_SMALL_ATOM);
         let fb = read_bytes(&mut buf, 1).unwrap()[0];
         let atom = AtomPacker::unpack(&mut buf, fb).unwrap();
         assert_eq!(atom.kind, AtomKind::SmallLegacy);
@@ -192,21 +224,40 @@ mod tests {
 
     #[test]
     fn pack_small_atom_utf8() {
-        let mut buf = BufWriter::new(Cursor::new(Vec::<u8>::new()));
+        let mut buf = Vec::new();
 
-        let atom = Atom {
-            kind: AtomKind::SmallUTF8,
-            value: VALUE.to_string(),
-        };
+        let atom = Atom::new(AtomKind::SmallUTF8, VALUE.to_string());
 
         AtomPacker::pack(atom, &mut buf).unwrap();
-        let buf = buf.into_inner().unwrap().into_inner();
         assert_eq!(buf, PACKED_SMALL_ATOM_UTF8);
     }
 
+    #[test]
+    fn latin1() {
+        for name in ["foo_bar", "it's", "héllo"] {
+            let atom = Atom::new(AtomKind::SmallLegacy, name.to_string());
+            let mut buf = Vec::new();
+            AtomPacker::pack(atom.clone(), &mut buf).unwrap();
+            assert_eq!(buf.len(), 2 + name.chars().count());
+            assert_eq!(buf.len(), AtomPacker::size(&atom).unwrap());
+
+            let mut read = ReadBuf::new(&buf);
+            let fb = read_bytes(&mut read, 1).unwrap()[0];
+            assert_eq!(AtomPacker::unpack(&mut read, fb).unwrap(), atom);
+        }
+
+        // [0xC3, 0xA9] is "é" in UTF-8, but two characters in Latin-1.
+        let mut buf = ReadBuf::new(&[100, 0, 2, 0xC3, 0xA9]);
+        let fb = read_bytes(&mut buf, 1).unwrap()[0];
+        assert_eq!(AtomPacker::unpack(&mut buf, fb).unwrap().value, "Ã©");
+
+        let euro = Atom::new(AtomKind::Legacy, "€".to_string());
+        assert!(AtomPacker::size(&euro).is_err());
+    }
+
     #[test]
     fn unpack_small_atom_utf8() {
-        let mut buf = BufReader::new(Cursor::new(PACKED_SMALL_ATOM_UTF8.to_vec()));
+        let mut buf = ReadBuf::new(&PACKED_SMALL_ATOM_UTF8);
         let fb = read_bytes(&mut buf, 1).unwrap()[0];
         let atom = AtomPacker::unpack(&mut buf, fb).unwrap();
         assert_eq!(atom.kind, AtomKind::SmallUTF8);
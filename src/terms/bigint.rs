@@ -0,0 +1,114 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::*;
+use crate::utils::*;
+
+use alloc::vec;
+use anyhow::*;
+
+const SMALL_BIG_EXT: u8 = 110;
+const LARGE_BIG_EXT: u8 = 111;
+
+pub struct BigIntPacker;
+impl Term<BigInt> for BigIntPacker {
+    /// Packs as a small bignum when there are at most 255 digits.
+    fn pack(data: BigInt, buf: &mut Vec<u8>) -> Result<()> {
+        match u8::try_from(data.digits.len()) {
+            Result::Ok(length) => write_bytes(buf, vec![SMALL_BIG_EXT, length])?,
+            Err(_) => {
+                write_bytes(buf, vec![LARGE_BIG_EXT])?;
+                write_bytes(
+                    buf,
+                    u32::try_from(data.digits.len())?.to_be_bytes().to_vec(),
+                )?;
+            }
+        }
+
+        write_bytes(buf, vec![u8::from(data.negative)])?;
+        write_bytes(buf, data.digits)
+    }
+
+    fn unpack(buf: &mut ReadBuf<'_>, fb: u8) -> Result<BigInt> {
+        let length = match fb {
+            SMALL_BIG_EXT => read_bytes(buf, 1)?[0].into(),
+            _ => u32::from_be_bytes(buf.take(4)?.try_into().unwrap()) as usize,
+        };
+
+        let negative = match read_bytes(buf, 1)?[0] {
+            0 => false,
+            1 => true,
+            sign => return Err(anyhow!("Bignum has an unknown sign {}", sign)),
+        };
+
+        let digits = read_bytes(buf, length)?;
+        Ok(BigInt { negative, digits })
+    }
+
+    fn size(data: &BigInt) -> Result<usize> {
+        let header = match u8::try_from(data.digits.len()) {
+            Result::Ok(_) => 3,
+            Err(_) => {
+                u32::try_from(data.digits.len())?;
+                6
+            }
+        };
+        Ok(header + data.digits.len())
+    }
+
+    fn can_pack(data: &AnyTerm) -> bool {
+        matches!(data, AnyTerm::BigInt(_))
+    }
+
+    fn can_unpack(first_byte: &u8) -> bool {
+        first_byte == &SMALL_BIG_EXT || first_byte == &LARGE_BIG_EXT
+    }
+}
+
+impl BigIntPacker {
+    /// Returns true if the bignum would be packed with this first byte.
+    pub(crate) fn is_canonical(data: &BigInt, fb: u8) -> bool {
+        (fb == SMALL_BIG_EXT) == (data.digits.len() <= u8::MAX.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2^32 and -2^40.
+    const PACKED_BIG: [u8; 8] = [110, 5, 0, 0, 0, 0, 0, 1];
+    const PACKED_NEGATIVE: [u8; 9] = [110, 6, 1, 0, 0, 0, 0, 0, 1];
+
+    #[test]
+    fn pack() {
+        let mut buf = Vec::new();
+        BigIntPacker::pack(BigInt::from(1i128 << 32), &mut buf).unwrap();
+        assert_eq!(buf, PACKED_BIG);
+
+        let mut buf = Vec::new();
+        BigIntPacker::pack(BigInt::from(-(1i128 << 40)), &mut buf).unwrap();
+        assert_eq!(buf, PACKED_NEGATIVE);
+    }
+
+    #[test]
+    fn unpack() {
+        let mut buf = ReadBuf::new(&PACKED_NEGATIVE);
+        let fb = read_bytes(&mut buf, 1).unwrap()[0];
+        let value = BigIntPacker::unpack(&mut buf, fb).unwrap();
+        assert_eq!(value.to_i128(), Some(-(1 << 40)));
+        assert!(BigIntPacker::is_canonical(&value, fb));
+        assert!(!BigIntPacker::is_canonical(&value, LARGE_BIG_EXT));
+    }
+}
@@ -0,0 +1,141 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::*;
+use crate::utils::*;
+
+use alloc::vec;
+use anyhow::*;
+
+const BINARY_EXT: u8 = 109;
+const BIT_BINARY_EXT: u8 = 77;
+
+pub struct BinaryPacker;
+impl Term<Vec<u8>> for BinaryPacker {
+    fn pack(data: Vec<u8>, buf: &mut Vec<u8>) -> Result<()> {
+        BinaryPacker::write_slice(buf, &data)
+    }
+
+    fn unpack(buf: &mut ReadBuf<'_>, _: u8) -> Result<Vec<u8>> {
+        let length = u32::from_be_bytes(buf.take(4)?.try_into().unwrap());
+        read_bytes(buf, length as usize)
+    }
+
+    fn size(data: &Vec<u8>) -> Result<usize> {
+        u32::try_from(data.len())?;
+        Ok(5 + data.len())
+    }
+
+    fn can_pack(data: &AnyTerm) -> bool {
+        matches!(data, AnyTerm::Binary(_))
+    }
+
+    fn can_unpack(first_byte: &u8) -> bool {
+        first_byte == &BINARY_EXT
+    }
+}
+
+impl BinaryPacker {
+    /// Writes a binary without taking ownership of its bytes.
+    pub(crate) fn write_slice(buf: &mut Vec<u8>, data: &[u8]) -> Result<()> {
+        let length = u32::try_from(data.len())?;
+        write_bytes(buf, vec![BINARY_EXT])?;
+        write_bytes(buf, length.to_be_bytes().to_vec())?;
+        buf.extend_from_slice(data);
+        Ok(())
+    }
+}
+
+pub struct BitBinaryPacker;
+impl Term<BitBinary> for BitBinaryPacker {
+    fn pack(data: BitBinary, buf: &mut Vec<u8>) -> Result<()> {
+        BitBinaryPacker::size(&data)?;
+        let length = u32::try_from(data.bytes.len())?;
+        write_bytes(buf, vec![BIT_BINARY_EXT])?;
+        write_bytes(buf, length.to_be_bytes().to_vec())?;
+        write_bytes(buf, vec![data.bits])?;
+        write_bytes(buf, data.bytes)
+    }
+
+    fn unpack(buf: &mut ReadBuf<'_>, _: u8) -> Result<BitBinary> {
+        let length = u32::from_be_bytes(buf.take(4)?.try_into().unwrap());
+        let bits = read_bytes(buf, 1)?[0];
+        if !(1..=8).contains(&bits) || length == 0 {
+            return Err(anyhow!("Bitstring has {} bits in its last byte", bits));
+        }
+
+        let bytes = read_bytes(buf, length as usize)?;
+        Ok(BitBinary { bytes, bits })
+    }
+
+    fn size(data: &BitBinary) -> Result<usize> {
+        if !(1..=8).contains(&data.bits) || data.bytes.is_empty() {
+            return Err(anyhow!(
+                "Bitstring can't have {} bits in its last byte",
+                data.bits
+            ));
+        }
+
+        u32::try_from(data.bytes.len())?;
+        Ok(6 + data.bytes.len())
+    }
+
+    fn can_pack(data: &AnyTerm) -> bool {
+        matches!(data, AnyTerm::BitBinary(_))
+    }
+
+    fn can_unpack(first_byte: &u8) -> bool {
+        first_byte == &BIT_BINARY_EXT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACKED_BINARY: [u8; 8] = [109, 0, 0, 0, 3, 1, 2, 3];
+    const PACKED_BIT_BINARY: [u8; 8] = [77, 0, 0, 0, 2, 3, 1, 0xE0];
+
+    #[test]
+    fn binary() {
+        let mut buf = Vec::new();
+        BinaryPacker::pack(vec![1, 2, 3], &mut buf).unwrap();
+        assert_eq!(buf, PACKED_BINARY);
+
+        let mut buf = ReadBuf::new(&PACKED_BINARY);
+        let fb = read_bytes(&mut buf, 1).unwrap()[0];
+        assert_eq!(BinaryPacker::unpack(&mut buf, fb).unwrap(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn bit_binary() {
+        let value = BitBinary {
+            bytes: vec![1, 0xE0],
+            bits: 3,
+        };
+        let mut buf = Vec::new();
+        BitBinaryPacker::pack(value.clone(), &mut buf).unwrap();
+        assert_eq!(buf, PACKED_BIT_BINARY);
+
+        let mut buf = ReadBuf::new(&PACKED_BIT_BINARY);
+        let fb = read_bytes(&mut buf, 1).unwrap()[0];
+        assert_eq!(BitBinaryPacker::unpack(&mut buf, fb).unwrap(), value);
+
+        let empty = BitBinary {
+            bytes: vec![],
+            bits: 3,
+        };
+        assert!(BitBinaryPacker::size(&empty).is_err());
+    }
+}
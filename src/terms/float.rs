@@ -1,20 +1,21 @@
-//! Copyright 2022 andre4ik3
-//!
-//! Licensed under the Apache License, Version 2.0 (the "License");
-//! you may not use this file except in compliance with the License.
-//! You may obtain a copy of the License at
-//!
-//!     http://www.apache.org/licenses/LICENSE-2.0
-//!
-//! Unless required by applicable law or agreed to in writing, software
-//! distributed under the License is distributed on an "AS IS" BASIS,
-//! WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
-//! See the License for the specific language governing permissions and
-//! limitations under the License.
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
 
 use super::*;
 use crate::utils::*;
 
+use alloc::vec;
 use anyhow::*;
 
 const FLOAT_EXT: u8 = 99;
@@ -23,13 +24,13 @@ const NEW_FLOAT_EXT: u8 = 70;
 pub struct FloatPacker;
 impl Term<f64> for FloatPacker {
     /// Always packs as new (IEEE) float.
-    fn pack(data: f64, buf: &mut BufWriter<Cursor<Vec<u8>>>) -> Result<()> {
+    fn pack(data: f64, buf: &mut Vec<u8>) -> Result<()> {
         write_bytes(buf, vec![NEW_FLOAT_EXT])?;
         write_bytes(buf, data.to_be_bytes().to_vec())?;
         Ok(())
     }
 
-    fn unpack(buf: &mut BufReader<Cursor<Vec<u8>>>, fb: u8) -> Result<f64> {
+    fn unpack(buf: &mut ReadBuf<'_>, fb: u8) -> Result<f64> {
         if !FloatPacker::can_unpack(&fb) {
             return Err(anyhow!("Unknown float type"));
         }
@@ -48,11 +49,12 @@ impl Term<f64> for FloatPacker {
         unreachable!()
     }
 
+    fn size(_: &f64) -> Result<usize> {
+        Ok(9)
+    }
+
     fn can_pack(data: &AnyTerm) -> bool {
-        match data {
-            &AnyTerm::Float(_) => true,
-            _ => false,
-        }
+        matches!(data, AnyTerm::Float(_))
     }
 
     fn can_unpack(first_byte: &u8) -> bool {
@@ -60,6 +62,42 @@ impl Term<f64> for FloatPacker {
     }
 }
 
+pub struct LegacyFloatPacker;
+impl Term<LegacyFloat> for LegacyFloatPacker {
+    /// Writes the text exactly as it was read.
+    fn pack(data: LegacyFloat, buf: &mut Vec<u8>) -> Result<()> {
+        write_bytes(buf, vec![FLOAT_EXT])?;
+        write_bytes(buf, data.text.to_vec())?;
+        Ok(())
+    }
+
+    fn unpack(buf: &mut ReadBuf<'_>, fb: u8) -> Result<LegacyFloat> {
+        if !LegacyFloatPacker::can_unpack(&fb) {
+            return Err(anyhow!("Unknown float type"));
+        }
+
+        let text = read_bytes(buf, 31)?;
+        let value = str_from_u8_nul_utf8(&text)?.parse::<f64>()?;
+        // This shouldn't panic because read_bytes checks length.
+        Ok(LegacyFloat {
+            value,
+            text: text.try_into().unwrap(),
+        })
+    }
+
+    fn size(_: &LegacyFloat) -> Result<usize> {
+        Ok(32)
+    }
+
+    fn can_pack(data: &AnyTerm) -> bool {
+        matches!(data, AnyTerm::LegacyFloat(_))
+    }
+
+    fn can_unpack(first_byte: &u8) -> bool {
+        first_byte == &FLOAT_EXT
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -73,22 +111,40 @@ mod tests {
 
     #[test]
     fn pack() {
-        let mut buf = BufWriter::new(Cursor::new(Vec::<u8>::new()));
+        let mut buf = Vec::new();
         FloatPacker::pack(VALUE, &mut buf).unwrap();
-        let buf = buf.into_inner().unwrap().into_inner();
         assert_eq!(buf, PACKED_NEW_FLOAT);
     }
 
     #[test]
     fn unpack_old() {
-        let mut buf = BufReader::new(Cursor::new(PACKED_FLOAT.to_vec()));
+        let mut buf = ReadBuf::new(&PACKED_FLOAT);
         let fb = read_bytes(&mut buf, 1).unwrap()[0];
         assert_eq!(FloatPacker::unpack(&mut buf, fb).unwrap(), VALUE);
     }
 
+    #[test]
+    fn legacy() {
+        let mut buf = ReadBuf::new(&PACKED_FLOAT);
+        let fb = read_bytes(&mut buf, 1).unwrap()[0];
+        let float = LegacyFloatPacker::unpack(&mut buf, fb).unwrap();
+        assert_eq!(float.value, VALUE);
+
+        let mut buf = Vec::new();
+        LegacyFloatPacker::pack(float, &mut buf).unwrap();
+        assert_eq!(buf, PACKED_FLOAT);
+
+        let float = LegacyFloat::from(-1.5e300);
+        assert_eq!(&float.text[..28], b"-1.50000000000000007876e+300");
+        assert_eq!(
+            LegacyFloat::from(1.5).text[..27],
+            *b"1.50000000000000000000e+00\0"
+        );
+    }
+
     #[test]
     fn unpack_new() {
-        let mut buf = BufReader::new(Cursor::new(PACKED_NEW_FLOAT.to_vec()));
+        let mut buf = ReadBuf::new(&PACKED_NEW_FLOAT);
         let fb = read_bytes(&mut buf, 1).unwrap()[0];
         assert_eq!(FloatPacker::unpack(&mut buf, fb).unwrap(), VALUE);
     }
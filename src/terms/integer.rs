@@ -1,27 +1,28 @@
-//! Copyright 2022 andre4ik3
-//!
-//! Licensed under the Apache License, Version 2.0 (the "License");
-//! you may not use this file except in compliance with the License.
-//! You may obtain a copy of the License at
-//!
-//!     http://www.apache.org/licenses/LICENSE-2.0
-//!
-//! Unless required by applicable law or agreed to in writing, software
-//! distributed under the License is distributed on an "AS IS" BASIS,
-//! WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
-//! See the License for the specific language governing permissions and
-//! limitations under the License.
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
 
 use super::*;
 use crate::utils::*;
 
+use alloc::vec;
 use anyhow::*;
 
 const INTEGER_EXT: u8 = 98;
 
 pub struct IntegerPacker;
 impl Term<i32> for IntegerPacker {
-    fn pack(data: i32, buf: &mut BufWriter<Cursor<Vec<u8>>>) -> Result<()> {
+    fn pack(data: i32, buf: &mut Vec<u8>) -> Result<()> {
         let bytes = data.to_be_bytes();
 
         write_bytes(buf, vec![INTEGER_EXT])?;
@@ -30,16 +31,17 @@ impl Term<i32> for IntegerPacker {
         Ok(())
     }
 
-    fn unpack(buf: &mut BufReader<Cursor<Vec<u8>>>, _: u8) -> Result<i32> {
+    fn unpack(buf: &mut ReadBuf<'_>, _: u8) -> Result<i32> {
         let bytes = read_bytes(buf, 4)?;
         Ok(i32::from_be_bytes(bytes.try_into().unwrap()))
     }
 
+    fn size(_: &i32) -> Result<usize> {
+        Ok(5)
+    }
+
     fn can_pack(data: &AnyTerm) -> bool {
-        match data {
-            &AnyTerm::Integer(_) => true,
-            _ => false,
-        }
+        matches!(data, AnyTerm::Integer(_))
     }
 
     fn can_unpack(first_byte: &u8) -> bool {
@@ -56,15 +58,14 @@ mod tests {
 
     #[test]
     fn pack() {
-        let mut buf = BufWriter::new(Cursor::new(Vec::<u8>::new()));
+        let mut buf = Vec::new();
         IntegerPacker::pack(VALUE, &mut buf).unwrap();
-        let buf = buf.into_inner().unwrap().into_inner();
         assert_eq!(buf, PACKED_INTEGER);
     }
 
     #[test]
     fn unpack() {
-        let mut buf = BufReader::new(Cursor::new(PACKED_INTEGER.to_vec()));
+        let mut buf = ReadBuf::new(&PACKED_INTEGER);
         read_bytes(&mut buf, 1).unwrap();
         assert_eq!(IntegerPacker::unpack(&mut buf, INTEGER_EXT).unwrap(), VALUE);
     }
@@ -0,0 +1,144 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::*;
+use crate::packing::{pack_buf, term_size, unpack_tagged};
+use crate::utils::*;
+use crate::UnpackOptions;
+
+use alloc::vec;
+use anyhow::*;
+
+const NIL_EXT: u8 = 106;
+const LIST_EXT: u8 = 108;
+
+pub struct NilPacker;
+impl Term<()> for NilPacker {
+    fn pack(_: (), buf: &mut Vec<u8>) -> Result<()> {
+        write_bytes(buf, vec![NIL_EXT])
+    }
+
+    fn unpack(_: &mut ReadBuf<'_>, _: u8) -> Result<()> {
+        Ok(())
+    }
+
+    fn size(_: &()) -> Result<usize> {
+        Ok(1)
+    }
+
+    fn can_pack(data: &AnyTerm) -> bool {
+        matches!(data, AnyTerm::Nil)
+    }
+
+    fn can_unpack(first_byte: &u8) -> bool {
+        first_byte == &NIL_EXT
+    }
+}
+
+pub struct ListPacker;
+impl Term<List> for ListPacker {
+    /// Writes the elements, then the tail.
+    fn pack(data: List, buf: &mut Vec<u8>) -> Result<()> {
+        ListPacker::write_header(buf, data.elements.len())?;
+        for element in data.elements {
+            pack_buf(buf, element)?;
+        }
+        pack_buf(buf, *data.tail)
+    }
+
+    fn unpack(buf: &mut ReadBuf<'_>, fb: u8) -> Result<List> {
+        match unpack_tagged(buf, fb, &UnpackOptions::new())? {
+            AnyTerm::List(list) => Ok(list),
+            other => Err(anyhow!("Expected a list, found {}", other.type_name())),
+        }
+    }
+
+    fn size(data: &List) -> Result<usize> {
+        u32::try_from(data.elements.len())?;
+        data.elements
+            .iter()
+            .try_fold(5 + term_size(&data.tail)?, |size, element| {
+                Ok(size + term_size(element)?)
+            })
+    }
+
+    fn can_pack(data: &AnyTerm) -> bool {
+        matches!(data, AnyTerm::List(_))
+    }
+
+    fn can_unpack(first_byte: &u8) -> bool {
+        first_byte == &LIST_EXT
+    }
+}
+
+impl ListPacker {
+    /// Reads the number of elements that follows the first byte.
+    pub(crate) fn read_length(buf: &mut ReadBuf<'_>) -> Result<usize> {
+        Ok(u32::from_be_bytes(buf.take(4)?.try_into().unwrap()) as usize)
+    }
+}
+
+impl ListPacker {
+    /// Writes the start of a list with this many elements. The elements and
+    /// the tail have to follow.
+    pub(crate) fn write_header(buf: &mut Vec<u8>, length: usize) -> Result<()> {
+        let length = u32::try_from(length)?;
+        write_bytes(buf, vec![LIST_EXT])?;
+        write_bytes(buf, length.to_be_bytes().to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACKED_LIST: [u8; 10] = [108, 0, 0, 0, 2, 97, 1, 97, 2, 106];
+    const PACKED_IMPROPER: [u8; 9] = [108, 0, 0, 0, 1, 97, 1, 97, 2];
+
+    #[test]
+    fn pack() {
+        let list = List::new(vec![AnyTerm::SmallInt(1), AnyTerm::SmallInt(2)]);
+        assert_eq!(ListPacker::size(&list).unwrap(), PACKED_LIST.len());
+
+        let mut buf = Vec::new();
+        ListPacker::pack(list, &mut buf).unwrap();
+        assert_eq!(buf, PACKED_LIST);
+
+        let mut buf = Vec::new();
+        NilPacker::pack((), &mut buf).unwrap();
+        assert_eq!(buf, [106]);
+    }
+
+    #[test]
+    fn unpack() {
+        let mut buf = ReadBuf::new(&PACKED_LIST);
+        let fb = read_bytes(&mut buf, 1).unwrap()[0];
+        let list = ListPacker::unpack(&mut buf, fb).unwrap();
+        assert!(list.is_proper());
+        assert_eq!(list.elements, [AnyTerm::SmallInt(1), AnyTerm::SmallInt(2)]);
+    }
+
+    #[test]
+    fn improper() {
+        let mut buf = ReadBuf::new(&PACKED_IMPROPER);
+        let fb = read_bytes(&mut buf, 1).unwrap()[0];
+        let list = ListPacker::unpack(&mut buf, fb).unwrap();
+        assert!(!list.is_proper());
+        assert_eq!(*list.tail, AnyTerm::SmallInt(2));
+
+        let mut packed = Vec::new();
+        ListPacker::pack(list, &mut packed).unwrap();
+        assert_eq!(packed, PACKED_IMPROPER);
+    }
+}
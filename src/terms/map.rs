@@ -0,0 +1,101 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::*;
+use crate::packing::{pack_buf, term_size, unpack_tagged};
+use crate::utils::*;
+use crate::UnpackOptions;
+
+use alloc::vec;
+use anyhow::*;
+
+const MAP_EXT: u8 = 116;
+
+pub struct MapPacker;
+impl Term<Vec<(AnyTerm, AnyTerm)>> for MapPacker {
+    /// Writes the pairs in the order they are in.
+    fn pack(data: Vec<(AnyTerm, AnyTerm)>, buf: &mut Vec<u8>) -> Result<()> {
+        MapPacker::write_header(buf, data.len())?;
+        for (key, value) in data {
+            pack_buf(buf, key)?;
+            pack_buf(buf, value)?;
+        }
+        Ok(())
+    }
+
+    fn unpack(buf: &mut ReadBuf<'_>, fb: u8) -> Result<Vec<(AnyTerm, AnyTerm)>> {
+        match unpack_tagged(buf, fb, &UnpackOptions::new())? {
+            AnyTerm::Map(pairs) => Ok(pairs),
+            other => Err(anyhow!("Expected a map, found {}", other.type_name())),
+        }
+    }
+
+    fn size(data: &Vec<(AnyTerm, AnyTerm)>) -> Result<usize> {
+        u32::try_from(data.len())?;
+        data.iter().try_fold(5, |size, (key, value)| {
+            Ok(size + term_size(key)? + term_size(value)?)
+        })
+    }
+
+    fn can_pack(data: &AnyTerm) -> bool {
+        matches!(data, AnyTerm::Map(_))
+    }
+
+    fn can_unpack(first_byte: &u8) -> bool {
+        first_byte == &MAP_EXT
+    }
+}
+
+impl MapPacker {
+    /// Reads the number of pairs that follows the first byte.
+    pub(crate) fn read_arity(buf: &mut ReadBuf<'_>) -> Result<usize> {
+        Ok(u32::from_be_bytes(buf.take(4)?.try_into().unwrap()) as usize)
+    }
+}
+
+impl MapPacker {
+    /// Writes the start of a map with this many pairs, which have to follow
+    /// as a key and then a value each.
+    pub(crate) fn write_header(buf: &mut Vec<u8>, arity: usize) -> Result<()> {
+        let arity = u32::try_from(arity)?;
+        write_bytes(buf, vec![MAP_EXT])?;
+        write_bytes(buf, arity.to_be_bytes().to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACKED_MAP: [u8; 10] = [116, 0, 0, 0, 1, 119, 1, 97, 97, 1];
+
+    fn value() -> Vec<(AnyTerm, AnyTerm)> {
+        vec![(AnyTerm::from(Atom::from("a")), AnyTerm::SmallInt(1))]
+    }
+
+    #[test]
+    fn pack() {
+        let mut buf = Vec::new();
+        MapPacker::pack(value(), &mut buf).unwrap();
+        assert_eq!(buf, PACKED_MAP);
+        assert_eq!(MapPacker::size(&value()).unwrap(), PACKED_MAP.len());
+    }
+
+    #[test]
+    fn unpack() {
+        let mut buf = ReadBuf::new(&PACKED_MAP);
+        let fb = read_bytes(&mut buf, 1).unwrap()[0];
+        assert_eq!(MapPacker::unpack(&mut buf, fb).unwrap(), value());
+    }
+}
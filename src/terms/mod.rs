@@ -1,43 +1,65 @@
-//! Copyright 2022 andre4ik3
-//!
-//! Licensed under the Apache License, Version 2.0 (the "License");
-//! you may not use this file except in compliance with the License.
-//! You may obtain a copy of the License at
-//!
-//!     http://www.apache.org/licenses/LICENSE-2.0
-//!
-//! Unless required by applicable law or agreed to in writing, software
-//! distributed under the License is distributed on an "AS IS" BASIS,
-//! WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
-//! See the License for the specific language governing permissions and
-//! limitations under the License.
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
 
 mod atom;
+mod bigint;
+mod binary;
 mod float;
 mod integer;
+mod list;
+mod map;
+mod pid;
+mod port;
+mod reference;
 mod small_integer;
 mod string;
+mod tuple;
 
 pub use atom::*;
+pub use bigint::*;
+pub use binary::*;
 pub use float::*;
 pub use integer::*;
+pub use list::*;
+pub use map::*;
+pub use pid::*;
+pub use port::*;
+pub use reference::*;
 pub use small_integer::*;
 pub use string::*;
+pub use tuple::*;
 
 use crate::structs::*;
 
+use crate::utils::ReadBuf;
+
+use alloc::{borrow::Cow, string::String, vec::Vec};
 use anyhow::Result;
-use std::io::{BufReader, BufWriter, Cursor};
 
 /// Represents an Erlang term.
 pub trait Term<T> {
     /// This function should write the data to the buffer.
     /// It should write the Term ID as the first byte.
-    fn pack(data: T, buf: &mut BufWriter<Cursor<Vec<u8>>>) -> Result<()>;
+    fn pack(data: T, buf: &mut Vec<u8>) -> Result<()>;
 
     /// This function should read the buffer and return the data.
     /// It should look at the first byte to determine the term variant.
-    fn unpack(buf: &mut BufReader<Cursor<Vec<u8>>>, fb: u8) -> Result<T>;
+    fn unpack(buf: &mut ReadBuf<'_>, fb: u8) -> Result<T>;
+
+    /// This function should return the number of bytes pack would write,
+    /// including the Term ID, or fail if pack would fail.
+    fn size(data: &T) -> Result<usize>;
 
     // Return true if this data type can be packed by the implementation.
     fn can_pack(data: &AnyTerm) -> bool;
@@ -48,11 +70,184 @@ pub trait Term<T> {
 }
 
 /// Represents any term value, in unpacked form.
+#[derive(Debug, Clone, PartialEq)]
 pub enum AnyTerm {
     SmallInt(u8),
     Integer(i32),
+    BigInt(BigInt),
     Float(f64),
+    /// Only produced by lossless unpacking; see UnpackOptions.
+    LegacyFloat(LegacyFloat),
     Port(Port),
+    Pid(Pid),
+    Reference(Reference),
     Atom(Atom),
+    /// A list of bytes packed as STRING_EXT, with one char per byte.
     String(String),
+    /// The empty list.
+    Nil,
+    List(List),
+    Tuple(Vec<AnyTerm>),
+    /// The pairs of a map, in the order they were packed in.
+    Map(Vec<(AnyTerm, AnyTerm)>),
+    Binary(Vec<u8>),
+    BitBinary(BitBinary),
+}
+
+impl AnyTerm {
+    /// A short human-readable name for the kind of term, used in errors.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            AnyTerm::SmallInt(_) | AnyTerm::Integer(_) | AnyTerm::BigInt(_) => "integer",
+            AnyTerm::Float(_) | AnyTerm::LegacyFloat(_) => "float",
+            AnyTerm::Port(_) => "port",
+            AnyTerm::Pid(_) => "pid",
+            AnyTerm::Reference(_) => "reference",
+            AnyTerm::Atom(_) => "atom",
+            AnyTerm::String(_) => "string",
+            AnyTerm::Nil | AnyTerm::List(_) => "list",
+            AnyTerm::Tuple(_) => "tuple",
+            AnyTerm::Map(_) => "map",
+            AnyTerm::Binary(_) => "binary",
+            AnyTerm::BitBinary(_) => "bitstring",
+        }
+    }
+
+    /// Returns the value of an integer, whether it is small or not.
+    pub fn as_integer(&self) -> Option<i32> {
+        match self {
+            AnyTerm::SmallInt(value) => Some((*value).into()),
+            AnyTerm::Integer(value) => Some(*value),
+            AnyTerm::BigInt(value) => value.to_i128().and_then(|value| value.try_into().ok()),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of a float.
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            AnyTerm::Float(value) => Some(*value),
+            AnyTerm::LegacyFloat(float) => Some(float.value),
+            _ => None,
+        }
+    }
+
+    /// Returns the atom, if the term is one.
+    pub fn as_atom(&self) -> Option<&Atom> {
+        match self {
+            AnyTerm::Atom(atom) => Some(atom),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of an atom, if the term is one.
+    pub fn as_atom_str(&self) -> Option<&str> {
+        self.as_atom().map(|atom| atom.value.as_str())
+    }
+
+    /// Returns the value of a string (STRING_EXT).
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            AnyTerm::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the port, if the term is one.
+    pub fn as_port(&self) -> Option<&Port> {
+        match self {
+            AnyTerm::Port(port) => Some(port),
+            _ => None,
+        }
+    }
+
+    /// Returns the pid, if the term is one.
+    pub fn as_pid(&self) -> Option<&Pid> {
+        match self {
+            AnyTerm::Pid(pid) => Some(pid),
+            _ => None,
+        }
+    }
+
+    /// Returns the reference, if the term is one.
+    pub fn as_reference(&self) -> Option<&Reference> {
+        match self {
+            AnyTerm::Reference(reference) => Some(reference),
+            _ => None,
+        }
+    }
+
+    /// Returns the elements of a tuple.
+    pub fn as_tuple(&self) -> Option<&[AnyTerm]> {
+        match self {
+            AnyTerm::Tuple(elements) => Some(elements),
+            _ => None,
+        }
+    }
+
+    /// Returns the elements of a proper list. The elements of strings packed
+    /// as STRING_EXT are made on the fly, since there are none to borrow.
+    pub fn as_list(&self) -> Option<Cow<'_, [AnyTerm]>> {
+        match self {
+            AnyTerm::Nil => Some(Cow::Borrowed(&[])),
+            AnyTerm::List(list) if list.is_proper() => Some(Cow::Borrowed(&list.elements)),
+            AnyTerm::String(value) => Some(Cow::Owned(value.chars().map(char_term).collect())),
+            _ => None,
+        }
+    }
+
+    /// Returns the pairs of a map.
+    pub fn as_map(&self) -> Option<&[(AnyTerm, AnyTerm)]> {
+        match self {
+            AnyTerm::Map(pairs) => Some(pairs),
+            _ => None,
+        }
+    }
+
+    /// Returns the bytes of a binary.
+    pub fn as_binary(&self) -> Option<&[u8]> {
+        match self {
+            AnyTerm::Binary(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the term is the atom with the given name.
+    pub fn is_atom(&self, name: &str) -> bool {
+        self.as_atom_str() == Some(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accessors() {
+        let atom = AnyTerm::Atom(Atom::from("ok"));
+        assert_eq!(atom.as_atom_str(), Some("ok"));
+        assert!(atom.is_atom("ok"));
+        assert!(!atom.is_atom("error"));
+        assert_eq!(atom.as_integer(), None);
+        assert_eq!(atom.type_name(), "atom");
+
+        assert_eq!(AnyTerm::SmallInt(5).as_integer(), Some(5));
+        assert_eq!(AnyTerm::Integer(-5).as_integer(), Some(-5));
+        assert_eq!(AnyTerm::Float(1.5).as_float(), Some(1.5));
+        assert_eq!(AnyTerm::String("hi".to_string()).as_str(), Some("hi"));
+        assert_eq!(AnyTerm::String("hi".to_string()).as_atom(), None);
+
+        assert_eq!(AnyTerm::Binary(vec![1, 2]).as_binary(), Some(&[1, 2][..]));
+        assert_eq!(AnyTerm::Nil.as_list().as_deref(), Some(&[][..]));
+        let list = AnyTerm::from(vec![AnyTerm::SmallInt(1)]);
+        assert_eq!(list.as_list().as_deref(), Some(&[AnyTerm::SmallInt(1)][..]));
+        let string = AnyTerm::String("a".to_string());
+        assert_eq!(
+            string.as_list().as_deref(),
+            Some(&[AnyTerm::SmallInt(97)][..])
+        );
+        let map = AnyTerm::Map(vec![(atom.clone(), AnyTerm::Nil)]);
+        assert_eq!(map.as_map(), Some(&[(atom, AnyTerm::Nil)][..]));
+        assert_eq!(map.as_binary(), None);
+    }
 }
@@ -0,0 +1,107 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::port::{read_node, read_u32};
+use super::*;
+use crate::utils::*;
+
+use alloc::vec;
+use anyhow::*;
+
+const PID_EXT: u8 = 103;
+const NEW_PID_EXT: u8 = 88;
+
+pub struct PidPacker;
+impl Term<Pid> for PidPacker {
+    fn pack(data: Pid, buf: &mut Vec<u8>) -> Result<()> {
+        PidPacker::size(&data)?;
+        let first_byte = match data.kind {
+            PidKind::Pid => PID_EXT,
+            PidKind::NewPid => NEW_PID_EXT,
+        };
+
+        write_bytes(buf, vec![first_byte])?;
+        AtomPacker::pack(data.node, buf)?;
+        write_bytes(buf, data.id.to_be_bytes().to_vec())?;
+        write_bytes(buf, data.serial.to_be_bytes().to_vec())?;
+        match data.kind {
+            // The creation was checked above, so this doesn't truncate.
+            PidKind::Pid => write_bytes(buf, vec![data.creation as u8]),
+            PidKind::NewPid => write_bytes(buf, data.creation.to_be_bytes().to_vec()),
+        }
+    }
+
+    fn unpack(buf: &mut ReadBuf<'_>, fb: u8) -> Result<Pid> {
+        let node = read_node(buf)?;
+        let id = read_u32(buf)?;
+        let serial = read_u32(buf)?;
+        let (kind, creation) = match fb {
+            PID_EXT => (PidKind::Pid, read_bytes(buf, 1)?[0].into()),
+            NEW_PID_EXT => (PidKind::NewPid, read_u32(buf)?),
+            _ => return Err(anyhow!("Unknown first byte")),
+        };
+
+        Ok(Pid {
+            kind,
+            node,
+            id,
+            serial,
+            creation,
+        })
+    }
+
+    fn size(data: &Pid) -> Result<usize> {
+        let node = AtomPacker::size(&data.node)?;
+        match data.kind {
+            PidKind::Pid => {
+                u8::try_from(data.creation)?;
+                Ok(1 + node + 9)
+            }
+            PidKind::NewPid => Ok(1 + node + 12),
+        }
+    }
+
+    fn can_pack(data: &AnyTerm) -> bool {
+        matches!(data, AnyTerm::Pid(_))
+    }
+
+    fn can_unpack(first_byte: &u8) -> bool {
+        first_byte == &PID_EXT || first_byte == &NEW_PID_EXT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACKED_PID: [u8; 18] = [88, 119, 3, 97, 64, 98, 0, 0, 0, 80, 0, 0, 0, 0, 0, 0, 0, 1];
+
+    #[test]
+    fn pack() {
+        let pid = Pid::new(Atom::from("a@b"), 80, 0, 1);
+        assert_eq!(PidPacker::size(&pid).unwrap(), PACKED_PID.len());
+
+        let mut buf = Vec::new();
+        PidPacker::pack(pid, &mut buf).unwrap();
+        assert_eq!(buf, PACKED_PID);
+    }
+
+    #[test]
+    fn unpack() {
+        let mut buf = ReadBuf::new(&PACKED_PID);
+        let fb = read_bytes(&mut buf, 1).unwrap()[0];
+        let pid = PidPacker::unpack(&mut buf, fb).unwrap();
+        assert_eq!(pid, Pid::new(Atom::from("a@b"), 80, 0, 1));
+    }
+}
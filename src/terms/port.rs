@@ -1,99 +1,161 @@
-// //! Copyright 2022 andre4ik3
-// //!
-// //! Licensed under the Apache License, Version 2.0 (the "License");
-// //! you may not use this file except in compliance with the License.
-// //! You may obtain a copy of the License at
-// //!
-// //!     http://www.apache.org/licenses/LICENSE-2.0
-// //!
-// //! Unless required by applicable law or agreed to in writing, software
-// //! distributed under the License is distributed on an "AS IS" BASIS,
-// //! WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
-// //! See the License for the specific language governing permissions and
-// //! limitations under the License.
-
-// use super::*;
-// use crate::utils::*;
-
-// use anyhow::*;
-
-// const PORT_EXT: u8 = 102;
-// const NEW_PORT_EXT: u8 = 89;
-// const V4_PORT_EXT: u8 = 120;
-
-// pub struct PortPacker;
-// impl Term<Port> for PortPacker {
-//     /// Always packs as a V4 port.
-//     fn pack(data: Port, buf: &mut BufWriter<Cursor<Vec<u8>>>) -> Result<()> {
-//         todo!()
-//     }
-
-//     fn unpack(buf: &mut BufReader<Cursor<Vec<u8>>>, fb: u8) -> Result<Port> {
-//         let node_fb = read_bytes(buf, 1)?[0];
-
-//         if !AtomPacker::can_unpack(&node_fb) {
-//             return Err(anyhow!("Invalid node atom."));
-//         }
-
-//         let node = AtomPacker::unpack(buf, node_fb)?;
-
-//         let id_len = match fb {};
-
-//         todo!()
-//     }
-
-//     fn can_pack(data: &AnyTerm) -> bool {
-//         match data {
-//             &AnyTerm::SmallInt(_) => true,
-//             _ => false,
-//         }
-//     }
-
-//     fn can_unpack(first_byte: &u8) -> bool {
-//         match first_byte {
-//             &PORT_EXT => true,
-//             &NEW_PORT_EXT => true,
-//             &V4_PORT_EXT => true,
-//             _ => false,
-//         }
-//     }
-// }
-
-// #[cfg(test)]
-// mod tests {
-//     use super::*;
-//     use crate::terms::AnyTerm;
-
-//     const VALUE: u8 = 248;
-//     const PACKED_INTEGER: [u8; 2] = [97, 248];
-
-//     #[test]
-//     fn can_pack() {
-//         assert_eq!(SmallIntPacker::can_pack(&AnyTerm::Integer(123)), false);
-//         assert_eq!(SmallIntPacker::can_pack(&AnyTerm::SmallInt(123)), true);
-//     }
-
-//     #[test]
-//     fn can_unpack() {
-//         assert_eq!(SmallIntPacker::can_unpack(&SMALL_INTEGER_EXT), true);
-//         assert_eq!(SmallIntPacker::can_unpack(&123), false);
-//     }
-
-//     #[test]
-//     fn pack() {
-//         let mut buf = BufWriter::new(Cursor::new(Vec::<u8>::new()));
-//         SmallIntPacker::pack(VALUE, &mut buf).unwrap();
-//         let buf = buf.into_inner().unwrap().into_inner();
-//         assert_eq!(buf, PACKED_INTEGER);
-//     }
-
-//     #[test]
-//     fn unpack() {
-//         let mut buf = BufReader::new(Cursor::new(PACKED_INTEGER.to_vec()));
-//         read_bytes(&mut buf, 1).unwrap();
-//         assert_eq!(
-//             SmallIntPacker::unpack(&mut buf, SMALL_INTEGER_EXT).unwrap(),
-//             VALUE
-//         );
-//     }
-// }
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::*;
+use crate::utils::*;
+
+use alloc::vec;
+use anyhow::*;
+
+const PORT_EXT: u8 = 102;
+const NEW_PORT_EXT: u8 = 89;
+const V4_PORT_EXT: u8 = 120;
+
+pub struct PortPacker;
+impl Term<Port> for PortPacker {
+    fn pack(data: Port, buf: &mut Vec<u8>) -> Result<()> {
+        PortPacker::size(&data)?;
+        let first_byte = match data.kind {
+            PortKind::Port => PORT_EXT,
+            PortKind::NewPort => NEW_PORT_EXT,
+            PortKind::V4 => V4_PORT_EXT,
+        };
+
+        write_bytes(buf, vec![first_byte])?;
+        AtomPacker::pack(data.node, buf)?;
+        // The sizes were checked above, so these don't truncate.
+        match data.kind {
+            PortKind::Port => {
+                write_bytes(buf, (data.id as u32).to_be_bytes().to_vec())?;
+                write_bytes(buf, vec![data.creation as u8])
+            }
+            PortKind::NewPort => {
+                write_bytes(buf, (data.id as u32).to_be_bytes().to_vec())?;
+                write_bytes(buf, data.creation.to_be_bytes().to_vec())
+            }
+            PortKind::V4 => {
+                write_bytes(buf, data.id.to_be_bytes().to_vec())?;
+                write_bytes(buf, data.creation.to_be_bytes().to_vec())
+            }
+        }
+    }
+
+    fn unpack(buf: &mut ReadBuf<'_>, fb: u8) -> Result<Port> {
+        let node = read_node(buf)?;
+        let (kind, id, creation) = match fb {
+            PORT_EXT => (
+                PortKind::Port,
+                read_u32(buf)?.into(),
+                read_bytes(buf, 1)?[0].into(),
+            ),
+            NEW_PORT_EXT => (PortKind::NewPort, read_u32(buf)?.into(), read_u32(buf)?),
+            V4_PORT_EXT => {
+                let id = u64::from_be_bytes(buf.take(8)?.try_into().unwrap());
+                (PortKind::V4, id, read_u32(buf)?)
+            }
+            _ => return Err(anyhow!("Unknown first byte")),
+        };
+
+        Ok(Port {
+            kind,
+            node,
+            id,
+            creation,
+        })
+    }
+
+    fn size(data: &Port) -> Result<usize> {
+        let node = AtomPacker::size(&data.node)?;
+        match data.kind {
+            PortKind::Port => {
+                u32::try_from(data.id)?;
+                u8::try_from(data.creation)?;
+                Ok(1 + node + 5)
+            }
+            PortKind::NewPort => {
+                u32::try_from(data.id)?;
+                Ok(1 + node + 8)
+            }
+            PortKind::V4 => Ok(1 + node + 12),
+        }
+    }
+
+    fn can_pack(data: &AnyTerm) -> bool {
+        matches!(data, AnyTerm::Port(_))
+    }
+
+    fn can_unpack(first_byte: &u8) -> bool {
+        first_byte == &PORT_EXT || first_byte == &NEW_PORT_EXT || first_byte == &V4_PORT_EXT
+    }
+}
+
+/// Reads the node atom that pids, ports and references start with.
+pub(crate) fn read_node(buf: &mut ReadBuf<'_>) -> Result<Atom> {
+    let fb = read_bytes(buf, 1)?[0];
+    if !AtomPacker::can_unpack(&fb) {
+        return Err(anyhow!("Invalid node atom"));
+    }
+    AtomPacker::unpack(buf, fb)
+}
+
+pub(crate) fn read_u32(buf: &mut ReadBuf<'_>) -> Result<u32> {
+    Ok(u32::from_be_bytes(buf.take(4)?.try_into().unwrap()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACKED_PORT: [u8; 14] = [89, 119, 3, 97, 64, 98, 0, 0, 0, 5, 0, 0, 0, 1];
+    const PACKED_OLD_PORT: [u8; 11] = [102, 119, 3, 97, 64, 98, 0, 0, 0, 5, 1];
+
+    #[test]
+    fn pack() {
+        let port = Port::new(Atom::from("a@b"), 5, 1);
+        assert_eq!(port.kind, PortKind::NewPort);
+        assert_eq!(PortPacker::size(&port).unwrap(), PACKED_PORT.len());
+
+        let mut buf = Vec::new();
+        PortPacker::pack(port, &mut buf).unwrap();
+        assert_eq!(buf, PACKED_PORT);
+
+        let port = Port::new(Atom::from("a@b"), 1 << 40, 1);
+        assert_eq!(port.kind, PortKind::V4);
+        let mut buf = Vec::new();
+        PortPacker::pack(port, &mut buf).unwrap();
+        assert_eq!(buf.len(), 18);
+    }
+
+    #[test]
+    fn unpack() {
+        for packed in [&PACKED_PORT[..], &PACKED_OLD_PORT[..]] {
+            let mut buf = ReadBuf::new(packed);
+            let fb = read_bytes(&mut buf, 1).unwrap()[0];
+            let port = PortPacker::unpack(&mut buf, fb).unwrap();
+            assert_eq!(port.node, Atom::from("a@b"));
+            assert_eq!((port.id, port.creation), (5, 1));
+
+            let mut repacked = Vec::new();
+            PortPacker::pack(port, &mut repacked).unwrap();
+            assert_eq!(repacked, packed);
+        }
+    }
+
+    #[test]
+    fn too_large() {
+        let mut port = Port::new(Atom::from("a@b"), 5, 256);
+        port.kind = PortKind::Port;
+        assert!(PortPacker::size(&port).is_err());
+    }
+}
@@ -0,0 +1,161 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::port::{read_node, read_u32};
+use super::*;
+use crate::utils::*;
+
+use alloc::vec;
+use anyhow::*;
+
+const REFERENCE_EXT: u8 = 101;
+const NEW_REFERENCE_EXT: u8 = 114;
+const NEWER_REFERENCE_EXT: u8 = 90;
+
+pub struct ReferencePacker;
+impl Term<Reference> for ReferencePacker {
+    fn pack(data: Reference, buf: &mut Vec<u8>) -> Result<()> {
+        ReferencePacker::size(&data)?;
+        // The sizes were checked above, so these don't truncate.
+        match data.kind {
+            ReferenceKind::Reference => {
+                write_bytes(buf, vec![REFERENCE_EXT])?;
+                AtomPacker::pack(data.node, buf)?;
+                write_bytes(buf, data.id[0].to_be_bytes().to_vec())?;
+                return write_bytes(buf, vec![data.creation as u8]);
+            }
+            ReferenceKind::NewReference => {
+                write_bytes(buf, vec![NEW_REFERENCE_EXT])?;
+                write_bytes(buf, (data.id.len() as u16).to_be_bytes().to_vec())?;
+                AtomPacker::pack(data.node, buf)?;
+                write_bytes(buf, vec![data.creation as u8])?;
+            }
+            ReferenceKind::NewerReference => {
+                write_bytes(buf, vec![NEWER_REFERENCE_EXT])?;
+                write_bytes(buf, (data.id.len() as u16).to_be_bytes().to_vec())?;
+                AtomPacker::pack(data.node, buf)?;
+                write_bytes(buf, data.creation.to_be_bytes().to_vec())?;
+            }
+        }
+
+        for word in data.id {
+            write_bytes(buf, word.to_be_bytes().to_vec())?;
+        }
+        Ok(())
+    }
+
+    fn unpack(buf: &mut ReadBuf<'_>, fb: u8) -> Result<Reference> {
+        if fb == REFERENCE_EXT {
+            let node = read_node(buf)?;
+            let id = vec![read_u32(buf)?];
+            let creation = read_bytes(buf, 1)?[0].into();
+            return Ok(Reference {
+                kind: ReferenceKind::Reference,
+                node,
+                creation,
+                id,
+            });
+        }
+
+        let length = u16::from_be_bytes(buf.take(2)?.try_into().unwrap());
+        let node = read_node(buf)?;
+        let (kind, creation) = match fb {
+            NEW_REFERENCE_EXT => (ReferenceKind::NewReference, read_bytes(buf, 1)?[0].into()),
+            NEWER_REFERENCE_EXT => (ReferenceKind::NewerReference, read_u32(buf)?),
+            _ => return Err(anyhow!("Unknown first byte")),
+        };
+        let id = (0..length)
+            .map(|_| read_u32(buf))
+            .collect::<Result<Vec<u32>>>()?;
+
+        Ok(Reference {
+            kind,
+            node,
+            creation,
+            id,
+        })
+    }
+
+    fn size(data: &Reference) -> Result<usize> {
+        let node = AtomPacker::size(&data.node)?;
+        match data.kind {
+            ReferenceKind::Reference => {
+                if data.id.len() != 1 {
+                    return Err(anyhow!("REFERENCE_EXT has exactly one ID word"));
+                }
+                u8::try_from(data.creation)?;
+                Ok(1 + node + 5)
+            }
+            ReferenceKind::NewReference => {
+                u16::try_from(data.id.len())?;
+                u8::try_from(data.creation)?;
+                Ok(3 + node + 1 + 4 * data.id.len())
+            }
+            ReferenceKind::NewerReference => {
+                u16::try_from(data.id.len())?;
+                Ok(3 + node + 4 + 4 * data.id.len())
+            }
+        }
+    }
+
+    fn can_pack(data: &AnyTerm) -> bool {
+        matches!(data, AnyTerm::Reference(_))
+    }
+
+    fn can_unpack(first_byte: &u8) -> bool {
+        first_byte == &REFERENCE_EXT
+            || first_byte == &NEW_REFERENCE_EXT
+            || first_byte == &NEWER_REFERENCE_EXT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACKED_REFERENCE: [u8; 24] = [
+        90, 0, 3, 119, 3, 97, 64, 98, 0, 0, 0, 1, 0, 0, 0, 7, 0, 0, 0, 8, 0, 0, 0, 9,
+    ];
+
+    fn value() -> Reference {
+        Reference::new(Atom::from("a@b"), 1, vec![7, 8, 9])
+    }
+
+    #[test]
+    fn pack() {
+        let mut buf = Vec::new();
+        ReferencePacker::pack(value(), &mut buf).unwrap();
+        assert_eq!(buf, PACKED_REFERENCE);
+        assert_eq!(ReferencePacker::size(&value()).unwrap(), buf.len());
+    }
+
+    #[test]
+    fn unpack() {
+        let mut buf = ReadBuf::new(&PACKED_REFERENCE);
+        let fb = read_bytes(&mut buf, 1).unwrap()[0];
+        assert_eq!(ReferencePacker::unpack(&mut buf, fb).unwrap(), value());
+    }
+
+    #[test]
+    fn old_reference() {
+        let mut reference = value();
+        reference.kind = ReferenceKind::Reference;
+        assert!(ReferencePacker::size(&reference).is_err());
+
+        reference.id = vec![7];
+        let mut buf = Vec::new();
+        ReferencePacker::pack(reference.clone(), &mut buf).unwrap();
+        assert_eq!(buf, [101, 119, 3, 97, 64, 98, 0, 0, 0, 7, 1]);
+    }
+}
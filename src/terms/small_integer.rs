@@ -1,17 +1,18 @@
-//! Copyright 2022 andre4ik3
-//!
-//! Licensed under the Apache License, Version 2.0 (the "License");
-//! you may not use this file except in compliance with the License.
-//! You may obtain a copy of the License at
-//!
-//!     http://www.apache.org/licenses/LICENSE-2.0
-//!
-//! Unless required by applicable law or agreed to in writing, software
-//! distributed under the License is distributed on an "AS IS" BASIS,
-//! WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
-//! See the License for the specific language governing permissions and
-//! limitations under the License.
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
 
+use alloc::vec;
 use anyhow::*;
 
 use super::*;
@@ -21,21 +22,22 @@ const SMALL_INTEGER_EXT: u8 = 97;
 
 pub struct SmallIntPacker;
 impl Term<u8> for SmallIntPacker {
-    fn pack(data: u8, buf: &mut BufWriter<Cursor<Vec<u8>>>) -> Result<()> {
+    fn pack(data: u8, buf: &mut Vec<u8>) -> Result<()> {
         write_bytes(buf, vec![SMALL_INTEGER_EXT, data])?;
         Ok(())
     }
 
-    fn unpack(buf: &mut BufReader<Cursor<Vec<u8>>>, _: u8) -> Result<u8> {
+    fn unpack(buf: &mut ReadBuf<'_>, _: u8) -> Result<u8> {
         let bytes = read_bytes(buf, 1)?;
         Ok(bytes[0])
     }
 
+    fn size(_: &u8) -> Result<usize> {
+        Ok(2)
+    }
+
     fn can_pack(data: &AnyTerm) -> bool {
-        match data {
-            &AnyTerm::SmallInt(_) => true,
-            _ => false,
-        }
+        matches!(data, AnyTerm::SmallInt(_))
     }
 
     fn can_unpack(first_byte: &u8) -> bool {
@@ -52,15 +54,14 @@ mod tests {
 
     #[test]
     fn pack() {
-        let mut buf = BufWriter::new(Cursor::new(Vec::<u8>::new()));
+        let mut buf = Vec::new();
         SmallIntPacker::pack(VALUE, &mut buf).unwrap();
-        let buf = buf.into_inner().unwrap().into_inner();
         assert_eq!(buf, PACKED_INTEGER);
     }
 
     #[test]
     fn unpack() {
-        let mut buf = BufReader::new(Cursor::new(PACKED_INTEGER.to_vec()));
+        let mut buf = ReadBuf::new(&PACKED_INTEGER);
         read_bytes(&mut buf, 1).unwrap();
         assert_eq!(
             SmallIntPacker::unpack(&mut buf, SMALL_INTEGER_EXT).unwrap(),
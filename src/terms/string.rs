@@ -1,17 +1,18 @@
-//! Copyright 2022 andre4ik3
-//!
-//! Licensed under the Apache License, Version 2.0 (the "License");
-//! you may not use this file except in compliance with the License.
-//! You may obtain a copy of the License at
-//!
-//!     http://www.apache.org/licenses/LICENSE-2.0
-//!
-//! Unless required by applicable law or agreed to in writing, software
-//! distributed under the License is distributed on an "AS IS" BASIS,
-//! WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
-//! See the License for the specific language governing permissions and
-//! limitations under the License.
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
 
+use alloc::vec;
 use anyhow::*;
 
 use super::*;
@@ -21,24 +22,37 @@ const STRING_EXT: u8 = 107;
 
 pub struct StringPacker;
 impl Term<String> for StringPacker {
-    fn pack(data: String, buf: &mut BufWriter<Cursor<Vec<u8>>>) -> Result<()> {
-        let length = u16::try_from(data.len())?.to_be_bytes().to_vec();
+    /// Each char is written as one Latin-1 byte.
+    fn pack(data: String, buf: &mut Vec<u8>) -> Result<()> {
+        let bytes = latin1_bytes(&data).ok_or_else(|| anyhow!("String is not Latin-1"))?;
+        let length = u16::try_from(bytes.len())?.to_be_bytes().to_vec();
         write_bytes(buf, vec![STRING_EXT])?;
         write_bytes(buf, length)?;
-        write_bytes(buf, data.into_bytes())?;
+        write_bytes(buf, bytes)?;
         Ok(())
     }
 
-    fn unpack(buf: &mut BufReader<Cursor<Vec<u8>>>, _: u8) -> Result<String> {
+    fn unpack(buf: &mut ReadBuf<'_>, _: u8) -> Result<String> {
         let length = read_bytes(buf, 2)?;
         let length = u16::from_be_bytes(length.try_into().unwrap());
-        let bytes = read_bytes(buf, length.into())?;
-        Ok(String::from_utf8(bytes)?)
+        let bytes = buf.take(length.into())?;
+        Ok(bytes.iter().map(|&byte| char::from(byte)).collect())
+    }
+
+    fn size(data: &String) -> Result<usize> {
+        if !data.chars().all(|c| u32::from(c) <= 0xFF) {
+            return Err(anyhow!("String is not Latin-1"));
+        }
+        let length = data.chars().count();
+        u16::try_from(length)?;
+        Ok(3 + length)
     }
 
     fn can_pack(data: &AnyTerm) -> bool {
         match data {
-            AnyTerm::String(s) => s.chars().all(char::is_alphanumeric) && s.len() <= 65535,
+            AnyTerm::String(s) => {
+                s.chars().all(|c| u32::from(c) <= 0xFF) && s.chars().count() <= 65535
+            }
             _ => false,
         }
     }
@@ -48,6 +62,16 @@ impl Term<String> for StringPacker {
     }
 }
 
+/// Returns the Latin-1 bytes of a string, if every char fits into one.
+pub(crate) fn latin1_bytes(value: &str) -> Option<Vec<u8>> {
+    value.chars().map(|c| u8::try_from(c).ok()).collect()
+}
+
+/// Returns the integer term for a char of a string, as it is in the list.
+pub(crate) fn char_term(c: char) -> AnyTerm {
+    AnyTerm::from(u32::from(c) as i32)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -57,17 +81,37 @@ mod tests {
 
     #[test]
     fn pack() {
-        let mut buf = BufWriter::new(Cursor::new(Vec::<u8>::new()));
+        let mut buf = Vec::new();
         StringPacker::pack(VALUE.to_string(), &mut buf).unwrap();
-        let buf = buf.into_inner().unwrap().into_inner();
         assert_eq!(buf, PACKED_VALUE);
     }
 
     #[test]
     fn unpack() {
-        let mut buf = BufReader::new(Cursor::new(PACKED_VALUE.to_vec()));
+        let mut buf = ReadBuf::new(&PACKED_VALUE);
         let fb = read_bytes(&mut buf, 1).unwrap()[0];
         let value = StringPacker::unpack(&mut buf, fb).unwrap();
         assert_eq!(value, VALUE.to_string());
     }
+
+    #[test]
+    fn latin1() {
+        let value = "héllo wörld".to_string();
+        let term = AnyTerm::String(value.clone());
+        assert!(StringPacker::can_pack(&term));
+
+        let mut buf = Vec::new();
+        StringPacker::pack(value.clone(), &mut buf).unwrap();
+        assert_eq!(buf[..4], [107, 0, 11, 104]);
+        assert_eq!(buf[4], 0xE9);
+        assert_eq!(buf.len(), StringPacker::size(&value).unwrap());
+
+        let mut read = ReadBuf::new(&buf);
+        let fb = read_bytes(&mut read, 1).unwrap()[0];
+        assert_eq!(StringPacker::unpack(&mut read, fb).unwrap(), value);
+
+        assert!(!StringPacker::can_pack(&AnyTerm::String("€".to_string())));
+        assert!(StringPacker::size(&"€".to_string()).is_err());
+        assert!(!StringPacker::can_pack(&AnyTerm::String("a".repeat(65536))));
+    }
 }
@@ -0,0 +1,134 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::*;
+use crate::packing::{pack_buf, term_size, unpack_tagged};
+use crate::utils::*;
+use crate::UnpackOptions;
+
+use alloc::vec;
+use anyhow::*;
+
+const SMALL_TUPLE_EXT: u8 = 104;
+const LARGE_TUPLE_EXT: u8 = 105;
+
+pub struct TuplePacker;
+impl Term<Vec<AnyTerm>> for TuplePacker {
+    /// Packs as a small tuple when the arity fits into a byte.
+    fn pack(data: Vec<AnyTerm>, buf: &mut Vec<u8>) -> Result<()> {
+        TuplePacker::write_header(buf, data.len())?;
+        for element in data {
+            pack_buf(buf, element)?;
+        }
+        Ok(())
+    }
+
+    fn unpack(buf: &mut ReadBuf<'_>, fb: u8) -> Result<Vec<AnyTerm>> {
+        match unpack_tagged(buf, fb, &UnpackOptions::new())? {
+            AnyTerm::Tuple(elements) => Ok(elements),
+            other => Err(anyhow!("Expected a tuple, found {}", other.type_name())),
+        }
+    }
+
+    fn size(data: &Vec<AnyTerm>) -> Result<usize> {
+        let header = match u8::try_from(data.len()) {
+            Result::Ok(_) => 2,
+            Err(_) => {
+                u32::try_from(data.len())?;
+                5
+            }
+        };
+
+        data.iter()
+            .try_fold(header, |size, element| Ok(size + term_size(element)?))
+    }
+
+    fn can_pack(data: &AnyTerm) -> bool {
+        matches!(data, AnyTerm::Tuple(_))
+    }
+
+    fn can_unpack(first_byte: &u8) -> bool {
+        first_byte == &SMALL_TUPLE_EXT || first_byte == &LARGE_TUPLE_EXT
+    }
+}
+
+impl TuplePacker {
+    /// Writes the start of a tuple with this many elements, which have to
+    /// follow.
+    pub(crate) fn write_header(buf: &mut Vec<u8>, arity: usize) -> Result<()> {
+        match u8::try_from(arity) {
+            Result::Ok(arity) => write_bytes(buf, vec![SMALL_TUPLE_EXT, arity]),
+            Err(_) => {
+                write_bytes(buf, vec![LARGE_TUPLE_EXT])?;
+                write_bytes(buf, u32::try_from(arity)?.to_be_bytes().to_vec())
+            }
+        }
+    }
+
+    /// Reads the arity that follows the first byte. In lossless mode, large
+    /// tuples that would fit into a small one are an error, since they would
+    /// be packed differently.
+    pub(crate) fn read_arity(buf: &mut ReadBuf<'_>, fb: u8, lossless: bool) -> Result<usize> {
+        if fb == SMALL_TUPLE_EXT {
+            return Ok(read_bytes(buf, 1)?[0].into());
+        }
+
+        let arity = u32::from_be_bytes(buf.take(4)?.try_into().unwrap()) as usize;
+        if lossless && arity <= u8::MAX.into() {
+            return Err(anyhow!(
+                "A large tuple of {} elements can't be unpacked losslessly",
+                arity
+            ));
+        }
+        Ok(arity)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PACKED_TUPLE: [u8; 7] = [104, 2, 97, 1, 119, 1, 97];
+
+    fn value() -> Vec<AnyTerm> {
+        vec![AnyTerm::SmallInt(1), AnyTerm::from(Atom::from("a"))]
+    }
+
+    #[test]
+    fn pack() {
+        let mut buf = Vec::new();
+        TuplePacker::pack(value(), &mut buf).unwrap();
+        assert_eq!(buf, PACKED_TUPLE);
+        assert_eq!(TuplePacker::size(&value()).unwrap(), PACKED_TUPLE.len());
+    }
+
+    #[test]
+    fn unpack() {
+        let mut buf = ReadBuf::new(&PACKED_TUPLE);
+        let fb = read_bytes(&mut buf, 1).unwrap()[0];
+        assert_eq!(TuplePacker::unpack(&mut buf, fb).unwrap(), value());
+    }
+
+    #[test]
+    fn large() {
+        let elements = vec![AnyTerm::Nil; 300];
+        let mut buf = Vec::new();
+        TuplePacker::pack(elements.clone(), &mut buf).unwrap();
+        assert_eq!(buf[..5], [105, 0, 0, 1, 44]);
+
+        let mut read = ReadBuf::new(&buf);
+        let fb = read_bytes(&mut read, 1).unwrap()[0];
+        assert_eq!(TuplePacker::unpack(&mut read, fb).unwrap(), elements);
+    }
+}
@@ -0,0 +1,225 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::convert::list_elements;
+use crate::packing::*;
+use crate::structs::*;
+use crate::terms::*;
+
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use anyhow::*;
+
+const UNDEFINED: &str = "undefined";
+
+/// A Rust value that can be written as a term, without building an AnyTerm.
+pub trait IntoTerm {
+    /// This function should write the value to the buffer as a single term,
+    /// starting with its Term ID. The format version is not written.
+    fn write_term(self, buf: &mut Vec<u8>) -> Result<()>;
+}
+
+/// A Rust value that can be read back from an unpacked term.
+pub trait FromTerm: Sized {
+    /// This function should convert the term, or explain why it can't.
+    fn from_term(term: AnyTerm) -> Result<Self>;
+}
+
+impl IntoTerm for AnyTerm {
+    fn write_term(self, buf: &mut Vec<u8>) -> Result<()> {
+        pack_buf(buf, self)
+    }
+}
+
+impl FromTerm for AnyTerm {
+    fn from_term(term: AnyTerm) -> Result<Self> {
+        Ok(term)
+    }
+}
+
+impl IntoTerm for Atom {
+    fn write_term(self, buf: &mut Vec<u8>) -> Result<()> {
+        AtomPacker::pack(self, buf)
+    }
+}
+
+impl IntoTerm for String {
+    /// Written like AnyTerm::from: STRING_EXT when it fits, otherwise a
+    /// list of code points.
+    fn write_term(self, buf: &mut Vec<u8>) -> Result<()> {
+        pack_buf(buf, self.into())
+    }
+}
+
+impl IntoTerm for &str {
+    fn write_term(self, buf: &mut Vec<u8>) -> Result<()> {
+        self.to_string().write_term(buf)
+    }
+}
+
+impl IntoTerm for f64 {
+    fn write_term(self, buf: &mut Vec<u8>) -> Result<()> {
+        FloatPacker::pack(self, buf)
+    }
+}
+
+impl IntoTerm for f32 {
+    fn write_term(self, buf: &mut Vec<u8>) -> Result<()> {
+        FloatPacker::pack(self.into(), buf)
+    }
+}
+
+impl IntoTerm for bool {
+    fn write_term(self, buf: &mut Vec<u8>) -> Result<()> {
+        pack_buf(buf, self.into())
+    }
+}
+
+impl<T: IntoTerm> IntoTerm for Option<T> {
+    /// `None` is written as the atom `undefined`.
+    fn write_term(self, buf: &mut Vec<u8>) -> Result<()> {
+        match self {
+            Some(value) => value.write_term(buf),
+            None => AtomPacker::pack(Atom::from(UNDEFINED), buf),
+        }
+    }
+}
+
+impl<T: FromTerm> FromTerm for Option<T> {
+    fn from_term(term: AnyTerm) -> Result<Self> {
+        match term {
+            AnyTerm::Atom(atom) if atom.value == UNDEFINED => Ok(None),
+            other => Ok(Some(T::from_term(other)?)),
+        }
+    }
+}
+
+impl<T: IntoTerm> IntoTerm for Vec<T> {
+    /// An empty Vec is [], anything else a proper list.
+    fn write_term(self, buf: &mut Vec<u8>) -> Result<()> {
+        if !self.is_empty() {
+            ListPacker::write_header(buf, self.len())?;
+            for element in self {
+                element.write_term(buf)?;
+            }
+        }
+        NilPacker::pack((), buf)
+    }
+}
+
+impl<T: FromTerm> FromTerm for Vec<T> {
+    fn from_term(term: AnyTerm) -> Result<Self> {
+        list_elements(term)?.into_iter().map(T::from_term).collect()
+    }
+}
+
+/// Everything with a From impl for AnyTerm is written through it.
+macro_rules! into_term_via_from {
+    ($($ty:ty),*) => {$(
+        impl IntoTerm for $ty {
+            fn write_term(self, buf: &mut Vec<u8>) -> Result<()> {
+                pack_buf(buf, self.into())
+            }
+        }
+    )*};
+}
+
+into_term_via_from!(Port, Pid, Reference, BigInt);
+
+/// Integers pick the same small or regular encoding as their AnyTerm form,
+/// which doesn't allocate.
+macro_rules! into_term_integer {
+    ($($ty:ty),*) => {$(
+        impl IntoTerm for $ty {
+            fn write_term(self, buf: &mut Vec<u8>) -> Result<()> {
+                pack_buf(buf, AnyTerm::from(self))
+            }
+        }
+    )*};
+}
+
+into_term_integer!(u8, i8, u16, i16, u32, i32, u64, i64, u128, i128, usize, isize);
+
+/// Everything with a TryFrom<AnyTerm> impl converts the same way.
+macro_rules! from_term_via_try_from {
+    ($($ty:ty),*) => {$(
+        impl FromTerm for $ty {
+            fn from_term(term: AnyTerm) -> Result<Self> {
+                <$ty>::try_from(term)
+            }
+        }
+    )*};
+}
+
+from_term_via_try_from!(u8, i8, u16, i16, u32, i32, u64, i64, u128, i128, usize, isize);
+from_term_via_try_from!(f32, f64, bool, String, Atom, Port, Pid, Reference);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn written(value: impl IntoTerm) -> Vec<u8> {
+        let mut buf = Vec::new();
+        value.write_term(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn write_scalars() {
+        assert_eq!(written(248u8), [97, 248]);
+        assert_eq!(written(299792458i64), [98, 17, 222, 120, 74]);
+        assert_eq!(written("Hello"), [107, 0, 5, 72, 101, 108, 108, 111]);
+        assert_eq!(written(true), [119, 4, 116, 114, 117, 101]);
+    }
+
+    #[test]
+    fn write_strings() {
+        // The same as AnyTerm::from, even when STRING_EXT can't be used.
+        for value in ["héllo", "€", ""] {
+            let mut buf = Vec::new();
+            pack_buf(&mut buf, AnyTerm::from(value)).unwrap();
+            assert_eq!(written(value), buf);
+            assert_eq!(written(value.to_string()), buf);
+        }
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(written(Vec::<u8>::new()), [106]);
+        assert_eq!(written(vec![1u8, 2]), [108, 0, 0, 0, 2, 97, 1, 97, 2, 106]);
+
+        let list = AnyTerm::from(vec![AnyTerm::SmallInt(1), AnyTerm::SmallInt(2)]);
+        assert_eq!(Vec::<u8>::from_term(list).unwrap(), [1, 2]);
+        let string = AnyTerm::String("hi".to_string());
+        assert_eq!(Vec::<u8>::from_term(string).unwrap(), [104, 105]);
+    }
+
+    #[test]
+    fn write_option() {
+        assert_eq!(written(Some(1u8)), [97, 1]);
+        assert_eq!(written(None::<u8>), written(Atom::from(UNDEFINED)));
+    }
+
+    #[test]
+    fn read_option() {
+        let undefined = AnyTerm::from(Atom::from(UNDEFINED));
+        assert_eq!(Option::<u8>::from_term(undefined).unwrap(), None);
+        assert_eq!(
+            Option::<u8>::from_term(AnyTerm::SmallInt(1)).unwrap(),
+            Some(1)
+        );
+    }
+}
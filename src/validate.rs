@@ -0,0 +1,1064 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::limits::{DecodeLimits, Limit, LimitExceeded};
+use crate::packing::{COMPRESSED, FORMAT_VERSION};
+use crate::policy::{PolicyViolation, SafePolicy};
+use crate::terms::AnyTerm;
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::{self, Display, Formatter, Write};
+
+const NEW_FLOAT_EXT: u8 = 70;
+const BIT_BINARY_EXT: u8 = 77;
+const NEW_PID_EXT: u8 = 88;
+const NEW_PORT_EXT: u8 = 89;
+const NEWER_REFERENCE_EXT: u8 = 90;
+const SMALL_INTEGER_EXT: u8 = 97;
+const INTEGER_EXT: u8 = 98;
+const FLOAT_EXT: u8 = 99;
+const ATOM_EXT: u8 = 100;
+const REFERENCE_EXT: u8 = 101;
+const PORT_EXT: u8 = 102;
+const PID_EXT: u8 = 103;
+const SMALL_TUPLE_EXT: u8 = 104;
+const LARGE_TUPLE_EXT: u8 = 105;
+const NIL_EXT: u8 = 106;
+const STRING_EXT: u8 = 107;
+const LIST_EXT: u8 = 108;
+const BINARY_EXT: u8 = 109;
+const SMALL_BIG_EXT: u8 = 110;
+const LARGE_BIG_EXT: u8 = 111;
+const NEW_FUN_EXT: u8 = 112;
+const EXPORT_EXT: u8 = 113;
+const NEW_REFERENCE_EXT: u8 = 114;
+const SMALL_ATOM_EXT: u8 = 115;
+const MAP_EXT: u8 = 116;
+const ATOM_UTF8_EXT: u8 = 118;
+const SMALL_ATOM_UTF8_EXT: u8 = 119;
+const V4_PORT_EXT: u8 = 120;
+
+/// Atoms can have at most this many characters.
+const MAX_ATOM_CHARS: usize = 255;
+/// References have at most this many ID words.
+const MAX_REFERENCE_WORDS: usize = 5;
+/// Compressed terms that would be larger than this aren't decompressed,
+/// so that a small input can't make validate allocate gigabytes.
+#[cfg(feature = "std")]
+const MAX_DECOMPRESSED_SIZE: usize = 64 * 1024 * 1024;
+
+/// A problem found by validate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    /// The offset of the problem from the start of the data, where the
+    /// format version is byte 0. For compressed data, this is an offset into
+    /// the decompressed data, except for bytes left after the compressed
+    /// term.
+    pub offset: usize,
+    /// Where the problem is in the term, like `/2/value` for the value of
+    /// the third pair of a map. The whole term is `/`.
+    pub path: String,
+    pub message: String,
+}
+
+impl Display for Problem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at byte {}: {}", self.path, self.offset, self.message)
+    }
+}
+
+/// The result of validate: every problem found, in the order they appear.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    pub problems: Vec<Problem>,
+}
+
+impl Report {
+    /// Returns true if no problems were found.
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if self.is_valid() {
+            return f.write_str("No problems found");
+        }
+
+        for (i, problem) in self.problems.iter().enumerate() {
+            if i > 0 {
+                f.write_char('\n')?;
+            }
+            write!(f, "{}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks that some bytes are a well formed term, without unpacking it:
+/// every tag, length and atom, the format version, and that nothing is left
+/// over after the term. This covers the whole format, so data that passes
+/// can still use terms that unpack doesn't support.
+///
+/// Problems that leave the rest of the data readable, like an atom that
+/// isn't valid UTF-8, are all reported. Unknown tags and truncated data stop
+/// the check, since nothing after them can be found.
+pub fn validate(data: &[u8]) -> Report {
+    if let Some(&[FORMAT_VERSION, COMPRESSED]) = data.get(..2) {
+        return validate_compressed(data);
+    }
+
+    let mut validator = Validator::new(data);
+    validator.check();
+    validator.report()
+}
+
+/// Checks the term that starts at the given offset, returning where it
+/// ends, or the first problem with it.
+pub(crate) fn skip_term(data: &[u8], start: usize) -> anyhow::Result<usize> {
+    let mut validator = Validator::new(data);
+    validator.pos = start;
+    let _ = validator.walk();
+
+    match validator.problems.into_iter().next() {
+        Some(problem) => Err(anyhow::anyhow!("{}", problem)),
+        None => Ok(validator.pos),
+    }
+}
+
+/// Checks the term that starts at the given offset against some limits and
+/// a policy, without allocating anything for it. Other problems are left
+/// for the decoder to report.
+pub(crate) fn check_options(
+    data: &[u8],
+    start: usize,
+    limits: &DecodeLimits,
+    policy: Option<&SafePolicy>,
+) -> anyhow::Result<()> {
+    let mut validator = Validator::new(data);
+    validator.pos = start;
+    validator.limits = Some(limits.clone());
+    validator.policy = policy;
+    let _ = validator.walk();
+
+    if let Some(exceeded) = validator.exceeded {
+        return Err(anyhow::Error::new(exceeded));
+    }
+    match validator.violation {
+        Some(violation) => Err(anyhow::Error::new(violation)),
+        None => Ok(()),
+    }
+}
+
+/// Finds where a term ends in data that arrives a piece at a time. Each scan
+/// carries on from the subterm the last one stopped at, so the data before
+/// it isn't walked again.
+#[cfg(feature = "std")]
+pub(crate) struct TermScan {
+    /// Where the subterm that stopped the last scan starts.
+    pos: usize,
+    stack: Vec<Frame>,
+}
+
+#[cfg(feature = "std")]
+impl TermScan {
+    /// Starts looking for the end of the term that starts at start.
+    pub(crate) fn new(start: usize) -> Self {
+        TermScan {
+            pos: start,
+            stack: Vec::new(),
+        }
+    }
+
+    /// Returns where the term ends, or None if the data ends first. The data
+    /// has to start with the same bytes as it did for the last scan.
+    pub(crate) fn scan(&mut self, data: &[u8]) -> anyhow::Result<Option<usize>> {
+        let mut validator = Validator::new(data);
+        validator.pos = self.pos;
+        validator.stack = core::mem::take(&mut self.stack);
+        let _ = validator.walk();
+
+        if validator.truncated {
+            // Containers are only pushed once their header is read, so the
+            // stack is as it was before the subterm that ran out of data.
+            if let Some(frame) = validator.stack.last_mut() {
+                frame.started -= 1;
+            }
+            self.pos = validator.term_start;
+            self.stack = validator.stack;
+            return Ok(None);
+        }
+        match validator.problems.into_iter().next() {
+            Some(problem) => Err(anyhow::anyhow!("{}", problem)),
+            None => Ok(Some(validator.pos)),
+        }
+    }
+}
+
+/// Every how many children of a container the start of one is kept, so that
+/// finding a child only skips over the few before it. Containers with no more
+/// subterms than this aren't kept at all, since skipping over them is cheap.
+pub(crate) const STRIDE: usize = 16;
+
+/// Where the containers in some data are, as found by index. Other terms
+/// aren't kept, since their header says where they end.
+#[derive(Debug, Default)]
+pub(crate) struct Layout {
+    /// The containers with more than STRIDE subterms, in the order they
+    /// start.
+    pub containers: Vec<Container>,
+    /// Where every STRIDE-th child of each container starts, after the first.
+    pub marks: Vec<usize>,
+    /// The number of subterms, including the whole term.
+    pub terms: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Container {
+    pub start: usize,
+    pub end: usize,
+    /// The index in marks of the container's first mark.
+    pub marks: usize,
+}
+
+/// Validates uncompressed data like validate, and also returns where its
+/// containers are.
+pub(crate) fn index(data: &[u8]) -> (Report, Layout) {
+    let mut validator = Validator::new(data);
+    validator.layout = Some(Layout::default());
+    validator.check();
+    let mut layout = validator.layout.take().unwrap_or_default();
+    // Containers are kept as they end, after their children.
+    layout
+        .containers
+        .sort_unstable_by_key(|container| container.start);
+    (validator.report(), layout)
+}
+
+/// Reads the header of a term in data that was already checked. Returns
+/// where its children start, or where it ends if it has none, and how many
+/// children it has.
+pub(crate) fn header(data: &[u8], start: usize) -> (usize, usize) {
+    let mut validator = Validator::new(data);
+    validator.pos = start;
+    let _ = validator.tag(start);
+    let children = validator.stack.last().map_or(0, |frame| frame.len);
+    (validator.pos, children)
+}
+
+#[cfg(feature = "std")]
+fn validate_compressed(data: &[u8]) -> Report {
+    match crate::packing::decompress_term_prefix(&data[2..], MAX_DECOMPRESSED_SIZE) {
+        Result::Ok((term, used)) => {
+            let mut decompressed = Vec::with_capacity(term.len() + 1);
+            decompressed.push(FORMAT_VERSION);
+            decompressed.extend(term);
+            let mut report = validate(&decompressed);
+
+            let end = 2 + used;
+            if end < data.len() {
+                report.problems.push(Problem {
+                    offset: end,
+                    path: String::from("/"),
+                    message: format!("{} bytes left after the compressed term", data.len() - end),
+                });
+            }
+            report
+        }
+        Err(error) => {
+            let mut validator = Validator::new(data);
+            validator.problem(2, &format!("Failed to decompress: {:#}", error));
+            validator.report()
+        }
+    }
+}
+
+#[cfg(not(feature = "std"))]
+fn validate_compressed(data: &[u8]) -> Report {
+    let mut validator = Validator::new(data);
+    validator.problem(1, "Compressed terms need the std feature");
+    validator.report()
+}
+
+/// Returned when the rest of the data can't be checked.
+struct Stop;
+
+type Step = core::result::Result<(), Stop>;
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Tuple,
+    /// The elements of a list, then its tail.
+    List,
+    /// Keys and values, one after the other.
+    Map,
+    /// The free variables of a fun, which has to end at the given offset.
+    Fun {
+        end: usize,
+    },
+}
+
+/// A container whose elements are being checked.
+struct Frame {
+    kind: Kind,
+    /// The number of terms in the container.
+    len: usize,
+    /// The number of terms started so far.
+    started: usize,
+    /// Where the container is, when indexing.
+    open: Option<Open>,
+}
+
+/// A container that's being indexed.
+#[derive(Clone, Copy)]
+struct Open {
+    start: usize,
+    /// The number of terms in the layout when the container started.
+    terms: usize,
+    /// The number of marks of open containers when the container started.
+    marks: usize,
+}
+
+impl Frame {
+    fn write_segment(&self, path: &mut String) {
+        let index = self.started - 1;
+        let _ = match self.kind {
+            Kind::Tuple => write!(path, "/{}", index),
+            Kind::List if index == self.len - 1 => write!(path, "/tail"),
+            Kind::List => write!(path, "/{}", index),
+            Kind::Map if index % 2 == 0 => write!(path, "/{}/key", index / 2),
+            Kind::Map => write!(path, "/{}/value", index / 2),
+            Kind::Fun { .. } => write!(path, "/free/{}", index),
+        };
+    }
+}
+
+/// Walks the data with an explicit stack, so that deeply nested terms can't
+/// overflow the real one.
+struct Validator<'a> {
+    data: &'a [u8],
+    pos: usize,
+    stack: Vec<Frame>,
+    problems: Vec<Problem>,
+    layout: Option<Layout>,
+    /// Marks of the open containers, which are moved to the layout as each
+    /// one ends, so that the marks of a container stay together.
+    marks: Vec<usize>,
+    /// Whether the data ended too soon.
+    truncated: bool,
+    /// Where the last term that was started begins.
+    term_start: usize,
+    limits: Option<DecodeLimits>,
+    /// Roughly how many bytes decoding would allocate so far.
+    allocated: usize,
+    atoms: usize,
+    /// The limit that stopped the walk, if any.
+    exceeded: Option<LimitExceeded>,
+    policy: Option<&'a SafePolicy>,
+    /// The term that broke the policy, if any.
+    violation: Option<PolicyViolation>,
+}
+
+impl<'a> Validator<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Validator {
+            data,
+            pos: 0,
+            stack: Vec::new(),
+            problems: Vec::new(),
+            layout: None,
+            marks: Vec::new(),
+            truncated: false,
+            term_start: 0,
+            limits: None,
+            allocated: 0,
+            atoms: 0,
+            exceeded: None,
+            policy: None,
+            violation: None,
+        }
+    }
+
+    fn check(&mut self) {
+        match self.data.first() {
+            None => self.problem(0, "Data is empty"),
+            Some(&FORMAT_VERSION) => {
+                self.pos = 1;
+                self.run();
+            }
+            Some(&version) => self.problem(
+                0,
+                &format!(
+                    "Expected format version {}, found {}",
+                    FORMAT_VERSION, version
+                ),
+            ),
+        }
+    }
+
+    fn report(self) -> Report {
+        Report {
+            problems: self.problems,
+        }
+    }
+
+    fn path(&self) -> String {
+        if self.stack.is_empty() {
+            return String::from("/");
+        }
+
+        let mut path = String::new();
+        for frame in &self.stack {
+            frame.write_segment(&mut path);
+        }
+        path
+    }
+
+    fn problem(&mut self, offset: usize, message: &str) {
+        let path = self.path();
+        self.problems.push(Problem {
+            offset,
+            path,
+            message: message.into(),
+        });
+    }
+
+    fn run(&mut self) {
+        if self.walk().is_err() {
+            return;
+        }
+
+        let remaining = self.data.len() - self.pos;
+        if remaining > 0 {
+            let message = format!("{} bytes left after the term", remaining);
+            self.problem(self.pos, &message);
+        }
+    }
+
+    /// Checks one term and everything in it.
+    fn walk(&mut self) -> Step {
+        // A walk that was cut short carries on with the stack it had.
+        if self.stack.is_empty() {
+            self.term()?;
+        }
+
+        while let Some(frame) = self.stack.last_mut() {
+            if frame.started < frame.len {
+                frame.started += 1;
+                self.term()?;
+                continue;
+            }
+
+            // Popped first, so that problems with the container itself are
+            // reported at its own path. A fun with the wrong size could still
+            // be read, so the check carries on after it.
+            let kind = frame.kind;
+            if let Some(open) = frame.open {
+                self.finish_container(open);
+            }
+            self.stack.pop();
+            if let Kind::Fun { end } = kind {
+                if self.pos != end {
+                    let message = format!("Fun should end at byte {}", end);
+                    self.problem(self.pos, &message);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn take(&mut self, num: usize) -> core::result::Result<&'a [u8], Stop> {
+        match self.data.get(self.pos..).and_then(|rest| rest.get(..num)) {
+            Some(bytes) => {
+                self.pos += num;
+                Ok(bytes)
+            }
+            None => {
+                let missing = num - (self.data.len() - self.pos);
+                let message = format!("Data ends {} bytes too soon", missing);
+                self.problem(self.pos, &message);
+                self.truncated = true;
+                Err(Stop)
+            }
+        }
+    }
+
+    fn u8(&mut self) -> core::result::Result<u8, Stop> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> core::result::Result<usize, Stop> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]).into())
+    }
+
+    fn u32(&mut self) -> core::result::Result<usize, Stop> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes(bytes.try_into().unwrap()) as usize)
+    }
+
+    fn push(&mut self, kind: Kind, len: usize) {
+        // Containers are pushed right after their header, so the last term
+        // that was started is the container.
+        let open = self.layout.as_ref().map(|layout| Open {
+            start: self.term_start,
+            terms: layout.terms,
+            marks: self.marks.len(),
+        });
+
+        self.stack.push(Frame {
+            kind,
+            len,
+            started: 0,
+            open,
+        });
+    }
+
+    fn finish_container(&mut self, open: Open) {
+        if let Some(layout) = self.layout.as_mut() {
+            // Its children have all ended, so the marks after its first one
+            // are its own.
+            let marks = self.marks.drain(open.marks..);
+            if layout.terms - open.terms > STRIDE {
+                let first = layout.marks.len();
+                layout.marks.extend(marks);
+                layout.containers.push(Container {
+                    start: open.start,
+                    end: self.pos,
+                    marks: first,
+                });
+            }
+        }
+    }
+
+    /// Checks the term at pos. Scalars are checked completely; containers
+    /// are pushed onto the stack, for run to check their elements.
+    fn term(&mut self) -> Step {
+        let start = self.pos;
+        self.term_start = start;
+        if let Some(layout) = self.layout.as_mut() {
+            layout.terms += 1;
+            if let Some(frame) = self.stack.last() {
+                let child = frame.started - 1;
+                if child > 0 && child % STRIDE == 0 {
+                    self.marks.push(start);
+                }
+            }
+        }
+
+        self.allocate(core::mem::size_of::<AnyTerm>())?;
+        self.tag(start)
+    }
+
+    fn tag(&mut self, start: usize) -> Step {
+        let tag = self.u8()?;
+        self.allowed(tag, start)?;
+
+        match tag {
+            SMALL_INTEGER_EXT => self.skip(1),
+            INTEGER_EXT => self.skip(4),
+            NEW_FLOAT_EXT => self.new_float(),
+            FLOAT_EXT => self.float(),
+            ATOM_EXT | SMALL_ATOM_EXT | ATOM_UTF8_EXT | SMALL_ATOM_UTF8_EXT => self.atom(tag),
+            NIL_EXT => Ok(()),
+            STRING_EXT => {
+                let len = self.u16()?;
+                self.contents(len)
+            }
+            BINARY_EXT => {
+                let len = self.u32()?;
+                self.contents(len)
+            }
+            BIT_BINARY_EXT => self.bit_binary(),
+            SMALL_BIG_EXT => {
+                let len = self.u8()?.into();
+                self.big(len)
+            }
+            LARGE_BIG_EXT => {
+                let len = self.u32()?;
+                self.big(len)
+            }
+            SMALL_TUPLE_EXT => {
+                let len = self.u8()?.into();
+                self.open(Kind::Tuple, len, len)
+            }
+            LARGE_TUPLE_EXT => {
+                let len = self.u32()?;
+                self.open(Kind::Tuple, len, len)
+            }
+            LIST_EXT => {
+                let len = self.u32()?;
+                self.open(Kind::List, len, len.saturating_add(1))
+            }
+            MAP_EXT => {
+                let len = self.u32()?;
+                self.open(Kind::Map, len, len.saturating_mul(2))
+            }
+            PID_EXT | NEW_PID_EXT => self.pid(tag),
+            PORT_EXT | NEW_PORT_EXT | V4_PORT_EXT => self.port(tag),
+            REFERENCE_EXT | NEW_REFERENCE_EXT | NEWER_REFERENCE_EXT => self.reference(tag),
+            EXPORT_EXT => self.export(),
+            NEW_FUN_EXT => self.fun(start),
+            _ => {
+                self.problem(start, &format!("Unknown tag {}", tag));
+                Err(Stop)
+            }
+        }
+    }
+
+    fn skip(&mut self, num: usize) -> Step {
+        self.take(num).map(|_| ())
+    }
+
+    /// Checks a value against its limit, if there are limits.
+    fn limit(&mut self, limit: Limit, value: usize) -> Step {
+        let exceeded = match &self.limits {
+            Some(limits) => limits.check(limit, value).err(),
+            None => None,
+        };
+
+        match exceeded {
+            Some(exceeded) => {
+                self.problem(self.pos, &exceeded.to_string());
+                self.exceeded = Some(exceeded);
+                Err(Stop)
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Checks a tag against the policy, if there is one.
+    fn allowed(&mut self, tag: u8, start: usize) -> Step {
+        let identifiers = match self.policy {
+            Some(policy) => policy.allows_identifiers(),
+            None => return Ok(()),
+        };
+
+        let kind = match tag {
+            NEW_FUN_EXT => "Funs",
+            EXPORT_EXT => "Exports",
+            PID_EXT | NEW_PID_EXT if !identifiers => "Pids",
+            PORT_EXT | NEW_PORT_EXT | V4_PORT_EXT if !identifiers => "Ports",
+            REFERENCE_EXT | NEW_REFERENCE_EXT | NEWER_REFERENCE_EXT if !identifiers => "References",
+            _ => return Ok(()),
+        };
+        self.violation(start, &format!("{} are not allowed", kind))
+    }
+
+    fn violation(&mut self, offset: usize, message: &str) -> Step {
+        self.problem(offset, message);
+        self.violation = self.problems.last().cloned().map(PolicyViolation);
+        Err(Stop)
+    }
+
+    fn allocate(&mut self, bytes: usize) -> Step {
+        self.allocated = self.allocated.saturating_add(bytes);
+        self.limit(Limit::Allocation, self.allocated)
+    }
+
+    /// Skips the bytes of a string or binary.
+    fn contents(&mut self, len: usize) -> Step {
+        self.limit(Limit::Length, len)?;
+        self.allocate(len)?;
+        self.skip(len)
+    }
+
+    /// Checks a container's header, then pushes it.
+    fn open(&mut self, kind: Kind, len: usize, terms: usize) -> Step {
+        self.limit(Limit::Length, len)?;
+        self.limit(Limit::Depth, self.stack.len() + 1)?;
+        self.push(kind, terms);
+        Ok(())
+    }
+
+    fn new_float(&mut self) -> Step {
+        let start = self.pos;
+        let bytes = self.take(8)?;
+        if !f64::from_be_bytes(bytes.try_into().unwrap()).is_finite() {
+            self.problem(start, "Float is not finite");
+        }
+        Ok(())
+    }
+
+    fn float(&mut self) -> Step {
+        let start = self.pos;
+        let text = self.take(31)?;
+        let is_valid = crate::utils::str_from_u8_nul_utf8(text)
+            .ok()
+            .and_then(|text| text.parse::<f64>().ok())
+            .is_some_and(f64::is_finite);
+
+        if !is_valid {
+            self.problem(start, "Float text is not a number");
+        }
+        Ok(())
+    }
+
+    fn atom(&mut self, tag: u8) -> Step {
+        // The tag was just read.
+        let term = self.pos - 1;
+        let len = match tag {
+            ATOM_EXT | ATOM_UTF8_EXT => self.u16()?,
+            _ => self.u8()?.into(),
+        };
+
+        self.atoms += 1;
+        self.limit(Limit::Atoms, self.atoms)?;
+        self.allocate(len)?;
+
+        let start = self.pos;
+        let name = self.take(len)?;
+
+        // Every byte is a Latin-1 character, so only UTF-8 can be invalid.
+        let chars = match tag {
+            ATOM_UTF8_EXT | SMALL_ATOM_UTF8_EXT => match core::str::from_utf8(name) {
+                Result::Ok(name) => {
+                    self.allowed_atom(term, name)?;
+                    name.chars().count()
+                }
+                Err(error) => {
+                    let offset = start + error.valid_up_to();
+                    self.problem(offset, "Atom is not valid UTF-8");
+                    return Ok(());
+                }
+            },
+            _ => {
+                if self.policy.is_some() {
+                    let name: String = name.iter().map(|&byte| char::from(byte)).collect();
+                    self.allowed_atom(term, &name)?;
+                }
+                name.len()
+            }
+        };
+
+        if chars > MAX_ATOM_CHARS {
+            let message = format!(
+                "Atom has {} characters, more than {}",
+                chars, MAX_ATOM_CHARS
+            );
+            self.problem(start, &message);
+        }
+        Ok(())
+    }
+
+    /// Checks an atom's name against the policy, if there is one.
+    fn allowed_atom(&mut self, term: usize, name: &str) -> Step {
+        match self.policy {
+            Some(policy) if !policy.allows_atom(name) => {
+                self.violation(term, &format!("Atom '{}' is not allowed", name))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks a term that has to be an atom, like the node of a pid.
+    fn atom_field(&mut self, field: &str) -> Step {
+        let start = self.pos;
+        match self.u8()? {
+            tag @ (ATOM_EXT | SMALL_ATOM_EXT | ATOM_UTF8_EXT | SMALL_ATOM_UTF8_EXT) => {
+                self.atom(tag)
+            }
+            tag => {
+                let message = format!("Expected an atom for the {}, found tag {}", field, tag);
+                self.problem(start, &message);
+                Err(Stop)
+            }
+        }
+    }
+
+    /// Checks a term that has to be an integer, like the old index of a fun.
+    fn integer_field(&mut self, field: &str) -> Step {
+        let start = self.pos;
+        match self.u8()? {
+            SMALL_INTEGER_EXT => self.skip(1),
+            INTEGER_EXT => self.skip(4),
+            tag => {
+                let message = format!("Expected an integer for the {}, found tag {}", field, tag);
+                self.problem(start, &message);
+                Err(Stop)
+            }
+        }
+    }
+
+    fn bit_binary(&mut self) -> Step {
+        let len = self.u32()?;
+        let start = self.pos;
+        let bits = self.u8()?;
+
+        let is_valid = match len {
+            0 => bits == 0,
+            _ => (1..=8).contains(&bits),
+        };
+        if !is_valid {
+            let message = format!("Bit binary can't end with {} bits", bits);
+            self.problem(start, &message);
+        }
+        self.contents(len)
+    }
+
+    fn big(&mut self, len: usize) -> Step {
+        let start = self.pos;
+        let sign = self.u8()?;
+        if sign > 1 {
+            self.problem(
+                start,
+                &format!("Integer sign should be 0 or 1, not {}", sign),
+            );
+        }
+        self.allocate(len)?;
+        self.skip(len)
+    }
+
+    fn pid(&mut self, tag: u8) -> Step {
+        self.atom_field("node")?;
+        // ID and serial, then the creation.
+        self.skip(8)?;
+        self.skip(if tag == NEW_PID_EXT { 4 } else { 1 })
+    }
+
+    fn port(&mut self, tag: u8) -> Step {
+        self.atom_field("node")?;
+        match tag {
+            PORT_EXT => self.skip(4 + 1),
+            NEW_PORT_EXT => self.skip(4 + 4),
+            _ => self.skip(8 + 4),
+        }
+    }
+
+    fn reference(&mut self, tag: u8) -> Step {
+        if tag == REFERENCE_EXT {
+            self.atom_field("node")?;
+            return self.skip(4 + 1);
+        }
+
+        let start = self.pos;
+        let words = self.u16()?;
+        if words > MAX_REFERENCE_WORDS {
+            let message = format!(
+                "Reference has {} ID words, more than {}",
+                words, MAX_REFERENCE_WORDS
+            );
+            self.problem(start, &message);
+        }
+
+        self.atom_field("node")?;
+        let creation = if tag == NEWER_REFERENCE_EXT { 4 } else { 1 };
+        self.skip(creation + words * 4)
+    }
+
+    fn export(&mut self) -> Step {
+        self.atom_field("module")?;
+        self.atom_field("function")?;
+        let start = self.pos;
+        match self.u8()? {
+            SMALL_INTEGER_EXT => self.skip(1),
+            tag => {
+                let message = format!("Expected a small integer for the arity, found tag {}", tag);
+                self.problem(start, &message);
+                Err(Stop)
+            }
+        }
+    }
+
+    fn fun(&mut self, start: usize) -> Step {
+        // The size includes itself but not the tag.
+        let end = (start + 1).saturating_add(self.u32()?);
+        // Arity, uniq and index.
+        self.skip(1 + 16 + 4)?;
+        let free = self.u32()?;
+
+        self.atom_field("module")?;
+        self.integer_field("old index")?;
+        self.integer_field("old uniq")?;
+
+        let pid = self.pos;
+        match self.u8()? {
+            tag @ (PID_EXT | NEW_PID_EXT) => self.pid(tag)?,
+            tag => {
+                let message = format!("Expected a pid for the fun, found tag {}", tag);
+                self.problem(pid, &message);
+                return Err(Stop);
+            }
+        }
+
+        self.open(Kind::Fun { end }, free, free)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(data: &[u8]) -> Vec<String> {
+        validate(data)
+            .problems
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn valid() {
+        let terms: [&[u8]; 6] = [
+            &[131, 97, 1],
+            &[131, 119, 2, 111, 107],
+            &[131, 107, 0, 2, 104, 105],
+            // {[1 | 2], #{a => <<1>>}}
+            &[
+                131, 104, 2, 108, 0, 0, 0, 1, 97, 1, 97, 2, 116, 0, 0, 0, 1, 119, 1, 97, 109, 0, 0,
+                0, 1, 1,
+            ],
+            // <0.1.2> on a@b
+            &[
+                131, 88, 119, 3, 97, 64, 98, 0, 0, 0, 1, 0, 0, 0, 2, 0, 0, 0, 3,
+            ],
+            // Latin-1 atoms can have any bytes.
+            &[131, 115, 2, 0xE9, 0xFF],
+        ];
+
+        for data in terms {
+            assert!(validate(data).is_valid(), "{:?}", messages(data));
+        }
+        assert_eq!(validate(&[131, 106]).to_string(), "No problems found");
+    }
+
+    #[test]
+    fn version() {
+        assert_eq!(messages(&[]), ["/ at byte 0: Data is empty"]);
+        assert_eq!(
+            messages(&[130, 97, 1]),
+            ["/ at byte 0: Expected format version 131, found 130"]
+        );
+    }
+
+    #[test]
+    fn every_problem() {
+        // [<<"bad utf-8">> atom, 1.0e400, sign 2 big] ++ trailing bytes
+        let mut data = vec![131, 108, 0, 0, 0, 3, 119, 2, 0xC3, 0x28];
+        data.extend([99]);
+        data.extend(b"1.0e400");
+        data.resize(data.len() + 24, 0);
+        data.extend([110, 1, 2, 5, 106, 0, 0]);
+
+        assert_eq!(
+            messages(&data),
+            [
+                "/0 at byte 8: Atom is not valid UTF-8",
+                "/1 at byte 11: Float text is not a number",
+                "/2 at byte 44: Integer sign should be 0 or 1, not 2",
+                "/ at byte 47: 2 bytes left after the term",
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_paths() {
+        // #{a => {1, <<"too long">>}}
+        let data = [
+            131, 116, 0, 0, 0, 1, 119, 1, 97, 104, 2, 97, 1, 109, 0, 0, 0, 9, 1,
+        ];
+        assert_eq!(
+            messages(&data),
+            ["/0/value/1 at byte 18: Data ends 8 bytes too soon"]
+        );
+
+        let data = [131, 108, 0, 0, 0, 1, 97, 1, 42];
+        assert_eq!(messages(&data), ["/tail at byte 8: Unknown tag 42"]);
+    }
+
+    #[test]
+    fn long_atoms() {
+        let mut data = vec![131, 118, 1, 0];
+        data.extend("é".repeat(128).as_bytes());
+        assert!(validate(&data).is_valid());
+
+        let mut data = vec![131, 100, 1, 0];
+        data.extend([b'a'; 256]);
+        assert_eq!(
+            messages(&data),
+            ["/ at byte 4: Atom has 256 characters, more than 255"]
+        );
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let mut data = vec![131];
+        for _ in 0..100_000 {
+            data.extend([104, 1]);
+        }
+        data.push(106);
+        assert!(validate(&data).is_valid());
+    }
+
+    #[test]
+    fn funs() {
+        // fun m:f/0 as a NEW_FUN_EXT with one free variable.
+        let mut fun = vec![0; 1 + 16 + 4];
+        fun.extend([0, 0, 0, 1, 119, 1, 109, 97, 0, 97, 0]);
+        fun.extend([88, 119, 1, 110, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0]);
+        fun.extend([97, 7]);
+
+        let mut data = vec![131, 112];
+        data.extend(((fun.len() + 4) as u32).to_be_bytes());
+        data.extend(&fun);
+        assert!(validate(&data).is_valid(), "{:?}", messages(&data));
+
+        data[5] += 1;
+        data.push(106);
+        assert_eq!(
+            messages(&data),
+            [
+                "/ at byte 56: Fun should end at byte 57",
+                "/ at byte 56: 1 bytes left after the term",
+            ]
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn compressed() {
+        let data = crate::compress(vec![131, 107, 0, 2, 104, 105], 6).unwrap();
+        assert!(validate(&data).is_valid());
+
+        let mut data = crate::compress(vec![131, 119, 1, 0xFF], 6).unwrap();
+        assert_eq!(messages(&data), ["/ at byte 3: Atom is not valid UTF-8"]);
+
+        data.truncate(8);
+        assert_eq!(messages(&data).len(), 1);
+
+        let mut data = crate::compress(vec![131, 97, 1], 6).unwrap();
+        let end = data.len();
+        data.extend([0, 0]);
+        assert_eq!(
+            messages(&data),
+            [format!(
+                "/ at byte {}: 2 bytes left after the compressed term",
+                end
+            )]
+        );
+
+        // Claims to inflate to 4 GiB.
+        let mut data = vec![131, 80, 0xFF, 0xFF, 0xFF, 0xFF];
+        data.extend(&crate::compress(vec![131, 97, 1], 6).unwrap()[6..]);
+        let messages = messages(&data);
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].starts_with("/ at byte 2: Failed to decompress"));
+    }
+}
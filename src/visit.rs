@@ -0,0 +1,550 @@
+// Copyright 2022 andre4ik3
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::packing::{COMPRESSED, FORMAT_VERSION};
+use crate::utils::{str_from_u8_nul_utf8, ReadBuf};
+use crate::validate::skip_term;
+
+use alloc::{string::String, vec::Vec};
+use anyhow::*;
+
+const NEW_FLOAT_EXT: u8 = 70;
+const BIT_BINARY_EXT: u8 = 77;
+const SMALL_INTEGER_EXT: u8 = 97;
+const INTEGER_EXT: u8 = 98;
+const FLOAT_EXT: u8 = 99;
+const ATOM_EXT: u8 = 100;
+const SMALL_TUPLE_EXT: u8 = 104;
+const LARGE_TUPLE_EXT: u8 = 105;
+const NIL_EXT: u8 = 106;
+const STRING_EXT: u8 = 107;
+const LIST_EXT: u8 = 108;
+const BINARY_EXT: u8 = 109;
+const SMALL_BIG_EXT: u8 = 110;
+const LARGE_BIG_EXT: u8 = 111;
+const SMALL_ATOM_EXT: u8 = 115;
+const MAP_EXT: u8 = 116;
+const ATOM_UTF8_EXT: u8 = 118;
+const SMALL_ATOM_UTF8_EXT: u8 = 119;
+
+/// Receives the parts of a term as visit reads them, so that terms can be
+/// processed without building them. Containers are reported as a begin
+/// call, then their contents, then an end call.
+///
+/// Every begin and scalar callback fails by default, so a visitor only has
+/// to implement the ones it expects.
+pub trait Visitor {
+    /// Integers, including big ones that fit.
+    fn integer(&mut self, value: i64) -> Result<()> {
+        Err(anyhow!("Unexpected integer {}", value))
+    }
+
+    /// Integers that don't fit in an i64, as their sign and magnitude, with
+    /// the least significant byte first.
+    fn big_integer(&mut self, negative: bool, digits: &[u8]) -> Result<()> {
+        let _ = (negative, digits);
+        Err(anyhow!("Unexpected big integer"))
+    }
+
+    fn float(&mut self, value: f64) -> Result<()> {
+        Err(anyhow!("Unexpected float {}", value))
+    }
+
+    /// Atoms, with Latin-1 names converted to UTF-8.
+    fn atom(&mut self, name: &str) -> Result<()> {
+        Err(anyhow!("Unexpected atom {}", name))
+    }
+
+    /// Lists of bytes packed as STRING_EXT.
+    fn string(&mut self, bytes: &[u8]) -> Result<()> {
+        let _ = bytes;
+        Err(anyhow!("Unexpected string"))
+    }
+
+    fn binary(&mut self, bytes: &[u8]) -> Result<()> {
+        let _ = bytes;
+        Err(anyhow!("Unexpected binary"))
+    }
+
+    /// Bitstrings, where only the given number of bits of the last byte are
+    /// used.
+    fn bitstring(&mut self, bytes: &[u8], bits: u8) -> Result<()> {
+        let _ = (bytes, bits);
+        Err(anyhow!("Unexpected bitstring"))
+    }
+
+    /// Starts a tuple. Its elements come next.
+    fn begin_tuple(&mut self, arity: usize) -> Result<()> {
+        Err(anyhow!("Unexpected tuple of {} elements", arity))
+    }
+
+    fn end_tuple(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Starts a list, including [] with a length of 0. Its elements come
+    /// next, then its tail if it isn't [].
+    fn begin_list(&mut self, len: usize) -> Result<()> {
+        Err(anyhow!("Unexpected list of {} elements", len))
+    }
+
+    /// Ends a list. proper is false if the list had a tail other than [],
+    /// which was visited just before this.
+    fn end_list(&mut self, proper: bool) -> Result<()> {
+        let _ = proper;
+        Ok(())
+    }
+
+    /// Starts a map. Its keys and values come next, alternating.
+    fn begin_map(&mut self, pairs: usize) -> Result<()> {
+        Err(anyhow!("Unexpected map of {} pairs", pairs))
+    }
+
+    fn end_map(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Any other term, like pids, ports, references and funs, as its tag and
+    /// its packed bytes, tag included.
+    fn other(&mut self, tag: u8, bytes: &[u8]) -> Result<()> {
+        let _ = bytes;
+        Err(anyhow!("Unexpected term with tag {}", tag))
+    }
+}
+
+/// Reads packed data and calls the visitor for each part of the term, in
+/// order. Bytes after the term are ignored, like unpack does.
+pub fn visit<V: Visitor + ?Sized>(data: &[u8], visitor: &mut V) -> Result<()> {
+    match data.get(..2) {
+        #[cfg(feature = "std")]
+        Some(&[FORMAT_VERSION, COMPRESSED]) => {
+            let mut decompressed = alloc::vec![FORMAT_VERSION];
+            decompressed.extend(crate::packing::decompress_term(&data[2..])?);
+            visit(&decompressed, visitor)
+        }
+        #[cfg(not(feature = "std"))]
+        Some(&[FORMAT_VERSION, COMPRESSED]) => {
+            Err(anyhow!("Compressed terms need the std feature"))
+        }
+        Some(&[FORMAT_VERSION, _]) => visit_term(&data[1..], visitor),
+        _ => Err(anyhow!("Format version mismatch!")),
+    }
+}
+
+/// Like visit, for a term without a format version.
+pub(crate) fn visit_term<V: Visitor + ?Sized>(data: &[u8], visitor: &mut V) -> Result<()> {
+    Walker::new(data).walk(visitor)
+}
+
+enum Kind {
+    Tuple,
+    List,
+    /// A list whose improper tail is being visited.
+    Tail,
+    Map,
+}
+
+struct Frame {
+    kind: Kind,
+    /// The number of terms left before the end of the container, not
+    /// counting the tail of a list.
+    left: usize,
+}
+
+/// Reads terms with an explicit stack, so that deeply nested terms can't
+/// overflow the real one.
+struct Walker<'a> {
+    data: &'a [u8],
+    buf: ReadBuf<'a>,
+    stack: Vec<Frame>,
+}
+
+impl<'a> Walker<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Walker {
+            data,
+            buf: ReadBuf::new(data),
+            stack: Vec::new(),
+        }
+    }
+
+    fn walk<V: Visitor + ?Sized>(&mut self, visitor: &mut V) -> Result<()> {
+        let tag = self.u8()?;
+        self.term(tag, visitor)?;
+
+        while let Some(frame) = self.stack.last_mut() {
+            if frame.left > 0 {
+                frame.left -= 1;
+                let tag = self.u8()?;
+                self.term(tag, visitor)?;
+                continue;
+            }
+
+            match frame.kind {
+                Kind::Tuple => {
+                    self.stack.pop();
+                    visitor.end_tuple()?;
+                }
+                Kind::Map => {
+                    self.stack.pop();
+                    visitor.end_map()?;
+                }
+                Kind::List => {
+                    let tag = self.buf.take(1)?[0];
+                    if tag == NIL_EXT {
+                        self.stack.pop();
+                        visitor.end_list(true)?;
+                    } else {
+                        frame.kind = Kind::Tail;
+                        self.term(tag, visitor)?;
+                    }
+                }
+                Kind::Tail => {
+                    self.stack.pop();
+                    visitor.end_list(false)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.buf.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<usize> {
+        let bytes = self.buf.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]).into())
+    }
+
+    fn u32(&mut self) -> Result<usize> {
+        let bytes = self.buf.take(4)?;
+        Ok(u32::from_be_bytes(bytes.try_into().unwrap()) as usize)
+    }
+
+    fn push(&mut self, kind: Kind, left: usize) {
+        self.stack.push(Frame { kind, left });
+    }
+
+    /// Visits a scalar, or begins a container and pushes it onto the stack.
+    fn term<V: Visitor + ?Sized>(&mut self, tag: u8, visitor: &mut V) -> Result<()> {
+        match tag {
+            SMALL_INTEGER_EXT => visitor.integer(self.u8()?.into()),
+            INTEGER_EXT => {
+                let bytes = self.buf.take(4)?;
+                visitor.integer(i32::from_be_bytes(bytes.try_into().unwrap()).into())
+            }
+            SMALL_BIG_EXT | LARGE_BIG_EXT => {
+                let len = match tag {
+                    SMALL_BIG_EXT => self.u8()?.into(),
+                    _ => self.u32()?,
+                };
+                let negative = self.u8()? != 0;
+                let digits = self.buf.take(len)?;
+                match small_big(negative, digits) {
+                    Some(value) => visitor.integer(value),
+                    None => visitor.big_integer(negative, digits),
+                }
+            }
+            NEW_FLOAT_EXT => {
+                let bytes = self.buf.take(8)?;
+                visitor.float(f64::from_be_bytes(bytes.try_into().unwrap()))
+            }
+            FLOAT_EXT => {
+                let text = self.buf.take(31)?;
+                visitor.float(str_from_u8_nul_utf8(text)?.parse()?)
+            }
+            ATOM_EXT | ATOM_UTF8_EXT | SMALL_ATOM_EXT | SMALL_ATOM_UTF8_EXT => {
+                let len = match tag {
+                    ATOM_EXT | ATOM_UTF8_EXT => self.u16()?,
+                    _ => self.u8()?.into(),
+                };
+                let name = self.buf.take(len)?;
+                match tag {
+                    ATOM_UTF8_EXT | SMALL_ATOM_UTF8_EXT => {
+                        visitor.atom(core::str::from_utf8(name)?)
+                    }
+                    _ => match core::str::from_utf8(name) {
+                        Result::Ok(name) if name.is_ascii() => visitor.atom(name),
+                        _ => {
+                            let name: String = name.iter().map(|&byte| char::from(byte)).collect();
+                            visitor.atom(&name)
+                        }
+                    },
+                }
+            }
+            STRING_EXT => {
+                let len = self.u16()?;
+                visitor.string(self.buf.take(len)?)
+            }
+            BINARY_EXT => {
+                let len = self.u32()?;
+                visitor.binary(self.buf.take(len)?)
+            }
+            BIT_BINARY_EXT => {
+                let len = self.u32()?;
+                let bits = self.u8()?;
+                visitor.bitstring(self.buf.take(len)?, bits)
+            }
+            SMALL_TUPLE_EXT | LARGE_TUPLE_EXT => {
+                let arity = match tag {
+                    SMALL_TUPLE_EXT => self.u8()?.into(),
+                    _ => self.u32()?,
+                };
+                visitor.begin_tuple(arity)?;
+                self.push(Kind::Tuple, arity);
+                Ok(())
+            }
+            NIL_EXT => {
+                visitor.begin_list(0)?;
+                visitor.end_list(true)
+            }
+            LIST_EXT => {
+                let len = self.u32()?;
+                visitor.begin_list(len)?;
+                self.push(Kind::List, len);
+                Ok(())
+            }
+            MAP_EXT => {
+                let pairs = self.u32()?;
+                visitor.begin_map(pairs)?;
+                self.push(Kind::Map, pairs.saturating_mul(2));
+                Ok(())
+            }
+            _ => {
+                let start = self.buf.position() - 1;
+                let end = skip_term(self.data, start)?;
+                self.buf.take(end - start - 1)?;
+                visitor.other(tag, &self.data[start..end])
+            }
+        }
+    }
+}
+
+/// Returns the value of a big integer if it fits in an i64.
+fn small_big(negative: bool, digits: &[u8]) -> Option<i64> {
+    let len = digits
+        .iter()
+        .rposition(|&digit| digit != 0)
+        .map_or(0, |last| last + 1);
+    if len > 8 {
+        return None;
+    }
+
+    let magnitude = digits[..len]
+        .iter()
+        .rev()
+        .fold(0u64, |value, &digit| (value << 8) | u64::from(digit));
+
+    match negative {
+        true => (magnitude <= 1 << 63).then(|| (magnitude as i64).wrapping_neg()),
+        false => i64::try_from(magnitude).ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+
+    /// Writes terms back out in Erlang syntax.
+    #[derive(Default)]
+    struct Printer {
+        out: String,
+        /// Whether a comma is needed before the next term, for each level.
+        commas: Vec<bool>,
+    }
+
+    impl Printer {
+        fn next(&mut self) {
+            if let Some(comma) = self.commas.last_mut() {
+                if *comma {
+                    self.out.push(',');
+                }
+                *comma = true;
+            }
+        }
+
+        fn open(&mut self, bracket: &str) {
+            self.next();
+            self.out.push_str(bracket);
+            self.commas.push(false);
+        }
+    }
+
+    impl Visitor for Printer {
+        fn integer(&mut self, value: i64) -> Result<()> {
+            self.next();
+            write!(self.out, "{}", value)?;
+            Ok(())
+        }
+
+        fn big_integer(&mut self, negative: bool, digits: &[u8]) -> Result<()> {
+            self.next();
+            write!(self.out, "big({}, {})", negative, digits.len())?;
+            Ok(())
+        }
+
+        fn float(&mut self, value: f64) -> Result<()> {
+            self.next();
+            write!(self.out, "{:?}", value)?;
+            Ok(())
+        }
+
+        fn atom(&mut self, name: &str) -> Result<()> {
+            self.next();
+            self.out.push_str(name);
+            Ok(())
+        }
+
+        fn string(&mut self, bytes: &[u8]) -> Result<()> {
+            self.next();
+            write!(self.out, "{:?}", core::str::from_utf8(bytes)?)?;
+            Ok(())
+        }
+
+        fn binary(&mut self, bytes: &[u8]) -> Result<()> {
+            self.next();
+            write!(self.out, "<<{:?}>>", bytes)?;
+            Ok(())
+        }
+
+        fn begin_tuple(&mut self, _: usize) -> Result<()> {
+            self.open("{");
+            Ok(())
+        }
+
+        fn end_tuple(&mut self) -> Result<()> {
+            self.commas.pop();
+            self.out.push('}');
+            Ok(())
+        }
+
+        fn begin_list(&mut self, _: usize) -> Result<()> {
+            self.open("[");
+            Ok(())
+        }
+
+        fn end_list(&mut self, proper: bool) -> Result<()> {
+            self.commas.pop();
+            self.out.push_str(if proper { "]" } else { "|]" });
+            Ok(())
+        }
+
+        fn begin_map(&mut self, _: usize) -> Result<()> {
+            self.open("#{");
+            Ok(())
+        }
+
+        fn end_map(&mut self) -> Result<()> {
+            self.commas.pop();
+            self.out.push('}');
+            Ok(())
+        }
+
+        fn other(&mut self, tag: u8, bytes: &[u8]) -> Result<()> {
+            self.next();
+            write!(self.out, "other({}, {})", tag, bytes.len())?;
+            Ok(())
+        }
+    }
+
+    fn print(data: &[u8]) -> String {
+        let mut printer = Printer::default();
+        visit(data, &mut printer).unwrap();
+        printer.out
+    }
+
+    #[test]
+    fn events() {
+        // {[1, "ab"], #{café => <<7>>}, []}
+        let data = [
+            131, 104, 3, 108, 0, 0, 0, 2, 97, 1, 107, 0, 2, 97, 98, 106, 116, 0, 0, 0, 1, 100, 0,
+            4, 99, 97, 102, 0xE9, 109, 0, 0, 0, 1, 7, 106,
+        ];
+        assert_eq!(print(&data), "{[1,\"ab\"],#{café,<<[7]>>},[]}");
+
+        // [1.5 | 2]
+        let data = [131, 108, 0, 0, 0, 1, 70, 63, 248, 0, 0, 0, 0, 0, 0, 97, 2];
+        assert_eq!(print(&data), "[1.5,2|]");
+
+        // {<0.1.0>, 3}
+        let data = [
+            131, 104, 2, 88, 119, 1, 110, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 97, 3,
+        ];
+        assert_eq!(print(&data), "{other(88, 16),3}");
+    }
+
+    #[test]
+    fn integers() {
+        assert_eq!(print(&[131, 98, 255, 255, 255, 255]), "-1");
+        assert_eq!(print(&[131, 110, 5, 1, 0, 0, 0, 0, 1]), "-4294967296");
+        let mut data = vec![131, 110, 8, 1];
+        data.extend([0, 0, 0, 0, 0, 0, 0, 128]);
+        assert_eq!(print(&data), i64::MIN.to_string());
+        data[11] = 129;
+        assert_eq!(print(&data), "big(true, 8)");
+    }
+
+    #[test]
+    fn large_list() {
+        /// Sums a list without keeping its elements.
+        #[derive(Default)]
+        struct Sum {
+            total: i64,
+            done: bool,
+        }
+
+        impl Visitor for Sum {
+            fn integer(&mut self, value: i64) -> Result<()> {
+                self.total += value;
+                Ok(())
+            }
+
+            fn begin_list(&mut self, _: usize) -> Result<()> {
+                Ok(())
+            }
+
+            fn end_list(&mut self, proper: bool) -> Result<()> {
+                self.done = proper;
+                Ok(())
+            }
+        }
+
+        let len = 100_000u32;
+        let mut data = vec![131, 108];
+        data.extend(len.to_be_bytes());
+        for _ in 0..len {
+            data.extend([97, 2]);
+        }
+        data.push(106);
+
+        let mut sum = Sum::default();
+        visit(&data, &mut sum).unwrap();
+        assert_eq!(sum.total, 200_000);
+        assert!(sum.done);
+
+        // Anything the visitor doesn't expect is an error.
+        assert!(visit(&[131, 104, 0], &mut sum).is_err());
+    }
+
+    #[test]
+    fn invalid() {
+        let mut printer = Printer::default();
+        assert!(visit(&[131, 108, 0, 0, 0, 2, 97, 1], &mut printer).is_err());
+        assert!(visit(&[130, 97, 1], &mut printer).is_err());
+        assert!(visit(&[131, 42], &mut printer).is_err());
+    }
+}